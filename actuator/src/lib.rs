@@ -1,17 +1,22 @@
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::io::Write;
-use std::net::{IpAddr, TcpStream};
+use std::net::IpAddr;
+use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
 
-use log::debug;
-use mdns_sd::{ServiceDaemon, ServiceInfo};
+use log::{debug, error};
+use mdns_sd::ServiceInfo;
 
+use device::address::Address;
+use device::client::Client;
+use device::discovery::Backend;
+use device::error::Error;
+use device::handle::DeviceHandle;
 use device::id::Id;
 use device::message::Message;
-use device::model::Model;
 use device::name::Name;
+use device::router::Router;
 use device::{Device, Handler};
 
 /// An Actuator mutates the Environment.
@@ -20,28 +25,42 @@ pub trait Actuator: Device {
 
     fn get_environment(&self) -> &Arc<Mutex<Option<ServiceInfo>>>;
 
-    /// By default, an `Actuator` forwards all incoming requests to the `Environment`.
-    fn get_handler(&self) -> Handler {
+    /// Returns the names of the `Command`s which this `Actuator` accepts (e.g. `HeatBy`).
+    fn get_command_names() -> Vec<String>;
+
+    /// An `Actuator` advertises the endpoints served by its default [`get_handler`](Actuator::get_handler).
+    fn get_endpoints(&self) -> Vec<String> {
+        vec![String::from("POST /command")]
+    }
+
+    /// An `Actuator` advertises the names of the `Command`s it accepts, so that the `Controller`
+    /// can check that this `Actuator` understands a `Command` before sending it.
+    fn get_properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("commands"), Self::get_command_names().join(","));
+        properties
+    }
+
+    /// By default, an `Actuator` forwards all incoming `POST /command` requests to the `Environment`.
+    fn get_handler(&self, _address: Address) -> Handler {
         // Anything which depends on self must be cloned outside of the |stream| lambda.
         // We cannot refer to `self` inside of this lambda.
         let self_name = self.get_name().clone();
         let self_id = self.get_id().clone();
-        let self_model = Self::get_model();
 
         let environment = Arc::clone(self.get_environment());
 
-        Box::new(move |stream| {
-            if let Ok(message) = Message::read(stream) {
-                if message.start_line == "POST /command HTTP/1.1" {
-                    Self::handle_post_command(stream, &environment, message, &self_id, self_model, &self_name)
-                } else {
-                    let msg = format!("cannot parse request: {}", message.start_line);
-                    Self::handler_failure(self_name.clone(), stream, msg.as_str())
-                }
-            } else {
-                Self::handler_failure(self_name.clone(), stream, "unable to read Message from stream")
-            }
-        })
+        // Connections to the Environment are shared by all of this Actuator's workers
+        let client = Client::new();
+
+        // after repeated failures, the Environment is forgotten, and discovered again
+        let failures = Arc::new(AtomicU32::new(0));
+
+        Router::new(self.get_name().clone())
+            .post("/command", move |stream, message, _| {
+                Self::handle_post_command(stream, &environment, &failures, &client, message, &self_id, &self_name)
+            })
+            .into_handler()
     }
 
     /// Describes how `POST /command` requests are handled by `Actuator`s.
@@ -51,50 +70,73 @@ pub trait Actuator: Device {
     fn handle_post_command(
         stream: &mut impl Write,
         environment: &Arc<Mutex<Option<ServiceInfo>>>,
+        failures: &AtomicU32,
+        client: &Client,
         message: Message,
         self_id: &Id,
-        self_model: Model,
         self_name: &Name,
-    ) {
+    ) -> Result<(), Error> {
         // send a Command to this Actuator (Command is in the body)
         //     ex: curl 10.12.50.26:5454/command -d '{"name":"HeatBy","value":"25"}'
 
-        let environment = environment.lock().unwrap();
+        let mut environment = environment.lock().unwrap();
 
         match environment.as_ref().map(Self::extract_address) {
-            Some(address) => {
+            Some(Ok(address)) => {
                 debug!("[Actuator] forwarding body {:?} as-is to environment @ {}", message.body, address);
 
-                let mut environment = TcpStream::connect(address.to_string()).unwrap();
-
                 let mut headers = HashMap::new();
                 headers.insert("id", self_id.to_string());
-                headers.insert("model", self_model.to_string());
+                headers.insert("model", Self::get_model().to_string());
 
                 // forward Command to Environment
                 let forwarded_command = message.with_headers(headers);
-                forwarded_command.write(&mut environment);
-
-                // ack request from Controller to close the socket
-                let ack = Message::respond_ok();
-                ack.write(stream)
+                let forwarded = client.send(&address, &forwarded_command);
+                Self::record_request(&mut environment, failures, forwarded.is_ok());
+
+                match forwarded {
+                    Ok(_) => {
+                        // acknowledge the request from the Controller
+                        let ack = Message::respond_ok();
+                        ack.write(stream)
+                    }
+                    Err(e) => {
+                        let msg = format!("could not forward command to environment @ {}: {}", address, e);
+                        Self::handler_failure(self_name.clone(), stream, 502, "environment_unreachable", msg.as_str())
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                let msg = format!("could not find environment: {}", e);
+                Self::handler_failure(self_name.clone(), stream, 503, "environment_not_found", msg.as_str())
             }
             None => {
                 let msg = "could not find environment";
-                Self::handler_failure(self_name.clone(), stream, msg)
+                Self::handler_failure(self_name.clone(), stream, 503, "environment_not_found", msg)
             }
         }
     }
 
-    fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String) -> JoinHandle<()> {
-        std::thread::spawn(move || {
+    /// Starts this `Actuator` on a new thread, returning a `DeviceHandle` which can be used to stop it.
+    ///
+    /// The `Actuator` finds (and is found by) its peers using the `discovery` `Backend`.
+    fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String, discovery: Backend) -> DeviceHandle {
+        DeviceHandle::spawn(name.clone(), move |shutdown| {
             let device = Self::new(id, name);
 
-            let mdns = ServiceDaemon::new().unwrap();
+            let discovery = match discovery.connect(&shutdown) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    error!("[Actuator] {} cannot start discovery: {}", device.get_name(), e);
+                    return;
+                }
+            };
 
-            device.discover_once("_environment", device.get_environment(), mdns.clone());
+            device.discover_once("_environment", device.get_environment(), &discovery, &shutdown);
 
-            device.respond(ip, port, group.as_str(), mdns)
+            if let Err(e) = device.respond(ip, port, group.as_str(), discovery.as_ref(), &shutdown) {
+                error!("[Actuator] {} stopped responding to requests: {}", device.get_name(), e)
+            }
         })
     }
 }
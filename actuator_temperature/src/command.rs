@@ -22,6 +22,11 @@ impl Display for Command {
 }
 
 impl Command {
+    /// Returns the name of every `Command` which the `TemperatureActuator` accepts.
+    pub fn names() -> [&'static str; 2] {
+        ["CoolBy", "HeatBy"]
+    }
+
     /// Attempts to parse a `Command` from the provided string or string slice.
     pub fn parse<S: Into<String>>(s: S) -> Result<Command, String> {
         let original = s.into();
@@ -1,13 +1,17 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use mdns_sd::ServiceInfo;
 
 use actuator::Actuator;
+use device::address::Address;
 use device::id::Id;
 use device::model::Model;
 use device::name::Name;
 use device::{Device, Handler};
 
+use crate::command::Command;
+
 pub mod command;
 
 /// `TemperatureActuator` is an example implementation of `Actuator`.
@@ -30,8 +34,16 @@ impl Device for TemperatureActuator {
         Model::Thermo5000
     }
 
-    fn get_handler(&self) -> Handler {
-        Actuator::get_handler(self)
+    fn get_handler(&self, address: Address) -> Handler {
+        Actuator::get_handler(self, address)
+    }
+
+    fn get_endpoints(&self) -> Vec<String> {
+        Actuator::get_endpoints(self)
+    }
+
+    fn get_properties(&self) -> HashMap<String, String> {
+        Actuator::get_properties(self)
     }
 }
 
@@ -47,11 +59,14 @@ impl Actuator for TemperatureActuator {
     fn get_environment(&self) -> &Arc<Mutex<Option<ServiceInfo>>> {
         &self.environment
     }
+
+    fn get_command_names() -> Vec<String> {
+        Command::names().iter().map(|name| name.to_string()).collect()
+    }
 }
 
 #[cfg(test)]
 mod actuator_temperature_tests {
-    use std::collections::HashMap;
     use std::net::IpAddr;
 
     use super::*;
@@ -127,4 +142,14 @@ mod actuator_temperature_tests {
 
         compare_service_info(actual, &expected)
     }
+
+    #[test]
+    fn test_get_service_info() {
+        let actuator = TemperatureActuator::new(Id::new("myId"), Name::new("myName"));
+        let info = actuator.get_service_info(IpAddr::from([1, 2, 3, 4]), 42, "_actuator").unwrap();
+
+        assert_eq!(TemperatureActuator::extract_commands(&info), vec!["CoolBy", "HeatBy"]);
+        assert_eq!(TemperatureActuator::extract_endpoints(&info), vec!["POST /command"]);
+        assert_eq!(TemperatureActuator::extract_kind(&info), None);
+    }
 }
@@ -1,7 +1,7 @@
-use std::time::Duration;
-
 use actuator::Actuator;
 use actuator_temperature::TemperatureActuator;
+use device::discovery::Backend;
+use device::handle::wait_for_termination;
 use device::id::Id;
 use device::name::Name;
 
@@ -15,7 +15,13 @@ fn main() {
     let ip = local_ip_address::local_ip().unwrap();
     let group = String::from("_actuator");
 
-    TemperatureActuator::start(ip, port, id, name, group);
+    // Devices find each other via mDNS, unless DISCOVERY=static selects a fixed list of Devices instead
+    let discovery = Backend::from_env().unwrap();
+
+    let handle = TemperatureActuator::start(ip, port, id, name, group, discovery);
     println!("TemperatureActuator is running...");
-    std::thread::sleep(Duration::MAX)
+
+    // stop cleanly on Ctrl-C, or when the container runtime sends SIGTERM
+    wait_for_termination().unwrap();
+    handle.shutdown();
 }
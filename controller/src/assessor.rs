@@ -1,33 +1,328 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
 use phf::{phf_map, Map};
 
 use actuator_temperature::command::Command as Thermo5000;
 use datum::unit::Unit;
 use datum::Datum;
+use device::id::Id;
+use device::json::{escape, Value};
+
+use crate::pid::Pid;
+use crate::script::Scripts;
+
+/// `Assess` decides which `Command` (if any) to send to an `Actuator`, based on the latest `Datum`
+/// from its `Sensor`, and anything it remembers about that `Sensor`'s previous `Datum`s.
+///
+/// Any `FnMut(&Datum) -> Option<Box<dyn actuator::Command>>` closure can be used to `Assess` data.
+pub trait Assess: Send {
+    fn assess(&mut self, datum: &Datum) -> Option<Box<dyn actuator::Command>>;
 
-#[derive(Clone)]
-pub struct Assessor {
-    pub(crate) assess: fn(&Datum) -> Option<Box<dyn actuator::Command>>,
+    /// Aims for `setpoint` from now on, without forgetting anything about previous `Datum`s.
+    ///
+    /// `Assess`es which do not aim for a setpoint (like closures) ignore it.
+    fn set_setpoint(&mut self, _setpoint: Option<f32>) {}
+}
+
+impl<F: FnMut(&Datum) -> Option<Box<dyn actuator::Command>> + Send> Assess for F {
+    fn assess(&mut self, datum: &Datum) -> Option<Box<dyn actuator::Command>> {
+        self(datum)
+    }
+}
+
+/// An `Assessor` describes how the data from a `Sensor` should be assessed.
+///
+/// **Design Decision**: `Assessor`s are described by their parameters, rather than by arbitrary
+/// functions, so that they can be installed over HTTP (see `PUT /assessors/:id`) and saved to disk.
+/// Each `Assessor` is turned into an [`Assess`] with [`build`](Self::build), which holds any state it needs.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Assessor {
+    /// Sends Thermo5000 `Command`s which bring the temperature back to the `setpoint` whenever it
+    /// leaves the band between `low` and `high` (in degrees C).
+    Thermostat { setpoint: f32, low: f32, high: f32 },
+    /// Sends Thermo5000 `Command`s chosen by a [`Pid`] controller, no larger than `max_output`.
+    Pid {
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        setpoint: f32,
+        max_output: f32,
+    },
+    /// Sends the `Command`s chosen by the script called `name` (see [`Scripts`]), which is told the
+    /// `setpoint` (if any) to aim for.
+    Script { name: String, setpoint: Option<f32> },
 }
 
 /// Default `Assessor`s for different `Model`s of `Device`.
 ///
-/// Can be overridden by the user.
+/// Can be overridden by the user, for a specific `Sensor`, with `PUT /assessors/:id`.
 pub static DEFAULT_ASSESSOR: Map<&str, Assessor> = phf_map! {
     // keys here should match Model ids defined in model.rs
-    "thermo5000" => Assessor { assess: |datum| {
+    "thermo5000" => Assessor::Thermostat { setpoint: 25.0, low: 22.0, high: 28.0 },
+};
+
+/// Allows `Assessor`s to be converted to JSON `String`s with `to_string()`.
+impl Display for Assessor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Assessor::Thermostat { setpoint, low, high } => {
+                write!(f, r#"{{"type":"thermostat","setpoint":{},"low":{},"high":{}}}"#, setpoint, low, high)
+            }
+            Assessor::Pid {
+                kp,
+                ki,
+                kd,
+                setpoint,
+                max_output,
+            } => write!(
+                f,
+                r#"{{"type":"pid","kp":{},"ki":{},"kd":{},"setpoint":{},"max_output":{}}}"#,
+                kp, ki, kd, setpoint, max_output
+            ),
+            Assessor::Script { name, setpoint: None } => write!(f, r#"{{"type":"script","name":"{}"}}"#, escape(name.as_str())),
+            Assessor::Script {
+                name,
+                setpoint: Some(setpoint),
+            } => write!(f, r#"{{"type":"script","name":"{}","setpoint":{}}}"#, escape(name.as_str()), setpoint),
+        }
+    }
+}
+
+impl Assessor {
+    /// Creates a new [`Assess`] with these parameters, for the `Sensor` with this `id`, which has not
+    /// seen any data yet.
+    ///
+    /// Only `Script` `Assessor`s use the `id` and the `scripts`.
+    pub fn build(&self, id: &Id, scripts: &Scripts) -> Box<dyn Assess> {
+        match self.clone() {
+            Assessor::Thermostat { setpoint, low, high } => Box::new(Thermostat { setpoint, low, high }),
+            Assessor::Pid {
+                kp,
+                ki,
+                kd,
+                setpoint,
+                max_output,
+            } => Box::new(Pid::new(kp, ki, kd, setpoint, max_output)),
+            Assessor::Script { name, setpoint } => scripts.build(name.as_str(), id, setpoint),
+        }
+    }
+
+    /// Returns the setpoint which this `Assessor` aims for, if any.
+    pub fn setpoint(&self) -> Option<f32> {
+        match self {
+            Assessor::Thermostat { setpoint, .. } | Assessor::Pid { setpoint, .. } => Some(*setpoint),
+            Assessor::Script { setpoint, .. } => *setpoint,
+        }
+    }
+
+    /// Returns `true` if `other` is this `Assessor`, aiming for a (possibly) different setpoint.
+    fn retargets(&self, other: &Assessor) -> bool {
+        match (self, other) {
+            (Assessor::Script { name, .. }, Assessor::Script { name: other_name, .. }) => name == other_name,
+            (_, Assessor::Script { .. }) => false,
+            _ => other.setpoint().is_some_and(|setpoint| self.with_setpoint(setpoint) == *other),
+        }
+    }
+
+    /// Returns a copy of this `Assessor` which aims for `setpoint` instead (see `PUT /setpoints/:id`).
+    ///
+    /// A `Thermostat`'s band moves along with its `setpoint`, so that it stays the same width.
+    pub fn with_setpoint(&self, setpoint: f32) -> Assessor {
+        match self.clone() {
+            Assessor::Thermostat { setpoint: old, low, high } => Assessor::Thermostat {
+                setpoint,
+                low: low + (setpoint - old),
+                high: high + (setpoint - old),
+            },
+            Assessor::Pid { kp, ki, kd, max_output, .. } => Assessor::Pid {
+                kp,
+                ki,
+                kd,
+                setpoint,
+                max_output,
+            },
+            Assessor::Script { name, .. } => Assessor::Script {
+                name,
+                setpoint: Some(setpoint),
+            },
+        }
+    }
+
+    /// Attempts to parse an `Assessor` from the provided JSON, e.g. `{"type":"thermostat","setpoint":25,"low":22,"high":28}`.
+    pub fn parse<S: AsRef<str>>(s: S) -> Result<Assessor, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        Self::from_json(&json)
+    }
 
-        let t = datum.get_as_float().unwrap();
-        assert_eq!(datum.unit, Unit::DegreesC);
+    fn from_json(json: &Value) -> Result<Assessor, String> {
+        let number = |key: &str| {
+            json.get(key)
+                .and_then(Value::as_f64)
+                .filter(|n| n.is_finite())
+                .map(|n| n as f32)
+                .ok_or(format!("assessor is missing a numeric \"{}\"", key))
+        };
 
-        if t > 28.0 {
-            Some(Box::new(Thermo5000::CoolBy(t - 25.0)))
-        } else if t < 22.0 {
-            Some(Box::new(Thermo5000::HeatBy(25.0 - t)))
+        match json.get("type").and_then(Value::as_str) {
+            Some("thermostat") => {
+                let (setpoint, low, high) = (number("setpoint")?, number("low")?, number("high")?);
+
+                if low <= setpoint && setpoint <= high {
+                    Ok(Assessor::Thermostat { setpoint, low, high })
+                } else {
+                    Err(format!("thermostat setpoint {} must be between low {} and high {}", setpoint, low, high))
+                }
+            }
+            Some("pid") => {
+                let (kp, ki, kd) = (number("kp")?, number("ki")?, number("kd")?);
+                let (setpoint, max_output) = (number("setpoint")?, number("max_output")?);
+
+                if kp < 0.0 || ki < 0.0 || kd < 0.0 {
+                    Err(String::from("pid gains must not be negative"))
+                } else if max_output <= 0.0 {
+                    Err(String::from("pid max_output must be positive"))
+                } else {
+                    Ok(Assessor::Pid {
+                        kp,
+                        ki,
+                        kd,
+                        setpoint,
+                        max_output,
+                    })
+                }
+            }
+            Some("script") => {
+                let name = json
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .filter(|name| !name.is_empty())
+                    .ok_or(String::from("script assessor is missing a \"name\""))?;
+
+                let setpoint = match json.get("setpoint") {
+                    None | Some(Value::Null) => None,
+                    Some(_) => Some(number("setpoint")?),
+                };
+
+                Ok(Assessor::Script {
+                    name: name.to_string(),
+                    setpoint,
+                })
+            }
+            Some(other) => Err(format!("unknown assessor type \"{}\", expected \"thermostat\", \"pid\" or \"script\"", other)),
+            None => Err(String::from("assessor is missing a \"type\"")),
+        }
+    }
+
+    /// Serializes `assessors` as a JSON array of `{"id":...,"assessor":...}` objects, sorted by `Id`.
+    pub fn all_to_json(assessors: &HashMap<Id, Assessor>) -> String {
+        let mut assessors: Vec<(&Id, &Assessor)> = assessors.iter().collect();
+        assessors.sort_by_key(|(id, _)| id.to_string());
+
+        let assessors: Vec<String> = assessors
+            .into_iter()
+            .map(|(id, assessor)| format!(r#"{{"id":"{}","assessor":{}}}"#, escape(id.to_string().as_str()), assessor))
+            .collect();
+
+        format!("[{}]", assessors.join(","))
+    }
+
+    /// Parses the JSON produced by [`all_to_json`](Self::all_to_json).
+    pub fn all_from_json<S: AsRef<str>>(s: S) -> Result<HashMap<Id, Assessor>, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        let entries = json.as_array().ok_or(String::from("assessors must be a JSON array"))?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                let id = entry.get("id").and_then(Value::as_str).ok_or(String::from("assessor is missing an \"id\""))?;
+                let assessor = entry.get("assessor").ok_or(format!("no assessor for id {}", id))?;
+                Ok((Id::new(id), Self::from_json(assessor)?))
+            })
+            .collect()
+    }
+}
+
+/// A `Thermostat` brings the temperature back to the `setpoint` whenever it leaves the band between
+/// `low` and `high` (see [`Assessor::Thermostat`]).
+struct Thermostat {
+    setpoint: f32,
+    low: f32,
+    high: f32,
+}
+
+impl Assess for Thermostat {
+    fn assess(&mut self, datum: &Datum) -> Option<Box<dyn actuator::Command>> {
+        // a Datum which is not a temperature cannot be assessed by a thermostat
+        let t = datum.get_as_float().filter(|_| datum.unit == Unit::DegreesC)?;
+
+        if t > self.high {
+            Some(Box::new(Thermo5000::CoolBy(t - self.setpoint)))
+        } else if t < self.low {
+            Some(Box::new(Thermo5000::HeatBy(self.setpoint - t)))
         } else {
             None
         }
-    }}
-};
+    }
+
+    /// Moves the band along with the `setpoint`, as [`Assessor::with_setpoint`] does.
+    fn set_setpoint(&mut self, setpoint: Option<f32>) {
+        if let Some(setpoint) = setpoint {
+            self.low += setpoint - self.setpoint;
+            self.high += setpoint - self.setpoint;
+            self.setpoint = setpoint;
+        }
+    }
+}
+
+/// `Assessments` keeps the running [`Assess`] for each `Sensor`, so that it can remember that `Sensor`'s
+/// previous data from one `Datum` to the next.
+///
+/// When the `Assessor` for a `Sensor` changes (e.g. via `PUT /assessors/:id`), a new `Assess` is
+/// built for it, which starts over with no memory of previous data. When only its setpoint changes
+/// (e.g. via `PUT /setpoints/:id`, or a `Schedule`), the running `Assess` is told the new setpoint
+/// instead, so that e.g. a `Pid` keeps its integral.
+#[derive(Default)]
+pub struct Assessments {
+    scripts: Scripts,
+    running: HashMap<Id, (Assessor, Box<dyn Assess>)>,
+}
+
+impl Assessments {
+    /// Creates `Assessments` which run `Script` `Assessor`s with these `scripts`.
+    pub fn new(scripts: Scripts) -> Assessments {
+        Assessments {
+            scripts,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Assesses the latest `datum` from the `Sensor` with the given `id`, using the `assessor` which
+    /// is currently configured for it.
+    pub fn assess(&mut self, id: &Id, assessor: &Assessor, datum: &Datum) -> Option<Box<dyn actuator::Command>> {
+        let scripts = &self.scripts;
+        let (current, running) = self
+            .running
+            .entry(id.clone())
+            .or_insert_with(|| (assessor.clone(), assessor.build(id, scripts)));
+
+        if current != assessor {
+            if current.retargets(assessor) {
+                running.set_setpoint(assessor.setpoint());
+            } else {
+                *running = assessor.build(id, scripts);
+            }
+            *current = assessor.clone();
+        }
+
+        running.assess(datum)
+    }
+
+    /// Forgets the state of any `Sensor` for which `keep` returns `false`.
+    pub fn retain(&mut self, keep: impl Fn(&Id) -> bool) {
+        self.running.retain(|id, _| keep(id));
+    }
+}
 
 #[cfg(test)]
 mod assessor_tests {
@@ -37,11 +332,11 @@ mod assessor_tests {
 
     #[test]
     fn test_thermo5000() {
-        let assessor = DEFAULT_ASSESSOR.get("thermo5000").unwrap();
+        let mut assessor = DEFAULT_ASSESSOR.get("thermo5000").unwrap().build(&Id::new("my_id"), &Scripts::default());
 
         let too_cold = Datum::new(21.0, Unit::DegreesC, Utc::now());
 
-        let actual = (assessor.assess)(&too_cold).unwrap();
+        let actual = assessor.assess(&too_cold).unwrap();
         let expected = Thermo5000::HeatBy(4.0);
 
         // it is very difficult to compare a Box<dyn actuator::Command> to a Thermo5000::Command
@@ -50,14 +345,232 @@ mod assessor_tests {
         assert_eq!(actual.to_string(), expected.to_string());
 
         let too_hot = Datum::new(30.0, Unit::DegreesC, Utc::now());
-        let actual = (assessor.assess)(&too_hot).unwrap();
+        let actual = assessor.assess(&too_hot).unwrap();
         let expected = Thermo5000::CoolBy(5.0);
 
         assert_eq!(actual.to_string(), expected.to_string());
 
         let just_right = Datum::new(25.0, Unit::DegreesC, Utc::now());
-        let actual = (assessor.assess)(&just_right);
+        let actual = assessor.assess(&just_right);
+
+        assert!(actual.is_none());
+    }
+
+    #[test]
+    fn test_thermostat() {
+        let mut assessor = Assessor::Thermostat {
+            setpoint: 20.0,
+            low: 19.0,
+            high: 23.0,
+        }
+        .build(&Id::new("my_id"), &Scripts::default());
 
+        let actual = assessor.assess(&Datum::new(21.0, Unit::DegreesC, Utc::now()));
         assert!(actual.is_none());
+
+        let actual = assessor.assess(&Datum::new(24.0, Unit::DegreesC, Utc::now())).unwrap();
+        assert_eq!(actual.to_string(), Thermo5000::CoolBy(4.0).to_string());
+
+        // a Datum which is not a temperature is ignored
+        let actual = assessor.assess(&Datum::new(true, Unit::PoweredOn, Utc::now()));
+        assert!(actual.is_none());
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        let expected = Assessor::Thermostat {
+            setpoint: 21.5,
+            low: 20.0,
+            high: 23.0,
+        };
+        let serialized = expected.to_string();
+        assert_eq!(serialized, r#"{"type":"thermostat","setpoint":21.5,"low":20,"high":23}"#);
+        assert_eq!(Assessor::parse(serialized), Ok(expected));
+    }
+
+    #[test]
+    fn test_with_setpoint() {
+        let thermostat = DEFAULT_ASSESSOR.get("thermo5000").unwrap().with_setpoint(21.0);
+        let expected = Assessor::Thermostat {
+            setpoint: 21.0,
+            low: 18.0,
+            high: 24.0,
+        };
+        assert_eq!(thermostat, expected);
+
+        let pid = Assessor::Pid {
+            kp: 2.0,
+            ki: 0.1,
+            kd: 0.5,
+            setpoint: 25.0,
+            max_output: 10.0,
+        };
+        let expected = Assessor::Pid {
+            kp: 2.0,
+            ki: 0.1,
+            kd: 0.5,
+            setpoint: 21.0,
+            max_output: 10.0,
+        };
+        assert_eq!(pid.with_setpoint(21.0), expected);
+    }
+
+    #[test]
+    fn test_display_and_parse_pid() {
+        let expected = Assessor::Pid {
+            kp: 2.0,
+            ki: 0.1,
+            kd: 0.5,
+            setpoint: 21.0,
+            max_output: 10.0,
+        };
+        let serialized = expected.to_string();
+        assert_eq!(serialized, r#"{"type":"pid","kp":2,"ki":0.1,"kd":0.5,"setpoint":21,"max_output":10}"#);
+        assert_eq!(Assessor::parse(serialized), Ok(expected));
+    }
+
+    #[test]
+    fn test_display_and_parse_script() {
+        let expected = Assessor::Script {
+            name: String::from("gentle"),
+            setpoint: None,
+        };
+        let serialized = expected.to_string();
+        assert_eq!(serialized, r#"{"type":"script","name":"gentle"}"#);
+        assert_eq!(Assessor::parse(serialized), Ok(expected.clone()));
+
+        let held = expected.with_setpoint(21.0);
+        let serialized = held.to_string();
+        assert_eq!(serialized, r#"{"type":"script","name":"gentle","setpoint":21}"#);
+        assert_eq!(Assessor::parse(serialized), Ok(held));
+
+        assert_eq!(
+            Assessor::parse(r#"{"type":"script"}"#),
+            Err(String::from("script assessor is missing a \"name\""))
+        );
+    }
+
+    #[test]
+    fn test_closure() {
+        let mut count = 0;
+        let mut assessor = move |_: &Datum| -> Option<Box<dyn actuator::Command>> {
+            count += 1;
+            Some(Box::new(Thermo5000::HeatBy(count as f32)))
+        };
+
+        let datum = Datum::new(21.0, Unit::DegreesC, Utc::now());
+        assert_eq!(Assess::assess(&mut assessor, &datum).unwrap().to_string(), Thermo5000::HeatBy(1.0).to_string());
+        assert_eq!(Assess::assess(&mut assessor, &datum).unwrap().to_string(), Thermo5000::HeatBy(2.0).to_string());
+    }
+
+    #[test]
+    fn test_assessments() {
+        let mut assessments = Assessments::default();
+        let id = Id::new("my_id");
+        let pid = Assessor::Pid {
+            kp: 0.0,
+            ki: 1.0,
+            kd: 0.0,
+            setpoint: 20.0,
+            max_output: 100.0,
+        };
+
+        let start = Utc::now();
+        let at = |seconds: i64, t: f32| Datum::new(t, Unit::DegreesC, start + chrono::Duration::seconds(seconds));
+
+        // the integral term accumulates from one Datum to the next...
+        assert!(assessments.assess(&id, &pid, &at(0, 19.0)).is_none());
+        assert_eq!(
+            assessments.assess(&id, &pid, &at(1, 19.0)).unwrap().to_string(),
+            Thermo5000::HeatBy(1.0).to_string()
+        );
+        assert_eq!(
+            assessments.assess(&id, &pid, &at(2, 19.0)).unwrap().to_string(),
+            Thermo5000::HeatBy(2.0).to_string()
+        );
+
+        // ...and survives a change of setpoint...
+        let retargeted = pid.with_setpoint(21.0);
+        assert_eq!(
+            assessments.assess(&id, &retargeted, &at(3, 19.0)).unwrap().to_string(),
+            Thermo5000::HeatBy(4.0).to_string()
+        );
+
+        // ...until the Assessor is changed, when it starts over
+        let retuned = Assessor::Pid {
+            kp: 0.0,
+            ki: 2.0,
+            kd: 0.0,
+            setpoint: 20.0,
+            max_output: 100.0,
+        };
+        assert!(assessments.assess(&id, &retuned, &at(4, 19.0)).is_none());
+
+        assessments.retain(|_| false);
+        assert!(assessments.assess(&id, &retuned, &at(5, 19.0)).is_none());
+    }
+
+    #[test]
+    fn test_assessments_move_thermostat_band() {
+        let mut assessments = Assessments::default();
+        let id = Id::new("my_id");
+        let thermostat = DEFAULT_ASSESSOR.get("thermo5000").unwrap();
+
+        let warm = Datum::new(27.0, Unit::DegreesC, Utc::now());
+        assert!(assessments.assess(&id, thermostat, &warm).is_none());
+
+        // the band (22 to 28) moves down to 18 to 24, along with the setpoint
+        let actual = assessments.assess(&id, &thermostat.with_setpoint(21.0), &warm).unwrap();
+        assert_eq!(actual.to_string(), Thermo5000::CoolBy(6.0).to_string());
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(
+            Assessor::parse(r#"{"type":"thermostat","setpoint":30,"low":20,"high":23}"#),
+            Err(String::from("thermostat setpoint 30 must be between low 20 and high 23"))
+        );
+        assert_eq!(
+            Assessor::parse(r#"{"type":"thermostat","setpoint":"warm","low":20,"high":23}"#),
+            Err(String::from("assessor is missing a numeric \"setpoint\""))
+        );
+        assert_eq!(
+            Assessor::parse(r#"{"type":"oracle"}"#),
+            Err(String::from("unknown assessor type \"oracle\", expected \"thermostat\", \"pid\" or \"script\""))
+        );
+        assert_eq!(Assessor::parse(r#"{"setpoint":20}"#), Err(String::from("assessor is missing a \"type\"")));
+        assert_eq!(
+            Assessor::parse(r#"{"type":"pid","kp":-1,"ki":0,"kd":0,"setpoint":20,"max_output":5}"#),
+            Err(String::from("pid gains must not be negative"))
+        );
+        assert_eq!(
+            Assessor::parse(r#"{"type":"pid","kp":1,"ki":0,"kd":0,"setpoint":20,"max_output":0}"#),
+            Err(String::from("pid max_output must be positive"))
+        );
+        assert!(Assessor::parse("nope").is_err());
+    }
+
+    #[test]
+    fn test_all_to_and_from_json() {
+        let mut assessors = HashMap::new();
+        assessors.insert(Id::new("b"), DEFAULT_ASSESSOR.get("thermo5000").unwrap().clone());
+        assessors.insert(
+            Id::new("a"),
+            Assessor::Thermostat {
+                setpoint: 18.0,
+                low: 17.0,
+                high: 19.0,
+            },
+        );
+
+        let json = Assessor::all_to_json(&assessors);
+        assert_eq!(
+            json,
+            r#"[{"id":"a","assessor":{"type":"thermostat","setpoint":18,"low":17,"high":19}},{"id":"b","assessor":{"type":"thermostat","setpoint":25,"low":22,"high":28}}]"#
+        );
+
+        assert_eq!(Assessor::all_from_json(json), Ok(assessors));
+        assert_eq!(Assessor::all_from_json("[]"), Ok(HashMap::new()));
+        assert!(Assessor::all_from_json(r#"[{"assessor":{}}]"#).is_err());
     }
 }
@@ -0,0 +1,492 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use mdns_sd::ServiceInfo;
+
+use datum::Datum;
+use device::broadcast::Broadcaster;
+use device::client::Client;
+use device::error::Error;
+use device::id::Id;
+use device::json::{escape, Value};
+use device::websocket::{Frame, Reader};
+
+use crate::event::Event;
+use crate::overrides::Override;
+use crate::Controller;
+
+/// A `Request` is a message sent by an operator to the `Controller` over a WebSocket.
+///
+/// Every `Request` is a JSON object with a `type` and the `id` of the `Sensor` / `Actuator` pair
+/// which it is about
+///
+///  - `{"type":"subscribe","id":"thermo-5000"}`
+///  - `{"type":"unsubscribe","id":"thermo-5000"}`
+///  - `{"type":"command","id":"thermo-5000","command":{"name":"HeatBy","value":"5"}}`
+///  - `{"type":"override","id":"thermo-5000","command":{"name":"HeatBy","value":"5"},"seconds":600}`
+///  - `{"type":"override","id":"thermo-5000","command":null}`, which holds the `Actuator` as it is,
+///    like `POST /overrides/:id` without a `command`
+///  - `{"type":"clear_override","id":"thermo-5000"}`, like `DELETE /overrides/:id`
+#[derive(PartialEq, Debug)]
+pub enum Request {
+    Subscribe(Id),
+    Unsubscribe(Id),
+    Command { id: Id, command: String },
+    Override { id: Id, command: Option<String>, seconds: Option<u64> },
+    ClearOverride(Id),
+}
+
+impl Request {
+    /// Attempts to parse a `Request` from the text of a WebSocket message.
+    pub fn parse(text: &str) -> Result<Request, String> {
+        let value = Value::parse(text).map_err(|e| e.to_string())?;
+
+        let kind = value.get("type").and_then(Value::as_str).ok_or("missing \"type\"")?;
+        let id = value.get("id").and_then(Value::as_str).map(Id::new).ok_or("missing \"id\"")?;
+
+        match kind {
+            "subscribe" => Ok(Request::Subscribe(id)),
+            "unsubscribe" => Ok(Request::Unsubscribe(id)),
+            "command" => match value.get("command") {
+                Some(command @ Value::Object(_)) => Ok(Request::Command {
+                    id,
+                    command: command.to_string(),
+                }),
+                _ => Err(String::from("\"command\" must be an object")),
+            },
+            "override" => {
+                let (command, seconds) = Override::parse_fields(&value)?;
+                Ok(Request::Override { id, command, seconds })
+            }
+            "clear_override" => Ok(Request::ClearOverride(id)),
+            other => Err(format!("unknown type \"{}\"", other)),
+        }
+    }
+}
+
+/// Anything which can wake up the thread serving a WebSocket.
+enum Input {
+    Frame(Frame),
+    Event(Event),
+    Closed,
+    Failed(Error),
+}
+
+/// A `Channel` serves a single WebSocket connection to an operator.
+///
+/// Operators `subscribe` to `Sensor`s to receive their buffered data, then every new `Datum` and
+/// `Command` for those `Sensor`s. Operators can also send `Command`s directly to `Actuator`s, and
+/// `override` the `Controller`, so that it stops assessing a `Sensor`'s data for some time.
+pub struct Channel {
+    data: Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>,
+    actuators: Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+    overrides: Arc<Mutex<HashMap<Id, Override>>>,
+    events: Arc<Broadcaster<Event>>,
+    client: Client,
+    subscriptions: HashSet<Id>,
+}
+
+impl Channel {
+    pub fn new(
+        data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>,
+        actuators: &Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+        overrides: &Arc<Mutex<HashMap<Id, Override>>>,
+        events: &Arc<Broadcaster<Event>>,
+    ) -> Channel {
+        Channel {
+            data: Arc::clone(data),
+            actuators: Arc::clone(actuators),
+            overrides: Arc::clone(overrides),
+            events: Arc::clone(events),
+            client: Client::new(),
+            subscriptions: HashSet::new(),
+        }
+    }
+
+    /// Serves this `Channel` on a `tcp_stream` which has already been upgraded to a WebSocket,
+    /// until either side closes it. A `Ping` is sent whenever the connection has been idle for `heartbeat`.
+    ///
+    /// **Design Decision**: frames are read on a separate thread, and `Event`s are forwarded from
+    /// another, so that this thread is the only one which writes to the `tcp_stream`, and frames
+    /// are never interleaved.
+    pub fn serve(mut self, tcp_stream: &mut TcpStream, heartbeat: Duration) -> Result<(), Error> {
+        let (sender, inputs) = mpsc::channel();
+
+        let mut reader = Reader::new(tcp_stream.try_clone()?);
+        let frames = sender.clone();
+
+        std::thread::spawn(move || loop {
+            let input = match reader.read_next() {
+                Ok(Some(frame)) => Input::Frame(frame),
+                Ok(None) => Input::Closed,
+                Err(e) => Input::Failed(e),
+            };
+
+            let done = !matches!(input, Input::Frame(_));
+            if frames.send(input).is_err() || done {
+                return;
+            }
+        });
+
+        let events = self.events.subscribe();
+
+        std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                if sender.send(Input::Event(event)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let result = self.run(tcp_stream, inputs, heartbeat);
+
+        // unblocks the reader thread, if the client has not closed the connection already
+        let _ = tcp_stream.shutdown(Shutdown::Both);
+
+        // a client closing the socket is the normal way for a WebSocket to end
+        if let Err(e) = result {
+            debug!("[Controller] closing WebSocket: {}", e);
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, tcp_stream: &mut TcpStream, inputs: Receiver<Input>, heartbeat: Duration) -> Result<(), Error> {
+        loop {
+            match inputs.recv_timeout(heartbeat) {
+                Ok(Input::Frame(Frame::Text(text))) => Frame::Text(self.handle(text.as_str())).write(tcp_stream)?,
+                Ok(Input::Frame(Frame::Binary(_))) => Frame::Text(Self::error("invalid_message", "binary messages are not supported")).write(tcp_stream)?,
+                Ok(Input::Frame(Frame::Ping(payload))) => Frame::Pong(payload).write(tcp_stream)?,
+                Ok(Input::Frame(Frame::Pong(_))) => {}
+                Ok(Input::Frame(Frame::Close(code))) => return Frame::Close(code).write(tcp_stream),
+                Ok(Input::Event(event)) => {
+                    if let Some(message) = self.notify(&event) {
+                        Frame::Text(message).write(tcp_stream)?
+                    }
+                }
+                Ok(Input::Closed) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Ok(Input::Failed(e)) => {
+                    // 1002 indicates that the client violated the WebSocket protocol
+                    //     see: https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1
+                    Frame::Close(Some(1002)).write(tcp_stream)?;
+                    return Err(e);
+                }
+                Err(RecvTimeoutError::Timeout) => Frame::Ping(vec![]).write(tcp_stream)?,
+            }
+        }
+    }
+
+    /// Handles a single text message from the client, returning the (JSON) reply.
+    pub fn handle(&mut self, text: &str) -> String {
+        let request = match Request::parse(text) {
+            Ok(request) => request,
+            Err(msg) => return Self::error("invalid_message", msg.as_str()),
+        };
+
+        match request {
+            Request::Subscribe(id) => {
+                let data: Vec<String> = match self.data.lock().unwrap().get(&id) {
+                    Some(buffer) => buffer.iter().map(|datum| datum.to_string()).collect(),
+                    None => Vec::new(), // this Sensor may not have been discovered yet
+                };
+
+                let reply = format!(r#"{{"type":"subscribed","id":"{}","data":[{}]}}"#, id, data.join(","));
+                self.subscriptions.insert(id);
+                reply
+            }
+            Request::Unsubscribe(id) => {
+                self.subscriptions.remove(&id);
+                format!(r#"{{"type":"unsubscribed","id":"{}"}}"#, id)
+            }
+            Request::Command { id, command } => match self.forward(&id, command.as_str()) {
+                Ok(()) => format!(r#"{{"type":"sent","id":"{}","command":{}}}"#, id, command),
+                Err(e) => Self::command_error(&e),
+            },
+            Request::Override { id, command, seconds } => {
+                match Controller::start_override(&self.client, &self.actuators, &self.overrides, &self.events, &id, command, seconds) {
+                    Ok(manual) => format!(r#"{{"type":"override","id":"{}","override":{}}}"#, id, manual.to_json()),
+                    Err(e) => Self::command_error(&e),
+                }
+            }
+            Request::ClearOverride(id) => match self.overrides.lock().unwrap().remove(&id) {
+                Some(_) => format!(r#"{{"type":"override","id":"{}","override":null}}"#, id),
+                None => Self::error("unknown_override", format!("no override for id: {}", id).as_str()),
+            },
+        }
+    }
+
+    /// Returns the message which should be sent to the client about this `event`, if the client is subscribed to it.
+    fn notify(&self, event: &Event) -> Option<String> {
+        if !self.subscriptions.contains(event.id()) {
+            return None;
+        }
+
+        match event {
+            Event::Datum { id, datum } => Some(format!(r#"{{"type":"datum","id":"{}","datum":{}}}"#, id, datum)),
+            Event::Command { id, command } => Some(format!(r#"{{"type":"command","id":"{}","command":{}}}"#, id, command)),
+        }
+    }
+
+    /// Sends the `command` to the `Actuator` with the given `id`, via its `POST /command` endpoint.
+    fn forward(&self, id: &Id, command: &str) -> Result<(), Error> {
+        Controller::forward_command(&self.client, &self.actuators, &self.events, id, command)
+    }
+
+    fn command_error(error: &Error) -> String {
+        match error {
+            Error::Discovery(msg) => Self::error("unknown_actuator", msg),
+            other => Self::error("command_failed", other.to_string().as_str()),
+        }
+    }
+
+    fn error(code: &str, msg: &str) -> String {
+        format!(r#"{{"type":"error","error":"{}","code":"{}"}}"#, escape(msg), escape(code))
+    }
+}
+
+#[cfg(test)]
+mod channel_tests {
+    use std::io::{BufReader, Read, Write};
+    use std::net::{IpAddr, TcpListener};
+
+    use datum::unit::Unit;
+    use device::discovery::PROTOCOL_VERSION;
+    use device::message::Message;
+
+    use super::*;
+
+    fn create_channel() -> Channel {
+        let mut buffer = VecDeque::new();
+        buffer.push_front(Datum::new_now(1.0, Unit::DegreesC));
+
+        let mut data = HashMap::new();
+        data.insert(Id::new("my_sensor"), buffer);
+
+        Channel::new(
+            &Arc::new(Mutex::new(data)),
+            &Arc::new(Mutex::new(HashMap::new())),
+            &Arc::new(Mutex::new(HashMap::new())),
+            &Arc::new(Broadcaster::default()),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let id = Id::new("my_id");
+
+        assert_eq!(Request::parse(r#"{"type":"subscribe","id":"my_id"}"#), Ok(Request::Subscribe(id.clone())));
+        assert_eq!(Request::parse(r#"{"type":"unsubscribe","id":"my_id"}"#), Ok(Request::Unsubscribe(id.clone())));
+
+        assert_eq!(
+            Request::parse(r#"{"type":"command","id":"my_id","command":{"name":"HeatBy","value":"5"}}"#),
+            Ok(Request::Command {
+                id: id.clone(),
+                command: String::from(r#"{"name":"HeatBy","value":"5"}"#)
+            })
+        );
+
+        assert_eq!(
+            Request::parse(r#"{"type":"override","id":"my_id","command":{"name":"CoolBy","value":"2"},"seconds":60}"#),
+            Ok(Request::Override {
+                id: id.clone(),
+                command: Some(String::from(r#"{"name":"CoolBy","value":"2"}"#)),
+                seconds: Some(60)
+            })
+        );
+
+        assert_eq!(
+            Request::parse(r#"{"type":"override","id":"my_id","command":null}"#),
+            Ok(Request::Override {
+                id: id.clone(),
+                command: None,
+                seconds: None
+            })
+        );
+
+        assert_eq!(Request::parse(r#"{"type":"clear_override","id":"my_id"}"#), Ok(Request::ClearOverride(id)));
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(Request::parse(r#"{"id":"my_id"}"#), Err(String::from("missing \"type\"")));
+        assert_eq!(Request::parse(r#"{"type":"subscribe"}"#), Err(String::from("missing \"id\"")));
+        assert_eq!(
+            Request::parse(r#"{"type":"reboot","id":"my_id"}"#),
+            Err(String::from("unknown type \"reboot\""))
+        );
+        assert_eq!(
+            Request::parse(r#"{"type":"command","id":"my_id","command":"HeatBy"}"#),
+            Err(String::from("\"command\" must be an object"))
+        );
+        assert_eq!(
+            Request::parse(r#"{"type":"override","id":"my_id","seconds":-1}"#),
+            Err(String::from("\"seconds\" must be a whole number from 0 to 31536000"))
+        );
+        assert_eq!(Request::parse("["), Err(String::from("parse error: unexpected end of input")));
+    }
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let mut channel = create_channel();
+        let datum = channel.data.lock().unwrap().get(&Id::new("my_sensor")).unwrap()[0].clone();
+
+        let event = Event::Datum {
+            id: Id::new("my_sensor"),
+            datum: datum.clone(),
+        };
+        assert_eq!(channel.notify(&event), None);
+
+        let actual = channel.handle(r#"{"type":"subscribe","id":"my_sensor"}"#);
+        let expected = format!(r#"{{"type":"subscribed","id":"my_sensor","data":[{}]}}"#, datum);
+        assert_eq!(actual, expected);
+
+        let expected = format!(r#"{{"type":"datum","id":"my_sensor","datum":{}}}"#, datum);
+        assert_eq!(channel.notify(&event), Some(expected));
+
+        let actual = channel.handle(r#"{"type":"unsubscribe","id":"my_sensor"}"#);
+        assert_eq!(actual, r#"{"type":"unsubscribed","id":"my_sensor"}"#);
+        assert_eq!(channel.notify(&event), None);
+    }
+
+    #[test]
+    fn test_command_unknown_actuator() {
+        let mut channel = create_channel();
+
+        let actual = channel.handle(r#"{"type":"command","id":"my_sensor","command":{"name":"HeatBy","value":"5"}}"#);
+        let expected = r#"{"type":"error","error":"cannot find Actuator with id: my_sensor","code":"unknown_actuator"}"#;
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let mut channel = create_channel();
+
+        let actual = channel.handle(r#"{"type":"reboot","id":"my_sensor"}"#);
+        let expected = r#"{"type":"error","error":"unknown type \"reboot\"","code":"invalid_message"}"#;
+
+        assert_eq!(actual, expected)
+    }
+
+    /// Starts a fake `Actuator` which accepts a single `Command`, and returns its `ServiceInfo`
+    /// along with a handle which yields the body of the `Command` it received.
+    fn start_actuator() -> (ServiceInfo, std::thread::JoinHandle<Option<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let properties = HashMap::from([(String::from("protocol"), PROTOCOL_VERSION.to_string())]);
+        let info = ServiceInfo::new("_actuator._tcp.local.", "my_actuator", "localhost", "127.0.0.1", port, properties).unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let message = Message::read(&mut stream).unwrap();
+            Message::respond_ok().write(&mut stream).unwrap();
+            message.body
+        });
+
+        (info, handle)
+    }
+
+    #[test]
+    fn test_override() {
+        let mut channel = create_channel();
+        let (info, actuator) = start_actuator();
+        channel.actuators.lock().unwrap().insert(Id::new("my_sensor"), info);
+
+        let events = channel.events.subscribe();
+
+        let actual = channel.handle(r#"{"type":"override","id":"my_sensor","command":{"name":"HeatBy","value":"5"}}"#);
+        let expected = r#"{"type":"override","id":"my_sensor","override":{"command":{"name":"HeatBy","value":"5"},"expires":null}}"#;
+        assert_eq!(actual, expected);
+
+        // the Command is sent to the Actuator's POST /command endpoint, and published to other clients
+        assert_eq!(actuator.join().unwrap(), Some(String::from(r#"{"name":"HeatBy","value":"5"}"#)));
+        assert_eq!(events.try_recv().unwrap().name(), "command");
+        assert!(channel.overrides.lock().unwrap().contains_key(&Id::new("my_sensor")));
+
+        // an override without a Command holds the Actuator as it is, as it does over HTTP
+        let actual = channel.handle(r#"{"type":"override","id":"my_sensor","command":null}"#);
+        assert_eq!(actual, r#"{"type":"override","id":"my_sensor","override":{"command":null,"expires":null}}"#);
+        assert!(channel.overrides.lock().unwrap().contains_key(&Id::new("my_sensor")));
+
+        let actual = channel.handle(r#"{"type":"clear_override","id":"my_sensor"}"#);
+        assert_eq!(actual, r#"{"type":"override","id":"my_sensor","override":null}"#);
+        assert!(channel.overrides.lock().unwrap().is_empty());
+
+        let actual = channel.handle(r#"{"type":"clear_override","id":"my_sensor"}"#);
+        assert_eq!(actual, r#"{"type":"error","error":"no override for id: my_sensor","code":"unknown_override"}"#);
+    }
+
+    /// Writes a masked text frame, as a WebSocket client would.
+    fn write_text(stream: &mut TcpStream, text: &str) {
+        let mut frame = vec![0x81, 0x80 | text.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(text.as_bytes());
+        stream.write_all(&frame).unwrap();
+    }
+
+    /// Reads an (unmasked, short) frame, as a WebSocket client would.
+    fn read_frame(stream: &mut impl Read) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+
+        let mut payload = vec![0u8; (header[1] & 0x7F) as usize];
+        stream.read_exact(&mut payload).unwrap();
+
+        (header[0] & 0x0F, payload)
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let channel = create_channel();
+        let events = Arc::clone(&channel.events);
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = Message::read(&mut stream).unwrap();
+            Message::respond_websocket_upgrade(&request).unwrap().write(&mut stream).unwrap();
+            channel.serve(&mut stream, Duration::from_secs(60))
+        });
+
+        let mut client = TcpStream::connect((IpAddr::from([127, 0, 0, 1]), port)).unwrap();
+
+        let mut headers = HashMap::new();
+        headers.insert("Upgrade", "websocket");
+        headers.insert("Connection", "Upgrade");
+        headers.insert("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+        headers.insert("Sec-WebSocket-Version", "13");
+        Message::request_get("/ws").with_headers(headers).write(&mut client).unwrap();
+
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let response = Message::read_next(&mut reader).unwrap().unwrap();
+        assert_eq!(response.status(), Some(101));
+
+        write_text(&mut client, r#"{"type":"unsubscribe","id":"other"}"#);
+        let (opcode, payload) = read_frame(&mut reader);
+        assert_eq!(opcode, 0x1);
+        assert_eq!(payload, br#"{"type":"unsubscribed","id":"other"}"#);
+
+        write_text(&mut client, r#"{"type":"subscribe","id":"other"}"#);
+        let (_, payload) = read_frame(&mut reader);
+        assert_eq!(payload, br#"{"type":"subscribed","id":"other","data":[]}"#);
+
+        // Events are pushed to subscribed clients as they happen
+        let command = String::from(r#"{"name":"HeatBy","value":"5"}"#);
+        events.publish(Event::Command { id: Id::new("other"), command });
+        let (_, payload) = read_frame(&mut reader);
+        assert_eq!(payload, br#"{"type":"command","id":"other","command":{"name":"HeatBy","value":"5"}}"#);
+
+        // the server echoes the client's Close frame, then closes the connection
+        client.write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xE8]).unwrap();
+        let (opcode, payload) = read_frame(&mut reader);
+        assert_eq!(opcode, 0x8);
+        assert_eq!(payload, vec![0x03, 0xE8]);
+
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// A `Clock` tells the `Controller` what time it is, when deciding which scheduled setpoint to use.
+///
+/// **Design Decision**: the time is read from a `Clock`, rather than from `Utc::now()`, so that
+/// tests can move time forward (e.g. to the weekend) without waiting for it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The `SystemClock` is the `Clock` used by default, which always tells the current time.
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A `ManualClock` tells whichever time it was last `set` to. Clones of a `ManualClock` share the same time.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let clock = ManualClock::new(start);
+        let shared = clock.clone();
+
+        clock.advance(Duration::hours(1));
+        assert_eq!(shared.now(), Utc.with_ymd_and_hms(2024, 1, 5, 13, 0, 0).unwrap());
+
+        shared.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use device::discovery::Backend;
+
+use crate::clock::{Clock, SystemClock};
+use crate::storage;
+
+/// `Config` describes how a [`Controller`](crate::Controller) should be run.
+///
+/// **Design Decision**: options are set with `with_*` methods, like `Message`s, so that new options
+/// can be added without changing every call to [`Controller::start`](crate::Controller::start).
+#[derive(Clone)]
+pub struct Config {
+    pub(crate) container_mode: bool,
+    pub(crate) discovery: Backend,
+    pub(crate) state_dir: Option<PathBuf>,
+    pub(crate) script_dir: Option<PathBuf>,
+    pub(crate) storage: storage::Backend,
+    pub(crate) retention: Option<Duration>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Config {
+    /// By default, a `Controller` is not run in a container, forgets everything it has been
+    /// configured with (e.g. `Assessor`s) and the data it has received when it stops, has no
+    /// scripts, and follows its `Schedule`s using the [`SystemClock`].
+    pub fn new(discovery: Backend) -> Config {
+        Config {
+            container_mode: false,
+            discovery,
+            state_dir: None,
+            script_dir: None,
+            storage: storage::Backend::Memory,
+            retention: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets whether the `Controller` is running in a container, which changes how its UI reaches it.
+    pub fn with_container_mode(mut self, container_mode: bool) -> Config {
+        self.container_mode = container_mode;
+        self
+    }
+
+    /// Keeps the `Controller`'s configuration in files in `dir`, so that it survives restarts.
+    ///
+    /// The directory is created if it does not already exist.
+    pub fn with_state_dir<P: Into<PathBuf>>(mut self, dir: P) -> Config {
+        self.state_dir = Some(dir.into());
+        self
+    }
+
+    /// Loads scripts for script `Assessor`s from the `.rhai` files in `dir`, and reloads them whenever they change.
+    pub fn with_script_dir<P: Into<PathBuf>>(mut self, dir: P) -> Config {
+        self.script_dir = Some(dir.into());
+        self
+    }
+
+    /// Stores every `Datum` the `Controller` receives using the `storage` `Backend`, and serves `GET /data` from it.
+    pub fn with_storage(mut self, storage: storage::Backend) -> Config {
+        self.storage = storage;
+        self
+    }
+
+    /// Forgets stored data once it is older than `retention`. By default, stored data is kept forever.
+    pub fn with_retention(mut self, retention: Duration) -> Config {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Follows `Schedule`s using the time told by `clock`, rather than the current time.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Config {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
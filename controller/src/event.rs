@@ -0,0 +1,71 @@
+use datum::Datum;
+use device::id::Id;
+
+/// An `Event` is something which happened in the `Controller` which clients may want to be told about.
+///
+/// `Event`s are published to clients of `GET /stream` as Server-Sent Events.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Event {
+    /// the `Controller` received a new `Datum` from the `Sensor` with the given `Id`
+    Datum { id: Id, datum: Datum },
+    /// the `Controller` sent a `Command` (serialized as JSON) to the `Actuator` with the given `Id`
+    Command { id: Id, command: String },
+}
+
+impl Event {
+    /// Returns the `Id` of the `Sensor` / `Actuator` pair which this `Event` is about.
+    pub fn id(&self) -> &Id {
+        match self {
+            Event::Datum { id, .. } => id,
+            Event::Command { id, .. } => id,
+        }
+    }
+
+    /// Returns the type of this `Event`, which clients can use to listen for specific kinds of `Event`s.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Datum { .. } => "datum",
+            Event::Command { .. } => "command",
+        }
+    }
+
+    /// Returns this `Event`, serialized as JSON.
+    pub fn data(&self) -> String {
+        match self {
+            Event::Datum { id, datum } => format!(r#"{{"id":"{}","datum":{}}}"#, id, datum),
+            Event::Command { id, command } => format!(r#"{{"id":"{}","command":{}}}"#, id, command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod event_tests {
+    use chrono::{TimeZone, Utc};
+
+    use datum::unit::Unit;
+
+    use super::*;
+
+    #[test]
+    fn test_datum() {
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let datum = Datum::new(1.5, Unit::DegreesC, timestamp);
+        let event = Event::Datum { id: Id::new("my_id"), datum };
+
+        assert_eq!(event.id(), &Id::new("my_id"));
+        assert_eq!(event.name(), "datum");
+        assert_eq!(
+            event.data(),
+            r#"{"id":"my_id","datum":{"value":"1.5","unit":"°C","timestamp":"2024-01-05T12:00:00+00:00"}}"#
+        );
+    }
+
+    #[test]
+    fn test_command() {
+        let command = String::from(r#"{"name":"HeatBy","value":"4"}"#);
+        let event = Event::Command { id: Id::new("my_id"), command };
+
+        assert_eq!(event.name(), "command");
+        assert_eq!(event.data(), r#"{"id":"my_id","command":{"name":"HeatBy","value":"4"}}"#);
+    }
+}
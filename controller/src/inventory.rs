@@ -0,0 +1,419 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use mdns_sd::ServiceInfo;
+
+use datum::Datum;
+use device::id::Id;
+use device::json::escape;
+use device::Device;
+
+use crate::assessor::{Assessor, DEFAULT_ASSESSOR};
+use crate::clock::Clock;
+use crate::liveness::{self, Liveness};
+use crate::overrides::Override;
+use crate::schedule::Schedule;
+use crate::Controller;
+
+/// A `Command` (serialized as JSON) which was sent to an `Actuator`, and when it was sent.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SentCommand {
+    pub command: String,
+    pub sent: DateTime<Utc>,
+}
+
+/// An `Inventory` describes every `Device` the `Controller` knows about, and how its `Sensor`s
+/// are paired with its `Actuator`s.
+///
+/// A `Sensor` is paired with the `Actuator` which has the same `Id`.
+///
+/// **Design Decision**: an `Inventory` shares the `Controller`'s own state, rather than copying it,
+/// so that it is always up to date. Its locks are taken in the same order as in the `Controller`'s
+/// polling loop, so that the two can never deadlock.
+#[derive(Clone)]
+pub struct Inventory {
+    pub(crate) sensors: Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+    pub(crate) data: Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>,
+    pub(crate) assessors: Arc<Mutex<HashMap<Id, Assessor>>>,
+    pub(crate) setpoints: Arc<Mutex<HashMap<Id, f32>>>,
+    pub(crate) schedules: Arc<Mutex<HashMap<Id, Schedule>>>,
+    pub(crate) actuators: Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+    pub(crate) overrides: Arc<Mutex<HashMap<Id, Override>>>,
+    pub(crate) liveness: Arc<Mutex<HashMap<String, Liveness>>>,
+    pub(crate) commands: Arc<Mutex<HashMap<Id, SentCommand>>>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Inventory {
+    /// Serializes every discovered `Device` as a JSON array, sorted by `Id`, with `Sensor`s after their `Actuator`s.
+    pub fn devices(&self) -> String {
+        let sensors = self.sensors.lock().unwrap();
+        let actuators = self.actuators.lock().unwrap();
+        let liveness = self.liveness.lock().unwrap();
+
+        let mut devices: Vec<(&str, &Id, &ServiceInfo)> = sensors.iter().map(|(id, info)| ("sensor", id, info)).collect();
+        devices.extend(actuators.iter().map(|(id, info)| ("actuator", id, info)));
+        devices.sort_by(|a, b| (a.1.to_string(), a.0).cmp(&(b.1.to_string(), b.0)));
+
+        let devices: Vec<String> = devices
+            .into_iter()
+            .map(|(kind, id, info)| Self::device_to_json(kind, id, info, liveness.get(info.get_fullname())))
+            .collect();
+
+        format!("[{}]", devices.join(","))
+    }
+
+    /// Serializes the `Sensor` and `Actuator` with the given `id` as JSON, along with the last `Datum`
+    /// received from the `Sensor`, and the last `Command` sent to the `Actuator`.
+    ///
+    /// The `mode` of the pair is `"manual"` while it is overridden by an operator, and `"automatic"` otherwise.
+    /// Its `setpoint` is the one its `Assessor` is aiming for instead of its own, if any, whether it was
+    /// set with `PUT /setpoints/:id` or comes from the pair's `Schedule`.
+    ///
+    /// Returns `None` if neither a `Sensor` nor an `Actuator` with this `id` has been discovered.
+    pub fn device(&self, id: &Id) -> Option<String> {
+        let sensors = self.sensors.lock().unwrap();
+        let data = self.data.lock().unwrap();
+        let assessors = self.assessors.lock().unwrap();
+        let setpoints = self.setpoints.lock().unwrap();
+        let schedules = self.schedules.lock().unwrap();
+        let actuators = self.actuators.lock().unwrap();
+        let overrides = self.overrides.lock().unwrap();
+        let liveness = self.liveness.lock().unwrap();
+        let commands = self.commands.lock().unwrap();
+
+        let sensor = sensors.get(id);
+        let actuator = actuators.get(id);
+        if sensor.is_none() && actuator.is_none() {
+            return None;
+        }
+
+        let to_json = |kind: &str, info: Option<&ServiceInfo>| match info {
+            Some(info) => Self::device_to_json(kind, id, info, liveness.get(info.get_fullname())),
+            None => String::from("null"),
+        };
+
+        let assessor = if assessors.contains_key(id) {
+            String::from(r#""custom""#)
+        } else if Self::model(sensor.or(actuator)).is_some_and(|model| DEFAULT_ASSESSOR.contains_key(model.as_str())) {
+            String::from(r#""default""#)
+        } else {
+            String::from("null")
+        };
+
+        let manual = overrides.get(id).filter(|manual| manual.is_active(Utc::now()));
+        let mode = if manual.is_some() { "manual" } else { "automatic" };
+        let manual = manual.map(Override::to_json).unwrap_or(String::from("null"));
+        let setpoint = Controller::setpoint(id, &setpoints, &schedules, self.clock.now())
+            .map(|setpoint| setpoint.to_string())
+            .unwrap_or(String::from("null"));
+
+        let last_datum = data
+            .get(id)
+            .and_then(|buffer| buffer.front())
+            .map(|datum| datum.to_string())
+            .unwrap_or(String::from("null"));
+
+        let last_command = commands
+            .get(id)
+            .map(|sent| format!(r#"{{"command":{},"sent":"{}"}}"#, sent.command, sent.sent.to_rfc3339()))
+            .unwrap_or(String::from("null"));
+
+        Some(format!(
+            r#"{{"id":"{}","paired":{},"issues":{},"sensor":{},"actuator":{},"assessor":{},"mode":"{}","setpoint":{},"schedule":{},"override":{},"last_datum":{},"last_command":{}}}"#,
+            escape(id.to_string().as_str()),
+            sensor.is_some() && actuator.is_some(),
+            Self::issues_to_json(&Self::issues(sensor, actuator, assessors.contains_key(id))),
+            to_json("sensor", sensor),
+            to_json("actuator", actuator),
+            assessor,
+            mode,
+            setpoint,
+            schedules.contains_key(id),
+            manual,
+            last_datum,
+            last_command
+        ))
+    }
+
+    /// Serializes every `Id` for which a `Sensor` or an `Actuator` has been discovered as a JSON array,
+    /// sorted by `Id`, along with anything which will stop the `Controller` from managing that pair.
+    pub fn pairs(&self) -> String {
+        let sensors = self.sensors.lock().unwrap();
+        let assessors = self.assessors.lock().unwrap();
+        let actuators = self.actuators.lock().unwrap();
+
+        let ids: BTreeSet<String> = sensors.keys().chain(actuators.keys()).map(|id| id.to_string()).collect();
+
+        let pairs: Vec<String> = ids
+            .into_iter()
+            .map(|id| {
+                let id = Id::new(id);
+                let sensor = sensors.get(&id);
+                let actuator = actuators.get(&id);
+
+                format!(
+                    r#"{{"id":"{}","sensor":{},"actuator":{},"paired":{},"issues":{}}}"#,
+                    escape(id.to_string().as_str()),
+                    sensor.is_some(),
+                    actuator.is_some(),
+                    sensor.is_some() && actuator.is_some(),
+                    Self::issues_to_json(&Self::issues(sensor, actuator, assessors.contains_key(&id)))
+                )
+            })
+            .collect();
+
+        format!("[{}]", pairs.join(","))
+    }
+
+    /// Lists everything which will stop the `Controller` from assessing the `sensor`'s data, or from
+    /// sending `Command`s to the `actuator`. A pair with no issues is managed by the `Controller`.
+    ///
+    /// **Design Decision**: this logic has been extracted from [`pairs`](Self::pairs) to make it
+    /// easier to test.
+    fn issues(sensor: Option<&ServiceInfo>, actuator: Option<&ServiceInfo>, custom_assessor: bool) -> Vec<String> {
+        let mut issues = Vec::new();
+
+        match sensor {
+            None => issues.push(String::from("no sensor")),
+            Some(info) => {
+                if let Err(msg) = Controller::check_compatibility(info, "GET /datum") {
+                    issues.push(format!("sensor is incompatible: {}", msg));
+                }
+            }
+        }
+
+        match actuator {
+            None => issues.push(String::from("no actuator")),
+            Some(info) => {
+                if let Err(msg) = Controller::check_compatibility(info, "POST /command") {
+                    issues.push(format!("actuator is incompatible: {}", msg));
+                }
+            }
+        }
+
+        match (Self::model(sensor), Self::model(actuator)) {
+            (Some(sensor), Some(actuator)) if sensor != actuator => issues.push(format!("sensor model {} does not match actuator model {}", sensor, actuator)),
+            (Some(model), _) if !custom_assessor && !DEFAULT_ASSESSOR.contains_key(model.as_str()) => issues.push(format!("no assessor for model {}", model)),
+            _ => {}
+        }
+
+        issues
+    }
+
+    fn issues_to_json(issues: &[String]) -> String {
+        let issues: Vec<String> = issues.iter().map(|issue| format!(r#""{}""#, escape(issue))).collect();
+        format!("[{}]", issues.join(","))
+    }
+
+    /// Returns the advertised model of a `Device`, if it has one which can be parsed.
+    fn model(info: Option<&ServiceInfo>) -> Option<String> {
+        info.and_then(Controller::extract_model).and_then(Result::ok).map(|model| model.to_string())
+    }
+
+    /// Serializes a single discovered `Device` (a `"sensor"` or an `"actuator"`) as JSON.
+    ///
+    /// A `Device` which has not been contacted yet has no `Liveness`, and is assumed to be online.
+    fn device_to_json(kind: &str, id: &Id, info: &ServiceInfo, liveness: Option<&Liveness>) -> String {
+        let name = Controller::extract_name(info).map(|name| name.to_string()).unwrap_or_default();
+        let model = Self::model(Some(info)).unwrap_or_default();
+        let address = Controller::extract_address(info)
+            .map(|address| format!(r#""{}""#, address))
+            .unwrap_or(String::from("null"));
+        let state = liveness.map(Liveness::state).unwrap_or(liveness::State::Online);
+        let last_seen = liveness.map(|l| format!(r#""{}""#, l.last_seen().to_rfc3339())).unwrap_or(String::from("null"));
+        let protocol = Controller::extract_protocol_version(info)
+            .and_then(Result::ok)
+            .map(|version| version.to_string())
+            .unwrap_or(String::from("null"));
+        let endpoint = if kind == "sensor" { "GET /datum" } else { "POST /command" };
+        let compatible = Controller::check_compatibility(info, endpoint).is_ok();
+
+        format!(
+            r#"{{"id":"{}","kind":"{}","name":"{}","model":"{}","address":{},"state":"{}","last_seen":{},"protocol":{},"compatible":{}}}"#,
+            escape(id.to_string().as_str()),
+            kind,
+            escape(name.as_str()),
+            escape(model.as_str()),
+            address,
+            state,
+            last_seen,
+            protocol,
+            compatible
+        )
+    }
+}
+
+#[cfg(test)]
+mod inventory_tests {
+    use chrono::TimeZone;
+
+    use datum::unit::Unit;
+    use device::discovery::PROTOCOL_VERSION;
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    fn service_info(group: &str, id: &str, model: &str) -> ServiceInfo {
+        let mut properties = HashMap::new();
+        properties.insert("id".to_string(), id.to_string());
+        properties.insert("name".to_string(), format!("My {}", id));
+        properties.insert("model".to_string(), model.to_string());
+        properties.insert("protocol".to_string(), PROTOCOL_VERSION.to_string());
+
+        let domain = format!("{}._tcp.local.", group);
+        let name = format!("{}.{}", id, model);
+        ServiceInfo::new(domain.as_str(), name.as_str(), "localhost", "127.0.0.1", 10101, properties).unwrap()
+    }
+
+    fn create_inventory(sensors: Vec<ServiceInfo>, actuators: Vec<ServiceInfo>) -> Inventory {
+        let by_id = |devices: Vec<ServiceInfo>| -> HashMap<Id, ServiceInfo> {
+            devices.into_iter().map(|info| (Controller::extract_id(&info).unwrap(), info)).collect()
+        };
+
+        Inventory {
+            sensors: Arc::new(Mutex::new(by_id(sensors))),
+            data: Arc::new(Mutex::new(HashMap::new())),
+            assessors: Arc::new(Mutex::new(HashMap::new())),
+            setpoints: Arc::new(Mutex::new(HashMap::new())),
+            schedules: Arc::new(Mutex::new(HashMap::new())),
+            actuators: Arc::new(Mutex::new(by_id(actuators))),
+            overrides: Arc::new(Mutex::new(HashMap::new())),
+            liveness: Arc::new(Mutex::new(HashMap::new())),
+            commands: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap())),
+        }
+    }
+
+    #[test]
+    fn test_issues() {
+        let sensor = service_info("_sensor", "my_id", "thermo5000");
+        let actuator = service_info("_actuator", "my_id", "thermo5000");
+        assert!(Inventory::issues(Some(&sensor), Some(&actuator), false).is_empty());
+
+        assert_eq!(Inventory::issues(Some(&sensor), None, false), vec!["no actuator"]);
+        assert_eq!(Inventory::issues(None, Some(&actuator), false), vec!["no sensor"]);
+
+        let unsupported = service_info("_actuator", "my_id", "unsupported");
+        assert_eq!(
+            Inventory::issues(Some(&sensor), Some(&unsupported), false),
+            vec!["sensor model thermo5000 does not match actuator model unsupported"]
+        );
+
+        // an unsupported Sensor is only managed by the Controller once it has been given an Assessor
+        let unsupported = service_info("_sensor", "my_id", "unsupported");
+        assert_eq!(
+            Inventory::issues(Some(&unsupported), None, false),
+            vec!["no actuator", "no assessor for model unsupported"]
+        );
+        assert_eq!(Inventory::issues(Some(&unsupported), None, true), vec!["no actuator"]);
+    }
+
+    #[test]
+    fn test_pairs() {
+        let inventory = create_inventory(
+            vec![service_info("_sensor", "paired", "thermo5000"), service_info("_sensor", "lonely", "thermo5000")],
+            vec![service_info("_actuator", "paired", "thermo5000")],
+        );
+
+        let expected = [
+            r#"{"id":"lonely","sensor":true,"actuator":false,"paired":false,"issues":["no actuator"]}"#,
+            r#"{"id":"paired","sensor":true,"actuator":true,"paired":true,"issues":[]}"#,
+        ]
+        .join(",");
+
+        assert_eq!(inventory.pairs(), format!("[{}]", expected));
+    }
+
+    #[test]
+    fn test_device() {
+        let inventory = create_inventory(
+            vec![service_info("_sensor", "my_id", "thermo5000")],
+            vec![service_info("_actuator", "my_id", "thermo5000")],
+        );
+
+        assert_eq!(inventory.device(&Id::new("unknown")), None);
+
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let mut buffer = VecDeque::new();
+        buffer.push_front(Datum::new(1.5, Unit::DegreesC, timestamp));
+        inventory.data.lock().unwrap().insert(Id::new("my_id"), buffer);
+
+        let command = SentCommand {
+            command: String::from(r#"{"name":"HeatBy","value":"4"}"#),
+            sent: timestamp,
+        };
+        inventory.commands.lock().unwrap().insert(Id::new("my_id"), command);
+
+        let actual = inventory.device(&Id::new("my_id")).unwrap();
+
+        let device = |kind: &str| {
+            format!(
+                r#"{{"id":"my_id","kind":"{}","name":"My my_id","model":"thermo5000","address":"127.0.0.1:10101","state":"online","last_seen":null,"protocol":1,"compatible":true}}"#,
+                kind
+            )
+        };
+
+        let expected = format!(
+            r#"{{"id":"my_id","paired":true,"issues":[],"sensor":{},"actuator":{},"assessor":"default","mode":"automatic","setpoint":null,"schedule":false,"override":null,"last_datum":{},"last_command":{}}}"#,
+            device("sensor"),
+            device("actuator"),
+            r#"{"value":"1.5","unit":"°C","timestamp":"2024-01-05T12:00:00+00:00"}"#,
+            r#"{"command":{"name":"HeatBy","value":"4"},"sent":"2024-01-05T12:00:00+00:00"}"#
+        );
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_device_mode() {
+        let inventory = create_inventory(vec![service_info("_sensor", "my_id", "thermo5000")], vec![]);
+        let id = Id::new("my_id");
+
+        inventory.setpoints.lock().unwrap().insert(id.clone(), 21.0);
+        let actual = inventory.device(&id).unwrap();
+        assert!(
+            actual.contains(r#""mode":"automatic","setpoint":21,"schedule":false,"override":null"#),
+            "{}",
+            actual
+        );
+
+        let manual = Override { command: None, expires: None };
+        inventory.overrides.lock().unwrap().insert(id.clone(), manual);
+        let actual = inventory.device(&id).unwrap();
+        assert!(
+            actual.contains(r#""mode":"manual","setpoint":21,"schedule":false,"override":{"command":null,"expires":null}"#),
+            "{}",
+            actual
+        );
+
+        // an Override which has expired no longer counts
+        let expired = Override {
+            command: None,
+            expires: Some(Utc::now() - chrono::Duration::seconds(1)),
+        };
+        inventory.overrides.lock().unwrap().insert(id.clone(), expired);
+        let actual = inventory.device(&id).unwrap();
+        assert!(actual.contains(r#""mode":"automatic""#), "{}", actual);
+    }
+
+    #[test]
+    fn test_device_schedule() {
+        let inventory = create_inventory(vec![service_info("_sensor", "my_id", "thermo5000")], vec![]);
+        let id = Id::new("my_id");
+
+        // the inventory's clock says it is Friday at noon
+        let schedule = Schedule::parse(r#"{"weekly":[{"days":["fri"],"at":"09:00","setpoint":21},{"days":["fri"],"at":"18:00","setpoint":16}]}"#).unwrap();
+        inventory.schedules.lock().unwrap().insert(id.clone(), schedule);
+
+        let actual = inventory.device(&id).unwrap();
+        assert!(actual.contains(r#""setpoint":21,"schedule":true"#), "{}", actual);
+
+        // a setpoint which has been set explicitly is held, whatever the Schedule says
+        inventory.setpoints.lock().unwrap().insert(id.clone(), 23.0);
+        let actual = inventory.device(&id).unwrap();
+        assert!(actual.contains(r#""setpoint":23,"schedule":true"#), "{}", actual);
+    }
+}
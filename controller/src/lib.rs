@@ -1,24 +1,83 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Write;
-use std::net::{IpAddr, TcpStream};
+use std::net::IpAddr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
 use std::time::Duration;
 
-use log::{debug, error};
-use mdns_sd::{ServiceDaemon, ServiceInfo};
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use mdns_sd::ServiceInfo;
 
+use datum::filter::Filter;
 use datum::Datum;
 use device::address::Address;
+use device::broadcast::Broadcaster;
+use device::client::Client;
+use device::discovery::PROTOCOL_VERSION;
+use device::error::Error;
+use device::handle::DeviceHandle;
 use device::id::Id;
+use device::json::{escape, Value};
 use device::message::Message;
 use device::model::Model;
 use device::name::Name;
+use device::pool::PoolConfig;
+use device::router::Router;
+use device::sse::EventStream;
 use device::{Device, Handler};
 
-use crate::assessor::{Assessor, DEFAULT_ASSESSOR};
+use crate::assessor::{Assessments, Assessor, DEFAULT_ASSESSOR};
+use crate::channel::Channel;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::event::Event;
+use crate::inventory::{Inventory, SentCommand};
+use crate::liveness::Liveness;
+use crate::overrides::Override;
+use crate::policy::{Logged, Outcome, Policy, Throttle};
+use crate::rule::Rule;
+use crate::schedule::Schedule;
+use crate::script::Scripts;
+use crate::storage::Storage;
+use crate::store::Store;
+use crate::streams::{Streams, MAX_STREAMS};
 
 mod assessor;
+mod channel;
+pub mod clock;
+pub mod config;
+mod event;
+mod inventory;
+mod liveness;
+mod overrides;
+mod pid;
+mod policy;
+mod rule;
+mod schedule;
+mod script;
+mod segments;
+mod setpoints;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod storage;
+mod store;
+mod streams;
+
+/// The file (within the `Controller`'s state directory) in which custom `Assessor`s are saved.
+const ASSESSORS_FILE: &str = "assessors.json";
+
+/// The file (within the `Controller`'s state directory) in which custom `Policy`s are saved.
+const POLICIES_FILE: &str = "policies.json";
+
+/// The file (within the `Controller`'s state directory) in which setpoints are saved.
+const SETPOINTS_FILE: &str = "setpoints.json";
+
+/// The file (within the `Controller`'s state directory) in which `Schedule`s are saved.
+const SCHEDULES_FILE: &str = "schedules.json";
+
+/// The file (within the `Controller`'s state directory) in which `Rule`s are saved.
+const RULES_FILE: &str = "rules.json";
 
 /// The Controller queries the `Sensor`s for `Datum`s and sends `Command`s to the `Actuator`s.
 ///
@@ -27,16 +86,29 @@ mod assessor;
 /// (optionally) constructs an appropriate command to send to that `Sensor`'s `Actuator`.
 ///
 /// The `Controller`'s state can be queried by an HTML frontend, so some historic data is held
-/// in memory.
+/// in memory. All of the data it receives can also be kept in a `Storage`.
 pub struct Controller {
     name: Name,
     id: Id,
-    address: Address,
     container_mode: bool,
+    store: Store,
+    scripts: Scripts,
+    clock: Arc<dyn Clock>,
     sensors: Arc<Mutex<HashMap<Id, ServiceInfo>>>,
     actuators: Arc<Mutex<HashMap<Id, ServiceInfo>>>,
     assessors: Arc<Mutex<HashMap<Id, Assessor>>>,
+    setpoints: Arc<Mutex<HashMap<Id, f32>>>,
+    schedules: Arc<Mutex<HashMap<Id, Schedule>>>,
+    rules: Arc<Mutex<HashMap<Id, Rule>>>,
+    policies: Arc<Mutex<HashMap<Id, Policy>>>,
     data: Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>,
+    storage: Option<Arc<dyn Storage>>,
+    overrides: Arc<Mutex<HashMap<Id, Override>>>,
+    liveness: Arc<Mutex<HashMap<String, Liveness>>>,
+    commands: Arc<Mutex<HashMap<Id, SentCommand>>>,
+    command_log: Arc<Mutex<HashMap<Id, VecDeque<Logged>>>>,
+    events: Arc<Broadcaster<Event>>,
+    streams: Arc<Streams>,
 }
 
 impl Device for Controller {
@@ -52,60 +124,344 @@ impl Device for Controller {
         Model::Controller
     }
 
-    fn get_handler(&self) -> Handler {
-        // Anything which depends on self must be cloned outside of the |stream| lambda.
-        // We cannot refer to `self` inside of this lambda.
+    fn get_handler(&self, address: Address) -> Handler {
+        // Anything which depends on self must be cloned outside of the |stream| lambdas.
+        // We cannot refer to `self` inside of these lambdas.
         let self_name = self.get_name().clone();
-        let self_data = Arc::clone(&self.data);
-        let self_address = self.address.to_string();
+        let export_name = self.get_name().clone();
+        let data = Arc::clone(&self.data);
+        let data_storage = self.storage.clone();
+        let export = Arc::clone(&self.data);
+        let datum = Arc::clone(&self.data);
+        let events = Arc::clone(&self.events);
+        let stream_name = self.get_name().clone();
+        let stream_slots = Arc::clone(&self.streams);
+        let ws_name = self.get_name().clone();
+        let ws_slots = Arc::clone(&self.streams);
+        let ws_data = Arc::clone(&self.data);
+        let ws_actuators = Arc::clone(&self.actuators);
+        let ws_overrides = Arc::clone(&self.overrides);
+        let ws_events = Arc::clone(&self.events);
+        let devices = self.inventory();
+        let device_name = self.get_name().clone();
+        let device = self.inventory();
+        let pairs = self.inventory();
+        let assessors = Arc::clone(&self.assessors);
+        let assessor_name = self.get_name().clone();
+        let assessor_sensors = Arc::clone(&self.sensors);
+        let assessor = Arc::clone(&self.assessors);
+        let put_name = self.get_name().clone();
+        let put_assessors = Arc::clone(&self.assessors);
+        let put_store = self.store.clone();
+        let delete_name = self.get_name().clone();
+        let delete_assessors = Arc::clone(&self.assessors);
+        let delete_store = self.store.clone();
+        let policies = Arc::clone(&self.policies);
+        let policy = Arc::clone(&self.policies);
+        let put_policy_name = self.get_name().clone();
+        let put_policies = Arc::clone(&self.policies);
+        let put_policy_store = self.store.clone();
+        let delete_policy_name = self.get_name().clone();
+        let delete_policies = Arc::clone(&self.policies);
+        let delete_policy_store = self.store.clone();
+        let command_log = Arc::clone(&self.command_log);
+        let put_setpoint_name = self.get_name().clone();
+        let put_setpoints = Arc::clone(&self.setpoints);
+        let put_setpoint_store = self.store.clone();
+        let delete_setpoint_name = self.get_name().clone();
+        let delete_setpoints = Arc::clone(&self.setpoints);
+        let delete_setpoint_store = self.store.clone();
+        let override_name = self.get_name().clone();
+        let override_client = Client::new();
+        let override_actuators = Arc::clone(&self.actuators);
+        let override_overrides = Arc::clone(&self.overrides);
+        let override_events = Arc::clone(&self.events);
+        let delete_override_name = self.get_name().clone();
+        let delete_overrides = Arc::clone(&self.overrides);
+        let schedule_name = self.get_name().clone();
+        let schedule = Arc::clone(&self.schedules);
+        let schedule_clock = Arc::clone(&self.clock);
+        let put_schedule_name = self.get_name().clone();
+        let put_schedules = Arc::clone(&self.schedules);
+        let put_schedule_store = self.store.clone();
+        let put_schedule_clock = Arc::clone(&self.clock);
+        let delete_schedule_name = self.get_name().clone();
+        let delete_schedules = Arc::clone(&self.schedules);
+        let delete_schedule_store = self.store.clone();
+        let rules = Arc::clone(&self.rules);
+        let rule_name = self.get_name().clone();
+        let rule = Arc::clone(&self.rules);
+        let put_rule_name = self.get_name().clone();
+        let put_rules = Arc::clone(&self.rules);
+        let put_rule_store = self.store.clone();
+        let delete_rule_name = self.get_name().clone();
+        let delete_rules = Arc::clone(&self.rules);
+        let delete_rule_store = self.store.clone();
+        let scripts = self.scripts.clone();
+        let self_address = address.to_string();
         let local_mode = self.container_mode;
 
-        Box::new(move |stream| {
-            if let Ok(message) = Message::read(stream) {
-                if message.start_line == "GET /data HTTP/1.1" {
-                    Self::handle_get_data(stream, &self_data)
-                } else if message.start_line == "GET /datum HTTP/1.1" {
-                    Self::handle_get_datum(stream, &self_data)
-                } else if message.start_line == "GET /ui HTTP/1.1" {
-                    Self::handle_get_ui(stream, local_mode, self_address.clone())
-                } else {
-                    let msg = format!("cannot parse request: {}", message.start_line);
-                    Self::handler_failure(self_name.clone(), stream, msg.as_str())
-                }
-            } else {
-                Self::handler_failure(self_name.clone(), stream, "unable to read Message from stream")
-            }
-        })
+        Router::new(self.get_name().clone())
+            .get("/data", move |stream, message, _| match Self::parse_data_query(&message) {
+                Ok((filter, ids)) => match &data_storage {
+                    Some(storage) => Self::handle_get_stored_data(self_name.clone(), stream, storage.as_ref(), &filter, ids.as_deref()),
+                    None => Self::handle_get_data(stream, &data, &filter, ids.as_deref()),
+                },
+                Err(msg) => Self::handler_failure(self_name.clone(), stream, 400, "invalid_query", msg.as_str()),
+            })
+            .get("/export", move |stream, message, _| match Self::parse_data_query(&message) {
+                Ok((filter, ids)) => Self::handle_get_export(stream, &export, &filter, ids.as_deref()),
+                Err(msg) => Self::handler_failure(export_name.clone(), stream, 400, "invalid_query", msg.as_str()),
+            })
+            .get("/datum", move |stream, _, _| Self::handle_get_datum(stream, &datum))
+            .get("/devices", move |stream, _, _| Self::handle_get_devices(stream, &devices))
+            .get("/devices/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_device(device_name.clone(), stream, &device, &id)
+            })
+            .get("/pairs", move |stream, _, _| Self::handle_get_pairs(stream, &pairs))
+            .get("/assessors", move |stream, _, _| Self::handle_get_assessors(stream, &assessors))
+            .get("/assessors/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_assessor(assessor_name.clone(), stream, &assessor_sensors, &assessor, &id)
+            })
+            .put("/assessors/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_put_assessor(put_name.clone(), stream, &put_assessors, &put_store, &id, message.body.as_deref())
+            })
+            .delete("/assessors/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_assessor(delete_name.clone(), stream, &delete_assessors, &delete_store, &id)
+            })
+            .get("/policies", move |stream, _, _| Self::handle_get_policies(stream, &policies))
+            .get("/policies/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_policy(stream, &policy, &id)
+            })
+            .put("/policies/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_put_policy(put_policy_name.clone(), stream, &put_policies, &put_policy_store, &id, message.body.as_deref())
+            })
+            .delete("/policies/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_policy(delete_policy_name.clone(), stream, &delete_policies, &delete_policy_store, &id)
+            })
+            .get("/commands/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_commands(stream, &command_log, &id)
+            })
+            .put("/setpoints/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_put_setpoint(
+                    put_setpoint_name.clone(),
+                    stream,
+                    &put_setpoints,
+                    &put_setpoint_store,
+                    &id,
+                    message.body.as_deref(),
+                )
+            })
+            .delete("/setpoints/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_setpoint(delete_setpoint_name.clone(), stream, &delete_setpoints, &delete_setpoint_store, &id)
+            })
+            .get("/schedules/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_schedule(schedule_name.clone(), stream, &schedule, schedule_clock.as_ref(), &id)
+            })
+            .put("/schedules/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                let body = message.body.as_deref();
+                Self::handle_put_schedule(
+                    put_schedule_name.clone(),
+                    stream,
+                    &put_schedules,
+                    &put_schedule_store,
+                    put_schedule_clock.as_ref(),
+                    &id,
+                    body,
+                )
+            })
+            .delete("/schedules/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_schedule(delete_schedule_name.clone(), stream, &delete_schedules, &delete_schedule_store, &id)
+            })
+            .get("/scripts", move |stream, _, _| Self::handle_get_scripts(stream, &scripts))
+            .get("/rules", move |stream, _, _| Self::handle_get_rules(stream, &rules))
+            .get("/rules/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_rule(rule_name.clone(), stream, &rule, &id)
+            })
+            .put("/rules/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_put_rule(put_rule_name.clone(), stream, &put_rules, &put_rule_store, &id, message.body.as_deref())
+            })
+            .delete("/rules/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_rule(delete_rule_name.clone(), stream, &delete_rules, &delete_rule_store, &id)
+            })
+            .post("/overrides/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_post_override(override_name.clone(), stream, &id, message.body.as_deref(), |command, seconds| {
+                    Self::start_override(
+                        &override_client,
+                        &override_actuators,
+                        &override_overrides,
+                        &override_events,
+                        &id,
+                        command,
+                        seconds,
+                    )
+                })
+            })
+            .delete("/overrides/:id", move |stream, _, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_delete_override(delete_override_name.clone(), stream, &delete_overrides, &id)
+            })
+            .get("/stream", move |stream, message, _| {
+                let Some(_slot) = stream_slots.open() else {
+                    return Self::handler_failure(
+                        stream_name.clone(),
+                        stream,
+                        503,
+                        "too_many_streams",
+                        "too many clients are streaming, try again later",
+                    );
+                };
+
+                let query = message.query();
+                let ids: Option<Vec<Id>> = query.get("id").map(|ids| ids.split(',').map(Id::new).collect());
+
+                // a client which stops reading must not hold on to this worker forever
+                stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+                Self::handle_get_stream(stream, events.subscribe(), ids.as_deref(), Duration::from_secs(15))
+            })
+            .get("/ws", move |stream, message, _| {
+                let Some(_slot) = ws_slots.open() else {
+                    return Self::handler_failure(
+                        ws_name.clone(),
+                        stream,
+                        503,
+                        "too_many_streams",
+                        "too many clients are streaming, try again later",
+                    );
+                };
+
+                let response = match Message::respond_websocket_upgrade(&message) {
+                    Ok(response) => response,
+                    Err(e) => return Self::handler_failure(ws_name.clone(), stream, 400, "invalid_upgrade", e.to_string().as_str()),
+                };
+
+                // The client must wait for this response before it sends any frames, so the
+                // connection's BufReader cannot have buffered any frames by this point.
+                //     see: https://www.rfc-editor.org/rfc/rfc6455#section-4.1
+                response.write(stream)?;
+
+                // the Channel sends a Ping when idle, so a dead client is noticed when that write fails
+                stream.set_read_timeout(None)?;
+                stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+                let channel = Channel::new(&ws_data, &ws_actuators, &ws_overrides, &ws_events);
+                channel.serve(stream, Duration::from_secs(15))
+            })
+            .get("/ui", move |stream, _, _| Self::handle_get_ui(stream, local_mode, self_address.clone()))
+            .into_handler()
+    }
+
+    fn get_endpoints(&self) -> Vec<String> {
+        [
+            "GET /data",
+            "GET /export",
+            "GET /datum",
+            "GET /devices",
+            "GET /devices/:id",
+            "GET /pairs",
+            "GET /assessors",
+            "GET /assessors/:id",
+            "PUT /assessors/:id",
+            "DELETE /assessors/:id",
+            "GET /policies",
+            "GET /policies/:id",
+            "PUT /policies/:id",
+            "DELETE /policies/:id",
+            "GET /commands/:id",
+            "PUT /setpoints/:id",
+            "DELETE /setpoints/:id",
+            "GET /schedules/:id",
+            "PUT /schedules/:id",
+            "DELETE /schedules/:id",
+            "GET /scripts",
+            "GET /rules",
+            "GET /rules/:id",
+            "PUT /rules/:id",
+            "DELETE /rules/:id",
+            "POST /overrides/:id",
+            "DELETE /overrides/:id",
+            "GET /stream",
+            "GET /ws",
+            "GET /ui",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    /// Every client of `GET /stream` and `GET /ws` holds on to a worker for as long as it is connected, so the
+    /// `Controller` needs more workers than the default: one for each of the [`MAX_STREAMS`] streaming clients,
+    /// and as many again as any other `Device` has, for every other request.
+    fn get_pool_config(&self) -> PoolConfig {
+        PoolConfig::new(MAX_STREAMS + PoolConfig::default().workers, 16)
     }
 }
 
 impl Controller {
-    fn new(id: Id, name: Name, address: Address, container_mode: bool) -> Self {
+    fn new(id: Id, name: Name, container_mode: bool, store: Store, scripts: Scripts, clock: Arc<dyn Clock>) -> Self {
         Self {
             name,
             id,
-            address,
             container_mode,
+            store,
+            scripts,
+            clock,
             sensors: Arc::new(Mutex::new(HashMap::new())),
             actuators: Arc::new(Mutex::new(HashMap::new())),
             assessors: Arc::new(Mutex::new(HashMap::new())),
+            setpoints: Arc::new(Mutex::new(HashMap::new())),
+            schedules: Arc::new(Mutex::new(HashMap::new())),
+            rules: Arc::new(Mutex::new(HashMap::new())),
+            policies: Arc::new(Mutex::new(HashMap::new())),
             data: Arc::new(Mutex::new(HashMap::new())),
+            storage: None,
+            overrides: Arc::new(Mutex::new(HashMap::new())),
+            liveness: Arc::new(Mutex::new(HashMap::new())),
+            commands: Arc::new(Mutex::new(HashMap::new())),
+            command_log: Arc::new(Mutex::new(HashMap::new())),
+            events: Arc::new(Broadcaster::default()),
+            streams: Streams::new(MAX_STREAMS),
         }
     }
 
     /// Describes how `GET /data` requests are handled by the `Controller`.
     ///
+    /// The `filter` is applied to each `Sensor`'s buffer separately. If `ids` are provided, only the
+    /// data from those `Sensor`s is returned.
+    ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_data(tcp_stream: &mut impl Write, data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>) {
+    fn handle_get_data(tcp_stream: &mut impl Write, data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>, filter: &Filter, ids: Option<&[Id]>) -> Result<(), Error> {
         // get all of the data in this Controller's buffer, grouped by Sensor
         //     ex: curl 10.12.50.26:5454/data
+        //
+        // or only some of it, for only some Sensors
+        //     ex: curl '10.12.50.26:5454/data?id=thermo-5000,thermo-6000&since=2024-01-05T12:39:36Z&limit=50'
 
         let data = data.lock().unwrap();
         let sensors: Vec<String> = data
             .iter()
+            .filter(|(id, _)| ids.is_none_or(|ids| ids.contains(id)))
             .map(|(id, buffer)| {
-                let data: Vec<String> = buffer.iter().map(|d| d.to_string()).collect();
+                let data: Vec<String> = filter.apply(buffer.iter()).iter().map(|d| d.to_string()).collect();
                 let data = data.join(",");
                 format!(r#"{{"id":"{}","data":[{}]}}"#, id, data)
             })
@@ -116,11 +472,146 @@ impl Controller {
         response.write(tcp_stream)
     }
 
+    /// Describes how `GET /data` requests are handled by a `Controller` which keeps its data in a `Storage`.
+    ///
+    /// The response has the same shape as that of [`handle_get_data`](Self::handle_get_data), but it
+    /// can include data which is no longer in the `Controller`'s buffers, or which was received before
+    /// the `Controller` was restarted.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_stored_data(self_name: Name, tcp_stream: &mut impl Write, storage: &dyn Storage, filter: &Filter, ids: Option<&[Id]>) -> Result<(), Error> {
+        // get all of the data in this Controller's Storage, grouped by Sensor
+        //     ex: curl 10.12.50.26:5454/data
+        //
+        // or only some of it, for only some Sensors
+        //     ex: curl '10.12.50.26:5454/data?id=thermo-5000&since=2024-01-05T12:39:36Z&until=2024-01-05T13:39:36Z'
+
+        let stored = match storage.query(filter, ids) {
+            Ok(stored) => stored,
+            Err(e) => {
+                let msg = format!("cannot read stored data: {}", e);
+                return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+            }
+        };
+
+        let sensors: Vec<String> = stored
+            .iter()
+            .map(|(id, data)| {
+                let data: Vec<String> = data.iter().map(|d| d.to_string()).collect();
+                format!(r#"{{"id":"{}","data":[{}]}}"#, id, data.join(","))
+            })
+            .collect();
+        let body = format!("[{}]", sensors.join(","));
+
+        let response = Message::respond_ok().with_body(body);
+        response.write(tcp_stream)
+    }
+
+    /// Parses the `Filter` and the (optional, comma-separated) `id` list from the query string of a
+    /// `GET /data` or `GET /export` request.
+    fn parse_data_query(message: &Message) -> Result<(Filter, Option<Vec<Id>>), String> {
+        let query = message.query();
+        let ids = query.get("id").map(|ids| ids.split(',').map(Id::new).collect());
+        Filter::parse(&query).map(|filter| (filter, ids))
+    }
+
+    /// Describes how `GET /export` requests are handled by the `Controller`.
+    ///
+    /// This returns the same data as [`handle_get_data`](Self::handle_get_data), but the body is
+    /// streamed with `Transfer-Encoding: chunked`, one `Datum` at a time, rather than being built
+    /// up as a single `String` first.
+    ///
+    /// **Design Decision**: each `Sensor`'s buffer is copied while the lock is held, and serialized
+    /// after the lock is released, so that a slow client cannot stall the `Controller`'s polling loop.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_export(
+        tcp_stream: &mut impl Write,
+        data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>,
+        filter: &Filter,
+        ids: Option<&[Id]>,
+    ) -> Result<(), Error> {
+        // stream all of the data in this Controller's buffer, grouped by Sensor
+        //     ex: curl 10.12.50.26:5454/export
+        //
+        // accepts the same query parameters as GET /data
+        //     ex: curl '10.12.50.26:5454/export?id=thermo-5000&since=2024-01-05T12:39:36Z'
+
+        let sensors: Vec<Id> = {
+            let data = data.lock().unwrap();
+            data.keys().filter(|id| ids.is_none_or(|ids| ids.contains(id))).cloned().collect()
+        };
+
+        let mut writer = Message::respond_ok().write_chunked(tcp_stream)?;
+        writer.write_all(b"[")?;
+
+        let mut separator = "";
+
+        for id in sensors.iter() {
+            let buffer: Vec<Datum> = match data.lock().unwrap().get(id) {
+                Some(buffer) => filter.apply(buffer.iter()).into_iter().cloned().collect(),
+                None => continue, // this Sensor's buffer was removed while we were streaming
+            };
+
+            writer.write_all(separator.as_bytes())?;
+            separator = ",";
+
+            write!(writer, r#"{{"id":"{}","data":["#, id)?;
+
+            for (index, datum) in buffer.iter().enumerate() {
+                if index > 0 {
+                    writer.write_all(b",")?;
+                }
+                write!(writer, "{}", datum)?;
+            }
+
+            writer.write_all(b"]}")?;
+        }
+
+        writer.write_all(b"]")?;
+        writer.finish().map(|_| ())
+    }
+
+    /// Describes how `GET /stream` requests are handled by the `Controller`.
+    ///
+    /// Every `Event` received from `events` is sent to the client as a Server-Sent Event, until the
+    /// client disconnects. If `ids` are provided, only `Event`s about those `Sensor`s / `Actuator`s
+    /// are sent. A heartbeat is sent whenever no `Event` has been sent for `heartbeat`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_stream(tcp_stream: &mut impl Write, events: Receiver<Event>, ids: Option<&[Id]>, heartbeat: Duration) -> Result<(), Error> {
+        // stream new Datums and Commands as they happen
+        //     ex: curl -N 10.12.50.26:5454/stream
+        //
+        // or only those for some Sensors
+        //     ex: curl -N '10.12.50.26:5454/stream?id=thermo-5000,thermo-6000'
+
+        let mut stream = EventStream::open(tcp_stream)?;
+
+        loop {
+            let sent = match events.recv_timeout(heartbeat) {
+                Ok(event) if ids.is_none_or(|ids| ids.contains(event.id())) => stream.send(event.name(), event.data().as_str()),
+                Ok(_) => Ok(()),
+                Err(RecvTimeoutError::Timeout) => stream.heartbeat(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()), // the Controller is shutting down
+            };
+
+            // a client closing the stream is the normal way for a stream to end
+            if let Err(e) = sent {
+                debug!("[Controller] closing event stream: {}", e);
+                return Ok(());
+            }
+        }
+    }
+
     /// Describes how `GET /datum` requests are handled by the `Controller`.
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_datum(tcp_stream: &mut impl Write, data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>) {
+    fn handle_get_datum(tcp_stream: &mut impl Write, data: &Arc<Mutex<HashMap<Id, VecDeque<Datum>>>>) -> Result<(), Error> {
         // get the latest Datum in this Controller's buffer, grouped by Sensor
         //     ex: curl 10.12.50.26:5454/datum
 
@@ -138,267 +629,2333 @@ impl Controller {
         response.write(tcp_stream)
     }
 
-    /// Describes how `GET /datum` requests are handled by the `Controller`.
+    /// Describes how `GET /devices` requests are handled by the `Controller`.
+    ///
+    /// Every discovered `Sensor` and `Actuator` is listed, along with its liveness `State`.
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_ui(tcp_stream: &mut impl Write, container_mode: bool, self_address: String) {
-        let html = include_str!("index.html");
+    fn handle_get_devices(tcp_stream: &mut impl Write, inventory: &Inventory) -> Result<(), Error> {
+        // get every Device this Controller knows about, and whether or not it is reachable
+        //     ex: curl 10.12.50.26:6565/devices
 
-        let html = if container_mode {
-            html.replace("192.168.2.16:6565", "localhost:6565")
-        } else {
-            html.replace("192.168.2.16:6565", self_address.as_str())
-        };
+        let response = Message::respond_ok().with_body(inventory.devices());
+        response.write(tcp_stream)
+    }
 
-        let mut headers = HashMap::new();
-        headers.insert("Content-Type", "text/html; charset=utf-8");
+    /// Describes how `GET /devices/:id` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_device(self_name: Name, tcp_stream: &mut impl Write, inventory: &Inventory, id: &Id) -> Result<(), Error> {
+        // get the Sensor and Actuator with a particular id, along with their latest Datum and Command
+        //     ex: curl 10.12.50.26:6565/devices/thermo-5000
+
+        match inventory.device(id) {
+            Some(device) => Message::respond_ok().with_body(device).write(tcp_stream),
+            None => {
+                let msg = format!("no Sensor or Actuator with id: {}", id);
+                Self::handler_failure(self_name, tcp_stream, 404, "unknown_device", msg.as_str())
+            }
+        }
+    }
 
-        let response = Message::respond_ok().with_body(html).with_headers(headers);
+    /// Describes how `GET /pairs` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_pairs(tcp_stream: &mut impl Write, inventory: &Inventory) -> Result<(), Error> {
+        // list which Sensors have matching Actuators, and anything which stops a pair from being managed
+        //     ex: curl 10.12.50.26:6565/pairs
 
+        let response = Message::respond_ok().with_body(inventory.pairs());
         response.write(tcp_stream)
     }
 
-    pub fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String, container_mode: bool) -> JoinHandle<()> {
-        std::thread::spawn(move || {
-            // --------------------------------------------------------------------------------
-            // create Device and discover required Message targets
-            // --------------------------------------------------------------------------------
-
-            let device = Self::new(id, name, Address::new(ip, port), container_mode);
-
-            let mut targets = HashMap::new();
-            targets.insert("_sensor", Arc::clone(&device.sensors));
-            targets.insert("_actuator", Arc::clone(&device.actuators));
+    /// Describes how `GET /assessors` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_assessors(tcp_stream: &mut impl Write, assessors: &Arc<Mutex<HashMap<Id, Assessor>>>) -> Result<(), Error> {
+        // list every custom Assessor which has been installed
+        //     ex: curl 10.12.50.26:6565/assessors
 
-            let mdns = ServiceDaemon::new().unwrap();
+        let assessors = Assessor::all_to_json(&assessors.lock().unwrap());
+        Message::respond_ok().with_body(assessors).write(tcp_stream)
+    }
 
-            for (group, devices) in targets.iter() {
-                device.discover_continually(group, devices, mdns.clone());
-            }
-            // --------------------------------------------------------------------------------
-            // ping the Sensors at regular intervals to get latest data
-            // --------------------------------------------------------------------------------
+    /// Describes how `GET /assessors/:id` requests are handled by the `Controller`.
+    ///
+    /// Responds with the custom `Assessor` for the `Sensor` with this `id`, if one has been installed,
+    /// or else with the default `Assessor` for that `Sensor`'s `Model`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_assessor(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        sensors: &Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+        assessors: &Arc<Mutex<HashMap<Id, Assessor>>>,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // get the Assessor which is used for a particular Sensor's data
+        //     ex: curl 10.12.50.26:6565/assessors/thermo-5000
+
+        let sensors = sensors.lock().unwrap();
+        let assessors = assessors.lock().unwrap();
+
+        let default = || {
+            let model = sensors.get(id).and_then(Self::extract_model).and_then(Result::ok)?;
+            DEFAULT_ASSESSOR.get(model.to_string().as_str()).cloned()
+        };
 
-            let sleep_duration = Duration::from_millis(50);
-            let buffer_size = 500;
+        match assessors.get(id) {
+            Some(assessor) => Message::respond_ok()
+                .with_body(Self::assessor_to_json(id, "custom", assessor))
+                .write(tcp_stream),
+            None => match default() {
+                Some(assessor) => Message::respond_ok()
+                    .with_body(Self::assessor_to_json(id, "default", &assessor))
+                    .write(tcp_stream),
+                None => {
+                    let msg = format!("no assessor for id: {}", id);
+                    Self::handler_failure(self_name, tcp_stream, 404, "unknown_assessor", msg.as_str())
+                }
+            },
+        }
+    }
 
-            let sensors = Arc::clone(&device.sensors);
-            let data = Arc::clone(&device.data);
-            let assessors = Arc::clone(&device.assessors);
-            let actuators = Arc::clone(&device.actuators);
+    /// Describes how `PUT /assessors/:id` requests are handled by the `Controller`.
+    ///
+    /// Installs the `Assessor` in the request body for the `Sensor` with this `id`, in place of the
+    /// default `Assessor` for its `Model`, and saves it so that it is still used after a restart.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_put_assessor(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        assessors: &Arc<Mutex<HashMap<Id, Assessor>>>,
+        store: &Store,
+        id: &Id,
+        body: Option<&str>,
+    ) -> Result<(), Error> {
+        // install a custom Assessor for a particular Sensor
+        //     ex: curl -X PUT 10.12.50.26:6565/assessors/thermo-5000 -d '{"type":"thermostat","setpoint":21,"low":19,"high":23}'
+
+        let assessor = match Assessor::parse(body.unwrap_or_default()) {
+            Ok(assessor) => assessor,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_assessor", msg.as_str()),
+        };
 
-            std::thread::spawn(move || {
-                let query = Message::request_get("/datum");
+        let mut assessors = assessors.lock().unwrap();
 
-                // sleep just for a moment so the Sensor has a chance to grab its first Datum from the Environment
-                std::thread::sleep(Duration::from_millis(100));
+        if let Err(e) = Self::save_or_rollback(&mut assessors, id, Some(assessor.clone()), store, ASSESSORS_FILE, Assessor::all_to_json) {
+            let msg = format!("cannot save assessor: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
 
-                loop {
-                    {
-                        let sensors = sensors.lock().unwrap();
-                        let mut data = data.lock().unwrap();
-                        let assessors = assessors.lock().unwrap();
-                        let actuators = actuators.lock().unwrap();
+        Message::respond_ok()
+            .with_body(Self::assessor_to_json(id, "custom", &assessor))
+            .write(tcp_stream)
+    }
 
-                        for (id, info) in sensors.iter() {
-                            let address = Self::extract_address(info);
-                            let mut stream = TcpStream::connect(address.to_string()).unwrap();
-                            let sensor_name = Self::extract_name(info).unwrap();
-                            let sensor_model = Self::extract_model(info).unwrap().unwrap();
-
-                            debug!("[Controller] querying {} for a Datum", sensor_name);
-                            query.write(&mut stream);
-                            let message = Message::read(&mut stream).unwrap();
-
-                            match Datum::parse(message.body.unwrap().trim_start_matches('[').trim_end_matches(']')) {
-                                Ok(datum) => {
-                                    debug!("[Controller] received a Datum from {}: {}", sensor_name, datum);
-
-                                    if !data.contains_key(id) {
-                                        data.insert(id.clone(), VecDeque::new());
-                                    }
-                                    let buffer: &mut VecDeque<Datum> = data.get_mut(id).unwrap();
-
-                                    // enforce buffer length, then save to buffer
-                                    if buffer.len() == buffer_size {
-                                        buffer.pop_back();
-                                    }
-                                    buffer.push_front(datum.clone());
-
-                                    // assess new data point and (maybe) send Command to Actuator
-                                    if let Some(assessor) = assessors.get(id).or_else(|| DEFAULT_ASSESSOR.get(sensor_model.to_string().as_str())) {
-                                        match (assessor.assess)(&datum) {
-                                            None => debug!("[Controller] assessed Datum, but will not produce Command for Actuator"),
-                                            Some(command) => {
-                                                debug!("[Controller] attempting to send Command to Actuator: {}", command);
-
-                                                match actuators.get(id) {
-                                                    None => error!("[Controller] cannot find Actuator with id: {}", id),
-                                                    Some(actuator) => {
-                                                        let actuator = <Self as Device>::extract_address(actuator).to_string();
-                                                        debug!("[Controller] connecting to Actuator @ {}", actuator);
-                                                        let mut stream = TcpStream::connect(actuator).unwrap();
-                                                        let command = Message::request_post("/command").with_body((*command).to_string());
-                                                        command.write(&mut stream);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    } else {
-                                        error!("[Controller] assessor does not contain id: {}\nknown ids: {:?}", id, assessors.keys())
-                                    }
-                                }
-                                Err(msg) => {
-                                    error!("[Controller] received error: {}", msg)
-                                }
-                            }
-                        }
-                    }
-                    std::thread::sleep(sleep_duration);
-                }
-            });
+    /// Describes how `DELETE /assessors/:id` requests are handled by the `Controller`.
+    ///
+    /// Removes the custom `Assessor` for the `Sensor` with this `id`, so that the default `Assessor`
+    /// for its `Model` is used again.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_assessor(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        assessors: &Arc<Mutex<HashMap<Id, Assessor>>>,
+        store: &Store,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // go back to using the default Assessor for a particular Sensor
+        //     ex: curl -X DELETE 10.12.50.26:6565/assessors/thermo-5000
+
+        let mut assessors = assessors.lock().unwrap();
+
+        if !assessors.contains_key(id) {
+            let msg = format!("no custom assessor for id: {}", id);
+            return Self::handler_failure(self_name, tcp_stream, 404, "unknown_assessor", msg.as_str());
+        }
 
-            // --------------------------------------------------------------------------------
-            // respond to incoming requests
-            // --------------------------------------------------------------------------------
+        if let Err(e) = Self::save_or_rollback(&mut assessors, id, None, store, ASSESSORS_FILE, Assessor::all_to_json) {
+            let msg = format!("cannot save assessors: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
 
-            device.respond(ip, port, group.as_str(), mdns)
-        })
+        Message::respond_no_content().write(tcp_stream)
     }
-}
 
-#[cfg(test)]
-mod controller_tests {
-    use datum::unit::Unit;
+    /// Sets the `value` for this `id` in `map` (or removes it, if `value` is `None`), then saves the
+    /// whole `map` to the `file` in the `store`, serialized with `to_json`.
+    ///
+    /// **Design Decision**: a change which would be forgotten on restart is not made at all, so if
+    /// the `map` cannot be saved, whatever was there for this `id` before is put back.
+    fn save_or_rollback<T>(
+        map: &mut HashMap<Id, T>,
+        id: &Id,
+        value: Option<T>,
+        store: &Store,
+        file: &str,
+        to_json: impl Fn(&HashMap<Id, T>) -> String,
+    ) -> Result<(), Error> {
+        let previous = match value {
+            Some(value) => map.insert(id.clone(), value),
+            None => map.remove(id),
+        };
 
-    use super::*;
+        let saved = store.save(file, to_json(map).as_str());
 
-    #[test]
-    fn test_get_name() {
-        let expected = Name::new("myName");
-        let address = Address::new(IpAddr::from([0, 0, 0, 0]), 10101);
-        let container_mode = false;
-        let controller = Controller::new(Id::new("myId"), expected.clone(), address, container_mode);
-        let actual = controller.get_name();
-        let expected = &expected;
-        assert_eq!(actual, expected);
-    }
+        if saved.is_err() {
+            match previous {
+                Some(previous) => map.insert(id.clone(), previous),
+                None => map.remove(id),
+            };
+        }
 
-    #[test]
-    fn test_get_id() {
-        let expected = Id::new("myId");
-        let address = Address::new(IpAddr::from([0, 0, 0, 0]), 10101);
-        let container_mode = false;
-        let controller = Controller::new(expected.clone(), Name::new("myName"), address, container_mode);
-        let actual = controller.get_id();
-        let expected = &expected;
-        assert_eq!(actual, expected);
+        saved
     }
 
-    #[test]
-    fn test_get_model() {
-        let actual = Controller::get_model();
-        let expected = Model::Controller;
-        assert_eq!(actual, expected);
+    /// Serializes the `Assessor` used for the `Sensor` with this `id`, and whether it is `"custom"` or `"default"`.
+    fn assessor_to_json(id: &Id, source: &str, assessor: &Assessor) -> String {
+        format!(
+            r#"{{"id":"{}","source":"{}","assessor":{}}}"#,
+            escape(id.to_string().as_str()),
+            source,
+            assessor
+        )
     }
 
-    #[test]
-    fn test_handle_get_data() {
-        let id = Id::new("my_sensor");
+    /// Describes how `GET /policies` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_policies(tcp_stream: &mut impl Write, policies: &Arc<Mutex<HashMap<Id, Policy>>>) -> Result<(), Error> {
+        // list every custom Policy which has been installed
+        //     ex: curl 10.12.50.26:6565/policies
 
-        let mut data = VecDeque::new();
-        let datum1 = Datum::new_now(1.0, Unit::DegreesC);
-        let datum2 = Datum::new_now(2.0, Unit::DegreesC);
-        let datum3 = Datum::new_now(3.0, Unit::DegreesC);
-        data.push_front(datum1.clone());
-        data.push_front(datum2.clone());
-        data.push_front(datum3.clone());
+        let policies = Policy::all_to_json(&policies.lock().unwrap());
+        Message::respond_ok().with_body(policies).write(tcp_stream)
+    }
 
-        let mut all_data = HashMap::new();
-        all_data.insert(id.clone(), data);
-        let all_data = Arc::new(Mutex::new(all_data));
+    /// Describes how `GET /policies/:id` requests are handled by the `Controller`.
+    ///
+    /// Responds with the custom `Policy` for the `Actuator` with this `id`, if one has been installed,
+    /// or else with the default `Policy`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_policy(tcp_stream: &mut impl Write, policies: &Arc<Mutex<HashMap<Id, Policy>>>, id: &Id) -> Result<(), Error> {
+        // get the Policy which limits the Commands sent to a particular Actuator
+        //     ex: curl 10.12.50.26:6565/policies/thermo-5000
 
-        let mut buffer = Vec::new();
+        let body = match policies.lock().unwrap().get(id) {
+            Some(policy) => Self::policy_to_json(id, "custom", policy),
+            None => Self::policy_to_json(id, "default", &Policy::default()),
+        };
 
-        Controller::handle_get_data(&mut buffer, &all_data);
+        Message::respond_ok().with_body(body).write(tcp_stream)
+    }
 
-        let actual = String::from_utf8(buffer).unwrap();
+    /// Describes how `PUT /policies/:id` requests are handled by the `Controller`.
+    ///
+    /// Installs the `Policy` in the request body for the `Actuator` with this `id`, in place of the
+    /// default `Policy`, and saves it so that it is still used after a restart.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_put_policy(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        policies: &Arc<Mutex<HashMap<Id, Policy>>>,
+        store: &Store,
+        id: &Id,
+        body: Option<&str>,
+    ) -> Result<(), Error> {
+        // limit the Commands sent to a particular Actuator
+        //     ex: curl -X PUT 10.12.50.26:6565/policies/thermo-5000 -d '{"hysteresis":0.5,"min_interval_ms":5000,"max_per_minute":6}'
+
+        let policy = match Policy::parse(body.unwrap_or_default()) {
+            Ok(policy) => policy,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_policy", msg.as_str()),
+        };
 
-        let json = [datum3, datum2, datum1].map(|e| e.to_string()).join(",");
-        let json = format!(r#"[{{"id":"{}","data":[{}]}}]"#, id, json);
+        let mut policies = policies.lock().unwrap();
 
-        let expected = [
-            "HTTP/1.1 200 OK",
-            "Content-Length: 257",
-            "Content-Type: text/json; charset=utf-8",
-            "",
-            json.as_str(),
-        ]
-        .join("\r\n");
+        if let Err(e) = Self::save_or_rollback(&mut policies, id, Some(policy.clone()), store, POLICIES_FILE, Policy::all_to_json) {
+            let msg = format!("cannot save policy: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        Message::respond_ok().with_body(Self::policy_to_json(id, "custom", &policy)).write(tcp_stream)
     }
 
-    #[test]
-    fn test_handle_get_datum() {
-        let id = Id::new("my_sensor");
-
-        let mut data = VecDeque::new();
-        let datum1 = Datum::new_now(1.0, Unit::DegreesC);
-        let datum2 = Datum::new_now(2.0, Unit::DegreesC);
-        let datum3 = Datum::new_now(3.0, Unit::DegreesC);
-        data.push_front(datum1.clone());
-        data.push_front(datum2.clone());
-        data.push_front(datum3.clone());
-
-        let mut all_data = HashMap::new();
-        all_data.insert(id.clone(), data);
-        let all_data = Arc::new(Mutex::new(all_data));
-
-        let mut buffer = Vec::new();
+    /// Describes how `DELETE /policies/:id` requests are handled by the `Controller`.
+    ///
+    /// Removes the custom `Policy` for the `Actuator` with this `id`, so that the default `Policy` is used again.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_policy(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        policies: &Arc<Mutex<HashMap<Id, Policy>>>,
+        store: &Store,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // go back to using the default Policy for a particular Actuator
+        //     ex: curl -X DELETE 10.12.50.26:6565/policies/thermo-5000
+
+        let mut policies = policies.lock().unwrap();
+
+        if !policies.contains_key(id) {
+            let msg = format!("no custom policy for id: {}", id);
+            return Self::handler_failure(self_name, tcp_stream, 404, "unknown_policy", msg.as_str());
+        }
 
-        Controller::handle_get_datum(&mut buffer, &all_data);
+        if let Err(e) = Self::save_or_rollback(&mut policies, id, None, store, POLICIES_FILE, Policy::all_to_json) {
+            let msg = format!("cannot save policies: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
 
-        let actual = String::from_utf8(buffer).unwrap();
+        Message::respond_no_content().write(tcp_stream)
+    }
 
-        let json = datum3.to_string();
-        let json = format!(r#"[{{"id":"{}","datum":[{}]}}]"#, id, json);
+    /// Describes how `GET /commands/:id` requests are handled by the `Controller`.
+    ///
+    /// Responds with the most recent `Command`s produced by the `Assessor` for this `id`, newest
+    /// first, and whether each was sent, suppressed by a `Policy`, or failed.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_commands(tcp_stream: &mut impl Write, command_log: &Arc<Mutex<HashMap<Id, VecDeque<Logged>>>>, id: &Id) -> Result<(), Error> {
+        // get the command log for a particular Actuator
+        //     ex: curl 10.12.50.26:6565/commands/thermo-5000
 
-        let expected = [
-            "HTTP/1.1 200 OK",
-            "Content-Length: 106",
-            "Content-Type: text/json; charset=utf-8",
-            "",
-            json.as_str(),
-        ]
-        .join("\r\n");
+        let command_log = command_log.lock().unwrap();
+        let logged: Vec<String> = command_log.get(id).into_iter().flatten().map(Logged::to_json).collect();
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        Message::respond_ok().with_body(format!("[{}]", logged.join(","))).write(tcp_stream)
     }
 
-    #[test]
-    fn test_handle_get_ui() {
+    /// Describes how `PUT /setpoints/:id` requests are handled by the `Controller`.
+    ///
+    /// The `Assessor` for the `Sensor` with this `id` aims for the setpoint in the request body,
+    /// rather than its own setpoint, until the setpoint is deleted. Setpoints are saved so that they
+    /// are still used after a restart.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_put_setpoint(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        setpoints: &Arc<Mutex<HashMap<Id, f32>>>,
+        store: &Store,
+        id: &Id,
+        body: Option<&str>,
+    ) -> Result<(), Error> {
+        // hold a particular Sensor at a particular temperature
+        //     ex: curl -X PUT 10.12.50.26:6565/setpoints/thermo-5000 -d '{"setpoint":21}'
+
+        let setpoint = match setpoints::parse(body.unwrap_or_default()) {
+            Ok(setpoint) => setpoint,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_setpoint", msg.as_str()),
+        };
+
+        let mut setpoints = setpoints.lock().unwrap();
+
+        if let Err(e) = Self::save_or_rollback(&mut setpoints, id, Some(setpoint), store, SETPOINTS_FILE, setpoints::all_to_json) {
+            let msg = format!("cannot save setpoint: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        let body = format!(r#"{{"id":"{}","setpoint":{}}}"#, escape(id.to_string().as_str()), setpoint);
+        Message::respond_ok().with_body(body).write(tcp_stream)
+    }
+
+    /// Describes how `DELETE /setpoints/:id` requests are handled by the `Controller`.
+    ///
+    /// The `Assessor` for the `Sensor` with this `id` aims for its own setpoint again.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_setpoint(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        setpoints: &Arc<Mutex<HashMap<Id, f32>>>,
+        store: &Store,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // go back to using the Assessor's own setpoint for a particular Sensor
+        //     ex: curl -X DELETE 10.12.50.26:6565/setpoints/thermo-5000
+
+        let mut setpoints = setpoints.lock().unwrap();
+
+        if !setpoints.contains_key(id) {
+            let msg = format!("no setpoint for id: {}", id);
+            return Self::handler_failure(self_name, tcp_stream, 404, "unknown_setpoint", msg.as_str());
+        }
+
+        if let Err(e) = Self::save_or_rollback(&mut setpoints, id, None, store, SETPOINTS_FILE, setpoints::all_to_json) {
+            let msg = format!("cannot save setpoints: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        Message::respond_no_content().write(tcp_stream)
+    }
+
+    /// Describes how `POST /overrides/:id` requests are handled by the `Controller`.
+    ///
+    /// The request body may contain a `"command"`, which is sent to the `Actuator` with this `id`,
+    /// and a number of `"seconds"` after which the `Override` expires. Until then, the `Controller`
+    /// does not assess the data from the paired `Sensor`.
+    ///
+    /// The `Override` is started by `start`, so that this method can be tested without an `Actuator`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_post_override(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        id: &Id,
+        body: Option<&str>,
+        start: impl FnOnce(Option<String>, Option<u64>) -> Result<Override, Error>,
+    ) -> Result<(), Error> {
+        // turn off automatic control of a particular Actuator for an hour, after sending it a Command
+        //     ex: curl -X POST 10.12.50.26:6565/overrides/thermo-5000 -d '{"command":{"name":"HeatBy","value":"5"},"seconds":3600}'
+
+        let fields = Value::parse(body.unwrap_or("{}"))
+            .map_err(|e| e.to_string())
+            .and_then(|json| Override::parse_fields(&json));
+
+        let (command, seconds) = match fields {
+            Ok(fields) => fields,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_override", msg.as_str()),
+        };
+
+        match start(command, seconds) {
+            Ok(manual) => {
+                let body = format!(r#"{{"id":"{}","override":{}}}"#, escape(id.to_string().as_str()), manual.to_json());
+                Message::respond_ok().with_body(body).write(tcp_stream)
+            }
+            Err(Error::Parse(msg)) => Self::handler_failure(self_name, tcp_stream, 400, "invalid_override", msg.as_str()),
+            Err(Error::Discovery(msg)) => Self::handler_failure(self_name, tcp_stream, 404, "unknown_actuator", msg.as_str()),
+            Err(e) => Self::handler_failure(self_name, tcp_stream, 502, "command_failed", e.to_string().as_str()),
+        }
+    }
+
+    /// Describes how `DELETE /overrides/:id` requests are handled by the `Controller`.
+    ///
+    /// The `Controller` starts assessing the data from the `Sensor` with this `id` again.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_override(self_name: Name, tcp_stream: &mut impl Write, overrides: &Arc<Mutex<HashMap<Id, Override>>>, id: &Id) -> Result<(), Error> {
+        // go back to automatic control of a particular Actuator
+        //     ex: curl -X DELETE 10.12.50.26:6565/overrides/thermo-5000
+
+        match overrides.lock().unwrap().remove(id) {
+            Some(_) => Message::respond_no_content().write(tcp_stream),
+            None => {
+                let msg = format!("no override for id: {}", id);
+                Self::handler_failure(self_name, tcp_stream, 404, "unknown_override", msg.as_str())
+            }
+        }
+    }
+
+    /// Describes how `GET /schedules/:id` requests are handled by the `Controller`.
+    ///
+    /// Responds with the `Schedule` for the `Sensor` / `Actuator` pair with this `id`, along with the
+    /// setpoint it gives right now.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_schedule(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        schedules: &Arc<Mutex<HashMap<Id, Schedule>>>,
+        clock: &dyn Clock,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // get the Schedule which changes the setpoint of a particular Sensor over time
+        //     ex: curl 10.12.50.26:6565/schedules/thermo-5000
+
+        match schedules.lock().unwrap().get(id) {
+            Some(schedule) => Message::respond_ok()
+                .with_body(Self::schedule_to_json(id, schedule, clock.now()))
+                .write(tcp_stream),
+            None => {
+                let msg = format!("no schedule for id: {}", id);
+                Self::handler_failure(self_name, tcp_stream, 404, "unknown_schedule", msg.as_str())
+            }
+        }
+    }
+
+    /// Describes how `PUT /schedules/:id` requests are handled by the `Controller`.
+    ///
+    /// Replaces the `Schedule` for the `Sensor` / `Actuator` pair with this `id` with the one in the
+    /// request body, and saves it so that it is still followed after a restart.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_put_schedule(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        schedules: &Arc<Mutex<HashMap<Id, Schedule>>>,
+        store: &Store,
+        clock: &dyn Clock,
+        id: &Id,
+        body: Option<&str>,
+    ) -> Result<(), Error> {
+        // change the setpoint of a particular Sensor at different times of the week
+        //     ex: curl -X PUT 10.12.50.26:6565/schedules/thermo-5000 -d '{"weekly":[{"days":["sat","sun"],"at":"09:00","setpoint":20}]}'
+
+        let schedule = match Schedule::parse(body.unwrap_or_default()) {
+            Ok(schedule) => schedule,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_schedule", msg.as_str()),
+        };
+
+        let mut schedules = schedules.lock().unwrap();
+
+        if let Err(e) = Self::save_or_rollback(&mut schedules, id, Some(schedule.clone()), store, SCHEDULES_FILE, Schedule::all_to_json) {
+            let msg = format!("cannot save schedule: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        Message::respond_ok()
+            .with_body(Self::schedule_to_json(id, &schedule, clock.now()))
+            .write(tcp_stream)
+    }
+
+    /// Describes how `DELETE /schedules/:id` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_schedule(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        schedules: &Arc<Mutex<HashMap<Id, Schedule>>>,
+        store: &Store,
+        id: &Id,
+    ) -> Result<(), Error> {
+        // stop changing the setpoint of a particular Sensor over time
+        //     ex: curl -X DELETE 10.12.50.26:6565/schedules/thermo-5000
+
+        let mut schedules = schedules.lock().unwrap();
+
+        if !schedules.contains_key(id) {
+            let msg = format!("no schedule for id: {}", id);
+            return Self::handler_failure(self_name, tcp_stream, 404, "unknown_schedule", msg.as_str());
+        }
+
+        if let Err(e) = Self::save_or_rollback(&mut schedules, id, None, store, SCHEDULES_FILE, Schedule::all_to_json) {
+            let msg = format!("cannot save schedules: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        Message::respond_no_content().write(tcp_stream)
+    }
+
+    /// Serializes the `Schedule` for the pair with this `id`, and the setpoint it gives at time `now`.
+    fn schedule_to_json(id: &Id, schedule: &Schedule, now: DateTime<Utc>) -> String {
+        let setpoint = schedule.setpoint_at(now).map(|setpoint| setpoint.to_string()).unwrap_or(String::from("null"));
+        format!(
+            r#"{{"id":"{}","schedule":{},"setpoint":{}}}"#,
+            escape(id.to_string().as_str()),
+            schedule,
+            setpoint
+        )
+    }
+
+    /// Loads any `Schedule`s which were saved to the `store` before this `Controller` was restarted.
+    fn load_schedules(store: &Store) -> Result<HashMap<Id, Schedule>, Error> {
+        match store.load(SCHEDULES_FILE)? {
+            None => Ok(HashMap::new()),
+            Some(json) => Schedule::all_from_json(json).map_err(Error::Parse),
+        }
+    }
+
+    /// Describes how `GET /scripts` requests are handled by the `Controller`.
+    ///
+    /// Responds with every script which has been loaded from the script directory, and the reason
+    /// why, for any script which cannot be compiled.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_scripts(tcp_stream: &mut impl Write, scripts: &Scripts) -> Result<(), Error> {
+        // list the scripts which can be used by script Assessors
+        //     ex: curl 10.12.50.26:6565/scripts
+
+        Message::respond_ok().with_body(scripts.to_json()).write(tcp_stream)
+    }
+
+    /// Describes how `GET /rules` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_rules(tcp_stream: &mut impl Write, rules: &Arc<Mutex<HashMap<Id, Rule>>>) -> Result<(), Error> {
+        // list every Rule which has been installed
+        //     ex: curl 10.12.50.26:6565/rules
+
+        let rules = Rule::all_to_json(&rules.lock().unwrap());
+        Message::respond_ok().with_body(rules).write(tcp_stream)
+    }
+
+    /// Describes how `GET /rules/:id` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_rule(self_name: Name, tcp_stream: &mut impl Write, rules: &Arc<Mutex<HashMap<Id, Rule>>>, id: &Id) -> Result<(), Error> {
+        // get a particular Rule
+        //     ex: curl 10.12.50.26:6565/rules/heatwave
+
+        match rules.lock().unwrap().get(id) {
+            Some(rule) => Message::respond_ok().with_body(Self::rule_to_json(id, rule)).write(tcp_stream),
+            None => {
+                let msg = format!("no rule with id: {}", id);
+                Self::handler_failure(self_name, tcp_stream, 404, "unknown_rule", msg.as_str())
+            }
+        }
+    }
+
+    /// Describes how `PUT /rules/:id` requests are handled by the `Controller`.
+    ///
+    /// Installs (or replaces) the `Rule` with this `id`, written in the rule language in the request
+    /// body, and saves it so that it is still followed after a restart. A `Rule`'s `id` is its own, and
+    /// need not be the `Id` of any `Sensor` or `Actuator`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_put_rule(
+        self_name: Name,
+        tcp_stream: &mut impl Write,
+        rules: &Arc<Mutex<HashMap<Id, Rule>>>,
+        store: &Store,
+        id: &Id,
+        body: Option<&str>,
+    ) -> Result<(), Error> {
+        // cool the whole building when two zones are too warm
+        //     ex: curl -X PUT 10.12.50.26:6565/rules/heatwave -d 'if zone-a temperature > 27 and zone-b temperature > 27 then CoolBy(3) on hvac-main'
+
+        let rule = match Rule::parse(body.unwrap_or_default()) {
+            Ok(rule) => rule,
+            Err(msg) => return Self::handler_failure(self_name, tcp_stream, 400, "invalid_rule", msg.as_str()),
+        };
+
+        let mut rules = rules.lock().unwrap();
+
+        if let Err(e) = Self::save_or_rollback(&mut rules, id, Some(rule.clone()), store, RULES_FILE, Rule::all_to_json) {
+            let msg = format!("cannot save rule: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        Message::respond_ok().with_body(Self::rule_to_json(id, &rule)).write(tcp_stream)
+    }
+
+    /// Describes how `DELETE /rules/:id` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_delete_rule(self_name: Name, tcp_stream: &mut impl Write, rules: &Arc<Mutex<HashMap<Id, Rule>>>, store: &Store, id: &Id) -> Result<(), Error> {
+        // stop following a particular Rule
+        //     ex: curl -X DELETE 10.12.50.26:6565/rules/heatwave
+
+        let mut rules = rules.lock().unwrap();
+
+        if !rules.contains_key(id) {
+            let msg = format!("no rule with id: {}", id);
+            return Self::handler_failure(self_name, tcp_stream, 404, "unknown_rule", msg.as_str());
+        }
+
+        if let Err(e) = Self::save_or_rollback(&mut rules, id, None, store, RULES_FILE, Rule::all_to_json) {
+            let msg = format!("cannot save rules: {}", e);
+            return Self::handler_failure(self_name, tcp_stream, 500, "storage_failure", msg.as_str());
+        }
+
+        Message::respond_no_content().write(tcp_stream)
+    }
+
+    /// Serializes the `Rule` with this `id`.
+    fn rule_to_json(id: &Id, rule: &Rule) -> String {
+        format!(
+            r#"{{"id":"{}","rule":"{}"}}"#,
+            escape(id.to_string().as_str()),
+            escape(rule.to_string().as_str())
+        )
+    }
+
+    /// Loads any `Rule`s which were saved to the `store` before this `Controller` was restarted.
+    fn load_rules(store: &Store) -> Result<HashMap<Id, Rule>, Error> {
+        match store.load(RULES_FILE)? {
+            None => Ok(HashMap::new()),
+            Some(json) => Rule::all_from_json(json).map_err(Error::Parse),
+        }
+    }
+
+    /// Returns the setpoint which the `Assessor` for the pair with this `id` should aim for at time
+    /// `now`, or `None` if it should aim for its own setpoint.
+    ///
+    /// A setpoint set with `PUT /setpoints/:id` is held, whatever the pair's `Schedule` says.
+    fn setpoint(id: &Id, setpoints: &HashMap<Id, f32>, schedules: &HashMap<Id, Schedule>, now: DateTime<Utc>) -> Option<f32> {
+        setpoints
+            .get(id)
+            .copied()
+            .or_else(|| schedules.get(id).and_then(|schedule| schedule.setpoint_at(now)))
+    }
+
+    /// Loads any setpoints which were saved to the `store` before this `Controller` was restarted.
+    fn load_setpoints(store: &Store) -> Result<HashMap<Id, f32>, Error> {
+        match store.load(SETPOINTS_FILE)? {
+            None => Ok(HashMap::new()),
+            Some(json) => setpoints::all_from_json(json).map_err(Error::Parse),
+        }
+    }
+
+    /// Serializes the `Policy` used for the `Actuator` with this `id`, and whether it is `"custom"` or `"default"`.
+    fn policy_to_json(id: &Id, source: &str, policy: &Policy) -> String {
+        format!(r#"{{"id":"{}","source":"{}","policy":{}}}"#, escape(id.to_string().as_str()), source, policy)
+    }
+
+    /// Loads any custom `Policy`s which were saved to the `store` before this `Controller` was restarted.
+    fn load_policies(store: &Store) -> Result<HashMap<Id, Policy>, Error> {
+        match store.load(POLICIES_FILE)? {
+            None => Ok(HashMap::new()),
+            Some(json) => Policy::all_from_json(json).map_err(Error::Parse),
+        }
+    }
+
+    /// Loads any custom `Assessor`s which were saved to the `store` before this `Controller` was restarted.
+    fn load_assessors(store: &Store) -> Result<HashMap<Id, Assessor>, Error> {
+        match store.load(ASSESSORS_FILE)? {
+            None => Ok(HashMap::new()),
+            Some(json) => Assessor::all_from_json(json).map_err(Error::Parse),
+        }
+    }
+
+    /// Returns an `Inventory` which shares this `Controller`'s view of its `Device`s.
+    fn inventory(&self) -> Inventory {
+        Inventory {
+            sensors: Arc::clone(&self.sensors),
+            data: Arc::clone(&self.data),
+            assessors: Arc::clone(&self.assessors),
+            setpoints: Arc::clone(&self.setpoints),
+            schedules: Arc::clone(&self.schedules),
+            actuators: Arc::clone(&self.actuators),
+            overrides: Arc::clone(&self.overrides),
+            liveness: Arc::clone(&self.liveness),
+            commands: Arc::clone(&self.commands),
+            clock: Arc::clone(&self.clock),
+        }
+    }
+
+    /// Checks that the `Device` described by `info` speaks the same [protocol](PROTOCOL_VERSION) as
+    /// this `Controller`, and responds to the `endpoint` (e.g. `GET /datum`) which is about to be used.
+    ///
+    /// A `Device` which does not advertise its endpoints is assumed to respond to all of them, but a
+    /// `Sensor` which advertises a `Kind` or `Unit` this `Controller` does not know is incompatible.
+    ///
+    /// **Design Decision**: this is checked before every request, rather than once, when a `Device`
+    /// is discovered, because a `Device` which is upgraded in place is discovered again under the
+    /// same name, with new properties.
+    fn check_compatibility(info: &ServiceInfo, endpoint: &str) -> Result<(), String> {
+        match Self::extract_protocol_version(info) {
+            None => return Err(String::from("no protocol version advertised")),
+            Some(Err(e)) => return Err(e.to_string()),
+            Some(Ok(version)) if version != PROTOCOL_VERSION => {
+                return Err(format!("speaks protocol v{}, but this Controller speaks v{}", version, PROTOCOL_VERSION))
+            }
+            Some(Ok(_)) => {}
+        }
+
+        let endpoints = Self::extract_endpoints(info);
+        if !endpoints.is_empty() && !endpoints.iter().any(|e| e == endpoint) {
+            return Err(format!("does not respond to {}", endpoint));
+        }
+
+        if let Some(Err(e)) = Self::extract_kind(info) {
+            return Err(e.to_string());
+        }
+
+        if let Some(Err(e)) = Self::extract_unit(info) {
+            return Err(e.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the `Actuator` described by `info` accepts the `command` (serialized as JSON).
+    ///
+    /// An `Actuator` which does not advertise the names of its `Command`s is assumed to accept all of them.
+    fn check_command(info: &ServiceInfo, command: &str) -> Result<(), String> {
+        let accepted = Self::extract_commands(info);
+        if accepted.is_empty() {
+            return Ok(());
+        }
+
+        let name = Value::parse(command).ok().and_then(|c| c.get("name").and_then(Value::as_str).map(String::from));
+
+        match name {
+            Some(name) if accepted.contains(&name) => Ok(()),
+            Some(name) => Err(format!("does not accept {} Commands, only {}", name, accepted.join(", "))),
+            None => Err(String::from("Command has no name")),
+        }
+    }
+
+    /// Forgets every `Device` which has been offline for longer than its advertised TTL, and starts
+    /// tracking the `Liveness` of every newly-discovered `Device`.
+    ///
+    /// **Design Decision**: this logic has been extracted from [`start`](Self::start) to make it
+    /// easier to test.
+    fn track_liveness(
+        sensors: &mut HashMap<Id, ServiceInfo>,
+        actuators: &mut HashMap<Id, ServiceInfo>,
+        liveness: &mut HashMap<String, Liveness>,
+        now: DateTime<Utc>,
+    ) {
+        let mut known = HashSet::new();
+
+        for devices in [sensors, actuators] {
+            devices.retain(|_, info| {
+                let ttl = chrono::Duration::seconds(info.get_host_ttl() as i64);
+                let expired = liveness.get(info.get_fullname()).is_some_and(|l| l.is_expired(now, ttl));
+                if expired {
+                    warn!(
+                        "[Controller] forgetting {}, which has been offline for more than {}s",
+                        info.get_fullname(),
+                        ttl.num_seconds()
+                    );
+                }
+                !expired
+            });
+
+            for info in devices.values() {
+                known.insert(info.get_fullname().to_string());
+            }
+        }
+
+        // Devices which have left the network (or just expired) are no longer tracked
+        liveness.retain(|fullname, _| known.contains(fullname));
+
+        for fullname in known {
+            liveness.entry(fullname).or_insert_with(|| Liveness::new(now));
+        }
+    }
+
+    /// Returns the latest `Datum` from each `Sensor` which is online, for `Rule`s to be evaluated over.
+    ///
+    /// **Design Decision**: a `Sensor`'s buffer still holds its last `Datum` after the `Sensor` stops
+    /// responding, so `Rule`s are not evaluated over the buffers directly. Otherwise, a `Rule` could keep
+    /// firing on a reading from a `Sensor` which went offline long ago.
+    fn current_readings(sensors: &HashMap<Id, ServiceInfo>, data: &HashMap<Id, VecDeque<Datum>>, liveness: &HashMap<String, Liveness>) -> HashMap<Id, Datum> {
+        sensors
+            .iter()
+            .filter(|(_, info)| liveness.get(info.get_fullname()).is_some_and(|l| l.state() == liveness::State::Online))
+            .filter_map(|(id, _)| Some((id.clone(), data.get(id)?.front()?.clone())))
+            .collect()
+    }
+
+    /// Records whether or not the latest request to the `Device` described by `info` succeeded.
+    fn record_liveness(liveness: &mut HashMap<String, Liveness>, info: &ServiceInfo, succeeded: bool) {
+        if let Some(liveness) = liveness.get_mut(info.get_fullname()) {
+            if succeeded {
+                liveness.succeeded(Utc::now());
+            } else {
+                liveness.failed(Utc::now());
+            }
+        }
+    }
+
+    /// Returns `true` if the `Device` described by `info` should be contacted at time `now`, i.e. it is
+    /// not `Offline`, or it is, but has not been contacted for longer than its backoff.
+    fn is_due(liveness: &HashMap<String, Liveness>, info: &ServiceInfo, now: DateTime<Utc>) -> bool {
+        liveness.get(info.get_fullname()).is_none_or(|liveness| liveness.is_due(now))
+    }
+
+    /// Describes how `GET /datum` requests are handled by the `Controller`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_ui(tcp_stream: &mut impl Write, container_mode: bool, self_address: String) -> Result<(), Error> {
+        let html = include_str!("index.html");
+
+        let html = if container_mode {
+            html.replace("192.168.2.16:6565", "localhost:6565")
+        } else {
+            html.replace("192.168.2.16:6565", self_address.as_str())
+        };
+
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type", "text/html; charset=utf-8");
+
+        let response = Message::respond_ok().with_body(html).with_headers(headers);
+
+        response.write(tcp_stream)
+    }
+
+    /// Starts the `Controller` on a new thread, returning a `DeviceHandle` which can be used to stop it.
+    ///
+    /// The `Controller` finds (and is found by) its peers using the `discovery` `Backend` in its `config`.
+    pub fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String, config: Config) -> DeviceHandle {
+        DeviceHandle::spawn(name.clone(), move |shutdown| {
+            // --------------------------------------------------------------------------------
+            // create Device and discover required Message targets
+            // --------------------------------------------------------------------------------
+
+            let store = Store::new(config.state_dir);
+            let scripts = Scripts::new(config.script_dir);
+            let mut device = Self::new(id, name, config.container_mode, store, scripts, config.clock);
+
+            // every Datum is kept in the configured Storage (if any), which GET /data is then served from
+            // a Controller which cannot open its Storage still controls its Actuators, keeping data only in memory
+            device.storage = match config.storage.open() {
+                Ok(storage) => storage,
+                Err(e) => {
+                    warn!("[Controller] cannot open {:?} storage, keeping data in memory instead: {}", config.storage, e);
+                    None
+                }
+            };
+
+            // ...until it is older than the configured retention period, checked once a minute
+            if let (Some(storage), Some(retention)) = (device.storage.clone(), config.retention) {
+                let pruning = shutdown.clone();
+
+                let prune = move || {
+                    let cutoff = chrono::Duration::from_std(retention)
+                        .ok()
+                        .and_then(|retention| Utc::now().checked_sub_signed(retention));
+                    if let Err(e) = cutoff.map_or(Ok(()), |cutoff| storage.prune(cutoff)) {
+                        error!("[Controller] cannot prune stored data: {}", e)
+                    }
+                };
+
+                shutdown.spawn(move || {
+                    prune();
+                    while pruning.sleep(Duration::from_secs(60)) {
+                        prune();
+                    }
+                });
+            }
+
+            // Assessors which were installed with PUT /assessors/:id before this Controller was restarted
+            match Self::load_assessors(&device.store) {
+                Ok(assessors) => *device.assessors.lock().unwrap() = assessors,
+                Err(e) => error!("[Controller] cannot load saved assessors: {}", e),
+            }
+
+            // ...and setpoints which were set with PUT /setpoints/:id
+            match Self::load_setpoints(&device.store) {
+                Ok(setpoints) => *device.setpoints.lock().unwrap() = setpoints,
+                Err(e) => error!("[Controller] cannot load saved setpoints: {}", e),
+            }
+
+            // ...and Schedules which were installed with PUT /schedules/:id
+            match Self::load_schedules(&device.store) {
+                Ok(schedules) => *device.schedules.lock().unwrap() = schedules,
+                Err(e) => error!("[Controller] cannot load saved schedules: {}", e),
+            }
+
+            // ...and Rules which were installed with PUT /rules/:id
+            match Self::load_rules(&device.store) {
+                Ok(rules) => *device.rules.lock().unwrap() = rules,
+                Err(e) => error!("[Controller] cannot load saved rules: {}", e),
+            }
+
+            // ...and Policies which were installed with PUT /policies/:id
+            match Self::load_policies(&device.store) {
+                Ok(policies) => *device.policies.lock().unwrap() = policies,
+                Err(e) => error!("[Controller] cannot load saved policies: {}", e),
+            }
+
+            // scripts are loaded before any data is assessed, then reloaded every second, so that
+            // new and edited scripts take effect without restarting the Controller
+            if let Err(e) = device.scripts.reload() {
+                error!("[Controller] cannot load scripts: {}", e)
+            }
+
+            let scripts = device.scripts.clone();
+            let reloading = shutdown.clone();
+
+            shutdown.spawn(move || {
+                while reloading.sleep(Duration::from_secs(1)) {
+                    if let Err(e) = scripts.reload() {
+                        error!("[Controller] cannot reload scripts: {}", e)
+                    }
+                }
+            });
+
+            let mut targets = HashMap::new();
+            targets.insert("_sensor", Arc::clone(&device.sensors));
+            targets.insert("_actuator", Arc::clone(&device.actuators));
+
+            let discovery = match config.discovery.connect(&shutdown) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    error!("[Controller] cannot start discovery: {}", e);
+                    return;
+                }
+            };
+
+            for (group, devices) in targets.iter() {
+                device.discover_continually(group, devices, &discovery, &shutdown);
+            }
+            // --------------------------------------------------------------------------------
+            // ping the Sensors at regular intervals to get latest data
+            // --------------------------------------------------------------------------------
+
+            let sleep_duration = Duration::from_millis(50);
+            let buffer_size = 500;
+
+            let sensors = Arc::clone(&device.sensors);
+            let data = Arc::clone(&device.data);
+            let storage = device.storage.clone();
+            let assessors = Arc::clone(&device.assessors);
+            let setpoints = Arc::clone(&device.setpoints);
+            let schedules = Arc::clone(&device.schedules);
+            let clock = Arc::clone(&device.clock);
+            let scripts = device.scripts.clone();
+            let rules = Arc::clone(&device.rules);
+            let policies = Arc::clone(&device.policies);
+            let actuators = Arc::clone(&device.actuators);
+            let overrides = Arc::clone(&device.overrides);
+            let liveness = Arc::clone(&device.liveness);
+            let command_log = Arc::clone(&device.command_log);
+            let events = Arc::clone(&device.events);
+            let polling = shutdown.clone();
+
+            shutdown.spawn(move || {
+                let query = Message::request_get("/datum");
+
+                // Connections to Sensors and Actuators are reused from one iteration of the loop to the next
+                let client = Client::new();
+
+                // Assessors remember each Sensor's previous data from one iteration of the loop to the next
+                let mut assessments = Assessments::new(scripts);
+
+                // ...as do the Throttles which apply each Actuator's Policy
+                let mut throttles: HashMap<Id, Throttle> = HashMap::new();
+
+                // sleep just for a moment so the Sensor has a chance to grab its first Datum from the Environment
+                polling.sleep(Duration::from_millis(100));
+
+                while !polling.is_requested() {
+                    // locks are only held while copying what each step needs, or recording what happened, and never
+                    // while waiting on a Device, the Storage, or a script, so that HTTP requests are not held up by them
+
+                    // find the Sensors which should be polled in this iteration of the loop
+                    let mut polled: Vec<(Id, ServiceInfo, Name, Model)> = Vec::new();
+
+                    {
+                        let mut sensors = sensors.lock().unwrap();
+                        let mut actuators = actuators.lock().unwrap();
+                        overrides.lock().unwrap().retain(|_, manual| manual.is_active(Utc::now()));
+                        let mut liveness = liveness.lock().unwrap();
+
+                        Self::track_liveness(&mut sensors, &mut actuators, &mut liveness, Utc::now());
+                        assessments.retain(|id| sensors.contains_key(id));
+                        throttles.retain(|id, _| actuators.contains_key(id));
+
+                        for (id, info) in sensors.iter() {
+                            let sensor_name = Self::extract_name(info).unwrap_or(Name::new("<unknown>"));
+
+                            let sensor_model = match Self::extract_model(info) {
+                                Some(Ok(model)) => model,
+                                Some(Err(e)) => {
+                                    warn!("[Controller] skipping {}: {}", sensor_name, e);
+                                    continue;
+                                }
+                                None => {
+                                    warn!("[Controller] skipping {}: no model advertised", sensor_name);
+                                    continue;
+                                }
+                            };
+
+                            if let Err(msg) = Self::check_compatibility(info, "GET /datum") {
+                                warn!("[Controller] skipping {}: {}", sensor_name, msg);
+                                continue;
+                            }
+
+                            // an offline Sensor would block this loop until the request times out, so it is polled less and less often
+                            if !Self::is_due(&liveness, info, Utc::now()) {
+                                debug!("[Controller] skipping {}, which is offline", sensor_name);
+                                continue;
+                            }
+
+                            polled.push((id.clone(), info.clone(), sensor_name, sensor_model));
+                        }
+                    }
+
+                    // the Commands produced by Assessors and Rules, for each Actuator, and the reading each was assessed from
+                    let mut commands: Vec<(Id, String, Option<f32>)> = Vec::new();
+
+                    for (id, info, sensor_name, sensor_model) in polled {
+                        debug!("[Controller] querying {} for a Datum", sensor_name);
+
+                        let queried = Self::extract_address(&info).and_then(|address| Self::query_sensor(&client, &address, &query));
+                        Self::record_liveness(&mut liveness.lock().unwrap(), &info, queried.is_ok());
+
+                        let datum = match queried {
+                            Ok(datum) => datum,
+                            Err(e) => {
+                                error!("[Controller] could not get a Datum from {}: {}", sensor_name, e);
+                                continue;
+                            }
+                        };
+
+                        debug!("[Controller] received a Datum from {}: {}", sensor_name, datum);
+
+                        {
+                            let mut data = data.lock().unwrap();
+                            let buffer: &mut VecDeque<Datum> = data.entry(id.clone()).or_default();
+
+                            // enforce buffer length, then save to buffer
+                            if buffer.len() == buffer_size {
+                                buffer.pop_back();
+                            }
+                            buffer.push_front(datum.clone());
+                        }
+
+                        // ...and keep it for longer than the buffer can, if there is a Storage
+                        if let Some(Err(e)) = storage.as_ref().map(|storage| storage.append(&id, &datum)) {
+                            error!("[Controller] cannot store Datum from {}: {}", sensor_name, e);
+                        }
+
+                        events.publish(Event::Datum {
+                            id: id.clone(),
+                            datum: datum.clone(),
+                        });
+
+                        // an operator has taken manual control of this Sensor's Actuator
+                        if overrides.lock().unwrap().contains_key(&id) {
+                            debug!("[Controller] {} is overridden, will not assess Datum", id);
+                            continue;
+                        }
+
+                        // assess new data point and (maybe) produce a Command for the Actuator
+                        let assessor = {
+                            let assessors = assessors.lock().unwrap();
+                            let setpoints = setpoints.lock().unwrap();
+                            let schedules = schedules.lock().unwrap();
+
+                            let assessor = assessors
+                                .get(&id)
+                                .or_else(|| DEFAULT_ASSESSOR.get(sensor_model.to_string().as_str()))
+                                .map(|assessor| match Self::setpoint(&id, &setpoints, &schedules, clock.now()) {
+                                    Some(setpoint) => assessor.with_setpoint(setpoint),
+                                    None => assessor.clone(),
+                                });
+
+                            if assessor.is_none() {
+                                error!("[Controller] assessor does not contain id: {}\nknown ids: {:?}", id, assessors.keys())
+                            }
+
+                            assessor
+                        };
+
+                        if let Some(assessor) = assessor {
+                            match assessments.assess(&id, &assessor, &datum) {
+                                None => debug!("[Controller] assessed Datum, but will not produce Command for Actuator"),
+                                Some(command) => commands.push((id.clone(), command.to_string(), datum.get_as_float())),
+                            }
+                        }
+                    }
+
+                    // Rules are evaluated over the latest data from every online Sensor, and can command any Actuator
+                    let readings = {
+                        let sensors = sensors.lock().unwrap();
+                        let data = data.lock().unwrap();
+                        let liveness = liveness.lock().unwrap();
+                        Self::current_readings(&sensors, &data, &liveness)
+                    };
+
+                    for (rule_id, rule) in rules.lock().unwrap().iter() {
+                        for action in rule.fire(&readings) {
+                            debug!("[Controller] rule {} fired for Actuator with id {}", rule_id, action.actuator);
+                            commands.push((action.actuator.clone(), action.command.clone(), None));
+                        }
+                    }
+
+                    // (maybe) send each Command to its Actuator
+                    for (id, command, reading) in commands {
+                        let now = Utc::now();
+                        let policy = policies.lock().unwrap().get(&id).cloned().unwrap_or_default();
+
+                        let actuator = {
+                            let actuators = actuators.lock().unwrap();
+                            let overrides = overrides.lock().unwrap();
+                            let liveness = liveness.lock().unwrap();
+
+                            // an operator has taken manual control of this Actuator
+                            if overrides.contains_key(&id) {
+                                debug!("[Controller] {} is overridden, will not send Command", id);
+                                continue;
+                            }
+
+                            debug!("[Controller] attempting to send Command to Actuator: {}", command);
+
+                            let Some(actuator) = actuators.get(&id) else {
+                                error!("[Controller] cannot find Actuator with id: {}", id);
+                                continue;
+                            };
+
+                            let compatible =
+                                Self::check_compatibility(actuator, "POST /command").and_then(|()| Self::check_command(actuator, command.as_str()));
+                            if let Err(msg) = compatible {
+                                warn!("[Controller] will not send Command to Actuator with id {}: {}", id, msg);
+                                continue;
+                            }
+
+                            // ...and so is an offline Actuator
+                            if !Self::is_due(&liveness, actuator, now) {
+                                debug!("[Controller] will not send Command to Actuator with id {}, which is offline", id);
+                                continue;
+                            }
+
+                            actuator.clone()
+                        };
+
+                        let throttle = throttles.entry(id.clone()).or_default();
+
+                        if let Err(reason) = throttle.check(&policy, command.as_str(), reading, now) {
+                            debug!("[Controller] will not send Command to Actuator with id {}: {}", id, reason);
+                            let outcome = Outcome::Suppressed(reason);
+                            Logged::record(command_log.lock().unwrap().entry(id).or_default(), Logged { command, at: now, outcome });
+                            continue;
+                        }
+
+                        let message = Message::request_post("/command").with_body(command.clone());
+                        let sent = Self::send_command(&client, &actuator, &message);
+                        Self::record_liveness(&mut liveness.lock().unwrap(), &actuator, sent.is_ok());
+
+                        match sent {
+                            Ok(()) => {
+                                throttle.sent(command.as_str(), reading, now);
+                                let outcome = Outcome::Sent;
+                                Logged::record(
+                                    command_log.lock().unwrap().entry(id.clone()).or_default(),
+                                    Logged {
+                                        command: command.clone(),
+                                        at: now,
+                                        outcome,
+                                    },
+                                );
+                                events.publish(Event::Command { id, command })
+                            }
+                            Err(e) => {
+                                error!("[Controller] could not send Command to Actuator with id {}: {}", id, e);
+                                let outcome = Outcome::Failed(e.to_string());
+                                Logged::record(command_log.lock().unwrap().entry(id).or_default(), Logged { command, at: now, outcome });
+                            }
+                        }
+                    }
+
+                    polling.sleep(sleep_duration);
+                }
+            });
+
+            // the last Command sent to each Actuator is recorded, whether it came from an Assessor or from an operator
+            let sent = device.events.subscribe();
+            let commands = Arc::clone(&device.commands);
+            shutdown.spawn(move || {
+                for event in sent {
+                    if let Event::Command { id, command } = event {
+                        commands.lock().unwrap().insert(id, SentCommand { command, sent: Utc::now() });
+                    }
+                }
+            });
+
+            // clients of GET /stream and GET /ws are waiting on Events, and would otherwise wait for their next heartbeat
+            let events = Arc::clone(&device.events);
+            shutdown.on_shutdown(move || events.close());
+
+            // --------------------------------------------------------------------------------
+            // respond to incoming requests
+            // --------------------------------------------------------------------------------
+
+            if let Err(e) = device.respond(ip, port, group.as_str(), discovery.as_ref(), &shutdown) {
+                error!("[Controller] stopped responding to requests: {}", e)
+            }
+        })
+    }
+
+    /// Sends the `query` to the `Sensor` at the specified `address` and parses its response as a `Datum`.
+    fn query_sensor(client: &Client, address: &Address, query: &Message) -> Result<Datum, Error> {
+        let message = client.send(address, query)?;
+
+        match message.body {
+            None => Err(Error::Protocol(format!("sensor responded without a body: {}", message.start_line))),
+            Some(body) => Datum::parse(body.trim_start_matches('[').trim_end_matches(']')).map_err(Error::Parse),
+        }
+    }
+
+    /// Sends the `command` (serialized as JSON) to the `POST /command` endpoint of the `Actuator`
+    /// with the given `id`, then publishes it to any interested clients as an `Event`.
+    ///
+    /// This is how `Command`s which did not come from an `Assessor` (e.g. manual `Command`s sent by
+    /// operators) reach `Actuator`s.
+    fn forward_command(
+        client: &Client,
+        actuators: &Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+        events: &Broadcaster<Event>,
+        id: &Id,
+        command: &str,
+    ) -> Result<(), Error> {
+        let actuator = actuators
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or(Error::Discovery(format!("cannot find Actuator with id: {}", id)))?;
+
+        Self::check_compatibility(&actuator, "POST /command")
+            .and_then(|()| Self::check_command(&actuator, command))
+            .map_err(|msg| Error::Protocol(format!("Actuator with id {} is incompatible: {}", id, msg)))?;
+
+        let message = Message::request_post("/command").with_body(command);
+        let response = client.send_to(&actuator, &message)?;
+
+        match response.status() {
+            Some(200..=299) => {
+                events.publish(Event::Command {
+                    id: id.clone(),
+                    command: String::from(command),
+                });
+                Ok(())
+            }
+            _ => Err(Error::Protocol(format!("Actuator rejected Command: {}", response.start_line))),
+        }
+    }
+
+    /// Starts an `Override` of the `Sensor` / `Actuator` pair with this `id`, which lasts for `seconds`
+    /// (or until it is cleared), after sending the `command` (if any) to the `Actuator`.
+    ///
+    /// If the `command` cannot be sent, no `Override` is started.
+    fn start_override(
+        client: &Client,
+        actuators: &Arc<Mutex<HashMap<Id, ServiceInfo>>>,
+        overrides: &Arc<Mutex<HashMap<Id, Override>>>,
+        events: &Broadcaster<Event>,
+        id: &Id,
+        command: Option<String>,
+        seconds: Option<u64>,
+    ) -> Result<Override, Error> {
+        // the expiry is worked out before the command is sent, so that an Actuator is never changed without an Override
+        let expires = match seconds {
+            None => None,
+            Some(seconds) => match Override::expires(Utc::now(), seconds) {
+                Some(expires) => Some(expires),
+                None => return Err(Error::Parse(format!("cannot override for {} seconds", seconds))),
+            },
+        };
+
+        match &command {
+            Some(command) => Self::forward_command(client, actuators, events, id, command)?,
+            None if !actuators.lock().unwrap().contains_key(id) => return Err(Error::Discovery(format!("cannot find Actuator with id: {}", id))),
+            None => {}
+        }
+
+        let manual = Override { command, expires };
+        overrides.lock().unwrap().insert(id.clone(), manual.clone());
+        Ok(manual)
+    }
+
+    /// Sends the `command` to the `Actuator` described by `actuator`.
+    ///
+    /// A `command` which the `Actuator` does not accept (with a `2xx` response) has not been sent.
+    fn send_command(client: &Client, actuator: &ServiceInfo, command: &Message) -> Result<(), Error> {
+        debug!("[Controller] sending Command to Actuator {}", actuator.get_fullname());
+        let response = client.send_to(actuator, command)?;
+
+        match response.status() {
+            Some(200..=299) => Ok(()),
+            _ => Err(Error::Protocol(format!("Actuator rejected Command: {}", response.start_line))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod controller_tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::{SocketAddr, TcpStream};
+
+    use chrono::{TimeZone, Utc};
+
+    use datum::unit::Unit;
+
+    use crate::clock::{ManualClock, SystemClock};
+
+    use super::*;
+
+    fn create_controller() -> Controller {
+        Controller::new(
+            Id::new("myId"),
+            Name::new("myName"),
+            false,
+            Store::default(),
+            Scripts::default(),
+            Arc::new(SystemClock),
+        )
+    }
+
+    #[test]
+    fn test_get_name() {
+        let expected = Name::new("myName");
+        let container_mode = false;
+        let controller = Controller::new(
+            Id::new("myId"),
+            expected.clone(),
+            container_mode,
+            Store::default(),
+            Scripts::default(),
+            Arc::new(SystemClock),
+        );
+        let actual = controller.get_name();
+        let expected = &expected;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_get_id() {
+        let expected = Id::new("myId");
+        let container_mode = false;
+        let controller = Controller::new(
+            expected.clone(),
+            Name::new("myName"),
+            container_mode,
+            Store::default(),
+            Scripts::default(),
+            Arc::new(SystemClock),
+        );
+        let actual = controller.get_id();
+        let expected = &expected;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_get_model() {
+        let actual = Controller::get_model();
+        let expected = Model::Controller;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_handle_get_data() {
+        let id = Id::new("my_sensor");
+
+        let mut data = VecDeque::new();
+        let datum1 = Datum::new_now(1.0, Unit::DegreesC);
+        let datum2 = Datum::new_now(2.0, Unit::DegreesC);
+        let datum3 = Datum::new_now(3.0, Unit::DegreesC);
+        data.push_front(datum1.clone());
+        data.push_front(datum2.clone());
+        data.push_front(datum3.clone());
+
+        let mut all_data = HashMap::new();
+        all_data.insert(id.clone(), data);
+        let all_data = Arc::new(Mutex::new(all_data));
+
+        let mut buffer = Vec::new();
+
+        Controller::handle_get_data(&mut buffer, &all_data, &Filter::default(), None).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = [datum3, datum2, datum1].map(|e| e.to_string()).join(",");
+        let json = format!(r#"[{{"id":"{}","data":[{}]}}]"#, id, json);
+
+        // timestamps are serialized with as much precision as the platform clock provides
+        let length = format!("Content-Length: {}", json.len());
+
+        let expected = ["HTTP/1.1 200 OK", length.as_str(), "Content-Type: text/json; charset=utf-8", "", json.as_str()].join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_handle_get_data_filtered() {
+        let t0 = Utc::now();
+        let second = chrono::Duration::seconds(1);
+
+        let mut all_data = HashMap::new();
+
+        for sensor in ["sensor_a", "sensor_b", "sensor_c"] {
+            let mut data = VecDeque::new();
+            for offset in 0..5 {
+                data.push_front(Datum::new(offset as f32, Unit::DegreesC, t0 + second * offset));
+            }
+            all_data.insert(Id::new(sensor), data);
+        }
+
+        let all_data = Arc::new(Mutex::new(all_data));
+
+        let mut buffer = Vec::new();
+
+        let filter = Filter {
+            since: Some(t0),
+            limit: Some(2),
+            offset: 1,
+            ..Filter::default()
+        };
+
+        let ids = [Id::new("sensor_b")];
+
+        Controller::handle_get_data(&mut buffer, &all_data, &filter, Some(&ids)).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = [
+            Datum::new(3.0, Unit::DegreesC, t0 + second * 3),
+            Datum::new(2.0, Unit::DegreesC, t0 + second * 2),
+        ]
+        .map(|e| e.to_string())
+        .join(",");
+        let json = format!(r#"[{{"id":"sensor_b","data":[{}]}}]"#, json);
+
+        let expected = Message::respond_ok().with_body(json);
+
+        assert_eq!(actual, expected.to_string())
+    }
+
+    #[test]
+    fn test_handle_get_export() {
+        let t0 = Utc::now();
+        let second = chrono::Duration::seconds(1);
+
+        let mut all_data = HashMap::new();
+
+        for sensor in ["sensor_a", "sensor_b", "sensor_c"] {
+            let mut data = VecDeque::new();
+            for offset in 0..5 {
+                data.push_front(Datum::new(offset as f32, Unit::DegreesC, t0 + second * offset));
+            }
+            all_data.insert(Id::new(sensor), data);
+        }
+
+        let all_data = Arc::new(Mutex::new(all_data));
+
+        let filter = Filter {
+            since: Some(t0),
+            ..Filter::default()
+        };
+
+        let ids = [Id::new("sensor_a"), Id::new("sensor_c")];
+
+        let mut exported = Vec::new();
+        Controller::handle_get_export(&mut exported, &all_data, &filter, Some(&ids)).unwrap();
+
+        let mut buffered = Vec::new();
+        Controller::handle_get_data(&mut buffered, &all_data, &filter, Some(&ids)).unwrap();
+
+        // the export is chunked, but is otherwise identical to GET /data
+        let exported = String::from_utf8(exported).unwrap();
+        assert!(exported.contains("Transfer-Encoding: chunked"));
+
+        let mut exported = exported.as_bytes();
+        let actual = Message::read_next(&mut exported).unwrap().unwrap();
+
+        let mut buffered = buffered.as_slice();
+        let expected = Message::read_next(&mut buffered).unwrap().unwrap();
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_handle_get_stored_data() {
+        let t0 = Utc::now();
+        let second = chrono::Duration::seconds(1);
+
+        let dir = std::env::temp_dir().join(format!("controller-stored-data-{}", std::process::id()));
+        let storage = crate::segments::Segments::open(dir.clone()).unwrap();
+
+        let mut all_data = HashMap::new();
+
+        for sensor in ["sensor_a", "sensor_b", "sensor_c"] {
+            let mut data = VecDeque::new();
+            for offset in 0..5 {
+                let datum = Datum::new(offset as f32, Unit::DegreesC, t0 + second * offset);
+                storage.append(&Id::new(sensor), &datum).unwrap();
+                data.push_front(datum);
+            }
+            all_data.insert(Id::new(sensor), data);
+        }
+
+        let all_data = Arc::new(Mutex::new(all_data));
+
+        let filter = Filter {
+            since: Some(t0),
+            limit: Some(2),
+            offset: 1,
+            ..Filter::default()
+        };
+
+        let ids = [Id::new("sensor_b")];
+
+        let mut stored = Vec::new();
+        Controller::handle_get_stored_data(Name::new("myName"), &mut stored, &storage, &filter, Some(&ids)).unwrap();
+
+        let mut buffered = Vec::new();
+        Controller::handle_get_data(&mut buffered, &all_data, &filter, Some(&ids)).unwrap();
+
+        // while the buffers still hold every Datum, GET /data returns the same data from either
+        assert_eq!(String::from_utf8(stored).unwrap(), String::from_utf8(buffered).unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn service_info(group: &str, id: &str, model: &str) -> ServiceInfo {
+        let mut properties = HashMap::new();
+        properties.insert("id".to_string(), id.to_string());
+        properties.insert("name".to_string(), format!("My {}", id));
+        properties.insert("model".to_string(), model.to_string());
+        properties.insert("protocol".to_string(), PROTOCOL_VERSION.to_string());
+
+        let domain = format!("{}._tcp.local.", group);
+        let name = format!("{}.{}", id, model);
+        ServiceInfo::new(domain.as_str(), name.as_str(), "localhost", "127.0.0.1", 10101, properties).unwrap()
+    }
+
+    #[test]
+    fn test_send_command_rejected() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            for code in [202, 503] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                Message::read_next(&mut reader).unwrap();
+                Message::respond(code)
+                    .with_headers(HashMap::from([("Connection", "close")]))
+                    .write(&mut stream)
+                    .unwrap();
+            }
+        });
+
+        let mut properties = HashMap::new();
+        properties.insert("id".to_string(), "thermo-5000".to_string());
+        let actuator = ServiceInfo::new("_actuator._tcp.local.", "thermo-5000", "localhost", "127.0.0.1", port, properties).unwrap();
+
+        let client = Client::new();
+        let command = Message::request_post("/command").with_body(r#"{"name":"HeatBy","value":"5"}"#);
+
+        assert_eq!(Controller::send_command(&client, &actuator, &command), Ok(()));
+
+        // an Actuator which responds with an error has not accepted the Command
+        let expected = Err(Error::Protocol(String::from("Actuator rejected Command: HTTP/1.1 503 Service Unavailable")));
+        assert_eq!(Controller::send_command(&client, &actuator, &command), expected);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_track_liveness() {
+        let now = Utc::now();
+        let ttl = chrono::Duration::seconds(120);
+
+        let pulled = service_info("_sensor", "pulled", "thermo5000");
+        let flaky = service_info("_sensor", "flaky", "thermo5000");
+        let idle = service_info("_actuator", "pulled", "thermo5000");
+
+        let mut sensors = HashMap::from([(Id::new("pulled"), pulled.clone()), (Id::new("flaky"), flaky.clone())]);
+        let mut actuators = HashMap::from([(Id::new("pulled"), idle.clone())]);
+        let mut liveness = HashMap::new();
+
+        // newly-discovered Devices are tracked
+        Controller::track_liveness(&mut sensors, &mut actuators, &mut liveness, now);
+        assert_eq!(liveness.len(), 3);
+
+        // one Sensor stops responding entirely, the other only fails once
+        (0..liveness::OFFLINE_AFTER).for_each(|_| Controller::record_liveness(&mut liveness, &pulled, false));
+        Controller::record_liveness(&mut liveness, &flaky, false);
+
+        assert_eq!(liveness[pulled.get_fullname()].state(), liveness::State::Offline);
+        assert_eq!(liveness[flaky.get_fullname()].state(), liveness::State::Degraded);
+
+        // the offline Sensor is not polled again until its backoff has passed, but the degraded one is
+        assert!(!Controller::is_due(&liveness, &pulled, Utc::now()));
+        assert!(Controller::is_due(&liveness, &flaky, Utc::now()));
+
+        // only the offline Sensor is forgotten, once its TTL has passed
+        Controller::track_liveness(&mut sensors, &mut actuators, &mut liveness, now + ttl + chrono::Duration::seconds(1));
+
+        assert_eq!(sensors.keys().collect::<Vec<_>>(), vec![&Id::new("flaky")]);
+        assert!(actuators.contains_key(&Id::new("pulled")));
+        assert!(!liveness.contains_key(pulled.get_fullname()));
+
+        // Devices which were removed via discovery are no longer tracked
+        actuators.clear();
+        Controller::track_liveness(&mut sensors, &mut actuators, &mut liveness, now);
+        assert_eq!(liveness.keys().collect::<Vec<_>>(), vec![flaky.get_fullname()]);
+    }
+
+    #[test]
+    fn test_current_readings() {
+        let now = Utc::now();
+
+        let online = service_info("_sensor", "online", "thermo5000");
+        let flaky = service_info("_sensor", "flaky", "thermo5000");
+        let new = service_info("_sensor", "new", "thermo5000");
+
+        let mut sensors = HashMap::from([
+            (Id::new("online"), online.clone()),
+            (Id::new("flaky"), flaky.clone()),
+            (Id::new("new"), new.clone()),
+        ]);
+        let mut liveness = HashMap::new();
+        Controller::track_liveness(&mut sensors, &mut HashMap::new(), &mut liveness, now);
+
+        let latest = Datum::new(30.0, Unit::DegreesC, now);
+        let older = Datum::new(20.0, Unit::DegreesC, now - chrono::Duration::seconds(1));
+
+        // a Sensor which was forgotten (e.g. it expired) can leave its data behind, too
+        let data = HashMap::from([
+            (Id::new("online"), VecDeque::from([latest.clone(), older.clone()])),
+            (Id::new("flaky"), VecDeque::from([latest.clone()])),
+            (Id::new("forgotten"), VecDeque::from([latest.clone()])),
+        ]);
+
+        let readings = Controller::current_readings(&sensors, &data, &liveness);
+        assert_eq!(
+            readings,
+            HashMap::from([(Id::new("online"), latest.clone()), (Id::new("flaky"), latest.clone())])
+        );
+
+        // once a Sensor stops responding, its last reading is no longer current, and Rules which depend on it stop firing
+        Controller::record_liveness(&mut liveness, &flaky, false);
+
+        let readings = Controller::current_readings(&sensors, &data, &liveness);
+        assert_eq!(readings, HashMap::from([(Id::new("online"), latest)]));
+
+        let rule = Rule::parse("if flaky > 25 then CoolBy(1) on flaky").unwrap();
+        assert!(rule.fire(&readings).is_empty());
+    }
+
+    #[test]
+    fn test_handle_get_devices() {
+        let sensor = service_info("_sensor", "my_id", "thermo5000");
+        let actuator = service_info("_actuator", "my_id", "thermo5000");
+
+        let last_seen = Utc::now();
+        let mut degraded = Liveness::new(last_seen);
+        degraded.failed(last_seen);
+
+        let controller = create_controller();
+        controller.sensors.lock().unwrap().insert(Id::new("my_id"), sensor.clone());
+        controller.actuators.lock().unwrap().insert(Id::new("my_id"), actuator);
+        controller.liveness.lock().unwrap().insert(sensor.get_fullname().to_string(), degraded);
+
+        let mut buffer = Vec::new();
+        Controller::handle_get_devices(&mut buffer, &controller.inventory()).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        // the Actuator has not been contacted yet
+        let json = [
+            r#"{"id":"my_id","kind":"actuator","name":"My my_id","model":"thermo5000","address":"127.0.0.1:10101","state":"online","last_seen":null,"protocol":1,"compatible":true}"#
+                .to_string(),
+            format!(
+                r#"{{"id":"my_id","kind":"sensor","name":"My my_id","model":"thermo5000","address":"127.0.0.1:10101","state":"degraded","last_seen":"{}","protocol":1,"compatible":true}}"#,
+                last_seen.to_rfc3339()
+            ),
+        ]
+        .join(",");
+
+        let expected = Message::respond_ok().with_body(format!("[{}]", json));
+
+        assert_eq!(actual, expected.to_string())
+    }
+
+    #[test]
+    fn test_handle_get_device() {
+        let controller = create_controller();
+        let actuator = service_info("_actuator", "my_id", "thermo5000");
+        controller.actuators.lock().unwrap().insert(Id::new("my_id"), actuator);
+
+        let mut buffer = Vec::new();
+        Controller::handle_get_device(Name::new("my_name"), &mut buffer, &controller.inventory(), &Id::new("my_id")).unwrap();
+        let actual = Message::read_next(&mut buffer.as_slice()).unwrap().unwrap();
+        assert_eq!(actual.status(), Some(200));
+        assert!(actual.body.unwrap().contains(r#""paired":false,"issues":["no sensor"],"sensor":null"#));
+
+        let mut buffer = Vec::new();
+        Controller::handle_get_device(Name::new("my_name"), &mut buffer, &controller.inventory(), &Id::new("other_id")).unwrap();
+        let actual = Message::read_next(&mut buffer.as_slice()).unwrap().unwrap();
+        assert_eq!(actual.status(), Some(404));
+    }
+
+    fn respond(handle: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>) -> Message {
+        let mut buffer = Vec::new();
+        handle(&mut buffer).unwrap();
+        Message::read_next(&mut buffer.as_slice()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_handle_assessors() {
+        let dir = std::env::temp_dir().join(format!("controller-assessors-{}", std::process::id()));
+        let store = Store::new(Some(dir.clone()));
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+
+        let sensors = Arc::new(Mutex::new(HashMap::from([(id.clone(), service_info("_sensor", "my_id", "thermo5000"))])));
+        let assessors = Arc::new(Mutex::new(HashMap::new()));
+
+        // before a custom Assessor is installed, the default Assessor for the Sensor's Model is used
+        let actual = respond(|stream| Controller::handle_get_assessor(name.clone(), stream, &sensors, &assessors, &id));
+        let expected = r#"{"id":"my_id","source":"default","assessor":{"type":"thermostat","setpoint":25,"low":22,"high":28}}"#;
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        let body = r#"{"type":"thermostat","setpoint":21,"low":19,"high":23}"#;
+        let actual = respond(|stream| Controller::handle_put_assessor(name.clone(), stream, &assessors, &store, &id, Some(body)));
+        let expected = r#"{"id":"my_id","source":"custom","assessor":{"type":"thermostat","setpoint":21,"low":19,"high":23}}"#;
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        let actual = respond(|stream| Controller::handle_get_assessor(name.clone(), stream, &sensors, &assessors, &id));
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        // the custom Assessor is remembered by the next Controller which uses the same state directory
+        let loaded = Controller::load_assessors(&store).unwrap();
+        assert_eq!(&loaded, &*assessors.lock().unwrap());
+
+        let actual = respond(|stream| Controller::handle_get_assessors(stream, &assessors));
+        assert_eq!(actual.body, Some(Assessor::all_to_json(&loaded)));
+
+        let actual = respond(|stream| Controller::handle_delete_assessor(name.clone(), stream, &assessors, &store, &id));
+        assert_eq!(actual.status(), Some(204));
+        assert!(Controller::load_assessors(&store).unwrap().is_empty());
+
+        let actual = respond(|stream| Controller::handle_delete_assessor(name.clone(), stream, &assessors, &store, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_handle_put_assessor_failure() {
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+        let assessors = Arc::new(Mutex::new(HashMap::new()));
+
+        let body = r#"{"type":"thermostat","setpoint":30,"low":19,"high":23}"#;
+        let actual = respond(|stream| Controller::handle_put_assessor(name.clone(), stream, &assessors, &Store::default(), &id, Some(body)));
+        assert_eq!(actual.status(), Some(400));
+
+        let actual = respond(|stream| Controller::handle_put_assessor(name.clone(), stream, &assessors, &Store::default(), &id, None));
+        assert_eq!(actual.status(), Some(400));
+        assert!(assessors.lock().unwrap().is_empty());
+
+        // a Sensor with no Model has no default Assessor
+        let sensors = Arc::new(Mutex::new(HashMap::new()));
+        let actual = respond(|stream| Controller::handle_get_assessor(name.clone(), stream, &sensors, &assessors, &id));
+        assert_eq!(actual.status(), Some(404));
+    }
+
+    #[test]
+    fn test_save_or_rollback() {
+        // a state directory which is really a file cannot be saved to
+        let file = std::env::temp_dir().join(format!("controller-rollback-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let store = Store::new(Some(file.clone()));
+
+        let id = Id::new("my_id");
+        let other = Id::new("other_id");
+        let mut setpoints = HashMap::from([(id.clone(), 21.0)]);
+
+        // a value which cannot be saved is not set...
+        assert!(Controller::save_or_rollback(&mut setpoints, &id, Some(25.0), &store, SETPOINTS_FILE, setpoints::all_to_json).is_err());
+        assert_eq!(setpoints, HashMap::from([(id.clone(), 21.0)]));
+
+        assert!(Controller::save_or_rollback(&mut setpoints, &other, Some(25.0), &store, SETPOINTS_FILE, setpoints::all_to_json).is_err());
+        assert_eq!(setpoints, HashMap::from([(id.clone(), 21.0)]));
+
+        // ...and a value which cannot be saved without is not removed
+        assert!(Controller::save_or_rollback(&mut setpoints, &id, None, &store, SETPOINTS_FILE, setpoints::all_to_json).is_err());
+        assert_eq!(setpoints, HashMap::from([(id.clone(), 21.0)]));
+
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_handle_policies() {
+        let dir = std::env::temp_dir().join(format!("controller-policies-{}", std::process::id()));
+        let store = Store::new(Some(dir.clone()));
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+        let policies = Arc::new(Mutex::new(HashMap::new()));
+
+        // before a custom Policy is installed, the default Policy is used
+        let actual = respond(|stream| Controller::handle_get_policy(stream, &policies, &id));
+        let expected = r#"{"id":"my_id","source":"default","policy":{"deadband":0,"hysteresis":0,"min_interval_ms":1000,"max_per_minute":null,"duplicate_window_ms":5000}}"#;
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        let body = r#"{"hysteresis":0.5,"max_per_minute":6}"#;
+        let actual = respond(|stream| Controller::handle_put_policy(name.clone(), stream, &policies, &store, &id, Some(body)));
+        let expected =
+            r#"{"id":"my_id","source":"custom","policy":{"deadband":0,"hysteresis":0.5,"min_interval_ms":1000,"max_per_minute":6,"duplicate_window_ms":5000}}"#;
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        let actual = respond(|stream| Controller::handle_get_policy(stream, &policies, &id));
+        assert_eq!(actual.body.as_deref(), Some(expected));
+
+        // the custom Policy is remembered by the next Controller which uses the same state directory
+        let loaded = Controller::load_policies(&store).unwrap();
+        assert_eq!(&loaded, &*policies.lock().unwrap());
+
+        let actual = respond(|stream| Controller::handle_get_policies(stream, &policies));
+        assert_eq!(actual.body, Some(Policy::all_to_json(&loaded)));
+
+        let actual = respond(|stream| Controller::handle_put_policy(name.clone(), stream, &policies, &store, &id, Some(r#"{"deadband":-1}"#)));
+        assert_eq!(actual.status(), Some(400));
+
+        let actual = respond(|stream| Controller::handle_delete_policy(name.clone(), stream, &policies, &store, &id));
+        assert_eq!(actual.status(), Some(204));
+        assert!(Controller::load_policies(&store).unwrap().is_empty());
+
+        let actual = respond(|stream| Controller::handle_delete_policy(name.clone(), stream, &policies, &store, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_handle_get_commands() {
+        let id = Id::new("my_id");
+        let command_log = Arc::new(Mutex::new(HashMap::new()));
+
+        let actual = respond(|stream| Controller::handle_get_commands(stream, &command_log, &id));
+        assert_eq!(actual.body.as_deref(), Some("[]"));
+
+        let at = Utc::now();
+        let command = String::from(r#"{"name":"HeatBy","value":"4"}"#);
+        let sent = Logged {
+            command: command.clone(),
+            at,
+            outcome: Outcome::Sent,
+        };
+        let suppressed = Logged {
+            command,
+            at,
+            outcome: Outcome::Suppressed(policy::Reason::Duplicate),
+        };
+
+        let mut log = VecDeque::new();
+        Logged::record(&mut log, sent.clone());
+        Logged::record(&mut log, suppressed.clone());
+        command_log.lock().unwrap().insert(id.clone(), log);
+
+        // the newest Command is listed first
+        let actual = respond(|stream| Controller::handle_get_commands(stream, &command_log, &id));
+        let expected = format!("[{},{}]", suppressed.to_json(), sent.to_json());
+        assert_eq!(actual.body, Some(expected));
+    }
+
+    #[test]
+    fn test_handle_setpoints() {
+        let dir = std::env::temp_dir().join(format!("controller-setpoints-{}", std::process::id()));
+        let store = Store::new(Some(dir.clone()));
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+        let setpoints = Arc::new(Mutex::new(HashMap::new()));
+
+        let actual = respond(|stream| Controller::handle_put_setpoint(name.clone(), stream, &setpoints, &store, &id, Some(r#"{"setpoint":21}"#)));
+        assert_eq!(actual.body.as_deref(), Some(r#"{"id":"my_id","setpoint":21}"#));
+
+        // the setpoint is remembered by the next Controller which uses the same state directory
+        assert_eq!(Controller::load_setpoints(&store).unwrap(), HashMap::from([(id.clone(), 21.0)]));
+
+        let actual = respond(|stream| Controller::handle_put_setpoint(name.clone(), stream, &setpoints, &store, &id, Some(r#"{"setpoint":"warm"}"#)));
+        assert_eq!(actual.status(), Some(400));
+        assert_eq!(setpoints.lock().unwrap().get(&id), Some(&21.0));
+
+        let actual = respond(|stream| Controller::handle_delete_setpoint(name.clone(), stream, &setpoints, &store, &id));
+        assert_eq!(actual.status(), Some(204));
+        assert!(Controller::load_setpoints(&store).unwrap().is_empty());
+
+        let actual = respond(|stream| Controller::handle_delete_setpoint(name.clone(), stream, &setpoints, &store, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_handle_schedules() {
+        let dir = std::env::temp_dir().join(format!("controller-schedules-{}", std::process::id()));
+        let store = Store::new(Some(dir.clone()));
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+        let schedules = Arc::new(Mutex::new(HashMap::new()));
+
+        // Friday 2024-01-05, at noon
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap());
+
+        let actual = respond(|stream| Controller::handle_get_schedule(name.clone(), stream, &schedules, &clock, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        let body = r#"{"weekly":[{"days":["mon","tue","wed","thu","fri"],"at":"08:00","setpoint":21},{"days":["sat","sun"],"at":"10:00","setpoint":18}]}"#;
+        let actual = respond(|stream| Controller::handle_put_schedule(name.clone(), stream, &schedules, &store, &clock, &id, Some(body)));
+        let schedule = r#"{"offset":"+00:00","weekly":[{"days":["mon","tue","wed","thu","fri"],"at":"08:00","setpoint":21},{"days":["sat","sun"],"at":"10:00","setpoint":18}],"exceptions":[]}"#;
+        assert_eq!(actual.body, Some(format!(r#"{{"id":"my_id","schedule":{},"setpoint":21}}"#, schedule)));
+
+        // the setpoint changes when the weekend begins
+        clock.advance(chrono::Duration::days(1));
+        let actual = respond(|stream| Controller::handle_get_schedule(name.clone(), stream, &schedules, &clock, &id));
+        assert_eq!(actual.body, Some(format!(r#"{{"id":"my_id","schedule":{},"setpoint":18}}"#, schedule)));
+
+        // the Schedule is remembered by the next Controller which uses the same state directory
+        assert_eq!(&Controller::load_schedules(&store).unwrap(), &*schedules.lock().unwrap());
+
+        let actual = respond(|stream| Controller::handle_put_schedule(name.clone(), stream, &schedules, &store, &clock, &id, Some(r#"{"weekly":{}}"#)));
+        assert_eq!(actual.status(), Some(400));
+
+        let actual = respond(|stream| Controller::handle_delete_schedule(name.clone(), stream, &schedules, &store, &id));
+        assert_eq!(actual.status(), Some(204));
+        assert!(Controller::load_schedules(&store).unwrap().is_empty());
+
+        let actual = respond(|stream| Controller::handle_delete_schedule(name.clone(), stream, &schedules, &store, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_setpoint() {
+        let id = Id::new("my_id");
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap());
+
+        let json = r#"{"weekly":[{"days":["fri"],"at":"18:00","setpoint":16}],"exceptions":[{"from":"2024-01-05T13:00:00Z","until":"2024-01-05T14:00:00Z","setpoint":12}]}"#;
+        let schedules = HashMap::from([(id.clone(), Schedule::parse(json).unwrap())]);
+        let mut setpoints = HashMap::new();
+
+        // a weekly Schedule with a single Change gives the same setpoint all week...
+        assert_eq!(Controller::setpoint(&id, &setpoints, &schedules, clock.now()), Some(16.0));
+
+        // ...except during an Exception
+        clock.advance(chrono::Duration::hours(1));
+        assert_eq!(Controller::setpoint(&id, &setpoints, &schedules, clock.now()), Some(12.0));
+
+        // a setpoint which has been set explicitly is held, whatever the Schedule says
+        setpoints.insert(id.clone(), 20.0);
+        assert_eq!(Controller::setpoint(&id, &setpoints, &schedules, clock.now()), Some(20.0));
+
+        assert_eq!(Controller::setpoint(&Id::new("other"), &setpoints, &schedules, clock.now()), None);
+    }
+
+    #[test]
+    fn test_handle_get_scripts() {
+        let dir = std::env::temp_dir().join(format!("controller-scripts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.rhai"), "fn assess(").unwrap();
+        std::fs::write(dir.join("idle.rhai"), "fn assess(datum, history, pair) { () }").unwrap();
+
+        let scripts = Scripts::new(Some(dir.clone()));
+        scripts.reload().unwrap();
+
+        let actual = respond(|stream| Controller::handle_get_scripts(stream, &scripts));
+        let body = actual.body.unwrap();
+        assert!(body.starts_with(r#"[{"name":"broken","error":""#), "{}", body);
+        assert!(body.ends_with(r#"{"name":"idle","error":null}]"#), "{}", body);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_handle_rules() {
+        let dir = std::env::temp_dir().join(format!("controller-rules-{}", std::process::id()));
+        let store = Store::new(Some(dir.clone()));
+        let name = Name::new("myName");
+        let id = Id::new("heatwave");
+        let rules = Arc::new(Mutex::new(HashMap::new()));
+
+        let actual = respond(|stream| Controller::handle_get_rule(name.clone(), stream, &rules, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        let body = "if zone-a temperature > 27 and zone-b temperature > 27 then CoolBy(3) on hvac-main";
+        let actual = respond(|stream| Controller::handle_put_rule(name.clone(), stream, &rules, &store, &id, Some(body)));
+        let expected = format!(r#"{{"id":"heatwave","rule":"{}"}}"#, body);
+        assert_eq!(actual.body, Some(expected.clone()));
+
+        let actual = respond(|stream| Controller::handle_get_rule(name.clone(), stream, &rules, &id));
+        assert_eq!(actual.body, Some(expected.clone()));
+
+        let actual = respond(|stream| Controller::handle_get_rules(stream, &rules));
+        assert_eq!(actual.body, Some(format!("[{}]", expected)));
+
+        // the Rule is remembered by the next Controller which uses the same state directory
+        let loaded = Controller::load_rules(&store).unwrap();
+        assert_eq!(&loaded, &*rules.lock().unwrap());
+
+        let actual =
+            respond(|stream| Controller::handle_put_rule(name.clone(), stream, &rules, &store, &id, Some("if zone-a > hot then CoolBy(3) on hvac-main")));
+        assert_eq!(actual.status(), Some(400));
+
+        let actual = respond(|stream| Controller::handle_delete_rule(name.clone(), stream, &rules, &store, &id));
+        assert_eq!(actual.status(), Some(204));
+        assert!(Controller::load_rules(&store).unwrap().is_empty());
+
+        let actual = respond(|stream| Controller::handle_delete_rule(name.clone(), stream, &rules, &store, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_handle_overrides() {
+        let name = Name::new("myName");
+        let id = Id::new("my_id");
+        let overrides = Arc::new(Mutex::new(HashMap::new()));
+
+        // an Override without a Command pauses automatic control, without sending anything to the Actuator
+        let body = r#"{"seconds":3600}"#;
+        let actual = respond(|stream| {
+            Controller::handle_post_override(name.clone(), stream, &id, Some(body), |command, seconds| {
+                assert_eq!((command, seconds), (None, Some(3600)));
+                let manual = Override { command: None, expires: None };
+                overrides.lock().unwrap().insert(id.clone(), manual.clone());
+                Ok(manual)
+            })
+        });
+        assert_eq!(actual.body.as_deref(), Some(r#"{"id":"my_id","override":{"command":null,"expires":null}}"#));
+
+        let actual = respond(|stream| Controller::handle_delete_override(name.clone(), stream, &overrides, &id));
+        assert_eq!(actual.status(), Some(204));
+
+        let actual = respond(|stream| Controller::handle_delete_override(name.clone(), stream, &overrides, &id));
+        assert_eq!(actual.status(), Some(404));
+
+        let actual = respond(|stream| Controller::handle_post_override(name.clone(), stream, &id, Some(r#"{"command":"HeatBy"}"#), |_, _| unreachable!()));
+        assert_eq!(actual.status(), Some(400));
+
+        // an Override cannot be started for an Actuator which has not been discovered
+        let actuators = Arc::new(Mutex::new(HashMap::new()));
+        let actual = respond(|stream| {
+            Controller::handle_post_override(name.clone(), stream, &id, None, |command, seconds| {
+                Controller::start_override(&Client::new(), &actuators, &overrides, &Broadcaster::default(), &id, command, seconds)
+            })
+        });
+        assert_eq!(actual.status(), Some(404));
+        assert!(overrides.lock().unwrap().is_empty());
+
+        // ...nor for longer than can be represented, which is rejected before any Command is sent
+        let body = r#"{"command":{"name":"HeatBy","value":"5"},"seconds":1e300}"#;
+        let actual = respond(|stream| Controller::handle_post_override(name.clone(), stream, &id, Some(body), |_, _| unreachable!()));
+        assert_eq!(actual.status(), Some(400));
+
+        let command = Some(String::from(r#"{"name":"HeatBy","value":"5"}"#));
+        let actual = Controller::start_override(&Client::new(), &actuators, &overrides, &Broadcaster::default(), &id, command, Some(u64::MAX));
+        assert_eq!(actual, Err(Error::Parse(format!("cannot override for {} seconds", u64::MAX))));
+        assert!(overrides.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_compatibility() {
+        let sensor = service_info("_sensor", "my_id", "thermo5000");
+        assert_eq!(Controller::check_compatibility(&sensor, "GET /datum"), Ok(()));
+
+        let mut properties = HashMap::new();
+        properties.insert(String::from("endpoints"), String::from("GET /data"));
+        properties.insert(String::from("unit"), String::from("°F"));
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let info = device::discovery::service_info("_sensor", &Id::new("my_id"), &Name::new("my_name"), Model::Thermo5000, ip, 10101, properties).unwrap();
+
+        assert_eq!(
+            Controller::check_compatibility(&info, "GET /datum"),
+            Err(String::from("does not respond to GET /datum"))
+        );
+        assert_eq!(
+            Controller::check_compatibility(&info, "GET /data"),
+            Err(String::from("parse error: cannot parse '°F' as a Unit"))
+        );
+
+        // a Device from before protocol versions were advertised
+        let mut old = HashMap::new();
+        old.insert(String::from("id"), String::from("my_id"));
+        let old = ServiceInfo::new("_sensor._tcp.local.", "my_id", "localhost", "127.0.0.1", 10101, old).unwrap();
+        assert_eq!(
+            Controller::check_compatibility(&old, "GET /datum"),
+            Err(String::from("no protocol version advertised"))
+        );
+
+        let mut newer = HashMap::new();
+        newer.insert(String::from("protocol"), (PROTOCOL_VERSION + 1).to_string());
+        let newer = ServiceInfo::new("_sensor._tcp.local.", "my_id", "localhost", "127.0.0.1", 10101, newer).unwrap();
+        assert!(Controller::check_compatibility(&newer, "GET /datum")
+            .unwrap_err()
+            .starts_with("speaks protocol v2"));
+    }
+
+    #[test]
+    fn test_check_command() {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("commands"), String::from("CoolBy,HeatBy"));
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let info = device::discovery::service_info("_actuator", &Id::new("my_id"), &Name::new("my_name"), Model::Thermo5000, ip, 10101, properties).unwrap();
+
+        assert_eq!(Controller::check_command(&info, r#"{"name":"HeatBy","value":"1.5"}"#), Ok(()));
+        assert_eq!(
+            Controller::check_command(&info, r#"{"name":"Dehumidify","value":"1.5"}"#),
+            Err(String::from("does not accept Dehumidify Commands, only CoolBy, HeatBy"))
+        );
+        assert_eq!(Controller::check_command(&info, "not json"), Err(String::from("Command has no name")));
+
+        // an Actuator which does not advertise its Commands is sent any of them
+        let info = service_info("_actuator", "my_id", "thermo5000");
+        assert_eq!(Controller::check_command(&info, r#"{"name":"Dehumidify","value":"1.5"}"#), Ok(()));
+    }
+
+    /// Starts a `Controller` on a free port, which finds no other `Device`s.
+    fn start_controller() -> (DeviceHandle, Address) {
+        let config = Config::new(device::discovery::Backend::Memory(device::discovery::MemoryRegistry::new()));
+        let handle = Controller::start(
+            IpAddr::from([127, 0, 0, 1]),
+            0,
+            Id::new("myId"),
+            Name::new("myName"),
+            String::from("_controller"),
+            config,
+        );
+        let address = handle.address().unwrap();
+        (handle, address)
+    }
+
+    #[test]
+    fn test_stream_limit() {
+        let (handle, address) = start_controller();
+
+        // every streaming client holds on to a worker for as long as it is connected...
+        let streams: Vec<BufReader<TcpStream>> = (0..MAX_STREAMS)
+            .map(|_| {
+                let mut stream = TcpStream::connect(SocketAddr::from(address)).unwrap();
+                Message::request_get("/stream").write(&mut stream).unwrap();
+
+                let mut reader = BufReader::new(stream);
+                let mut status = String::new();
+                reader.read_line(&mut status).unwrap();
+                assert!(status.starts_with("HTTP/1.1 200"), "{}", status);
+                reader
+            })
+            .collect();
+
+        // ...so any more are turned away...
+        let mut stream = TcpStream::connect(SocketAddr::from(address)).unwrap();
+        Message::request_get("/ws").write(&mut stream).unwrap();
+        assert_eq!(Message::read(&mut stream).unwrap().status(), Some(503));
+
+        // ...but every other request is still served
+        let response = Client::new().send(&address, &Message::request_get("/ui")).unwrap();
+        assert_eq!(response.status(), Some(200));
+
+        drop(streams);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_get_ui_uses_bound_port() {
+        let (handle, address) = start_controller();
+        assert_ne!(address.port(), 0);
+
+        // the UI connects back to the port the Controller actually bound, not the requested port 0
+        let response = Client::new().send(&address, &Message::request_get("/ui")).unwrap();
+        assert!(response.body.unwrap().contains(&address.to_string()));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_handle_get_stream() {
+        let events = Broadcaster::default();
+        let receiver = events.subscribe();
+
+        let datum = Datum::new_now(1.0, Unit::DegreesC);
+
+        let wanted = Event::Datum {
+            id: Id::new("wanted"),
+            datum: datum.clone(),
+        };
+        let unwanted = Event::Datum {
+            id: Id::new("unwanted"),
+            datum,
+        };
+        let command = Event::Command {
+            id: Id::new("wanted"),
+            command: String::from(r#"{"name":"HeatBy","value":"4"}"#),
+        };
+
+        events.publish(wanted.clone());
+        events.publish(unwanted);
+        events.publish(command.clone());
+
+        // dropping the Broadcaster ends the stream once all Events have been sent
+        drop(events);
+
+        let mut buffer = Vec::new();
+        let ids = [Id::new("wanted")];
+
+        Controller::handle_get_stream(&mut buffer, receiver, Some(&ids), Duration::from_secs(60)).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+        let (_, body) = actual.split_once("\r\n\r\n").unwrap();
+
+        let expected = format!("event: datum\ndata: {}\n\nevent: command\ndata: {}\n\n", wanted.data(), command.data());
+
+        assert!(actual.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(actual.contains("Content-Type: text/event-stream"));
+        assert_eq!(body, expected)
+    }
+
+    #[test]
+    fn test_handle_get_datum() {
+        let id = Id::new("my_sensor");
+
+        let mut data = VecDeque::new();
+        let datum1 = Datum::new_now(1.0, Unit::DegreesC);
+        let datum2 = Datum::new_now(2.0, Unit::DegreesC);
+        let datum3 = Datum::new_now(3.0, Unit::DegreesC);
+        data.push_front(datum1.clone());
+        data.push_front(datum2.clone());
+        data.push_front(datum3.clone());
+
+        let mut all_data = HashMap::new();
+        all_data.insert(id.clone(), data);
+        let all_data = Arc::new(Mutex::new(all_data));
+
+        let mut buffer = Vec::new();
+
+        Controller::handle_get_datum(&mut buffer, &all_data).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = datum3.to_string();
+        let json = format!(r#"[{{"id":"{}","datum":[{}]}}]"#, id, json);
+
+        // timestamps are serialized with as much precision as the platform clock provides
+        let length = format!("Content-Length: {}", json.len());
+
+        let expected = ["HTTP/1.1 200 OK", length.as_str(), "Content-Type: text/json; charset=utf-8", "", json.as_str()].join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_handle_get_ui() {
         let mut buffer = Vec::new();
 
         let address = String::from("1.2.3.4:5678");
         let container_mode = false;
 
-        Controller::handle_get_ui(&mut buffer, container_mode, address.clone());
+        Controller::handle_get_ui(&mut buffer, container_mode, address.clone()).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let html = include_str!("index.html").replace("192.168.2.16:6565", address.as_str());
 
-        let expected = [
-            "HTTP/1.1 200 OK",
-            "Content-Length: 1837",
-            "Content-Type: text/html; charset=utf-8",
-            "",
-            html.as_str(),
-        ]
-        .join("\r\n");
+        let length = format!("Content-Length: {}", html.len());
+
+        let expected = ["HTTP/1.1 200 OK", length.as_str(), "Content-Type: text/html; charset=utf-8", "", html.as_str()].join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 }
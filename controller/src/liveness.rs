@@ -0,0 +1,188 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// After this many consecutive failed requests, a `Device` is considered [`Offline`](State::Offline).
+pub const OFFLINE_AFTER: u32 = 3;
+
+/// An [`Offline`](State::Offline) `Device` is not contacted again until this many seconds after its
+/// last failed request, doubling with each further failure, up to [`MAX_RETRY_SECONDS`].
+const RETRY_SECONDS: i64 = 1;
+
+const MAX_RETRY_SECONDS: i64 = 60;
+
+/// The `State` of a discovered `Device`, as seen by the `Controller`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum State {
+    /// the last request to this `Device` succeeded (or none has been made yet)
+    Online,
+    /// some recent requests to this `Device` have failed
+    Degraded,
+    /// every recent request to this `Device` has failed
+    Offline,
+}
+
+/// Allows `State`s to be converted to `String`s with `to_string()`.
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            State::Online => "online",
+            State::Degraded => "degraded",
+            State::Offline => "offline",
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
+/// `Liveness` tracks how reachable a single discovered `Device` is.
+///
+/// **Design Decision**: liveness is inferred from the `Controller`'s own requests, rather than from
+/// discovery alone, because a `Device` which is unplugged (rather than shut down) never announces
+/// that it has left the network.
+///
+/// **Design Decision**: requests to an `Offline` `Device` back off exponentially (see [`is_due`](Self::is_due)),
+/// because each one blocks the `Controller`'s polling loop until it times out.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Liveness {
+    /// the number of requests which have failed since the last one which succeeded
+    failures: u32,
+    /// when this `Device` was discovered, or last responded to a request
+    last_seen: DateTime<Utc>,
+    /// when the last request to this `Device` failed, if one has failed since it was last seen
+    last_failed: Option<DateTime<Utc>>,
+}
+
+impl Liveness {
+    pub fn new(now: DateTime<Utc>) -> Liveness {
+        Liveness {
+            failures: 0,
+            last_seen: now,
+            last_failed: None,
+        }
+    }
+
+    /// Records a request to this `Device` which succeeded at time `now`.
+    pub fn succeeded(&mut self, now: DateTime<Utc>) {
+        self.failures = 0;
+        self.last_seen = now;
+        self.last_failed = None;
+    }
+
+    /// Records a request to this `Device` which failed at time `now`.
+    pub fn failed(&mut self, now: DateTime<Utc>) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_failed = Some(now);
+    }
+
+    pub fn state(&self) -> State {
+        match self.failures {
+            0 => State::Online,
+            n if n < OFFLINE_AFTER => State::Degraded,
+            _ => State::Offline,
+        }
+    }
+
+    pub fn last_seen(&self) -> DateTime<Utc> {
+        self.last_seen
+    }
+
+    /// Returns `true` if this `Device` should be contacted at time `now`.
+    ///
+    /// `Online` and `Degraded` `Device`s are always due. An `Offline` `Device` is due once its backoff
+    /// has passed since its last failed request.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        match self.last_failed {
+            Some(last_failed) if self.state() == State::Offline => now - last_failed >= self.backoff(),
+            _ => true,
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        // any more doublings would be capped anyway (and could eventually overflow)
+        let doublings = self.failures.saturating_sub(OFFLINE_AFTER).min(MAX_RETRY_SECONDS.ilog2() + 1);
+        Duration::seconds((RETRY_SECONDS << doublings).min(MAX_RETRY_SECONDS))
+    }
+
+    /// Returns `true` if this `Device` is offline, and has not been seen for longer than its `ttl`.
+    ///
+    /// An expired `Device` should be forgotten; if it comes back, it will be discovered again.
+    pub fn is_expired(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        self.state() == State::Offline && now - self.last_seen > ttl
+    }
+}
+
+#[cfg(test)]
+mod liveness_tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let mut liveness = Liveness::new(now);
+        assert_eq!(liveness.state(), State::Online);
+
+        liveness.failed(now);
+        assert_eq!(liveness.state(), State::Degraded);
+
+        (1..OFFLINE_AFTER).for_each(|_| liveness.failed(now));
+        assert_eq!(liveness.state(), State::Offline);
+
+        // a single successful request brings a Device back online
+        let later = now + Duration::seconds(10);
+        liveness.succeeded(later);
+        assert_eq!(liveness.state(), State::Online);
+        assert_eq!(liveness.last_seen(), later);
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let ttl = Duration::seconds(120);
+        let mut liveness = Liveness::new(now);
+
+        // a Device which is still online never expires, even if it has not been contacted in a while
+        assert!(!liveness.is_expired(now + ttl * 10, ttl));
+
+        (0..OFFLINE_AFTER).for_each(|_| liveness.failed(now));
+        assert!(!liveness.is_expired(now + ttl, ttl));
+        assert!(liveness.is_expired(now + ttl + Duration::seconds(1), ttl));
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let mut liveness = Liveness::new(now);
+
+        // a Device which has only failed a few times is retried right away
+        (1..OFFLINE_AFTER).for_each(|_| liveness.failed(now));
+        assert!(liveness.is_due(now));
+
+        // ...but an offline Device is retried after 1s, then 2s, then 4s...
+        liveness.failed(now);
+        assert!(!liveness.is_due(now));
+        assert!(liveness.is_due(now + Duration::seconds(1)));
+
+        liveness.failed(now);
+        assert!(!liveness.is_due(now + Duration::seconds(1)));
+        assert!(liveness.is_due(now + Duration::seconds(2)));
+
+        // ...up to once a minute
+        (0..100).for_each(|_| liveness.failed(now));
+        assert!(!liveness.is_due(now + Duration::seconds(59)));
+        assert!(liveness.is_due(now + Duration::seconds(60)));
+
+        // a Device which comes back online is due again immediately
+        liveness.succeeded(now);
+        assert!(liveness.is_due(now));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(State::Online.to_string(), "online");
+        assert_eq!(State::Degraded.to_string(), "degraded");
+        assert_eq!(State::Offline.to_string(), "offline");
+    }
+}
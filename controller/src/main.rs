@@ -1,6 +1,10 @@
 use std::time::Duration;
 
+use controller::config::Config;
+use controller::storage;
 use controller::Controller;
+use device::discovery::Backend;
+use device::handle::wait_for_termination;
 use device::id::Id;
 use device::name::Name;
 
@@ -15,7 +19,34 @@ fn main() {
     let group = String::from("_controller");
     let container_mode = true;
 
-    Controller::start(ip, port, id, name, group, container_mode);
+    // Devices find each other via mDNS, unless DISCOVERY=static selects a fixed list of Devices instead
+    let discovery = Backend::from_env().unwrap();
+
+    // Assessors installed over HTTP are saved here, so that they survive restarts
+    let state_dir = std::env::var("STATE_DIR").unwrap_or(String::from("state"));
+
+    // ...and script Assessors run the scripts in this directory, which are reloaded when they change
+    let script_dir = std::env::var("SCRIPT_DIR").unwrap_or(String::from("scripts"));
+
+    // the data received from Sensors is only kept in memory, unless STORAGE=segments or STORAGE=sqlite keeps it on disk
+    let storage = storage::Backend::from_env().unwrap();
+
+    let mut config = Config::new(discovery)
+        .with_container_mode(container_mode)
+        .with_state_dir(state_dir)
+        .with_script_dir(script_dir)
+        .with_storage(storage);
+
+    // ...where it is kept for RETENTION_HOURS, if set, or forever
+    if let Ok(hours) = std::env::var("RETENTION_HOURS") {
+        let hours: u64 = hours.parse().expect("RETENTION_HOURS must be a whole number of hours");
+        config = config.with_retention(Duration::from_secs(hours * 60 * 60));
+    }
+
+    let handle = Controller::start(ip, port, id, name, group, config);
     println!("Controller is running...");
-    std::thread::sleep(Duration::MAX)
+
+    // stop cleanly on Ctrl-C, or when the container runtime sends SIGTERM
+    wait_for_termination().unwrap();
+    handle.shutdown();
 }
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Utc};
+
+use device::json::Value;
+
+/// The longest that an `Override` can be set to last for, in seconds (one year). An `Override` which
+/// should last for longer than this should be left to last until it is cleared, instead.
+pub const MAX_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// An `Override` is a manual `Command` which an operator has sent to an `Actuator`, or a request
+/// from an operator that the `Controller` leave that `Actuator` alone for a while.
+///
+/// While an `Override` is active, the `Controller` does not assess the data from the paired
+/// `Sensor`, so that it does not immediately undo the operator's `Command`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Override {
+    /// the `Command` (serialized as JSON) which was sent to the `Actuator`, if any
+    pub command: Option<String>,
+    /// when this `Override` ends, or `None` if it lasts until it is explicitly cleared
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Override {
+    /// Returns `true` if this `Override` has not yet expired at time `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+
+    /// Returns this `Override`, serialized as JSON.
+    pub fn to_json(&self) -> String {
+        let command = self.command.as_deref().unwrap_or("null");
+        let expires = self.expires.map(|e| format!(r#""{}""#, e.to_rfc3339())).unwrap_or(String::from("null"));
+        format!(r#"{{"command":{},"expires":{}}}"#, command, expires)
+    }
+
+    /// Returns when an `Override` which starts at `now` and lasts for `seconds` ends, or `None` if
+    /// that cannot be represented.
+    pub fn expires(now: DateTime<Utc>, seconds: u64) -> Option<DateTime<Utc>> {
+        let seconds = i64::try_from(seconds).ok()?;
+        now.checked_add_signed(Duration::try_seconds(seconds)?)
+    }
+
+    /// Extracts the `"command"` (a JSON object, or `null`) and the number of `"seconds"` (a whole
+    /// number no larger than [`MAX_SECONDS`], or `null`) which describe an `Override`, from a JSON object like
+    /// `{"command":{"name":"HeatBy","value":"5"},"seconds":600}`.
+    ///
+    /// Either may be left out, in which case it is `None`.
+    pub fn parse_fields(json: &Value) -> Result<(Option<String>, Option<u64>), String> {
+        let command = match json.get("command") {
+            Some(command @ Value::Object(_)) => Some(command.to_string()),
+            Some(Value::Null) | None => None,
+            Some(_) => return Err(String::from("\"command\" must be an object or null")),
+        };
+
+        let seconds = match json.get("seconds") {
+            Some(Value::Number(seconds)) if (0.0..=MAX_SECONDS as f64).contains(seconds) && seconds.fract() == 0.0 => Some(*seconds as u64),
+            Some(Value::Null) | None => None,
+            Some(_) => return Err(format!("\"seconds\" must be a whole number from 0 to {}", MAX_SECONDS)),
+        };
+
+        Ok((command, seconds))
+    }
+}
+
+#[cfg(test)]
+mod overrides_tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_is_active() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let command = String::from(r#"{"name":"HeatBy","value":"4"}"#);
+
+        let forever = Override {
+            command: Some(command.clone()),
+            expires: None,
+        };
+        let expired = Override {
+            command: Some(command.clone()),
+            expires: Some(now),
+        };
+        let active = Override {
+            command: None,
+            expires: Some(now + chrono::Duration::seconds(1)),
+        };
+
+        assert!(forever.is_active(now));
+        assert!(!expired.is_active(now));
+        assert!(active.is_active(now));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let expires = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let command = String::from(r#"{"name":"HeatBy","value":"4"}"#);
+
+        let actual = Override {
+            command: Some(command),
+            expires: Some(expires),
+        }
+        .to_json();
+        let expected = r#"{"command":{"name":"HeatBy","value":"4"},"expires":"2024-01-05T12:00:00+00:00"}"#;
+
+        assert_eq!(actual, expected);
+
+        let paused = Override { command: None, expires: None };
+        assert_eq!(paused.to_json(), r#"{"command":null,"expires":null}"#);
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let json = Value::parse(r#"{"command":{"name":"HeatBy","value":"4"},"seconds":60}"#).unwrap();
+        assert_eq!(
+            Override::parse_fields(&json),
+            Ok((Some(String::from(r#"{"name":"HeatBy","value":"4"}"#)), Some(60)))
+        );
+
+        assert_eq!(Override::parse_fields(&Value::parse("{}").unwrap()), Ok((None, None)));
+
+        let expected = Err(String::from("\"seconds\" must be a whole number from 0 to 31536000"));
+
+        for seconds in ["-1", "1.5", "31536001", "1e300", "\"60\""] {
+            let json = Value::parse(format!(r#"{{"seconds":{}}}"#, seconds)).unwrap();
+            assert_eq!(Override::parse_fields(&json), expected, "{}", seconds);
+        }
+    }
+
+    #[test]
+    fn test_expires() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+
+        assert_eq!(Override::expires(now, 60), Some(now + Duration::seconds(60)));
+        assert_eq!(Override::expires(now, MAX_SECONDS), Some(now + Duration::days(365)));
+
+        // values which would overflow (or panic) are never turned into an expiry
+        assert_eq!(Override::expires(now, u64::MAX), None);
+        assert_eq!(Override::expires(now, i64::MAX as u64), None);
+        assert_eq!(Override::expires(now, 10_000_000_000_000), None);
+    }
+}
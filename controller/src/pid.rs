@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+
+use actuator_temperature::command::Command as Thermo5000;
+use datum::unit::Unit;
+use datum::Datum;
+
+use crate::assessor::Assess;
+
+/// A `Pid` controller chooses how much to heat (or cool) a Thermo5000 `Actuator` by, based on how far
+/// the temperature is from the `setpoint` now (proportional), how far it has been from the `setpoint`
+/// over time (integral), and how quickly that distance is changing (derivative).
+///
+/// The time between `Datum`s is taken from their timestamps, rather than from when they are assessed.
+///
+/// **Design Decision**: the integral term only accumulates while the output is not clamped to
+/// `max_output` (or while accumulating would bring the output back within `max_output`), so that a
+/// long period of saturation does not "wind up" an integral which then overshoots the `setpoint`.
+pub struct Pid {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    setpoint: f32,
+    max_output: f32,
+    integral: f32,
+    previous: Option<(f32, DateTime<Utc>)>,
+}
+
+impl Pid {
+    pub fn new(kp: f32, ki: f32, kd: f32, setpoint: f32, max_output: f32) -> Pid {
+        Pid {
+            kp,
+            ki,
+            kd,
+            setpoint,
+            max_output,
+            integral: 0.0,
+            previous: None,
+        }
+    }
+
+    /// Returns the (clamped) output of this controller for the temperature `t`, measured at `timestamp`.
+    ///
+    /// Positive outputs heat, negative outputs cool.
+    fn output(&mut self, t: f32, timestamp: DateTime<Utc>) -> f32 {
+        let error = self.setpoint - t;
+
+        // the first Datum (or a Datum which is no newer than the last one) does not contribute to the integral or derivative
+        let (integral, derivative) = match self.previous {
+            Some((previous, then)) if timestamp > then => {
+                let dt = (timestamp - then).num_milliseconds() as f32 / 1000.0;
+                (self.integral + error * dt, (error - previous) / dt)
+            }
+            _ => (self.integral, 0.0),
+        };
+
+        self.previous = Some((error, timestamp));
+
+        let unclamped = self.kp * error + self.ki * integral + self.kd * derivative;
+        let output = unclamped.clamp(-self.max_output, self.max_output);
+
+        if output == unclamped || error.signum() != unclamped.signum() {
+            self.integral = integral;
+        }
+
+        output
+    }
+}
+
+impl Assess for Pid {
+    fn assess(&mut self, datum: &Datum) -> Option<Box<dyn actuator::Command>> {
+        // a Datum which is not a temperature cannot be assessed by a PID controller for a thermostat
+        let t = datum.get_as_float().filter(|_| datum.unit == Unit::DegreesC)?;
+
+        let output = self.output(t, datum.timestamp);
+
+        if output > 0.0 {
+            Some(Box::new(Thermo5000::HeatBy(output)))
+        } else if output < 0.0 {
+            Some(Box::new(Thermo5000::CoolBy(-output)))
+        } else {
+            None
+        }
+    }
+
+    /// Aims for the new `setpoint`, keeping the integral accumulated so far.
+    fn set_setpoint(&mut self, setpoint: Option<f32>) {
+        if let Some(setpoint) = setpoint {
+            self.setpoint = setpoint;
+        }
+    }
+}
+
+#[cfg(test)]
+mod pid_tests {
+    use chrono::Duration;
+
+    use environment::generator::{Coefficients, DatumGenerator};
+
+    use super::*;
+
+    fn at(seconds: i64, t: f32) -> Datum {
+        let start = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        Datum::new(t, Unit::DegreesC, start + Duration::seconds(seconds))
+    }
+
+    #[test]
+    fn test_proportional() {
+        let mut pid = Pid::new(2.0, 0.0, 0.0, 20.0, 100.0);
+
+        assert_eq!(pid.assess(&at(0, 17.0)).unwrap().to_string(), Thermo5000::HeatBy(6.0).to_string());
+        assert_eq!(pid.assess(&at(1, 24.0)).unwrap().to_string(), Thermo5000::CoolBy(8.0).to_string());
+        assert!(pid.assess(&at(2, 20.0)).is_none());
+    }
+
+    #[test]
+    fn test_derivative() {
+        let mut pid = Pid::new(0.0, 0.0, 1.0, 20.0, 100.0);
+
+        // error falls from 4 to 1 over 3 seconds
+        assert!(pid.assess(&at(0, 16.0)).is_none());
+        assert_eq!(pid.assess(&at(3, 19.0)).unwrap().to_string(), Thermo5000::CoolBy(1.0).to_string());
+    }
+
+    #[test]
+    fn test_not_a_temperature() {
+        let mut pid = Pid::new(1.0, 1.0, 1.0, 20.0, 100.0);
+        assert!(pid.assess(&Datum::new(true, Unit::PoweredOn, Utc::now())).is_none());
+    }
+
+    #[test]
+    fn test_clamping() {
+        let mut pid = Pid::new(10.0, 0.0, 0.0, 20.0, 5.0);
+
+        assert_eq!(pid.assess(&at(0, 10.0)).unwrap().to_string(), Thermo5000::HeatBy(5.0).to_string());
+        assert_eq!(pid.assess(&at(1, 30.0)).unwrap().to_string(), Thermo5000::CoolBy(5.0).to_string());
+    }
+
+    #[test]
+    fn test_anti_windup() {
+        let mut pid = Pid::new(1.0, 1.0, 0.0, 20.0, 5.0);
+
+        // a long time spent far below the setpoint saturates the output...
+        for second in 0..100 {
+            assert_eq!(pid.assess(&at(second, 10.0)).unwrap().to_string(), Thermo5000::HeatBy(5.0).to_string());
+        }
+
+        // ...but does not wind up the integral, so the output falls as soon as the setpoint is passed
+        let output = pid.output(21.0, at(100, 21.0).timestamp);
+        assert!(output < 5.0, "output {} is still saturated", output);
+        assert!(pid.integral < 10.0, "integral {} has wound up", pid.integral);
+    }
+
+    #[test]
+    fn test_closed_loop() {
+        // the Environment starts at 15 degrees C, with no noise, so the trajectory is the same on every run
+        let coefficients = Coefficients::new(15.0, 0.0, 0.0, 0.0, 0.0);
+        let mut generator = DatumGenerator::new(coefficients, 0.0, Unit::DegreesC);
+
+        let setpoint = 21.0;
+        let mut pid = Pid::new(20.0, 1.0, 1.0, setpoint, 50.0);
+
+        let mut temperatures = Vec::new();
+
+        // one Datum is assessed every (simulated) second, and every Command is applied to the Environment
+        for second in 0..300 {
+            let t = generator.generate().get_as_float().unwrap();
+            temperatures.push(t);
+
+            if let Some(command) = pid.assess(&at(second, t)) {
+                let command = Thermo5000::parse(command.to_string()).unwrap();
+                generator.apply(&command);
+            }
+        }
+
+        // far from the setpoint, the output is saturated, so the temperature rises as quickly as it can...
+        for (second, t) in temperatures[..9].iter().enumerate() {
+            let expected = 15.0 + 0.5 * second as f32;
+            assert!((t - expected).abs() < 1e-4, "temperature {} at {}s, expected {}", t, second, expected);
+        }
+
+        // ...then it overshoots the setpoint only slightly...
+        let highest = temperatures.iter().cloned().fold(f32::MIN, f32::max);
+        assert!(highest > setpoint && highest < setpoint + 0.3, "highest temperature was {}", highest);
+
+        // ...and settles at the setpoint
+        let settled = &temperatures[100..];
+        assert!(
+            settled.iter().all(|t| (t - setpoint).abs() < 0.01),
+            "temperature did not settle at {}",
+            setpoint
+        );
+    }
+}
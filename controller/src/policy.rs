@@ -0,0 +1,482 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Utc};
+
+use device::id::Id;
+use device::json::{escape, Value};
+
+/// The number of `Command`s remembered in the command log, for each `Sensor` / `Actuator` pair.
+pub const LOG_SIZE: usize = 100;
+
+/// A `Policy` limits which of the `Command`s produced by an `Assessor` are actually sent to an
+/// `Actuator`, so that the `Actuator` is not flooded with near-identical `Command`s.
+///
+/// **Design Decision**: a `Policy` is applied after a `Command` has been assessed, rather than by
+/// each `Assessor`, so that every kind of `Assessor` can be throttled in the same way.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Policy {
+    /// `Command`s with a `value` smaller than this are not sent
+    pub deadband: f32,
+    /// after a `Command` has been sent, another `Command` with the same `name` is not sent until the
+    /// `Sensor`'s reading has moved by at least this much
+    pub hysteresis: f32,
+    /// the minimum time between any two `Command`s
+    pub min_interval: Duration,
+    /// the maximum number of `Command`s sent in any one minute, if any
+    pub max_per_minute: Option<u32>,
+    /// a `Command` which is identical to the last one sent is not sent again within this time
+    pub duplicate_window: Duration,
+}
+
+/// By default, at most one `Command` is sent each second, and an identical `Command` at most every five seconds.
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            deadband: 0.0,
+            hysteresis: 0.0,
+            min_interval: Duration::seconds(1),
+            max_per_minute: None,
+            duplicate_window: Duration::seconds(5),
+        }
+    }
+}
+
+/// Allows `Policy`s to be converted to JSON `String`s with `to_string()`.
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let max_per_minute = self.max_per_minute.map(|max| max.to_string()).unwrap_or(String::from("null"));
+        write!(
+            f,
+            r#"{{"deadband":{},"hysteresis":{},"min_interval_ms":{},"max_per_minute":{},"duplicate_window_ms":{}}}"#,
+            self.deadband,
+            self.hysteresis,
+            self.min_interval.num_milliseconds(),
+            max_per_minute,
+            self.duplicate_window.num_milliseconds()
+        )
+    }
+}
+
+impl Policy {
+    /// Attempts to parse a `Policy` from the provided JSON, e.g. `{"min_interval_ms":2000,"max_per_minute":10}`.
+    ///
+    /// Any field which is not provided takes its [default](Policy::default) value.
+    pub fn parse<S: AsRef<str>>(s: S) -> Result<Policy, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        Self::from_json(&json)
+    }
+
+    fn from_json(json: &Value) -> Result<Policy, String> {
+        // a field which is present must be a non-negative number
+        let number = |key: &str| match json.get(key) {
+            None => Ok(None),
+            Some(value) => match value.as_f64() {
+                Some(n) if n.is_finite() && n >= 0.0 => Ok(Some(n)),
+                _ => Err(format!("policy \"{}\" must be a non-negative number", key)),
+            },
+        };
+
+        let milliseconds = |key: &str| match number(key)? {
+            Some(ms) if ms.fract() != 0.0 => Err(format!("policy \"{}\" must be a whole number of milliseconds", key)),
+            ms => Ok(ms.map(|ms| Duration::milliseconds(ms as i64))),
+        };
+
+        let default = Policy::default();
+
+        let max_per_minute = match json.get("max_per_minute") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_f64() {
+                Some(max) if max.fract() == 0.0 && max >= 1.0 && max <= u32::MAX as f64 => Some(max as u32),
+                _ => return Err(String::from("policy \"max_per_minute\" must be a positive whole number, or null")),
+            },
+        };
+
+        Ok(Policy {
+            deadband: number("deadband")?.map_or(default.deadband, |n| n as f32),
+            hysteresis: number("hysteresis")?.map_or(default.hysteresis, |n| n as f32),
+            min_interval: milliseconds("min_interval_ms")?.unwrap_or(default.min_interval),
+            max_per_minute,
+            duplicate_window: milliseconds("duplicate_window_ms")?.unwrap_or(default.duplicate_window),
+        })
+    }
+
+    /// Serializes `policies` as a JSON array of `{"id":...,"policy":...}` objects, sorted by `Id`.
+    pub fn all_to_json(policies: &HashMap<Id, Policy>) -> String {
+        let mut policies: Vec<(&Id, &Policy)> = policies.iter().collect();
+        policies.sort_by_key(|(id, _)| id.to_string());
+
+        let policies: Vec<String> = policies
+            .into_iter()
+            .map(|(id, policy)| format!(r#"{{"id":"{}","policy":{}}}"#, escape(id.to_string().as_str()), policy))
+            .collect();
+
+        format!("[{}]", policies.join(","))
+    }
+
+    /// Parses the JSON produced by [`all_to_json`](Self::all_to_json).
+    pub fn all_from_json<S: AsRef<str>>(s: S) -> Result<HashMap<Id, Policy>, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        let entries = json.as_array().ok_or(String::from("policies must be a JSON array"))?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                let id = entry.get("id").and_then(Value::as_str).ok_or(String::from("policy is missing an \"id\""))?;
+                let policy = entry.get("policy").ok_or(format!("no policy for id {}", id))?;
+                Ok((Id::new(id), Self::from_json(policy)?))
+            })
+            .collect()
+    }
+}
+
+/// The reason that a `Command` was not sent, because of a [`Policy`].
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Reason {
+    Deadband,
+    Duplicate,
+    Hysteresis,
+    MinInterval,
+    MaxRate,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Reason::Deadband => "deadband",
+            Reason::Duplicate => "duplicate",
+            Reason::Hysteresis => "hysteresis",
+            Reason::MinInterval => "min_interval",
+            Reason::MaxRate => "max_rate",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// The last `Command` which was sent to an `Actuator`, and the reading which caused it to be sent.
+struct Sent {
+    command: String,
+    name: Option<String>,
+    reading: Option<f32>,
+    at: DateTime<Utc>,
+}
+
+/// A `Throttle` remembers the `Command`s recently sent to a single `Actuator`, so that a [`Policy`]
+/// can be applied to the next one.
+#[derive(Default)]
+pub struct Throttle {
+    last: Option<Sent>,
+    recent: VecDeque<DateTime<Utc>>,
+}
+
+impl Throttle {
+    /// Checks whether `command` (serialized as JSON), which was assessed from a `Sensor` `reading`,
+    /// may be sent at time `now`, according to the `policy`.
+    pub fn check(&mut self, policy: &Policy, command: &str, reading: Option<f32>, now: DateTime<Utc>) -> Result<(), Reason> {
+        let minute_ago = now - Duration::minutes(1);
+        self.recent.retain(|sent| *sent > minute_ago);
+
+        let (name, value) = Self::describe(command);
+
+        if value.is_some_and(|value| value.abs() < policy.deadband) {
+            return Err(Reason::Deadband);
+        }
+
+        if let Some(last) = &self.last {
+            if last.command == command && now - last.at < policy.duplicate_window {
+                return Err(Reason::Duplicate);
+            }
+
+            if let (Some(before), Some(after)) = (last.reading, reading) {
+                if last.name == name && (after - before).abs() < policy.hysteresis {
+                    return Err(Reason::Hysteresis);
+                }
+            }
+
+            if now - last.at < policy.min_interval {
+                return Err(Reason::MinInterval);
+            }
+        }
+
+        if policy.max_per_minute.is_some_and(|max| self.recent.len() >= max as usize) {
+            return Err(Reason::MaxRate);
+        }
+
+        Ok(())
+    }
+
+    /// Records that `command`, which was assessed from a `Sensor` `reading`, was sent at time `now`.
+    pub fn sent(&mut self, command: &str, reading: Option<f32>, now: DateTime<Utc>) {
+        let (name, _) = Self::describe(command);
+
+        self.last = Some(Sent {
+            command: command.to_string(),
+            name,
+            reading,
+            at: now,
+        });
+        self.recent.push_back(now);
+    }
+
+    /// Returns the `name` and numeric `value` of a `Command`, like `{"name":"HeatBy","value":"3.5"}`, if it has them.
+    fn describe(command: &str) -> (Option<String>, Option<f32>) {
+        let Ok(json) = Value::parse(command) else {
+            return (None, None);
+        };
+
+        let name = json.get("name").and_then(Value::as_str).map(String::from);
+        let value = json.get("value").and_then(|value| match value.as_str() {
+            Some(value) => value.parse().ok(),
+            None => value.as_f64().map(|value| value as f32),
+        });
+
+        (name, value)
+    }
+}
+
+/// What happened to a `Command` which was produced by an `Assessor`.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Outcome {
+    /// the `Command` was sent to the `Actuator`
+    Sent,
+    /// the `Command` was not sent, because of the `Actuator`'s `Policy`
+    Suppressed(Reason),
+    /// the `Command` could not be sent to the `Actuator`, for this reason
+    Failed(String),
+}
+
+/// An entry in the command log, which records every `Command` produced by an `Assessor`, and whether
+/// or not it was sent, so that `Policy`s can be tuned.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Logged {
+    /// the `Command` (serialized as JSON)
+    pub command: String,
+    pub at: DateTime<Utc>,
+    pub outcome: Outcome,
+}
+
+impl Logged {
+    /// Adds a `Logged` `Command` to the front of the `log`, forgetting the oldest entry if the log is full.
+    pub fn record(log: &mut VecDeque<Logged>, logged: Logged) {
+        if log.len() == LOG_SIZE {
+            log.pop_back();
+        }
+        log.push_front(logged);
+    }
+
+    /// Returns this `Logged` `Command`, serialized as JSON.
+    pub fn to_json(&self) -> String {
+        let (outcome, reason) = match &self.outcome {
+            Outcome::Sent => ("sent", String::from("null")),
+            Outcome::Suppressed(reason) => ("suppressed", format!(r#""{}""#, reason)),
+            Outcome::Failed(reason) => ("failed", format!(r#""{}""#, escape(reason))),
+        };
+
+        format!(
+            r#"{{"command":{},"at":"{}","outcome":"{}","reason":{}}}"#,
+            self.command,
+            self.at.to_rfc3339(),
+            outcome,
+            reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn heat_by(value: f32) -> String {
+        format!(r#"{{"name":"HeatBy","value":"{}"}}"#, value)
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap()
+    }
+
+    fn unthrottled() -> Policy {
+        Policy {
+            deadband: 0.0,
+            hysteresis: 0.0,
+            min_interval: Duration::zero(),
+            max_per_minute: None,
+            duplicate_window: Duration::zero(),
+        }
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        let expected = Policy {
+            deadband: 0.5,
+            hysteresis: 1.5,
+            min_interval: Duration::milliseconds(2000),
+            max_per_minute: Some(10),
+            duplicate_window: Duration::milliseconds(30000),
+        };
+        let serialized = expected.to_string();
+        assert_eq!(
+            serialized,
+            r#"{"deadband":0.5,"hysteresis":1.5,"min_interval_ms":2000,"max_per_minute":10,"duplicate_window_ms":30000}"#
+        );
+        assert_eq!(Policy::parse(serialized), Ok(expected));
+
+        // missing fields take their default values
+        assert_eq!(Policy::parse("{}"), Ok(Policy::default()));
+        assert_eq!(
+            Policy::parse(r#"{"max_per_minute":null,"deadband":2}"#),
+            Ok(Policy {
+                deadband: 2.0,
+                ..Policy::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(
+            Policy::parse(r#"{"deadband":-1}"#),
+            Err(String::from("policy \"deadband\" must be a non-negative number"))
+        );
+        assert_eq!(
+            Policy::parse(r#"{"min_interval_ms":1.5}"#),
+            Err(String::from("policy \"min_interval_ms\" must be a whole number of milliseconds"))
+        );
+        assert_eq!(
+            Policy::parse(r#"{"max_per_minute":0}"#),
+            Err(String::from("policy \"max_per_minute\" must be a positive whole number, or null"))
+        );
+        assert!(Policy::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_all_to_and_from_json() {
+        let policies = HashMap::from([(Id::new("b"), Policy::default()), (Id::new("a"), unthrottled())]);
+
+        let json = Policy::all_to_json(&policies);
+        assert_eq!(
+            json,
+            r#"[{"id":"a","policy":{"deadband":0,"hysteresis":0,"min_interval_ms":0,"max_per_minute":null,"duplicate_window_ms":0}},{"id":"b","policy":{"deadband":0,"hysteresis":0,"min_interval_ms":1000,"max_per_minute":null,"duplicate_window_ms":5000}}]"#
+        );
+        assert_eq!(Policy::all_from_json(json), Ok(policies));
+    }
+
+    #[test]
+    fn test_deadband() {
+        let policy = Policy {
+            deadband: 1.0,
+            ..unthrottled()
+        };
+        let mut throttle = Throttle::default();
+
+        assert_eq!(throttle.check(&policy, heat_by(0.5).as_str(), Some(20.0), now()), Err(Reason::Deadband));
+        assert_eq!(throttle.check(&policy, heat_by(1.5).as_str(), Some(20.0), now()), Ok(()));
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let policy = Policy {
+            duplicate_window: Duration::seconds(5),
+            ..unthrottled()
+        };
+        let mut throttle = Throttle::default();
+        throttle.sent(heat_by(2.0).as_str(), Some(20.0), now());
+
+        let later = now() + Duration::seconds(1);
+        assert_eq!(throttle.check(&policy, heat_by(2.0).as_str(), Some(20.0), later), Err(Reason::Duplicate));
+        assert_eq!(throttle.check(&policy, heat_by(2.5).as_str(), Some(20.0), later), Ok(()));
+
+        let much_later = now() + Duration::seconds(5);
+        assert_eq!(throttle.check(&policy, heat_by(2.0).as_str(), Some(20.0), much_later), Ok(()));
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let policy = Policy {
+            hysteresis: 1.0,
+            ..unthrottled()
+        };
+        let mut throttle = Throttle::default();
+        throttle.sent(heat_by(2.0).as_str(), Some(20.0), now());
+
+        // the reading has not moved far enough to send another HeatBy...
+        assert_eq!(throttle.check(&policy, heat_by(2.5).as_str(), Some(19.5), now()), Err(Reason::Hysteresis));
+        assert_eq!(throttle.check(&policy, heat_by(3.0).as_str(), Some(19.0), now()), Ok(()));
+
+        // ...but a Command with a different name is not held back
+        let cool_by = r#"{"name":"CoolBy","value":"1"}"#;
+        assert_eq!(throttle.check(&policy, cool_by, Some(20.5), now()), Ok(()));
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let policy = Policy {
+            min_interval: Duration::seconds(2),
+            ..unthrottled()
+        };
+        let mut throttle = Throttle::default();
+        assert_eq!(throttle.check(&policy, heat_by(2.0).as_str(), None, now()), Ok(()));
+        throttle.sent(heat_by(2.0).as_str(), None, now());
+
+        assert_eq!(
+            throttle.check(&policy, heat_by(3.0).as_str(), None, now() + Duration::seconds(1)),
+            Err(Reason::MinInterval)
+        );
+        assert_eq!(throttle.check(&policy, heat_by(3.0).as_str(), None, now() + Duration::seconds(2)), Ok(()));
+    }
+
+    #[test]
+    fn test_max_rate() {
+        let policy = Policy {
+            max_per_minute: Some(2),
+            ..unthrottled()
+        };
+        let mut throttle = Throttle::default();
+        throttle.sent(heat_by(1.0).as_str(), None, now());
+        throttle.sent(heat_by(2.0).as_str(), None, now() + Duration::seconds(30));
+
+        assert_eq!(
+            throttle.check(&policy, heat_by(3.0).as_str(), None, now() + Duration::seconds(45)),
+            Err(Reason::MaxRate)
+        );
+
+        // the first Command was sent more than a minute ago
+        assert_eq!(throttle.check(&policy, heat_by(3.0).as_str(), None, now() + Duration::seconds(61)), Ok(()));
+    }
+
+    #[test]
+    fn test_logged() {
+        let mut log = VecDeque::new();
+
+        for value in 0..(LOG_SIZE + 1) {
+            let logged = Logged {
+                command: heat_by(value as f32),
+                at: now(),
+                outcome: Outcome::Sent,
+            };
+            Logged::record(&mut log, logged);
+        }
+
+        assert_eq!(log.len(), LOG_SIZE);
+        assert_eq!(log.front().unwrap().command, heat_by(LOG_SIZE as f32));
+        assert_eq!(log.back().unwrap().command, heat_by(1.0));
+
+        let suppressed = Logged {
+            command: heat_by(1.0),
+            at: now(),
+            outcome: Outcome::Suppressed(Reason::MinInterval),
+        };
+        assert_eq!(
+            suppressed.to_json(),
+            r#"{"command":{"name":"HeatBy","value":"1"},"at":"2024-01-05T12:00:00+00:00","outcome":"suppressed","reason":"min_interval"}"#
+        );
+
+        let failed = Logged {
+            command: heat_by(1.0),
+            at: now(),
+            outcome: Outcome::Failed(String::from("connection \"refused\"")),
+        };
+        assert!(failed.to_json().ends_with(r#""outcome":"failed","reason":"connection \"refused\""}"#));
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use datum::unit::Unit;
+use datum::Datum;
+use device::id::Id;
+use device::json::{escape, Value};
+
+/// The quantities which a `Rule` can name after a `Sensor`'s `Id`, and the `Unit` each one requires.
+const QUANTITIES: [(&str, Unit); 1] = [("temperature", Unit::DegreesC)];
+
+/// The deepest a `Rule`'s `Condition` may be nested, counting each `not`, each pair of parentheses,
+/// and each `and` / `or` which joins two comparisons.
+///
+/// **Design Decision**: `Condition`s are parsed and evaluated recursively, so without this limit, a
+/// `Rule` like `if not not not ...` could overflow the stack, which aborts the whole `Controller`.
+const MAX_DEPTH: usize = 64;
+
+/// The ways in which a `Sensor`'s latest reading can be compared to a number.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn holds(&self, reading: f32, value: f32) -> bool {
+        match self {
+            Op::Gt => reading > value,
+            Op::Ge => reading >= value,
+            Op::Lt => reading < value,
+            Op::Le => reading <= value,
+            Op::Eq => reading == value,
+            Op::Ne => reading != value,
+        }
+    }
+}
+
+/// A `Condition` on the latest readings of one or more `Sensor`s.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Condition {
+    Compare { sensor: Id, unit: Option<Unit>, op: Op, value: f32 },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// Evaluates this `Condition` over the current reading of each `Sensor` in `readings`, returning
+    /// `None` if it cannot be known whether or not it holds.
+    ///
+    /// A comparison with a `Sensor` which has no current reading (e.g. because it is offline), whose
+    /// reading is not a number, or whose reading is not of the named quantity, is unknown.
+    ///
+    /// **Design Decision**: unknown comparisons are combined using three-valued logic, rather than
+    /// being treated as false, so that `not` cannot turn a missing reading into a `Condition` which holds.
+    /// `a and b` holds only if both do, and `a or b` holds if either does, whether or not the other is known.
+    fn evaluate(&self, readings: &HashMap<Id, Datum>) -> Option<bool> {
+        match self {
+            Condition::Compare { sensor, unit, op, value } => readings
+                .get(sensor)
+                .filter(|datum| unit.is_none_or(|unit| datum.unit == unit))
+                .and_then(|datum| datum.get_as_float().or(datum.get_as_int().map(|int| int as f32)))
+                .map(|reading| op.holds(reading, *value)),
+            Condition::And(left, right) => match (left.evaluate(readings), right.evaluate(readings)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Condition::Or(left, right) => match (left.evaluate(readings), right.evaluate(readings)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Condition::Not(condition) => condition.evaluate(readings).map(|holds| !holds),
+        }
+    }
+
+    /// Returns `true` if this `Condition` is known to hold for the current `readings` (see [`evaluate`](Self::evaluate)).
+    pub fn holds(&self, readings: &HashMap<Id, Datum>) -> bool {
+        self.evaluate(readings) == Some(true)
+    }
+}
+
+/// When a `Rule`'s `Condition` holds, the `Command` (serialized as JSON) is sent to the `Actuator`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Action {
+    pub actuator: Id,
+    pub command: String,
+}
+
+/// A `Rule` sends `Command`s to any `Actuator`s, based on the latest readings of any `Sensor`s, e.g.
+///
+/// ```text
+/// if zone-a temperature > 27 and zone-b temperature > 27 then CoolBy(3) on hvac-main
+/// ```
+///
+/// Conditions compare a `Sensor`'s latest reading to a number with `>`, `>=`, `<`, `<=`, `==` or
+/// `!=`, and can be combined with `and`, `or`, `not` and parentheses. Naming a quantity (e.g.
+/// `temperature`) after the `Sensor`'s `Id` is optional, but then the reading must be of that
+/// quantity. More than one action can follow `then`, separated by commas.
+///
+/// **Design Decision**: a `Rule` fires every time it is evaluated while its `Condition` holds, rather
+/// than only when its `Condition` becomes true. How often its `Command`s are actually sent is limited
+/// by each `Actuator`'s `Policy`, just like `Command`s from `Assessor`s.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Rule {
+    source: String,
+    pub condition: Condition,
+    pub actions: Vec<Action>,
+}
+
+/// Allows `Rule`s to be converted to `String`s (in the rule language) with `to_string()`.
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    Comma,
+    Op(Op),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            Token::Word(word) => word.as_str(),
+            Token::Open => "(",
+            Token::Close => ")",
+            Token::Comma => ",",
+            Token::Op(Op::Gt) => ">",
+            Token::Op(Op::Ge) => ">=",
+            Token::Op(Op::Lt) => "<",
+            Token::Op(Op::Le) => "<=",
+            Token::Op(Op::Eq) => "==",
+            Token::Op(Op::Ne) => "!=",
+        };
+
+        write!(f, "\"{}\"", string)
+    }
+}
+
+/// Parses the tokens of a single `Rule`, from left to right.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    /// Goes one level deeper into the `Condition`, failing if it would be nested more than [`MAX_DEPTH`] levels deep.
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("condition is nested more than {} levels deep", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(format!("expected {}, found end of rule", expected))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the (case-insensitive) `keyword`.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        let expected = format!("\"{}\"", keyword);
+        match self.next(expected.as_str())? {
+            Token::Word(word) if word.eq_ignore_ascii_case(keyword) => Ok(()),
+            other => Err(format!("expected {}, found {}", expected, other)),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next(expected.to_string().as_str())? {
+            token if token == expected => Ok(()),
+            other => Err(format!("expected {}, found {}", expected, other)),
+        }
+    }
+
+    fn word(&mut self, expected: &str) -> Result<String, String> {
+        match self.next(expected)? {
+            Token::Word(word) => Ok(word),
+            other => Err(format!("expected {}, found {}", expected, other)),
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        let word = self.word("a number")?;
+        word.parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite())
+            .ok_or(format!("expected a number, found \"{}\"", word))
+    }
+
+    // cond := and ("or" and)*
+    fn or(&mut self) -> Result<Condition, String> {
+        let depth = self.depth;
+        let mut condition = self.and()?;
+        while self.keyword("or") {
+            self.enter()?;
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(condition)
+    }
+
+    // and := unary ("and" unary)*
+    fn and(&mut self) -> Result<Condition, String> {
+        let depth = self.depth;
+        let mut condition = self.unary()?;
+        while self.keyword("and") {
+            self.enter()?;
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(condition)
+    }
+
+    // unary := "not" unary | "(" cond ")" | sensor [quantity] op number
+    fn unary(&mut self) -> Result<Condition, String> {
+        if self.keyword("not") {
+            self.enter()?;
+            let condition = Condition::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(condition);
+        }
+
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            self.enter()?;
+            let condition = self.or()?;
+            self.expect(Token::Close)?;
+            self.depth -= 1;
+            return Ok(condition);
+        }
+
+        let sensor = Id::new(self.word("a sensor id")?);
+
+        let unit = match self.peek() {
+            Some(Token::Word(quantity)) => {
+                let quantity = quantity.clone();
+                self.position += 1;
+                match QUANTITIES.iter().find(|(name, _)| name.eq_ignore_ascii_case(quantity.as_str())) {
+                    Some((_, unit)) => Some(*unit),
+                    None => {
+                        let names: Vec<&str> = QUANTITIES.iter().map(|(name, _)| *name).collect();
+                        return Err(format!("unknown quantity \"{}\", expected one of {}", quantity, names.join(", ")));
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let op = match self.next("a comparison")? {
+            Token::Op(op) => op,
+            other => return Err(format!("expected a comparison, found {}", other)),
+        };
+
+        Ok(Condition::Compare {
+            sensor,
+            unit,
+            op,
+            value: self.number()?,
+        })
+    }
+
+    // action := name "(" number ")" "on" actuator
+    fn action(&mut self) -> Result<Action, String> {
+        let name = self.word("a command name")?;
+        self.expect(Token::Open)?;
+        let value = self.number()?;
+        self.expect(Token::Close)?;
+        self.expect_keyword("on")?;
+        let actuator = Id::new(self.word("an actuator id")?);
+
+        Ok(Action {
+            actuator,
+            command: format!(r#"{{"name":"{}","value":"{}"}}"#, escape(name.as_str()), value),
+        })
+    }
+}
+
+impl Rule {
+    /// Returns the `Action`s of this `Rule` if its `Condition` holds for the current `readings`, or else none.
+    ///
+    /// `readings` should hold the latest `Datum` of each `Sensor` which is online, only.
+    pub fn fire(&self, readings: &HashMap<Id, Datum>) -> &[Action] {
+        if self.condition.holds(readings) {
+            self.actions.as_slice()
+        } else {
+            &[]
+        }
+    }
+
+    /// Attempts to parse a `Rule` from the provided string or string slice (see [`Rule`]).
+    pub fn parse<S: AsRef<str>>(s: S) -> Result<Rule, String> {
+        let source = s.as_ref().trim();
+
+        let mut parser = Parser {
+            tokens: Self::tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+
+        // rule := "if" cond "then" action ("," action)*
+        parser.expect_keyword("if")?;
+        let condition = parser.or()?;
+        parser.expect_keyword("then")?;
+
+        let mut actions = vec![parser.action()?];
+        while parser.peek() == Some(&Token::Comma) {
+            parser.position += 1;
+            actions.push(parser.action()?);
+        }
+
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} after the last action", token));
+        }
+
+        Ok(Rule {
+            source: source.to_string(),
+            condition,
+            actions,
+        })
+    }
+
+    fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                '>' | '<' | '=' | '!' => {
+                    let equals = chars.next_if_eq(&'=').is_some();
+                    match (c, equals) {
+                        ('>', false) => Token::Op(Op::Gt),
+                        ('>', true) => Token::Op(Op::Ge),
+                        ('<', false) => Token::Op(Op::Lt),
+                        ('<', true) => Token::Op(Op::Le),
+                        ('=', true) => Token::Op(Op::Eq),
+                        ('!', true) => Token::Op(Op::Ne),
+                        _ => return Err(format!("unknown operator \"{}\", expected one of >, >=, <, <=, ==, !=", c)),
+                    }
+                }
+                c => {
+                    let mut word = String::from(c);
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()<>=!,".contains(*c)) {
+                        word.push(c);
+                    }
+                    Token::Word(word)
+                }
+            };
+
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+
+    /// Serializes `rules` as a JSON array of `{"id":...,"rule":...}` objects, sorted by `Id`.
+    pub fn all_to_json(rules: &HashMap<Id, Rule>) -> String {
+        let mut rules: Vec<(&Id, &Rule)> = rules.iter().collect();
+        rules.sort_by_key(|(id, _)| id.to_string());
+
+        let rules: Vec<String> = rules
+            .into_iter()
+            .map(|(id, rule)| format!(r#"{{"id":"{}","rule":"{}"}}"#, escape(id.to_string().as_str()), escape(rule.source.as_str())))
+            .collect();
+
+        format!("[{}]", rules.join(","))
+    }
+
+    /// Parses the JSON produced by [`all_to_json`](Self::all_to_json).
+    pub fn all_from_json<S: AsRef<str>>(s: S) -> Result<HashMap<Id, Rule>, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        let entries = json.as_array().ok_or(String::from("rules must be a JSON array"))?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                let id = entry.get("id").and_then(Value::as_str).ok_or(String::from("rule is missing an \"id\""))?;
+                let rule = entry.get("rule").and_then(Value::as_str).ok_or(format!("no rule for id {}", id))?;
+                Ok((Id::new(id), Self::parse(rule)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod rule_tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    const RULE: &str = "if zone-a temperature > 27 and zone-b temperature > 27 then CoolBy(3) on hvac-main";
+
+    fn data(readings: &[(&str, Datum)]) -> HashMap<Id, Datum> {
+        readings.iter().map(|(id, datum)| (Id::new(*id), datum.clone())).collect()
+    }
+
+    fn celsius(t: f32) -> Datum {
+        Datum::new(t, Unit::DegreesC, Utc::now())
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = Rule::parse(RULE).unwrap();
+
+        let compare = |sensor: &str| Condition::Compare {
+            sensor: Id::new(sensor),
+            unit: Some(Unit::DegreesC),
+            op: Op::Gt,
+            value: 27.0,
+        };
+
+        assert_eq!(rule.condition, Condition::And(Box::new(compare("zone-a")), Box::new(compare("zone-b"))));
+        assert_eq!(
+            rule.actions,
+            vec![Action {
+                actuator: Id::new("hvac-main"),
+                command: String::from(r#"{"name":"CoolBy","value":"3"}"#)
+            }]
+        );
+        assert_eq!(rule.to_string(), RULE);
+    }
+
+    #[test]
+    fn test_precedence() {
+        // "and" binds more tightly than "or", and "not" more tightly than both
+        let rule = Rule::parse("if a > 1 or not b < 2 and c == 3 then HeatBy(1) on x").unwrap();
+
+        let compare = |sensor: &str, op: Op, value: f32| {
+            Box::new(Condition::Compare {
+                sensor: Id::new(sensor),
+                unit: None,
+                op,
+                value,
+            })
+        };
+
+        assert_eq!(
+            rule.condition,
+            Condition::Or(
+                compare("a", Op::Gt, 1.0),
+                Box::new(Condition::And(Box::new(Condition::Not(compare("b", Op::Lt, 2.0))), compare("c", Op::Eq, 3.0)))
+            )
+        );
+
+        let grouped = Rule::parse("if (a > 1 or b < 2) and c == 3 then HeatBy(1) on x").unwrap();
+        assert!(matches!(grouped.condition, Condition::And(_, _)));
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(Rule::parse(""), Err(String::from("expected \"if\", found end of rule")));
+        assert_eq!(
+            Rule::parse("if a humidity > 50 then CoolBy(1) on x"),
+            Err(String::from("unknown quantity \"humidity\", expected one of temperature"))
+        );
+        assert_eq!(
+            Rule::parse("if a = 1 then CoolBy(1) on x"),
+            Err(String::from("unknown operator \"=\", expected one of >, >=, <, <=, ==, !="))
+        );
+        assert_eq!(
+            Rule::parse("if a > warm then CoolBy(1) on x"),
+            Err(String::from("expected a number, found \"warm\""))
+        );
+        assert_eq!(Rule::parse("if a > 1 CoolBy(1) on x"), Err(String::from("expected \"then\", found \"CoolBy\"")));
+        assert_eq!(Rule::parse("if a > 1 then CoolBy(1)"), Err(String::from("expected \"on\", found end of rule")));
+        assert_eq!(
+            Rule::parse("if a > 1 then CoolBy(1) on x y"),
+            Err(String::from("unexpected \"y\" after the last action"))
+        );
+    }
+
+    #[test]
+    fn test_parse_too_deep() {
+        let too_deep = Err(format!("condition is nested more than {} levels deep", MAX_DEPTH));
+
+        let nots = format!("if {} a > 1 then CoolBy(1) on x", "not ".repeat(100_000));
+        assert_eq!(Rule::parse(nots), too_deep);
+
+        let parentheses = format!("if {}a > 1{} then CoolBy(1) on x", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(Rule::parse(parentheses), too_deep);
+
+        let conjunction = vec!["a > 1"; 100_000].join(" and ");
+        assert_eq!(Rule::parse(format!("if {} then CoolBy(1) on x", conjunction)), too_deep);
+
+        // ...but a Rule can be nested right up to the limit
+        let nots = format!("if {} a > 1 then CoolBy(1) on x", "not ".repeat(MAX_DEPTH));
+        assert!(Rule::parse(nots).is_ok());
+    }
+
+    #[test]
+    fn test_fire() {
+        let rule = Rule::parse(RULE).unwrap();
+
+        assert_eq!(
+            rule.fire(&data(&[("zone-a", celsius(28.0)), ("zone-b", celsius(29.0))])),
+            rule.actions.as_slice()
+        );
+        assert!(rule.fire(&data(&[("zone-a", celsius(28.0)), ("zone-b", celsius(26.0))])).is_empty());
+
+        // a Sensor with no data, or with data which is not a temperature, never satisfies a comparison
+        assert!(rule.fire(&data(&[("zone-a", celsius(28.0))])).is_empty());
+        let powered = Datum::new(true, Unit::PoweredOn, Utc::now());
+        assert!(rule.fire(&data(&[("zone-a", celsius(28.0)), ("zone-b", powered)])).is_empty());
+    }
+
+    #[test]
+    fn test_one_sensor_drives_several_actuators() {
+        let rule = Rule::parse("if lobby < 18 then HeatBy(2) on lobby, HeatBy(1.5) on hall").unwrap();
+
+        let actions = rule.fire(&data(&[("lobby", celsius(17.0))]));
+        let actuators: Vec<String> = actions.iter().map(|action| action.actuator.to_string()).collect();
+
+        assert_eq!(actuators, vec!["lobby", "hall"]);
+        assert_eq!(actions[1].command, r#"{"name":"HeatBy","value":"1.5"}"#);
+    }
+
+    #[test]
+    fn test_missing_readings() {
+        // a Sensor with no current reading can make a Condition hold, or not, only through `not`...
+        let rule = Rule::parse("if not lobby > 25 then HeatBy(1) on lobby").unwrap();
+        assert!(rule.fire(&data(&[])).is_empty());
+        assert!(!rule.fire(&data(&[("lobby", celsius(20.0))])).is_empty());
+
+        // ...but a Condition which holds whatever its reading would have been still holds
+        let rule = Rule::parse("if lobby > 25 or hall > 25 then CoolBy(1) on lobby").unwrap();
+        assert!(!rule.fire(&data(&[("lobby", celsius(30.0))])).is_empty());
+        assert!(rule.fire(&data(&[("lobby", celsius(20.0))])).is_empty());
+
+        let rule = Rule::parse("if not (lobby > 25 and hall > 25) then HeatBy(1) on lobby").unwrap();
+        assert!(!rule.fire(&data(&[("lobby", celsius(20.0))])).is_empty());
+        assert!(rule.fire(&data(&[("lobby", celsius(30.0))])).is_empty());
+    }
+
+    #[test]
+    fn test_all_to_and_from_json() {
+        let rules = HashMap::from([
+            (Id::new("b"), Rule::parse(RULE).unwrap()),
+            (Id::new("a"), Rule::parse("if x != 0 then HeatBy(1) on y").unwrap()),
+        ]);
+
+        let json = Rule::all_to_json(&rules);
+        assert!(json.starts_with(r#"[{"id":"a","rule":"if x != 0 then HeatBy(1) on y"},{"id":"b""#));
+        assert_eq!(Rule::all_from_json(json), Ok(rules));
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Timelike, Utc, Weekday};
+
+use device::id::Id;
+use device::json::{escape, Value};
+
+/// The names of the days of the week, as they appear in a `Schedule`'s JSON, starting on Monday.
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const WEEKDAYS: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+/// Every week, on each of the `days`, the setpoint changes to `setpoint` `at` this (local) time.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Change {
+    pub days: Vec<Weekday>,
+    pub at: NaiveTime,
+    pub setpoint: f32,
+}
+
+/// Between `from` and `until`, the setpoint is `setpoint`, whatever the weekly `Change`s say.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Exception {
+    pub from: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub setpoint: f32,
+}
+
+/// A `Schedule` changes the setpoint of a `Sensor` / `Actuator` pair over time, e.g.
+///
+/// ```json
+/// {
+///   "offset": "+01:00",
+///   "weekly": [
+///     {"days": ["mon","tue","wed","thu","fri"], "at": "07:00", "setpoint": 21},
+///     {"days": ["mon","tue","wed","thu","fri"], "at": "22:00", "setpoint": 17}
+///   ],
+///   "exceptions": [{"from": "2024-12-24T00:00:00Z", "until": "2024-12-27T00:00:00Z", "setpoint": 12}]
+/// }
+/// ```
+///
+/// Each weekly `Change` lasts until the next one, wrapping around from the end of the week to the
+/// start. Weekly times are local to the UTC `offset`, which is `+00:00` if it is left out.
+///
+/// **Design Decision**: a fixed UTC offset is used, rather than a named time zone, so that no time
+/// zone database is needed. Daylight saving time can be handled with `exceptions`, or by replacing
+/// the `Schedule` when the clocks change.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Schedule {
+    pub offset: FixedOffset,
+    pub weekly: Vec<Change>,
+    pub exceptions: Vec<Exception>,
+}
+
+/// Allows `Schedule`s to be converted to JSON `String`s with `to_string()`.
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let weekly: Vec<String> = self
+            .weekly
+            .iter()
+            .map(|change| {
+                let days: Vec<String> = change
+                    .days
+                    .iter()
+                    .map(|day| format!(r#""{}""#, DAYS[day.num_days_from_monday() as usize]))
+                    .collect();
+                format!(
+                    r#"{{"days":[{}],"at":"{}","setpoint":{}}}"#,
+                    days.join(","),
+                    change.at.format("%H:%M"),
+                    change.setpoint
+                )
+            })
+            .collect();
+
+        let exceptions: Vec<String> = self
+            .exceptions
+            .iter()
+            .map(|exception| {
+                format!(
+                    r#"{{"from":"{}","until":"{}","setpoint":{}}}"#,
+                    exception.from.to_rfc3339(),
+                    exception.until.to_rfc3339(),
+                    exception.setpoint
+                )
+            })
+            .collect();
+
+        write!(
+            f,
+            r#"{{"offset":"{}","weekly":[{}],"exceptions":[{}]}}"#,
+            self.offset,
+            weekly.join(","),
+            exceptions.join(",")
+        )
+    }
+}
+
+impl Schedule {
+    /// Returns the setpoint which this `Schedule` says should be used at time `now`, if any.
+    ///
+    /// An `Exception` which covers `now` wins over the weekly `Change`s. If more than one does, the
+    /// one which started most recently wins.
+    pub fn setpoint_at(&self, now: DateTime<Utc>) -> Option<f32> {
+        let exception = self
+            .exceptions
+            .iter()
+            .filter(|exception| exception.from <= now && now < exception.until)
+            .max_by_key(|exception| exception.from);
+
+        if let Some(exception) = exception {
+            return Some(exception.setpoint);
+        }
+
+        let second_of_week = |day: Weekday, at: NaiveTime| day.num_days_from_monday() * 24 * 60 * 60 + at.num_seconds_from_midnight();
+
+        let local = now.with_timezone(&self.offset);
+        let current = second_of_week(local.weekday(), local.time());
+
+        let changes: Vec<(u32, f32)> = self
+            .weekly
+            .iter()
+            .flat_map(|change| change.days.iter().map(|day| (second_of_week(*day, change.at), change.setpoint)))
+            .collect();
+
+        // the latest Change this week, or else the last Change of last week
+        changes
+            .iter()
+            .filter(|(second, _)| *second <= current)
+            .max_by_key(|(second, _)| *second)
+            .or_else(|| changes.iter().max_by_key(|(second, _)| *second))
+            .map(|(_, setpoint)| *setpoint)
+    }
+
+    /// Attempts to parse a `Schedule` from the provided JSON (see [`Schedule`]).
+    pub fn parse<S: AsRef<str>>(s: S) -> Result<Schedule, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        Self::from_json(&json)
+    }
+
+    fn from_json(json: &Value) -> Result<Schedule, String> {
+        let offset = match json.get("offset") {
+            None => FixedOffset::east_opt(0).unwrap(),
+            Some(offset) => offset
+                .as_str()
+                .and_then(|offset| offset.parse().ok())
+                .ok_or(String::from("schedule \"offset\" must be like \"+01:00\""))?,
+        };
+
+        let weekly = match json.get("weekly") {
+            None => Vec::new(),
+            Some(weekly) => weekly
+                .as_array()
+                .ok_or(String::from("schedule \"weekly\" must be an array"))?
+                .iter()
+                .map(Self::change_from_json)
+                .collect::<Result<Vec<Change>, String>>()?,
+        };
+
+        let exceptions = match json.get("exceptions") {
+            None => Vec::new(),
+            Some(exceptions) => exceptions
+                .as_array()
+                .ok_or(String::from("schedule \"exceptions\" must be an array"))?
+                .iter()
+                .map(Self::exception_from_json)
+                .collect::<Result<Vec<Exception>, String>>()?,
+        };
+
+        Ok(Schedule { offset, weekly, exceptions })
+    }
+
+    fn setpoint_from_json(json: &Value) -> Result<f32, String> {
+        json.get("setpoint")
+            .and_then(Value::as_f64)
+            .filter(|setpoint| setpoint.is_finite())
+            .map(|setpoint| setpoint as f32)
+            .ok_or(String::from("schedule entry is missing a numeric \"setpoint\""))
+    }
+
+    fn change_from_json(json: &Value) -> Result<Change, String> {
+        let days = json
+            .get("days")
+            .and_then(Value::as_array)
+            .filter(|days| !days.is_empty())
+            .ok_or(String::from("weekly change must have an array of \"days\""))?
+            .iter()
+            .map(|day| match day.as_str().and_then(|day| DAYS.iter().position(|name| *name == day)) {
+                Some(index) => Ok(WEEKDAYS[index]),
+                None => Err(format!("unknown day {}, expected one of {}", day, DAYS.join(", "))),
+            })
+            .collect::<Result<Vec<Weekday>, String>>()?;
+
+        let at = json
+            .get("at")
+            .and_then(Value::as_str)
+            .and_then(|at| NaiveTime::parse_from_str(at, "%H:%M").ok())
+            .ok_or(String::from("weekly change must have a time \"at\", like \"07:30\""))?;
+
+        Ok(Change {
+            days,
+            at,
+            setpoint: Self::setpoint_from_json(json)?,
+        })
+    }
+
+    fn exception_from_json(json: &Value) -> Result<Exception, String> {
+        let timestamp = |key: &str| {
+            json.get(key)
+                .and_then(Value::as_str)
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .ok_or(format!("exception must have an RFC 3339 timestamp \"{}\"", key))
+        };
+
+        let (from, until) = (timestamp("from")?, timestamp("until")?);
+        if until <= from {
+            return Err(String::from("exception must end (\"until\") after it starts (\"from\")"));
+        }
+
+        Ok(Exception {
+            from,
+            until,
+            setpoint: Self::setpoint_from_json(json)?,
+        })
+    }
+
+    /// Serializes `schedules` as a JSON array of `{"id":...,"schedule":...}` objects, sorted by `Id`.
+    pub fn all_to_json(schedules: &HashMap<Id, Schedule>) -> String {
+        let mut schedules: Vec<(&Id, &Schedule)> = schedules.iter().collect();
+        schedules.sort_by_key(|(id, _)| id.to_string());
+
+        let schedules: Vec<String> = schedules
+            .into_iter()
+            .map(|(id, schedule)| format!(r#"{{"id":"{}","schedule":{}}}"#, escape(id.to_string().as_str()), schedule))
+            .collect();
+
+        format!("[{}]", schedules.join(","))
+    }
+
+    /// Parses the JSON produced by [`all_to_json`](Self::all_to_json).
+    pub fn all_from_json<S: AsRef<str>>(s: S) -> Result<HashMap<Id, Schedule>, String> {
+        let json = Value::parse(s).map_err(|e| e.to_string())?;
+        let entries = json.as_array().ok_or(String::from("schedules must be a JSON array"))?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                let id = entry.get("id").and_then(Value::as_str).ok_or(String::from("schedule is missing an \"id\""))?;
+                let schedule = entry.get("schedule").ok_or(format!("no schedule for id {}", id))?;
+                Ok((Id::new(id), Self::from_json(schedule)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod schedule_tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const JSON: &str = r#"{"offset":"+01:00","weekly":[{"days":["mon","tue","wed","thu","fri"],"at":"07:00","setpoint":21},{"days":["mon","tue","wed","thu","fri"],"at":"22:00","setpoint":17},{"days":["sat"],"at":"09:00","setpoint":19}],"exceptions":[{"from":"2024-01-10T00:00:00+00:00","until":"2024-01-11T00:00:00+00:00","setpoint":12}]}"#;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2024-01-01 is a Monday
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        let schedule = Schedule::parse(JSON).unwrap();
+
+        assert_eq!(schedule.offset, FixedOffset::east_opt(3600).unwrap());
+        assert_eq!(schedule.weekly.len(), 3);
+        assert_eq!(schedule.weekly[2].days, vec![Weekday::Sat]);
+        assert_eq!(schedule.exceptions[0].setpoint, 12.0);
+
+        assert_eq!(schedule.to_string(), JSON);
+
+        let empty = Schedule::parse("{}").unwrap();
+        assert_eq!(empty.to_string(), r#"{"offset":"+00:00","weekly":[],"exceptions":[]}"#);
+        assert_eq!(empty.setpoint_at(at(1, 12, 0)), None);
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(
+            Schedule::parse(r#"{"weekly":[{"days":["someday"],"at":"07:00","setpoint":21}]}"#),
+            Err(String::from(r#"unknown day "someday", expected one of mon, tue, wed, thu, fri, sat, sun"#))
+        );
+        assert_eq!(
+            Schedule::parse(r#"{"weekly":[{"days":["mon"],"at":"7am","setpoint":21}]}"#),
+            Err(String::from("weekly change must have a time \"at\", like \"07:30\""))
+        );
+        assert_eq!(
+            Schedule::parse(r#"{"weekly":[{"days":[],"at":"07:00","setpoint":21}]}"#),
+            Err(String::from("weekly change must have an array of \"days\""))
+        );
+        assert_eq!(
+            Schedule::parse(r#"{"exceptions":[{"from":"2024-01-02T00:00:00Z","until":"2024-01-01T00:00:00Z","setpoint":12}]}"#),
+            Err(String::from("exception must end (\"until\") after it starts (\"from\")"))
+        );
+        assert_eq!(
+            Schedule::parse(r#"{"offset":"CET"}"#),
+            Err(String::from("schedule \"offset\" must be like \"+01:00\""))
+        );
+    }
+
+    #[test]
+    fn test_setpoint_at() {
+        let schedule = Schedule::parse(JSON).unwrap();
+
+        // Monday 07:00 local time is 06:00 UTC
+        assert_eq!(schedule.setpoint_at(at(1, 5, 59)), Some(19.0)); // still Saturday's setpoint, from last week
+        assert_eq!(schedule.setpoint_at(at(1, 6, 0)), Some(21.0));
+        assert_eq!(schedule.setpoint_at(at(1, 20, 59)), Some(21.0));
+        assert_eq!(schedule.setpoint_at(at(1, 21, 0)), Some(17.0));
+
+        // Friday night lasts until Saturday morning, which lasts until Monday morning
+        assert_eq!(schedule.setpoint_at(at(6, 7, 59)), Some(17.0));
+        assert_eq!(schedule.setpoint_at(at(6, 8, 0)), Some(19.0));
+        assert_eq!(schedule.setpoint_at(at(7, 23, 0)), Some(19.0));
+
+        // the exception covers all of Wednesday the 10th (UTC)
+        assert_eq!(schedule.setpoint_at(at(10, 0, 0)), Some(12.0));
+        assert_eq!(schedule.setpoint_at(at(10, 12, 0)), Some(12.0));
+        assert_eq!(schedule.setpoint_at(at(11, 0, 0)), Some(17.0));
+    }
+
+    #[test]
+    fn test_overlapping_exceptions() {
+        let json = r#"{"exceptions":[
+            {"from":"2024-01-01T00:00:00Z","until":"2024-01-31T00:00:00Z","setpoint":12},
+            {"from":"2024-01-10T00:00:00Z","until":"2024-01-11T00:00:00Z","setpoint":22}
+        ]}"#;
+        let schedule = Schedule::parse(json).unwrap();
+
+        assert_eq!(schedule.setpoint_at(at(9, 12, 0)), Some(12.0));
+        assert_eq!(schedule.setpoint_at(at(10, 12, 0)), Some(22.0));
+        assert_eq!(schedule.setpoint_at(at(11, 12, 0)), Some(12.0));
+    }
+
+    #[test]
+    fn test_all_to_and_from_json() {
+        let schedules = HashMap::from([(Id::new("b"), Schedule::parse(JSON).unwrap()), (Id::new("a"), Schedule::parse("{}").unwrap())]);
+
+        let json = Schedule::all_to_json(&schedules);
+        assert!(json.starts_with(r#"[{"id":"a","schedule":{"offset":"+00:00","weekly":[],"exceptions":[]}},{"id":"b""#));
+        assert_eq!(Schedule::all_from_json(json), Ok(schedules));
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use datum::value::Value;
+use datum::Datum;
+use device::error::Error;
+use device::id::Id;
+use device::json::escape;
+
+use crate::assessor::Assess;
+
+/// Only files with this extension in the script directory are loaded as scripts.
+const EXTENSION: &str = "rhai";
+
+/// The number of previous `Datum`s which are passed to a script, along with the latest one.
+const HISTORY_SIZE: usize = 50;
+
+/// A script as it was last read from disk, and either its compiled form or the reason it could not be compiled.
+struct Loaded {
+    source: String,
+    compiled: Result<Arc<AST>, String>,
+}
+
+/// `Scripts` are custom `Assessor`s written in [Rhai](https://rhai.rs), which are loaded from a
+/// directory while the `Controller` is running, so that new behaviour can be added without rebuilding it.
+///
+/// Each script, e.g. `gentle.rhai`, defines an `assess` function, which is given the latest `Datum`
+/// from a `Sensor`, the `Datum`s before it (newest first), and the `Sensor` / `Actuator` pair, e.g.
+///
+/// ```text
+/// fn assess(datum, history, pair) {
+///     let setpoint = if pair.setpoint == () { 21.0 } else { pair.setpoint };
+///     if datum.value < setpoint - 1.0 {
+///         #{ name: "HeatBy", value: `${setpoint - datum.value}` }
+///     }
+/// }
+/// ```
+///
+/// and returns the `Command` to send to the `Actuator`, as a map or a JSON string, or `()` to send nothing.
+///
+/// **Design Decision**: scripts are sandboxed. They cannot read files, reach the network, `import`
+/// other scripts, or `eval` code, and they are stopped if they run for too long or build values which
+/// are too large, so that a broken script cannot take the `Controller` down with it.
+#[derive(Clone)]
+pub struct Scripts {
+    dir: Option<PathBuf>,
+    engine: Arc<Engine>,
+    loaded: Arc<Mutex<HashMap<String, Loaded>>>,
+}
+
+/// A `Scripts` without a directory has no scripts.
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts::new(None)
+    }
+}
+
+/// A `Command` returned by a script, serialized as JSON.
+pub struct Scripted(String);
+
+impl actuator::Command for Scripted {}
+
+/// Allows `Scripted` `Command`s to be converted to `String`s with `to_string()`.
+impl Display for Scripted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Scripts {
+    pub fn new(dir: Option<PathBuf>) -> Scripts {
+        let mut engine = Engine::new();
+
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .set_max_operations(100_000)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(10_000)
+            .set_max_array_size(10_000)
+            .set_max_map_size(1_000)
+            .on_print(|msg| debug!("[Controller] script: {}", msg));
+
+        Scripts {
+            dir,
+            engine: Arc::new(engine),
+            loaded: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Compiles every script in the directory which is new or has changed since it was last loaded,
+    /// and forgets every script which has been removed.
+    ///
+    /// A script which cannot be compiled is still listed (see [`to_json`](Self::to_json)), with the
+    /// reason why, but cannot be used to assess any data until it is fixed.
+    ///
+    /// **Design Decision**: scripts are compared by their contents, rather than by their modification
+    /// times, which can be too coarse to notice a quick edit. Scripts are small, so reading them all
+    /// again is cheap.
+    pub fn reload(&self) -> Result<(), Error> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.loaded.lock().unwrap().clear();
+                return Ok(());
+            }
+            Err(e) => return Err(Error::Io(format!("cannot read {}: {}", dir.display(), e))),
+        };
+
+        let mut sources = HashMap::new();
+
+        for entry in entries {
+            let path = entry.map_err(|e| Error::Io(format!("cannot read {}: {}", dir.display(), e)))?.path();
+
+            let name = match (path.extension(), path.file_stem()) {
+                (Some(extension), Some(name)) if extension == EXTENSION => name.to_string_lossy().to_string(),
+                _ => continue,
+            };
+
+            match std::fs::read_to_string(&path) {
+                Ok(source) => {
+                    sources.insert(name, source);
+                }
+                Err(e) => warn!("[Controller] cannot read script {}: {}", path.display(), e),
+            }
+        }
+
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.retain(|name, _| sources.contains_key(name));
+
+        for (name, source) in sources {
+            if loaded.get(&name).is_some_and(|script| script.source == source) {
+                continue;
+            }
+
+            let compiled = self.engine.compile(source.as_str()).map(Arc::new).map_err(|e| e.to_string());
+
+            match &compiled {
+                Ok(_) => debug!("[Controller] loaded script {}", name),
+                Err(e) => warn!("[Controller] cannot compile script {}: {}", name, e),
+            }
+
+            loaded.insert(name, Loaded { source, compiled });
+        }
+
+        Ok(())
+    }
+
+    /// Serializes every loaded script as a JSON array of `{"name":...,"error":...}` objects, sorted by
+    /// name, where `error` is `null` for scripts which compiled successfully.
+    pub fn to_json(&self) -> String {
+        let loaded = self.loaded.lock().unwrap();
+
+        let mut scripts: Vec<(&String, &Loaded)> = loaded.iter().collect();
+        scripts.sort_by_key(|(name, _)| name.as_str());
+
+        let scripts: Vec<String> = scripts
+            .into_iter()
+            .map(|(name, script)| {
+                let error = match &script.compiled {
+                    Ok(_) => String::from("null"),
+                    Err(e) => format!(r#""{}""#, escape(e.as_str())),
+                };
+                format!(r#"{{"name":"{}","error":{}}}"#, escape(name.as_str()), error)
+            })
+            .collect();
+
+        format!("[{}]", scripts.join(","))
+    }
+
+    /// Runs the `assess` function of the script called `name`, returning the `Command` it chose (as JSON), if any.
+    fn assess(&self, name: &str, datum: &Datum, history: &VecDeque<Datum>, pair: &Map) -> Result<Option<String>, String> {
+        let ast = match self.loaded.lock().unwrap().get(name) {
+            None => return Err(format!("no script named {}", name)),
+            Some(script) => script.compiled.clone().map_err(|e| format!("script {} does not compile: {}", name, e))?,
+        };
+
+        let history: Array = history.iter().map(|datum| Dynamic::from_map(Self::datum_to_map(datum))).collect();
+        let args = (
+            Dynamic::from_map(Self::datum_to_map(datum)),
+            Dynamic::from_array(history),
+            Dynamic::from_map(pair.clone()),
+        );
+
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &ast, "assess", args)
+            .map_err(|e| format!("script {} failed: {}", name, e))?;
+
+        if result.is_unit() {
+            Ok(None)
+        } else if result.is_map() {
+            Ok(Some(rhai::format_map_as_json(&result.cast::<Map>())))
+        } else if result.is_string() {
+            Ok(Some(result.into_string().unwrap()))
+        } else {
+            Err(format!("script {} returned a {}, expected a map, a string, or ()", name, result.type_name()))
+        }
+    }
+
+    /// Converts a `Datum` to a map like `#{value: 21.3, unit: "°C", timestamp: "2024-01-05T17:14:39.963+00:00"}`.
+    fn datum_to_map(datum: &Datum) -> Map {
+        let value = match datum.value {
+            Value::Bool(value) => Dynamic::from_bool(value),
+            // an f32 is converted via its shortest representation, so that e.g. 21.3 does not become 21.299999237060547
+            Value::Float(value) => Dynamic::from_float(value.to_string().parse().unwrap_or(value as f64)),
+            Value::Int(value) => Dynamic::from_int(value as i64),
+        };
+
+        let mut map = Map::new();
+        map.insert("value".into(), value);
+        map.insert("unit".into(), datum.unit.to_string().into());
+        map.insert("timestamp".into(), datum.timestamp.to_rfc3339().into());
+        map
+    }
+
+    /// Creates a new [`Assess`] which assesses the data from the `Sensor` with this `id` using the
+    /// script called `name`, which has not seen any data yet.
+    pub fn build(&self, name: &str, id: &Id, setpoint: Option<f32>) -> Box<dyn Assess> {
+        let mut pair = Map::new();
+        pair.insert("id".into(), id.to_string().into());
+
+        let mut script = Script {
+            scripts: self.clone(),
+            name: name.to_string(),
+            pair,
+            history: VecDeque::new(),
+        };
+
+        script.set_setpoint(setpoint);
+        Box::new(script)
+    }
+}
+
+/// A `Script` assesses the data from a single `Sensor`, remembering its most recent `Datum`s.
+///
+/// **Design Decision**: the script is looked up by name each time a `Datum` is assessed, rather than
+/// once when the `Script` is built, so that a script which is edited takes effect immediately, without
+/// forgetting the `Sensor`'s history.
+struct Script {
+    scripts: Scripts,
+    name: String,
+    pair: Map,
+    history: VecDeque<Datum>,
+}
+
+impl Assess for Script {
+    fn assess(&mut self, datum: &Datum) -> Option<Box<dyn actuator::Command>> {
+        let assessed = self.scripts.assess(self.name.as_str(), datum, &self.history, &self.pair);
+
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_back();
+        }
+        self.history.push_front(datum.clone());
+
+        match assessed {
+            Ok(command) => command.map(|command| Box::new(Scripted(command)) as Box<dyn actuator::Command>),
+            Err(e) => {
+                warn!("[Controller] {}", e);
+                None
+            }
+        }
+    }
+
+    /// Passes the new `setpoint` to the script as `pair.setpoint`, keeping this `Sensor`'s history.
+    fn set_setpoint(&mut self, setpoint: Option<f32>) {
+        self.pair.insert(
+            "setpoint".into(),
+            setpoint.map(|setpoint| Dynamic::from_float(setpoint as f64)).unwrap_or(Dynamic::UNIT),
+        );
+    }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use chrono::Utc;
+
+    use datum::unit::Unit;
+
+    use super::*;
+
+    const GENTLE: &str = r#"
+        fn assess(datum, history, pair) {
+            if datum.unit != "°C" { return; }
+            let error = pair.setpoint - datum.value;
+            if error > 1.0 {
+                #{ name: "HeatBy", value: `${error / 2.0}` }
+            } else if error < -1.0 {
+                `{"name":"CoolBy","value":"${-error / 2.0}"}`
+            }
+        }
+    "#;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("controller-scripts-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn celsius(t: f32) -> Datum {
+        Datum::new(t, Unit::DegreesC, Utc::now())
+    }
+
+    fn assess(assess: &mut Box<dyn Assess>, datum: &Datum) -> Option<String> {
+        assess.assess(datum).map(|command| command.to_string())
+    }
+
+    #[test]
+    fn test_assess() {
+        let dir = dir("assess");
+        std::fs::write(dir.join("gentle.rhai"), GENTLE).unwrap();
+
+        let scripts = Scripts::new(Some(dir.clone()));
+        scripts.reload().unwrap();
+        assert_eq!(scripts.to_json(), r#"[{"name":"gentle","error":null}]"#);
+
+        let mut gentle = scripts.build("gentle", &Id::new("my_id"), Some(20.0));
+
+        assert_eq!(assess(&mut gentle, &celsius(17.0)), Some(String::from(r#"{"name":"HeatBy","value":"1.5"}"#)));
+        assert_eq!(assess(&mut gentle, &celsius(24.0)), Some(String::from(r#"{"name":"CoolBy","value":"2.0"}"#)));
+        assert_eq!(assess(&mut gentle, &celsius(20.5)), None);
+        assert_eq!(assess(&mut gentle, &Datum::new(true, Unit::PoweredOn, Utc::now())), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = dir("history");
+        let script = r#"
+            fn assess(datum, history, pair) {
+                if history.len() >= 2 && datum.value > history[0].value && history[0].value > history[1].value {
+                    #{ name: "CoolBy", value: `${history.len()}`, id: pair.id }
+                }
+            }
+        "#;
+        std::fs::write(dir.join("trend.rhai"), script).unwrap();
+
+        let scripts = Scripts::new(Some(dir.clone()));
+        scripts.reload().unwrap();
+
+        let mut trend = scripts.build("trend", &Id::new("my_id"), None);
+
+        // the temperature must rise twice in a row before the script cools
+        assert_eq!(assess(&mut trend, &celsius(20.0)), None);
+        assert_eq!(assess(&mut trend, &celsius(21.0)), None);
+        assert_eq!(
+            assess(&mut trend, &celsius(22.0)),
+            Some(String::from(r#"{"id":"my_id","name":"CoolBy","value":"2"}"#))
+        );
+
+        // only the most recent Datums are kept
+        for _ in 0..HISTORY_SIZE {
+            assess(&mut trend, &celsius(19.0));
+        }
+        assert_eq!(assess(&mut trend, &celsius(19.5)), None);
+        assert_eq!(
+            assess(&mut trend, &celsius(20.0)),
+            Some(format!(r#"{{"id":"my_id","name":"CoolBy","value":"{}"}}"#, HISTORY_SIZE))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hot_reload() {
+        let dir = dir("reload");
+        let scripts = Scripts::new(Some(dir.clone()));
+        let mut always = scripts.build("always", &Id::new("my_id"), None);
+
+        // a script which has not been loaded yet assesses nothing
+        assert_eq!(assess(&mut always, &celsius(20.0)), None);
+
+        std::fs::write(
+            dir.join("always.rhai"),
+            r#"fn assess(datum, history, pair) { #{ name: "HeatBy", value: "1" } }"#,
+        )
+        .unwrap();
+        scripts.reload().unwrap();
+        assert_eq!(assess(&mut always, &celsius(20.0)), Some(String::from(r#"{"name":"HeatBy","value":"1"}"#)));
+
+        // an edited script takes effect without building a new Assess
+        std::fs::write(
+            dir.join("always.rhai"),
+            r#"fn assess(datum, history, pair) { #{ name: "HeatBy", value: "2" } }"#,
+        )
+        .unwrap();
+        scripts.reload().unwrap();
+        assert_eq!(assess(&mut always, &celsius(20.0)), Some(String::from(r#"{"name":"HeatBy","value":"2"}"#)));
+
+        // a script which no longer compiles is listed with the reason why, and assesses nothing
+        std::fs::write(dir.join("always.rhai"), "fn assess(datum, history, pair) {").unwrap();
+        scripts.reload().unwrap();
+        assert!(scripts.to_json().starts_with(r#"[{"name":"always","error":""#));
+        assert_eq!(assess(&mut always, &celsius(20.0)), None);
+
+        // files without the .rhai extension are ignored, and removed scripts are forgotten
+        std::fs::write(dir.join("notes.txt"), "not a script").unwrap();
+        std::fs::remove_file(dir.join("always.rhai")).unwrap();
+        scripts.reload().unwrap();
+        assert_eq!(scripts.to_json(), "[]");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = dir("sandbox");
+        std::fs::write(dir.join("forever.rhai"), "fn assess(datum, history, pair) { loop {} }").unwrap();
+        std::fs::write(
+            dir.join("importer.rhai"),
+            r#"import "other" as other; fn assess(datum, history, pair) { other::assess(datum, history, pair) }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("number.rhai"), "fn assess(datum, history, pair) { 42 }").unwrap();
+
+        let scripts = Scripts::new(Some(dir.clone()));
+        scripts.reload().unwrap();
+
+        let id = Id::new("my_id");
+        let datum = celsius(20.0);
+
+        let forever = scripts.assess("forever", &datum, &VecDeque::new(), &Map::new());
+        assert!(forever.is_err_and(|e| e.contains("Too many operations")));
+
+        let importer = scripts.assess("importer", &datum, &VecDeque::new(), &Map::new());
+        assert!(importer.is_err());
+
+        let number = scripts.assess("number", &datum, &VecDeque::new(), &Map::new());
+        assert_eq!(number, Err(String::from("script number returned a i64, expected a map, a string, or ()")));
+
+        assert_eq!(assess(&mut scripts.build("missing", &id, None), &datum), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+
+use datum::filter::Filter;
+use datum::Datum;
+use device::error::Error;
+use device::id::Id;
+use device::json::{escape, Value};
+
+use crate::storage::{select, Storage};
+
+/// The extension of segment files. Any other files in a `Segments` directory are ignored.
+const EXTENSION: &str = "seg";
+
+/// Each segment holds the data timestamped within one hour.
+const SEGMENT_SECONDS: i64 = 60 * 60;
+
+/// `Segments` stores data in append-only files, one for each hour, in a directory.
+///
+/// Each line of a segment is one `Datum`, preceded by the (escaped) `Id` of the `Sensor` which sent
+/// it and a tab. Segments are named after the (Unix) second at which they start.
+///
+/// **Design Decision**: a `Datum` is only ever appended to the end of a segment, and old data is
+/// forgotten by deleting whole segments, so no file is ever rewritten. A `Controller` which stops
+/// part way through appending leaves at most one half-written line, which is skipped when read.
+pub struct Segments {
+    dir: PathBuf,
+    current: Mutex<Option<(i64, File)>>,
+}
+
+impl Segments {
+    /// Opens the `Segments` in `dir`, which is created if it does not already exist.
+    pub fn open(dir: PathBuf) -> Result<Segments, Error> {
+        std::fs::create_dir_all(&dir).map_err(|e| Error::Io(format!("cannot create {}: {}", dir.display(), e)))?;
+
+        Ok(Segments {
+            dir,
+            current: Mutex::new(None),
+        })
+    }
+
+    /// Returns the start of the segment which holds data timestamped at `timestamp`.
+    fn start_of(timestamp: DateTime<Utc>) -> i64 {
+        timestamp.timestamp().div_euclid(SEGMENT_SECONDS) * SEGMENT_SECONDS
+    }
+
+    fn path(&self, start: i64) -> PathBuf {
+        self.dir.join(format!("{}.{}", start, EXTENSION))
+    }
+
+    /// Returns the start of every segment in this directory, newest first.
+    fn starts(&self) -> Result<Vec<i64>, Error> {
+        let entries = std::fs::read_dir(&self.dir).map_err(|e| Error::Io(format!("cannot read {}: {}", self.dir.display(), e)))?;
+
+        let mut starts: Vec<i64> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == EXTENSION))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+            .collect();
+
+        starts.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(starts)
+    }
+
+    /// Returns every `(Id, Datum)` in the segment which starts at `start`, newest first.
+    fn read(&self, start: i64) -> Result<Vec<(Id, Datum)>, Error> {
+        let path = self.path(start);
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()), // pruned while we were reading
+            Err(e) => return Err(Error::Io(format!("cannot read {}: {}", path.display(), e))),
+        };
+
+        let rows = contents.lines().rev().filter_map(|line| match Self::parse_line(line) {
+            Ok(row) => Some(row),
+            Err(e) => {
+                debug!("[Segments] skipping line in {}: {}", path.display(), e);
+                None
+            }
+        });
+
+        Ok(rows.collect())
+    }
+
+    fn parse_line(line: &str) -> Result<(Id, Datum), String> {
+        let (id, datum) = line.split_once('\t').ok_or("no tab")?;
+
+        let id = Value::parse(format!(r#""{}""#, id)).map_err(|e| e.to_string())?;
+        let id = id.as_str().ok_or("Id is not a string")?;
+
+        Ok((Id::new(id), Datum::parse(datum)?))
+    }
+}
+
+impl Storage for Segments {
+    fn append(&self, id: &Id, datum: &Datum) -> Result<(), Error> {
+        let start = Self::start_of(datum.timestamp);
+        let mut current = self.current.lock().unwrap();
+
+        let file = match current.as_mut() {
+            Some((current_start, file)) if *current_start == start => file,
+            _ => {
+                let path = self.path(start);
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| Error::Io(format!("cannot open {}: {}", path.display(), e)))?;
+                &mut current.insert((start, file)).1
+            }
+        };
+
+        // the whole line is written at once, so that concurrent readers never see part of a Datum
+        let line = format!("{}\t{}\n", escape(id.to_string().as_str()), datum);
+        file.write_all(line.as_bytes())
+            .map_err(|e| Error::Io(format!("cannot append to {}: {}", self.path(start).display(), e)))
+    }
+
+    fn query(&self, filter: &Filter, ids: Option<&[Id]>) -> Result<Vec<(Id, Vec<Datum>)>, Error> {
+        let mut rows = Vec::new();
+
+        // segments are read newest first, so once each requested Sensor has `offset + limit` Datums
+        // within the filter, older segments cannot change the result and do not need to be read
+        let wanted = filter.limit.map(|limit| filter.offset.saturating_add(limit));
+        let mut found: HashMap<Id, usize> = HashMap::new();
+
+        for start in self.starts()? {
+            if let (Some(ids), Some(wanted)) = (ids, wanted) {
+                if ids.iter().all(|id| found.get(id).is_some_and(|n| *n >= wanted)) {
+                    break;
+                }
+            }
+
+            // segments which hold no data between `since` and `until` do not need to be read
+            let begins = DateTime::from_timestamp(start, 0).unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let ends = DateTime::from_timestamp(start + SEGMENT_SECONDS, 0).unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+            if filter.since.is_some_and(|since| ends <= since) {
+                break;
+            } else if filter.until.is_some_and(|until| begins > until) {
+                continue;
+            }
+
+            for (id, datum) in self.read(start)? {
+                if ids.is_none_or(|ids| ids.contains(&id)) {
+                    if filter.contains(&datum) {
+                        *found.entry(id.clone()).or_default() += 1;
+                    }
+                    rows.push((id, datum));
+                }
+            }
+        }
+
+        Ok(select(rows, filter, ids))
+    }
+
+    fn prune(&self, before: DateTime<Utc>) -> Result<(), Error> {
+        let mut current = self.current.lock().unwrap();
+
+        for start in self.starts()? {
+            if start + SEGMENT_SECONDS > before.timestamp() {
+                continue;
+            }
+
+            if current.as_ref().is_some_and(|(current_start, _)| *current_start == start) {
+                *current = None;
+            }
+
+            let path = self.path(start);
+            std::fs::remove_file(&path).map_err(|e| Error::Io(format!("cannot remove {}: {}", path.display(), e)))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod segments_tests {
+    use chrono::TimeZone;
+
+    use datum::unit::Unit;
+
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("controller-segments-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_append_and_query() {
+        let dir = dir("query");
+        let segments = Segments::open(dir.clone()).unwrap();
+
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let minute = chrono::Duration::minutes(1);
+
+        // 3 hours of data, from 2 Sensors, in 3 segments
+        for offset in 0..180 {
+            for sensor in ["sensor_a", "sensor\tb"] {
+                let datum = Datum::new(offset as f32, Unit::DegreesC, t0 + minute * offset);
+                segments.append(&Id::new(sensor), &datum).unwrap();
+            }
+        }
+
+        assert_eq!(segments.starts().unwrap().len(), 3);
+
+        let filter = Filter {
+            since: Some(t0 + minute * 50),
+            until: Some(t0 + minute * 70),
+            limit: Some(3),
+            offset: 1,
+        };
+
+        let expected: Vec<Datum> = [69, 68, 67]
+            .into_iter()
+            .map(|offset| Datum::new(offset as f32, Unit::DegreesC, t0 + minute * offset))
+            .collect();
+
+        let actual = segments.query(&filter, None).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor\tb"), expected.clone()), (Id::new("sensor_a"), expected.clone())]);
+
+        // data survives restarts
+        let reopened = Segments::open(dir.clone()).unwrap();
+        let actual = reopened.query(&filter, Some(&[Id::new("sensor_a")])).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor_a"), expected)]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_query_stops_early() {
+        let dir = dir("early");
+        let segments = Segments::open(dir.clone()).unwrap();
+
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let minute = chrono::Duration::minutes(1);
+
+        for offset in 0..180 {
+            let datum = Datum::new(offset as f32, Unit::DegreesC, t0 + minute * offset);
+            segments.append(&Id::new("sensor_a"), &datum).unwrap();
+        }
+
+        // replace the oldest segment with something which cannot be read
+        let oldest = segments.path(Segments::start_of(t0));
+        std::fs::remove_file(&oldest).unwrap();
+        std::fs::create_dir(&oldest).unwrap();
+
+        let filter = Filter {
+            limit: Some(10),
+            offset: 5,
+            ..Filter::default()
+        };
+
+        let actual = segments.query(&filter, Some(&[Id::new("sensor_a")])).unwrap();
+        let expected: Vec<f32> = (165..175).rev().map(|n| n as f32).collect();
+        assert_eq!(actual[0].1.iter().filter_map(|datum| datum.get_as_float()).collect::<Vec<f32>>(), expected);
+
+        // without a limit, every segment must be read
+        assert!(segments.query(&Filter::default(), Some(&[Id::new("sensor_a")])).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_half_written_line() {
+        let dir = dir("half");
+        let segments = Segments::open(dir.clone()).unwrap();
+
+        let datum = Datum::new(1.0, Unit::DegreesC, Utc::now());
+        segments.append(&Id::new("sensor_a"), &datum).unwrap();
+
+        let path = segments.path(Segments::start_of(datum.timestamp));
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"sensor_a\t{\"value\":\"2.0\",\"un").unwrap();
+
+        let actual = segments.query(&Filter::default(), None).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor_a"), vec![datum])]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = dir("prune");
+        let segments = Segments::open(dir.clone()).unwrap();
+
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let hour = chrono::Duration::hours(1);
+
+        for offset in 0..4 {
+            segments
+                .append(&Id::new("sensor_a"), &Datum::new(offset as f32, Unit::DegreesC, t0 + hour * offset))
+                .unwrap();
+        }
+
+        // only segments which end before the cutoff are removed
+        segments.prune(t0 + hour * 2 + chrono::Duration::minutes(30)).unwrap();
+
+        let actual: Vec<f32> = segments.query(&Filter::default(), None).unwrap()[0]
+            .1
+            .iter()
+            .filter_map(|datum| datum.get_as_float())
+            .collect();
+        assert_eq!(actual, vec![3.0, 2.0]);
+
+        // appending to a pruned segment starts it again
+        segments.prune(t0 + hour * 5).unwrap();
+        segments.append(&Id::new("sensor_a"), &Datum::new(4.0, Unit::DegreesC, t0 + hour * 3)).unwrap();
+        assert_eq!(segments.query(&Filter::default(), None).unwrap()[0].1.len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
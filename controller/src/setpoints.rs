@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use device::id::Id;
+use device::json::{escape, Value};
+
+/// Attempts to parse a setpoint from the provided JSON, e.g. `{"setpoint":21}`.
+pub fn parse<S: AsRef<str>>(s: S) -> Result<f32, String> {
+    let json = Value::parse(s).map_err(|e| e.to_string())?;
+    from_json(&json)
+}
+
+fn from_json(json: &Value) -> Result<f32, String> {
+    json.get("setpoint")
+        .and_then(Value::as_f64)
+        .filter(|setpoint| setpoint.is_finite())
+        .map(|setpoint| setpoint as f32)
+        .ok_or(String::from("missing a numeric \"setpoint\""))
+}
+
+/// Serializes `setpoints` as a JSON array of `{"id":...,"setpoint":...}` objects, sorted by `Id`.
+pub fn all_to_json(setpoints: &HashMap<Id, f32>) -> String {
+    let mut setpoints: Vec<(&Id, &f32)> = setpoints.iter().collect();
+    setpoints.sort_by_key(|(id, _)| id.to_string());
+
+    let setpoints: Vec<String> = setpoints
+        .into_iter()
+        .map(|(id, setpoint)| format!(r#"{{"id":"{}","setpoint":{}}}"#, escape(id.to_string().as_str()), setpoint))
+        .collect();
+
+    format!("[{}]", setpoints.join(","))
+}
+
+/// Parses the JSON produced by [`all_to_json`].
+pub fn all_from_json<S: AsRef<str>>(s: S) -> Result<HashMap<Id, f32>, String> {
+    let json = Value::parse(s).map_err(|e| e.to_string())?;
+    let entries = json.as_array().ok_or(String::from("setpoints must be a JSON array"))?;
+
+    entries
+        .iter()
+        .map(|entry| {
+            let id = entry.get("id").and_then(Value::as_str).ok_or(String::from("setpoint is missing an \"id\""))?;
+            Ok((Id::new(id), from_json(entry)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod setpoints_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(r#"{"setpoint":21.5}"#), Ok(21.5));
+        assert_eq!(parse(r#"{"setpoint":"21"}"#), Err(String::from("missing a numeric \"setpoint\"")));
+        assert_eq!(parse("{}"), Err(String::from("missing a numeric \"setpoint\"")));
+        assert!(parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_all_to_and_from_json() {
+        let setpoints = HashMap::from([(Id::new("b"), 19.0), (Id::new("a"), 21.5)]);
+
+        let json = all_to_json(&setpoints);
+        assert_eq!(json, r#"[{"id":"a","setpoint":21.5},{"id":"b","setpoint":19}]"#);
+        assert_eq!(all_from_json(json), Ok(setpoints));
+    }
+}
@@ -0,0 +1,243 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use rusqlite::{params, params_from_iter, Connection};
+
+use datum::filter::Filter;
+use datum::Datum;
+use device::error::Error;
+use device::id::Id;
+
+use crate::storage::Storage;
+
+/// `Sqlite` stores data in a table in an embedded SQLite database.
+///
+/// Each row holds the `Id` of the `Sensor` which sent a `Datum`, the `Datum`'s timestamp (in
+/// nanoseconds since the Unix epoch, so that rows can be selected by time), and the `Datum` itself.
+///
+/// **Design Decision**: the `Datum` is stored as the same JSON which is served by `GET /data`,
+/// rather than as separate columns, so that it is never re-serialized with less precision.
+pub struct Sqlite {
+    connection: Mutex<Connection>,
+}
+
+impl Sqlite {
+    /// Opens the database at `path`, creating it (and its directory) if it does not already exist.
+    pub fn open(path: &Path) -> Result<Sqlite, Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| Error::Io(format!("cannot create {}: {}", dir.display(), e)))?;
+        }
+
+        let connection = Connection::open(path).map_err(|e| Error::Io(format!("cannot open {}: {}", path.display(), e)))?;
+        Self::new(connection)
+    }
+
+    fn new(connection: Connection) -> Result<Sqlite, Error> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS data (id TEXT NOT NULL, timestamp INTEGER NOT NULL, datum TEXT NOT NULL);
+                 CREATE INDEX IF NOT EXISTS data_by_timestamp ON data (timestamp);
+                 CREATE INDEX IF NOT EXISTS data_by_id_and_timestamp ON data (id, timestamp);",
+            )
+            .map_err(Self::failure)?;
+
+        Ok(Sqlite {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn failure(e: rusqlite::Error) -> Error {
+        Error::Io(format!("SQLite failure: {}", e))
+    }
+
+    /// Returns `timestamp` in nanoseconds since the Unix epoch, saturating outside of the range of an `i64`.
+    fn nanos(timestamp: DateTime<Utc>) -> i64 {
+        timestamp
+            .timestamp_nanos_opt()
+            .unwrap_or(if timestamp < DateTime::UNIX_EPOCH { i64::MIN } else { i64::MAX })
+    }
+}
+
+impl Storage for Sqlite {
+    fn append(&self, id: &Id, datum: &Datum) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "INSERT INTO data (id, timestamp, datum) VALUES (?1, ?2, ?3)",
+                params![id.to_string(), Self::nanos(datum.timestamp), datum.to_string()],
+            )
+            .map(|_| ())
+            .map_err(Self::failure)
+    }
+
+    fn query(&self, filter: &Filter, ids: Option<&[Id]>) -> Result<Vec<(Id, Vec<Datum>)>, Error> {
+        let since = filter.since.map(Self::nanos).unwrap_or(i64::MIN);
+        let until = filter.until.map(Self::nanos).unwrap_or(i64::MAX);
+
+        // a negative LIMIT means no limit at all
+        let limit = filter.limit.map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX));
+        let offset = i64::try_from(filter.offset).unwrap_or(i64::MAX);
+
+        let connection = self.connection.lock().unwrap();
+
+        // find the Sensors which have any stored data, using the (id, timestamp) index
+        let stored: Vec<String> = {
+            let (sql, requested) = match ids {
+                None => (String::from("SELECT DISTINCT id FROM data"), Vec::new()),
+                Some(ids) => {
+                    let placeholders = vec!["?"; ids.len()].join(",");
+                    let requested: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                    (format!("SELECT DISTINCT id FROM data WHERE id IN ({})", placeholders), requested)
+                }
+            };
+
+            let mut statement = connection.prepare(sql.as_str()).map_err(Self::failure)?;
+            let rows = statement
+                .query_map(params_from_iter(requested), |row| row.get::<_, String>(0))
+                .map_err(Self::failure)?;
+            rows.collect::<Result<_, _>>().map_err(Self::failure)?
+        };
+
+        // then select one page of each Sensor's data, so that only the rows which are returned are read
+        let mut statement = connection
+            .prepare(
+                "SELECT datum FROM data WHERE id = ?1 AND timestamp > ?2 AND timestamp <= ?3
+                 ORDER BY timestamp DESC, rowid DESC LIMIT ?4 OFFSET ?5",
+            )
+            .map_err(Self::failure)?;
+
+        let mut selected = Vec::new();
+
+        for id in stored {
+            let rows = statement
+                .query_map(params![id, since, until, limit, offset], |row| row.get::<_, String>(0))
+                .map_err(Self::failure)?;
+
+            let mut data = Vec::new();
+
+            for row in rows {
+                match Datum::parse(row.map_err(Self::failure)?) {
+                    Ok(datum) => data.push(datum),
+                    Err(e) => debug!("[Sqlite] skipping row for {}: {}", id, e),
+                }
+            }
+
+            selected.push((Id::new(id), data));
+        }
+
+        selected.sort_by_key(|(id, _)| id.to_string());
+        Ok(selected)
+    }
+
+    fn prune(&self, before: DateTime<Utc>) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM data WHERE timestamp < ?1", params![Self::nanos(before)])
+            .map(|_| ())
+            .map_err(Self::failure)
+    }
+}
+
+#[cfg(test)]
+mod sqlite_tests {
+    use chrono::TimeZone;
+
+    use datum::unit::Unit;
+
+    use super::*;
+
+    #[test]
+    fn test_append_and_query() {
+        let sqlite = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let minute = chrono::Duration::minutes(1);
+
+        for offset in 0..180 {
+            for sensor in ["sensor_b", "sensor_a"] {
+                let datum = Datum::new(offset as f32, Unit::DegreesC, t0 + minute * offset);
+                sqlite.append(&Id::new(sensor), &datum).unwrap();
+            }
+        }
+
+        let filter = Filter {
+            since: Some(t0 + minute * 50),
+            until: Some(t0 + minute * 70),
+            limit: Some(3),
+            offset: 1,
+        };
+
+        let expected: Vec<Datum> = [69, 68, 67]
+            .into_iter()
+            .map(|offset| Datum::new(offset as f32, Unit::DegreesC, t0 + minute * offset))
+            .collect();
+
+        let actual = sqlite.query(&filter, None).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor_a"), expected.clone()), (Id::new("sensor_b"), expected.clone())]);
+
+        let actual = sqlite.query(&filter, Some(&[Id::new("sensor_b")])).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor_b"), expected)]);
+    }
+
+    #[test]
+    fn test_query_uses_index() {
+        let sqlite = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+        let connection = sqlite.connection.lock().unwrap();
+
+        let plan: Vec<String> = connection
+            .prepare("EXPLAIN QUERY PLAN SELECT datum FROM data WHERE id = ?1 AND timestamp > ?2 AND timestamp <= ?3 ORDER BY timestamp DESC LIMIT 5")
+            .unwrap()
+            .query_map(params!["sensor_a", 0, 1], |row| row.get::<_, String>(3))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+
+        assert!(plan.iter().any(|step| step.contains("data_by_id_and_timestamp")), "{:?}", plan);
+    }
+
+    #[test]
+    fn test_query_past_the_end() {
+        let sqlite = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+
+        for offset in 0..3 {
+            sqlite
+                .append(&Id::new("sensor_a"), &Datum::new(offset as f32, Unit::DegreesC, Utc::now()))
+                .unwrap();
+        }
+
+        let filter = Filter {
+            offset: 5,
+            ..Filter::default()
+        };
+
+        let actual = sqlite.query(&filter, Some(&[Id::new("sensor_a"), Id::new("sensor_z")])).unwrap();
+        assert_eq!(actual, vec![(Id::new("sensor_a"), vec![])]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let sqlite = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap();
+        let hour = chrono::Duration::hours(1);
+
+        for offset in 0..4 {
+            sqlite
+                .append(&Id::new("sensor_a"), &Datum::new(offset as f32, Unit::DegreesC, t0 + hour * offset))
+                .unwrap();
+        }
+
+        sqlite.prune(t0 + hour * 2).unwrap();
+
+        let actual: Vec<f32> = sqlite.query(&Filter::default(), None).unwrap()[0]
+            .1
+            .iter()
+            .filter_map(|datum| datum.get_as_float())
+            .collect();
+        assert_eq!(actual, vec![3.0, 2.0]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use datum::filter::Filter;
+use datum::Datum;
+use device::error::Error;
+use device::id::Id;
+
+use crate::segments::Segments;
+
+/// A `Storage` keeps every `Datum` the `Controller` receives, for longer than its in-memory buffers can.
+///
+/// **Design Decision**: the `Controller` still buffers the latest `Datum`s in memory, so that
+/// `Assessor`s, `GET /datum`, and the UI never have to wait for a `Storage`; a `Storage` is only
+/// read when historic data is requested with `GET /data`.
+pub trait Storage: Send + Sync {
+    /// Stores `datum`, which was received from the `Sensor` with the specified `id`.
+    fn append(&self, id: &Id, datum: &Datum) -> Result<(), Error>;
+
+    /// Returns the stored data (newest first) of every `Sensor`, or only of the `Sensor`s with the
+    /// specified `ids`, sorted by `Id`. The `filter` is applied to each `Sensor`'s data separately.
+    fn query(&self, filter: &Filter, ids: Option<&[Id]>) -> Result<Vec<(Id, Vec<Datum>)>, Error>;
+
+    /// Forgets (at least) every `Datum` older than `before`.
+    fn prune(&self, before: DateTime<Utc>) -> Result<(), Error>;
+}
+
+/// A `Backend` selects where a `Controller` stores the data it receives. It is chosen when the
+/// `Controller` is started.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backend {
+    /// Data is only kept in the `Controller`'s in-memory buffers, and is lost when it stops.
+    Memory,
+    /// Data is appended to segment files in the specified directory.
+    Segments(PathBuf),
+    /// Data is inserted into the SQLite database at the specified path.
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+impl Backend {
+    /// Selects a `Backend` using the `STORAGE` environment variable.
+    ///
+    /// - unset, or `memory`: [`Backend::Memory`]
+    /// - `segments`: [`Backend::Segments`], in the directory given by `STORAGE_DIR` (default `data`)
+    /// - `sqlite`: `Backend::Sqlite`, at `data.db` in the directory given by `STORAGE_DIR`, if the
+    ///   `Controller` was built with the `sqlite` feature
+    pub fn from_env() -> Result<Backend, Error> {
+        let backend = std::env::var("STORAGE").unwrap_or(String::from("memory"));
+        let dir = PathBuf::from(std::env::var("STORAGE_DIR").unwrap_or(String::from("data")));
+
+        match backend.as_str() {
+            "memory" => Ok(Backend::Memory),
+            "segments" => Ok(Backend::Segments(dir)),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(Backend::Sqlite(dir.join("data.db"))),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => Err(Error::Parse(String::from("STORAGE=sqlite requires the controller's 'sqlite' feature"))),
+            other => Err(Error::Parse(format!(
+                "unknown STORAGE backend '{}', expected 'memory', 'segments' or 'sqlite'",
+                other
+            ))),
+        }
+    }
+
+    /// Opens this `Backend`'s `Storage`, or returns `None` for [`Backend::Memory`], which stores nothing.
+    pub(crate) fn open(&self) -> Result<Option<Arc<dyn Storage>>, Error> {
+        match self {
+            Backend::Memory => Ok(None),
+            Backend::Segments(dir) => Ok(Some(Arc::new(Segments::open(dir.clone())?))),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(path) => Ok(Some(Arc::new(crate::sqlite::Sqlite::open(path)?))),
+        }
+    }
+}
+
+/// Groups `rows` by `Sensor`, keeping only those from the `Sensor`s with the specified `ids` (if any),
+/// then applies the `filter` to each `Sensor`'s data.
+///
+/// Each `Sensor`'s rows must be given newest first, as they would be found in its buffer.
+pub(crate) fn select(rows: impl IntoIterator<Item = (Id, Datum)>, filter: &Filter, ids: Option<&[Id]>) -> Vec<(Id, Vec<Datum>)> {
+    let mut grouped: HashMap<Id, Vec<Datum>> = HashMap::new();
+
+    for (id, datum) in rows {
+        if ids.is_none_or(|ids| ids.contains(&id)) {
+            grouped.entry(id).or_default().push(datum);
+        }
+    }
+
+    let mut selected: Vec<(Id, Vec<Datum>)> = grouped
+        .into_iter()
+        .map(|(id, data)| {
+            let data = filter.apply(data.iter()).into_iter().cloned().collect();
+            (id, data)
+        })
+        .collect();
+
+    selected.sort_by_key(|(id, _)| id.to_string());
+    selected
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use datum::unit::Unit;
+
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let t0 = Utc::now();
+        let second = chrono::Duration::seconds(1);
+
+        let rows = (0..5).rev().flat_map(|offset| {
+            ["sensor_b", "sensor_a", "sensor_c"].map(|sensor| (Id::new(sensor), Datum::new(offset as f32, Unit::DegreesC, t0 + second * offset)))
+        });
+
+        let filter = Filter {
+            since: Some(t0),
+            limit: Some(2),
+            offset: 1,
+            ..Filter::default()
+        };
+
+        let ids = [Id::new("sensor_b"), Id::new("sensor_a")];
+
+        let expected = [
+            Datum::new(3.0, Unit::DegreesC, t0 + second * 3),
+            Datum::new(2.0, Unit::DegreesC, t0 + second * 2),
+        ];
+
+        let actual = select(rows, &filter, Some(&ids));
+
+        assert_eq!(actual, vec![(Id::new("sensor_a"), expected.to_vec()), (Id::new("sensor_b"), expected.to_vec())]);
+    }
+}
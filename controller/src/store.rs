@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use device::error::Error;
+
+/// A `Store` keeps the `Controller`'s configuration in files, so that it survives restarts.
+///
+/// A `Store` without a directory saves nothing, and loads nothing.
+#[derive(Clone, Default)]
+pub struct Store {
+    dir: Option<PathBuf>,
+}
+
+impl Store {
+    pub fn new(dir: Option<PathBuf>) -> Store {
+        Store { dir }
+    }
+
+    /// Returns the contents of the file called `name`, or `None` if nothing has been saved there yet.
+    pub fn load(&self, name: &str) -> Result<Option<String>, Error> {
+        let Some(dir) = &self.dir else {
+            return Ok(None);
+        };
+
+        let path = dir.join(name);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(format!("cannot read {}: {}", path.display(), e))),
+        }
+    }
+
+    /// Replaces the contents of the file called `name` with `contents`.
+    ///
+    /// **Design Decision**: `contents` are written to a temporary file, which is then renamed, so
+    /// that a `Controller` which stops part way through saving never leaves a half-written file.
+    pub fn save(&self, name: &str, contents: &str) -> Result<(), Error> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        std::fs::create_dir_all(dir).map_err(|e| Error::Io(format!("cannot create {}: {}", dir.display(), e)))?;
+
+        let path = dir.join(name);
+        let temporary = dir.join(format!("{}.tmp", name));
+
+        std::fs::write(&temporary, contents).map_err(|e| Error::Io(format!("cannot write {}: {}", temporary.display(), e)))?;
+        std::fs::rename(&temporary, &path).map_err(|e| Error::Io(format!("cannot replace {}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("controller-store-{}", std::process::id()));
+        let store = Store::new(Some(dir.join("nested")));
+
+        assert_eq!(store.load("things.json"), Ok(None));
+
+        store.save("things.json", "[1,2,3]").unwrap();
+        assert_eq!(store.load("things.json"), Ok(Some(String::from("[1,2,3]"))));
+
+        store.save("things.json", "[]").unwrap();
+        assert_eq!(store.load("things.json"), Ok(Some(String::from("[]"))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_dir() {
+        let store = Store::default();
+        store.save("things.json", "[1,2,3]").unwrap();
+        assert_eq!(store.load("things.json"), Ok(None));
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The most clients which can be connected to `GET /stream` and `GET /ws` (together) at once.
+pub const MAX_STREAMS: usize = 12;
+
+/// `Streams` counts the clients which are connected to `GET /stream` or `GET /ws`, each of which
+/// holds on to one of the `Controller`'s workers for as long as it is connected.
+///
+/// **Design Decision**: there are always fewer streaming clients than workers, so that however many
+/// UI tabs are open, there are workers left over to serve the UI itself, and every other request.
+/// Clients which would go over the limit are turned away with a `503`, rather than being queued.
+pub struct Streams {
+    open: AtomicUsize,
+    max: usize,
+}
+
+/// A `Slot` is held by a single streaming client for as long as it is connected, and is given back
+/// to its `Streams` when it is dropped.
+pub struct Slot {
+    streams: Arc<Streams>,
+}
+
+impl Streams {
+    /// Creates `Streams` which allow at most `max` clients to be connected at once.
+    pub fn new(max: usize) -> Arc<Streams> {
+        Arc::new(Streams {
+            open: AtomicUsize::new(0),
+            max,
+        })
+    }
+
+    /// Returns a `Slot` for a new streaming client, or `None` if `max` clients are already connected.
+    pub fn open(self: &Arc<Self>) -> Option<Slot> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < self.max).then_some(open + 1))
+            .ok()
+            .map(|_| Slot { streams: Arc::clone(self) })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.streams.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod streams_tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let streams = Streams::new(2);
+
+        let first = streams.open();
+        let second = streams.open();
+        assert!(first.is_some() && second.is_some());
+        assert!(streams.open().is_none());
+
+        // a client which disconnects makes room for another
+        drop(first);
+        assert!(streams.open().is_some());
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::Datum;
+
+/// A `datum::filter::Filter` selects a page of `Datum`s from a buffer of `Datum`s.
+///
+/// `Datum`s are first filtered by `timestamp`, then `offset` `Datum`s are skipped, then at most
+/// `limit` `Datum`s are returned. `Datum`s are returned in the same order as they appear in the buffer.
+///
+/// **Design Decision**: `since` is exclusive while `until` is inclusive. A client which is polling
+/// for new `Datum`s can pass the `timestamp` of the latest `Datum` it has already seen as `since`,
+/// without receiving that `Datum` a second time.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Filter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl Filter {
+    /// Attempts to parse a `Filter` from the `since`, `until`, `limit`, and `offset` query parameters.
+    ///
+    /// Any other query parameters are ignored. Missing parameters do not filter anything out.
+    pub fn parse(query: &HashMap<String, String>) -> Result<Filter, String> {
+        fn timestamp(query: &HashMap<String, String>, key: &str) -> Result<Option<DateTime<Utc>>, String> {
+            match query.get(key) {
+                None => Ok(None),
+                Some(value) => match value.parse::<DateTime<Utc>>() {
+                    Ok(timestamp) => Ok(Some(timestamp)),
+                    Err(_) => Err(format!("cannot parse '{}' as a timestamp for '{}'", value, key)),
+                },
+            }
+        }
+
+        fn count(query: &HashMap<String, String>, key: &str) -> Result<Option<usize>, String> {
+            match query.get(key) {
+                None => Ok(None),
+                Some(value) => match value.parse::<usize>() {
+                    Ok(count) => Ok(Some(count)),
+                    Err(_) => Err(format!("cannot parse '{}' as a non-negative integer for '{}'", value, key)),
+                },
+            }
+        }
+
+        Ok(Filter {
+            since: timestamp(query, "since")?,
+            until: timestamp(query, "until")?,
+            limit: count(query, "limit")?,
+            offset: count(query, "offset")?.unwrap_or_default(),
+        })
+    }
+
+    /// Returns `true` if the `datum` falls within the `since` / `until` bounds of this `Filter`.
+    pub fn contains(&self, datum: &Datum) -> bool {
+        self.since.is_none_or(|since| datum.timestamp > since) && self.until.is_none_or(|until| datum.timestamp <= until)
+    }
+
+    /// Applies this `Filter` to the provided `data`, returning the selected page of `Datum`s.
+    pub fn apply<'a>(&self, data: impl IntoIterator<Item = &'a Datum>) -> Vec<&'a Datum> {
+        let data = data.into_iter().filter(|datum| self.contains(datum)).skip(self.offset);
+
+        match self.limit {
+            Some(limit) => data.take(limit).collect(),
+            None => data.collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod datum_filter_tests {
+    use chrono::TimeZone;
+
+    use crate::unit::Unit;
+
+    use super::*;
+
+    fn at(second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 5, 12, 0, second).unwrap()
+    }
+
+    // newest first, like the buffers held by Sensors and the Controller
+    fn create_data() -> Vec<Datum> {
+        (0..10).rev().map(|second| Datum::new(second as f32, Unit::DegreesC, at(second))).collect()
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn values(data: Vec<&Datum>) -> Vec<f32> {
+        data.iter().map(|datum| datum.get_as_float().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let actual = Filter::parse(&HashMap::new());
+        assert_eq!(actual, Ok(Filter::default()))
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let query = query(&[
+            ("since", "2024-01-05T12:00:01+00:00"),
+            ("until", "2024-01-05T12:00:08Z"),
+            ("limit", "3"),
+            ("offset", "2"),
+            ("unrelated", "ignored"),
+        ]);
+
+        let actual = Filter::parse(&query);
+
+        let expected = Filter {
+            since: Some(at(1)),
+            until: Some(at(8)),
+            limit: Some(3),
+            offset: 2,
+        };
+
+        assert_eq!(actual, Ok(expected))
+    }
+
+    #[test]
+    fn test_parse_failure_timestamp() {
+        let actual = Filter::parse(&query(&[("since", "yesterday")]));
+        let msg = String::from("cannot parse 'yesterday' as a timestamp for 'since'");
+        assert_eq!(actual, Err(msg))
+    }
+
+    #[test]
+    fn test_parse_failure_count() {
+        let actual = Filter::parse(&query(&[("limit", "-1")]));
+        let msg = String::from("cannot parse '-1' as a non-negative integer for 'limit'");
+        assert_eq!(actual, Err(msg))
+    }
+
+    #[test]
+    fn test_apply_default() {
+        let data = create_data();
+        let actual = values(Filter::default().apply(&data));
+        assert_eq!(actual, vec![9.0, 8.0, 7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0, 0.0])
+    }
+
+    #[test]
+    fn test_apply_since_and_until() {
+        let data = create_data();
+
+        let filter = Filter {
+            since: Some(at(3)),
+            until: Some(at(6)),
+            ..Filter::default()
+        };
+
+        // since is exclusive, until is inclusive
+        let actual = values(filter.apply(&data));
+        assert_eq!(actual, vec![6.0, 5.0, 4.0])
+    }
+
+    #[test]
+    fn test_apply_limit_and_offset() {
+        let data = create_data();
+
+        let filter = Filter {
+            limit: Some(3),
+            offset: 2,
+            ..Filter::default()
+        };
+
+        let actual = values(filter.apply(&data));
+        assert_eq!(actual, vec![7.0, 6.0, 5.0])
+    }
+
+    #[test]
+    fn test_apply_everything() {
+        let data = create_data();
+
+        let filter = Filter {
+            since: Some(at(1)),
+            until: Some(at(8)),
+            limit: Some(10),
+            offset: 5,
+        };
+
+        let actual = values(filter.apply(&data));
+        assert_eq!(actual, vec![3.0, 2.0])
+    }
+}
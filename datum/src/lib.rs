@@ -5,6 +5,7 @@ use chrono::{DateTime, Utc};
 use crate::unit::Unit;
 use crate::value::Value;
 
+pub mod filter;
 pub mod kind;
 pub mod unit;
 pub mod value;
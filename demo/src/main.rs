@@ -1,10 +1,11 @@
-use std::time::Duration;
-
 use uuid::Uuid;
 
 use actuator::Actuator;
 use actuator_temperature::TemperatureActuator;
+use controller::config::Config;
 use controller::Controller;
+use device::discovery::Backend;
+use device::handle::wait_for_termination;
 use device::id::Id;
 use device::name::Name;
 use environment::Environment;
@@ -28,42 +29,49 @@ fn main() {
     // spin up a sensor-actuator pair
     // --------------------------------------------------------------------------------
 
+    // Devices find each other via mDNS, unless DISCOVERY=static selects a fixed list of Devices instead
+    let discovery = Backend::from_env().unwrap();
+
     // id has to be the same for the sensor and its corresponding actuator, name does not
     let id = Id::new(Uuid::new_v4());
 
     // here is the Sensor
-    let sensor_port = 8787;
-    TemperatureSensor::start(ip, sensor_port, id.clone(), Name::new("My Thermo-5000 Sensor"), "_sensor".into());
+    // the Sensor, Actuator, and Environment are found via mDNS, so they can listen on any free port (port 0)
+    let sensor = TemperatureSensor::start(ip, 0, id.clone(), Name::new("My Thermo-5000 Sensor"), "_sensor".into(), discovery.clone());
 
     // here is the Actuator
-    TemperatureActuator::start(ip, 9898, id.clone(), Name::new("My Thermo-5000 Actuator"), "_actuator".into());
+    let actuator = TemperatureActuator::start(ip, 0, id.clone(), Name::new("My Thermo-5000 Actuator"), "_actuator".into(), discovery.clone());
 
     // --------------------------------------------------------------------------------
     // spin up the controller
     // --------------------------------------------------------------------------------
 
-    let container_mode = false;
-    Controller::start(
+    // the sensor and actuator have a new id every time the demo is run, so there is nothing worth saving
+    let config = Config::new(discovery.clone()).with_container_mode(false);
+    let controller = Controller::start(
         ip,
         controller_port,
         Id::new("controller"),
         Name::new("Controller"),
         String::from("_controller"),
-        container_mode,
+        config,
     );
 
     // --------------------------------------------------------------------------------
     // spin up the environment
     // --------------------------------------------------------------------------------
 
-    let environment_port = 5454;
-    Environment::start(
-        ip,
-        environment_port,
-        Id::new("environment"),
-        Name::new("Environment"),
-        String::from("_environment"),
-    );
+    let environment = Environment::start(ip, 0, Id::new("environment"), Name::new("Environment"), String::from("_environment"), discovery);
+
+    // --------------------------------------------------------------------------------
+    // run until Ctrl-C, then shut everything down
+    // --------------------------------------------------------------------------------
+
+    wait_for_termination().unwrap();
+
+    println!("\nRust MVP is shutting down...");
 
-    std::thread::sleep(Duration::MAX)
+    for handle in [controller, sensor, actuator, environment] {
+        handle.shutdown();
+    }
 }
@@ -1,9 +1,13 @@
 use std::fmt::{Display, Formatter};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+
+use mdns_sd::ServiceInfo;
+
+use crate::error::Error;
 
 /// An `Address` contains all the information required to route a `Message` to a `Device`, namely
 /// the `Device`'s IP address and port.
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Address {
     ip: IpAddr,
     port: u16,
@@ -13,6 +17,35 @@ impl Address {
     pub fn new(ip: IpAddr, port: u16) -> Address {
         Address { ip, port }
     }
+
+    /// Returns the IP address of this `Address`.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Returns the port of this `Address`.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+/// Allows the `Address` of a `Device` to be extracted from its `ServiceInfo` found via mDNS.
+impl TryFrom<&ServiceInfo> for Address {
+    type Error = Error;
+
+    fn try_from(info: &ServiceInfo) -> Result<Self, Self::Error> {
+        match info.get_addresses().iter().next() {
+            Some(ip) => Ok(Address::new(*ip, info.get_port())),
+            None => Err(Error::Discovery(format!("no addresses advertised for {}", info.get_fullname()))),
+        }
+    }
+}
+
+/// Allows `Address`es to be used with `std::net` APIs like `TcpStream::connect_timeout`.
+impl From<Address> for SocketAddr {
+    fn from(address: Address) -> Self {
+        SocketAddr::new(address.ip, address.port)
+    }
 }
 
 /// Allows `Address`es to be converted to `String`s with `to_string()`.
@@ -36,4 +69,15 @@ mod device_address_tests {
 
         assert_eq!(actual, expected)
     }
+
+    #[test]
+    fn test_into_socket_addr() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let address = Address::new(ip, 10101);
+
+        let expected: SocketAddr = "127.0.0.1:10101".parse().unwrap();
+        let actual = SocketAddr::from(address);
+
+        assert_eq!(actual, expected)
+    }
 }
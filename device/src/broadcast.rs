@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::{mpsc, Mutex};
+
+/// A `Broadcaster` delivers a copy of every published item to each of its subscribers.
+///
+/// Each subscriber receives items through its own bounded queue.
+///
+/// **Design Decision**: [`publish`](Self::publish) never blocks. If a subscriber's queue is full
+/// (because it is not keeping up), the item is dropped for that subscriber only. This guarantees
+/// that one slow client cannot stall the thread which publishes items (e.g. the `Controller`'s
+/// polling loop). Subscribers which have hung up are removed the next time an item is published.
+pub struct Broadcaster<T: Clone + Send> {
+    subscribers: Mutex<Vec<SyncSender<T>>>,
+    capacity: usize,
+    closed: AtomicBool,
+}
+
+impl<T: Clone + Send> Broadcaster<T> {
+    /// Creates a `Broadcaster` which queues up to `capacity` items for each subscriber.
+    pub fn new(capacity: usize) -> Broadcaster<T> {
+        Broadcaster {
+            subscribers: Mutex::new(Vec::new()),
+            capacity,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a `Receiver` which will receive every item published from now on.
+    ///
+    /// If this `Broadcaster` has been [`close`](Self::close)d, the `Receiver` is already disconnected.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let (sender, receiver) = mpsc::sync_channel(self.capacity);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if !self.closed.load(Ordering::SeqCst) {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Disconnects every subscriber, once they have received any items already queued for them.
+    /// Nothing more can be published after a `Broadcaster` is closed.
+    pub fn close(&self) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        subscribers.clear();
+    }
+
+    /// Sends a copy of the `item` to every current subscriber.
+    pub fn publish(&self, item: T) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| !matches!(subscriber.try_send(item.clone()), Err(TrySendError::Disconnected(_))));
+    }
+
+    /// Returns the number of current subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+/// By default, up to 64 items are queued for each subscriber.
+impl<T: Clone + Send> Default for Broadcaster<T> {
+    fn default() -> Self {
+        Broadcaster::new(64)
+    }
+}
+
+#[cfg(test)]
+mod device_broadcast_tests {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let broadcaster = Broadcaster::default();
+
+        let first = broadcaster.subscribe();
+        broadcaster.publish(1);
+        let second = broadcaster.subscribe();
+        broadcaster.publish(2);
+
+        assert_eq!(first.try_iter().collect::<Vec<i32>>(), vec![1, 2]);
+        assert_eq!(second.try_iter().collect::<Vec<i32>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_publish_to_slow_subscriber() {
+        let broadcaster = Broadcaster::new(2);
+        let slow = broadcaster.subscribe();
+
+        for item in 1..=5 {
+            broadcaster.publish(item);
+        }
+
+        // items which do not fit in the queue are dropped, but the subscriber is kept
+        assert_eq!(slow.try_iter().collect::<Vec<i32>>(), vec![1, 2]);
+        assert_eq!(broadcaster.subscribers(), 1);
+    }
+
+    #[test]
+    fn test_publish_removes_disconnected_subscribers() {
+        let broadcaster = Broadcaster::default();
+
+        let kept = broadcaster.subscribe();
+        drop(broadcaster.subscribe());
+        assert_eq!(broadcaster.subscribers(), 2);
+
+        broadcaster.publish(1);
+
+        assert_eq!(broadcaster.subscribers(), 1);
+        assert_eq!(kept.recv(), Ok(1));
+    }
+
+    #[test]
+    fn test_close() {
+        let broadcaster = Broadcaster::default();
+
+        let before = broadcaster.subscribe();
+        broadcaster.publish(1);
+        broadcaster.close();
+        broadcaster.publish(2);
+
+        // items queued before the Broadcaster was closed are still delivered
+        assert_eq!(before.recv(), Ok(1));
+        assert!(before.recv().is_err());
+
+        let after = broadcaster.subscribe();
+        assert!(after.recv().is_err());
+        assert_eq!(broadcaster.subscribers(), 0);
+    }
+}
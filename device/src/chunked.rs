@@ -0,0 +1,212 @@
+use std::io::{BufRead, ErrorKind, Write};
+
+use crate::error::Error;
+use crate::message::MAX_BODY_SIZE;
+
+/// A `ChunkedWriter` streams a `Message` body to the underlying writer using `Transfer-Encoding: chunked`.
+///
+/// Bytes written to a `ChunkedWriter` are buffered, and sent as a single chunk whenever the buffer
+/// fills up, or when the `ChunkedWriter` is flushed. [`finish`](Self::finish) must be called to
+/// send the final, empty chunk which tells the receiver that the body is complete.
+///
+/// **Design Decision**: dropping a `ChunkedWriter` without calling `finish` does _not_ send the final
+/// chunk. If a handler fails part-way through streaming a body, the receiver should see a truncated
+/// body (and an error), rather than a body which looks complete, but is not.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc9112#name-chunked-transfer-coding
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    capacity: usize,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Creates a `ChunkedWriter` which sends chunks of (at most) 8 KiB to the `inner` writer.
+    ///
+    /// The caller is responsible for having already written the start line and headers of the
+    /// `Message`, including `Transfer-Encoding: chunked` (see [`Message::write_chunked`](crate::message::Message::write_chunked)).
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        Self::with_capacity(8 * 1024, inner)
+    }
+
+    /// Creates a `ChunkedWriter` which sends chunks of (at most) `capacity` bytes to the `inner` writer.
+    pub fn with_capacity(capacity: usize, inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Sends any buffered bytes, followed by the final, empty chunk, then returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.send_chunk()?;
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Sends the contents of the buffer as a single chunk. Empty buffers are not sent, because an
+    /// empty chunk marks the end of the body.
+    fn send_chunk(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        write!(self.inner, "{:x}\r\n", self.buffer.len())?;
+        self.inner.write_all(&self.buffer)?;
+        self.inner.write_all(b"\r\n")?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.buffer.len() == self.capacity {
+            self.send_chunk()?;
+        }
+
+        let size = buf.len().min(self.capacity - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_chunk()?;
+        self.inner.flush()
+    }
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked` from the `reader`, discarding any trailers.
+///
+/// Bodies larger than [`MAX_BODY_SIZE`] in total are rejected before their chunks are read.
+pub(crate) fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::Protocol("connection closed before the last chunk was received".into()));
+        }
+
+        // chunk extensions (e.g. "1a;name=value") are permitted, but we ignore them
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| Error::Protocol(format!("invalid chunk size: '{}'", size)))?;
+
+        if size == 0 {
+            break;
+        }
+
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= MAX_BODY_SIZE)
+            .ok_or_else(|| Error::TooLarge(format!("chunked body exceeds the maximum of {}", MAX_BODY_SIZE)))?;
+
+        body.resize(end, 0);
+        reader.read_exact(&mut body[start..]).map_err(|error| match error.kind() {
+            ErrorKind::UnexpectedEof => Error::Protocol(format!("chunk is shorter than its size: {}", size)),
+            _ => Error::from(error),
+        })?;
+
+        // every chunk is followed by a CRLF
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.trim().is_empty() {
+            return Err(Error::Protocol(format!("chunk of size {} is not followed by CRLF", size)));
+        }
+    }
+
+    // the last chunk is followed by zero or more trailer fields, then a blank line
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod device_chunked_tests {
+    use super::*;
+
+    #[test]
+    fn test_chunked_writer() {
+        let mut writer = ChunkedWriter::with_capacity(4, Vec::new());
+
+        writer.write_all(b"Hello, World!").unwrap();
+        let actual = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        let expected = "4\r\nHell\r\n4\r\no, W\r\n4\r\norld\r\n1\r\n!\r\n0\r\n\r\n";
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_chunked_writer_flush() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+
+        writer.write_all(b"abc").unwrap();
+        writer.flush().unwrap();
+        writer.flush().unwrap(); // nothing is buffered, so this must not send the (empty) last chunk
+        writer.write_all(b"defghijklmnopqrstuvwxyz").unwrap();
+
+        let actual = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        let expected = "3\r\nabc\r\n17\r\ndefghijklmnopqrstuvwxyz\r\n0\r\n\r\n";
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let serialized = "4;ext=1\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+
+        let actual = read_chunked(&mut serialized.as_bytes()).unwrap();
+
+        assert_eq!(String::from_utf8(actual).unwrap(), "Wikipedia in\r\n\r\nchunks.")
+    }
+
+    #[test]
+    fn test_read_chunked_round_trip() {
+        let body = "°C ".repeat(1000);
+
+        let mut writer = ChunkedWriter::with_capacity(7, Vec::new());
+        writer.write_all(body.as_bytes()).unwrap();
+        let serialized = writer.finish().unwrap();
+
+        let actual = read_chunked(&mut serialized.as_slice()).unwrap();
+
+        assert_eq!(String::from_utf8(actual).unwrap(), body)
+    }
+
+    #[test]
+    fn test_read_chunked_invalid_size() {
+        let actual = read_chunked(&mut "xyz\r\nabc\r\n0\r\n\r\n".as_bytes());
+        assert_eq!(actual, Err(Error::Protocol("invalid chunk size: 'xyz'".into())))
+    }
+
+    #[test]
+    fn test_read_chunked_truncated() {
+        let actual = read_chunked(&mut "a\r\nabc".as_bytes());
+        assert_eq!(actual, Err(Error::Protocol("chunk is shorter than its size: 10".into())));
+
+        let actual = read_chunked(&mut "3\r\nabc\r\n".as_bytes());
+        assert_eq!(actual, Err(Error::Protocol("connection closed before the last chunk was received".into())));
+    }
+
+    #[test]
+    fn test_read_chunked_too_large() {
+        let too_large = Err(Error::TooLarge(format!("chunked body exceeds the maximum of {}", MAX_BODY_SIZE)));
+
+        let serialized = format!("{:x}\r\nabc\r\n0\r\n\r\n", MAX_BODY_SIZE + 1);
+        assert_eq!(read_chunked(&mut serialized.as_bytes()), too_large);
+
+        // the running total is checked too, and cannot overflow
+        let serialized = format!("3\r\nabc\r\n{:x}\r\nabc\r\n0\r\n\r\n", usize::MAX);
+        assert_eq!(read_chunked(&mut serialized.as_bytes()), too_large);
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use log::debug;
+use mdns_sd::ServiceInfo;
+
+use crate::address::Address;
+use crate::connection::{Connections, Timeouts};
+use crate::error::Error;
+use crate::message::Message;
+
+/// A `Client` sends requests to other `Device`s and waits for their responses.
+///
+/// Every request is bound by connect, read, and write `Timeouts`, so an unresponsive peer cannot
+/// block the caller forever. Idempotent requests (`GET`, `PUT`, `DELETE`, etc.) which fail are
+/// retried with exponential backoff. Connections are kept alive and reused between requests.
+///
+/// **Design Decision**: non-idempotent requests (like `POST /command`) are never retried. If a
+/// `POST` times out, the peer may have already acted on it, and sending it a second time could
+/// (for example) heat the `Environment` twice.
+pub struct Client {
+    connections: Connections,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Client {
+    /// Creates a `Client` with the default `Timeouts`, which retries failed idempotent requests
+    /// twice, waiting 50ms before the first retry and 100ms before the second.
+    pub fn new() -> Client {
+        Client {
+            connections: Connections::default(),
+            retries: 2,
+            backoff: Duration::from_millis(50),
+        }
+    }
+
+    /// Sets the `Timeouts` applied to every connection opened by this `Client`.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Client {
+        self.connections = self.connections.with_timeouts(timeouts);
+        self
+    }
+
+    /// Sets the number of times a failed idempotent request is retried.
+    pub fn with_retries(mut self, retries: u32) -> Client {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long to wait before the first retry. This delay doubles after every failed retry.
+    pub fn with_backoff(mut self, backoff: Duration) -> Client {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sends the `request` to the `Device` at the specified `address` and waits for its response.
+    pub fn send(&self, address: &Address, request: &Message) -> Result<Message, Error> {
+        let attempts = if request.is_idempotent() { self.retries + 1 } else { 1 };
+        let mut attempt = 0;
+
+        loop {
+            match self.connections.send(address, request) {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt + 1 < attempts && Self::is_retryable(&e) => {
+                    let delay = self.delay(attempt);
+                    debug!("[Client] request to {} failed ({}), retrying in {:?}", address, e, delay);
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends the `request` to the `Device` described by the `info` found via mDNS and waits for its response.
+    pub fn send_to(&self, info: &ServiceInfo, request: &Message) -> Result<Message, Error> {
+        self.send(&Address::try_from(info)?, request)
+    }
+
+    /// Returns how long to wait before retrying after the given (zero-indexed) failed `attempt`.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+
+    /// Returns `true` if the `error` might not happen again if the request were retried.
+    fn is_retryable(error: &Error) -> bool {
+        matches!(error, Error::Io(_) | Error::Timeout(_) | Error::Protocol(_))
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+#[cfg(test)]
+mod device_client_tests {
+    use std::io::Write;
+    use std::net::{IpAddr, TcpListener};
+    use std::thread::JoinHandle;
+
+    use super::*;
+
+    /// Starts a server which drops the first `failures` connections without responding, then
+    /// answers the next request with `200 OK`.
+    fn start_flaky_server(failures: usize) -> (Address, JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::new(IpAddr::from([127, 0, 0, 1]), listener.local_addr().unwrap().port());
+
+        let handle = std::thread::spawn(move || {
+            let mut accepted = 0;
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                accepted += 1;
+
+                if accepted > failures {
+                    Message::read(&mut stream).unwrap();
+                    Message::respond_ok().with_body("done").write(&mut stream).unwrap();
+                    stream.flush().unwrap();
+                    break;
+                }
+            }
+            accepted
+        });
+
+        (address, handle)
+    }
+
+    fn create_client() -> Client {
+        let timeouts = Timeouts::new(Duration::from_millis(500), Duration::from_millis(500), Duration::from_millis(500));
+        Client::new().with_timeouts(timeouts).with_backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_delay() {
+        let client = Client::new().with_backoff(Duration::from_millis(10));
+        assert_eq!(client.delay(0), Duration::from_millis(10));
+        assert_eq!(client.delay(1), Duration::from_millis(20));
+        assert_eq!(client.delay(2), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_send_retries_idempotent_requests() {
+        let (address, server) = start_flaky_server(2);
+        let client = create_client();
+
+        let response = client.send(&address, &Message::request_get("/datum")).unwrap();
+
+        assert_eq!(response.body, Some(String::from("done")));
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_send_does_not_retry_non_idempotent_requests() {
+        let (address, server) = start_flaky_server(1);
+        let client = create_client();
+
+        let response = client.send(&address, &Message::request_post("/command"));
+        assert!(response.is_err());
+
+        // the server is still waiting for a second connection, so give it one
+        let response = client.send(&address, &Message::request_post("/command")).unwrap();
+        assert_eq!(response.body, Some(String::from("done")));
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_send_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::new(IpAddr::from([127, 0, 0, 1]), listener.local_addr().unwrap().port());
+
+        // the connection is accepted by the OS, but nobody ever responds
+        let timeouts = Timeouts::new(Duration::from_millis(100), Duration::from_millis(100), Duration::from_millis(100));
+        let client = Client::new().with_timeouts(timeouts).with_retries(0);
+
+        let actual = client.send(&address, &Message::request_get("/datum"));
+
+        assert!(matches!(actual, Err(Error::Timeout(_))), "{:?}", actual);
+        drop(listener);
+    }
+}
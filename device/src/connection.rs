@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::address::Address;
+use crate::error::Error;
+use crate::message::Message;
+use crate::name::Name;
+use crate::Handler;
+
+/// `Timeouts` bound how long a `Connection` waits on its peer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Timeouts {
+    /// how long to wait for the peer to accept a new `Connection`
+    pub connect: Duration,
+    /// how long to wait for each read from the peer
+    pub read: Duration,
+    /// how long to wait for each write to the peer
+    pub write: Duration,
+}
+
+impl Timeouts {
+    pub fn new(connect: Duration, read: Duration, write: Duration) -> Timeouts {
+        Timeouts { connect, read, write }
+    }
+}
+
+/// By default, a peer has 1 second to accept a `Connection`, and 5 seconds for every read and write.
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::new(Duration::from_secs(1), Duration::from_secs(5), Duration::from_secs(5))
+    }
+}
+
+/// A `Connection` is a persistent HTTP/1.1 connection from this `Device` to a single peer.
+///
+/// **Design Decision**: a `Connection` holds on to a single `BufReader` for its whole lifetime.
+/// A `BufReader` may read past the end of one `Message` and into the next, so creating a new
+/// `BufReader` for every `Message` (as [`Message::read`] does) would lose data on a persistent connection.
+pub struct Connection {
+    address: Address,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    /// Opens a new `Connection` to the peer at the specified `address`, which is bound by the `timeouts`.
+    pub fn open(address: &Address, timeouts: &Timeouts) -> Result<Connection, Error> {
+        let stream = TcpStream::connect_timeout(&SocketAddr::from(*address), timeouts.connect)?;
+        stream.set_read_timeout(Some(timeouts.read))?;
+        stream.set_write_timeout(Some(timeouts.write))?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        Ok(Connection {
+            address: *address,
+            reader,
+            writer: stream,
+        })
+    }
+
+    /// Returns the `Address` of the peer on the other end of this `Connection`.
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Sends the `request` to the peer and waits for its response.
+    pub fn send(&mut self, request: &Message) -> Result<Message, Error> {
+        self.try_send(request)?
+            .ok_or(Error::Protocol("connection closed before a response was received".into()))
+    }
+
+    /// Sends the `request` to the peer and waits for its response, returning `None` if the peer
+    /// closed this `Connection` without responding.
+    fn try_send(&mut self, request: &Message) -> Result<Option<Message>, Error> {
+        request.write(&mut self.writer)?;
+        Message::read_next(&mut self.reader)
+    }
+
+    /// Returns `true` if the peer has not closed this (idle) `Connection`, nor sent anything on it.
+    fn is_open(&mut self) -> bool {
+        if !self.reader.buffer().is_empty() || self.writer.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        // an idle peer has nothing to send, so a read which would block means the Connection is still open
+        let open = matches!(self.reader.get_ref().peek(&mut [0]), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock);
+
+        self.writer.set_nonblocking(false).is_ok() && open
+    }
+}
+
+/// `Connections` caches idle `Connection`s to peers, keyed by their `Address`, so that they can be reused.
+///
+/// **Design Decision**: a peer may close an idle `Connection` at any time (see [`serve`]). Idle
+/// `Connection`s which the peer has already closed are discarded before they are used. When a
+/// cached `Connection` fails anyway, before the peer has sent any part of its response, an
+/// idempotent request (e.g. `GET`) is retried exactly once, on a brand new `Connection`. Other
+/// requests (e.g. `POST`) are never retried, because the peer may have already acted on them, nor
+/// are requests which fail on a new `Connection`, or which time out.
+pub struct Connections {
+    idle: Mutex<HashMap<Address, Vec<Connection>>>,
+    max_idle: usize,
+    timeouts: Timeouts,
+}
+
+impl Connections {
+    /// Creates an empty cache which holds at most `max_idle` idle `Connection`s to each peer.
+    pub fn new(max_idle: usize) -> Connections {
+        Connections {
+            idle: Mutex::new(HashMap::new()),
+            max_idle,
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Sets the `Timeouts` of every new `Connection` opened by this cache.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Connections {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sends the `request` to the peer at the specified `address` and waits for its response,
+    /// reusing an idle `Connection` to that peer, if there is one.
+    pub fn send(&self, address: &Address, request: &Message) -> Result<Message, Error> {
+        if let Some(mut connection) = self.take(address) {
+            match connection.try_send(request) {
+                Ok(Some(response)) => {
+                    self.release(connection, request, &response);
+                    return Ok(response);
+                }
+                Ok(None) | Err(Error::Io(_)) if request.is_idempotent() => {
+                    debug!("[Connections] idle connection to {} was closed, reconnecting", address)
+                }
+                Ok(None) => return Err(Error::Protocol("connection closed before a response was received".into())),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut connection = Connection::open(address, &self.timeouts)?;
+        let response = connection.send(request)?;
+        self.release(connection, request, &response);
+        Ok(response)
+    }
+
+    /// Returns the number of idle `Connection`s currently cached for the peer at the specified `address`.
+    pub fn idle(&self, address: &Address) -> usize {
+        self.idle.lock().unwrap().get(address).map(|idle| idle.len()).unwrap_or_default()
+    }
+
+    /// Removes an idle `Connection` to the peer at the specified `address` from the cache, if there is
+    /// one which is still open. Any which have been closed are discarded.
+    fn take(&self, address: &Address) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let idle = idle.get_mut(address)?;
+
+        while let Some(mut connection) = idle.pop() {
+            if connection.is_open() {
+                return Some(connection);
+            }
+            debug!("[Connections] idle connection to {} was closed, discarding it", address);
+        }
+
+        None
+    }
+
+    /// Returns the `connection` to the cache, unless either side asked for it to be closed, or the cache is full.
+    fn release(&self, connection: Connection, request: &Message, response: &Message) {
+        if !request.keep_alive() || !response.keep_alive() {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let idle = idle.entry(connection.address).or_default();
+
+        if idle.len() < self.max_idle {
+            idle.push(connection);
+        }
+    }
+}
+
+/// By default, up to 2 idle `Connection`s are kept open to each peer.
+impl Default for Connections {
+    fn default() -> Self {
+        Connections::new(2)
+    }
+}
+
+/// `OpenConnections` keeps track of the connections which a `Device` is serving, so that they can
+/// all be closed at once when the `Device` shuts down.
+#[derive(Default)]
+pub(crate) struct OpenConnections {
+    next: AtomicUsize,
+    streams: Mutex<HashMap<usize, TcpStream>>,
+}
+
+impl OpenConnections {
+    /// Starts tracking the `stream`, returning a key which must later be passed to [`remove`](Self::remove).
+    pub(crate) fn insert(&self, stream: &TcpStream) -> Result<usize, Error> {
+        let key = self.next.fetch_add(1, Ordering::SeqCst);
+        self.streams.lock().unwrap().insert(key, stream.try_clone()?);
+        Ok(key)
+    }
+
+    /// Stops tracking the stream with the given `key`.
+    pub(crate) fn remove(&self, key: usize) {
+        self.streams.lock().unwrap().remove(&key);
+    }
+
+    /// Shuts down every tracked stream, which unblocks any thread reading from or writing to it.
+    pub(crate) fn close_all(&self) {
+        self.streams.lock().unwrap().values().for_each(|stream| {
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+    }
+}
+
+/// Reads `Message`s from the `stream` and passes each of them to the `handler`, until the peer
+/// closes the connection, asks for it to be closed, or leaves it idle for longer than `idle_timeout`.
+///
+/// An `idle_timeout` of zero disables keep-alive: the connection is closed after the first `Message`.
+///
+/// **Design Decision**: a kept-alive connection occupies one worker of the `Device`'s `WorkerPool`
+/// for as long as it is open. To avoid starving new connections, the connection is closed after
+/// any response which is sent while other connections are `waiting` for a free worker. Clients
+/// using [`Connections`] will transparently reconnect.
+pub(crate) fn serve(name: &Name, mut stream: TcpStream, handler: &Handler, idle_timeout: Duration, waiting: &AtomicUsize) -> Result<(), Error> {
+    let keep_alive_enabled = !idle_timeout.is_zero();
+
+    if keep_alive_enabled {
+        stream.set_read_timeout(Some(idle_timeout))?;
+    }
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    loop {
+        let message = match Message::read_next(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(Error::Timeout(_)) => return Ok(()), // the peer is done with this connection
+            Err(Error::TooLarge(msg)) => {
+                // the unread head or body is left on the stream, so this connection cannot be used again
+                Message::respond_content_too_large().write(&mut stream)?;
+                return Err(Error::TooLarge(msg));
+            }
+            Err(e) => {
+                let msg = format!("unable to read Message from stream: {}", e);
+                Message::respond_error(400, "invalid_request", msg.as_str(), name).write(&mut stream)?;
+                return Err(e);
+            }
+        };
+
+        let keep_alive = keep_alive_enabled && message.keep_alive();
+
+        (*handler)(&mut stream, message)?;
+
+        if !keep_alive || waiting.load(Ordering::SeqCst) > 0 {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod device_connection_tests {
+    use std::net::{IpAddr, TcpListener};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    use super::*;
+
+    /// Starts a server which answers every request on a single persistent connection with a
+    /// body containing the number of requests it has seen so far on that connection.
+    fn start_server(connections: usize, idle_timeout: Duration) -> (Address, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::new(IpAddr::from([127, 0, 0, 1]), listener.local_addr().unwrap().port());
+
+        let handle = std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let count = Arc::new(AtomicUsize::new(0));
+                let handler: Handler = Box::new(move |stream, _| {
+                    let count = count.fetch_add(1, Ordering::SeqCst) + 1;
+                    Message::respond_ok().with_body(count.to_string()).write(stream)
+                });
+
+                serve(&Name::new("server"), stream.unwrap(), &handler, idle_timeout, &AtomicUsize::new(0)).unwrap();
+            }
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn test_connection_reuse() {
+        let (address, server) = start_server(1, Duration::from_secs(5));
+        let connections = Connections::default();
+        let request = Message::request_post("/command").with_body("Hello, World!");
+
+        for expected in 1..=3 {
+            let response = connections.send(&address, &request).unwrap();
+            assert_eq!(response.body, Some(expected.to_string()));
+            assert_eq!(connections.idle(&address), 1);
+        }
+
+        drop(connections);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let (address, server) = start_server(2, Duration::from_secs(5));
+        let connections = Connections::default();
+
+        let mut headers = HashMap::new();
+        headers.insert("Connection", "close");
+
+        // the server closes the connection after every request, so the count starts over each time
+        for _ in 1..=2 {
+            let request = Message::request_get("/datum").with_headers(headers.clone());
+            let response = connections.send(&address, &request).unwrap();
+            assert_eq!(response.body, Some(String::from("1")));
+            assert_eq!(connections.idle(&address), 0);
+        }
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reconnect_after_idle_timeout() {
+        let (address, server) = start_server(2, Duration::from_millis(50));
+        let connections = Connections::default();
+        let request = Message::request_get("/datum");
+
+        let response = connections.send(&address, &request).unwrap();
+        assert_eq!(response.body, Some(String::from("1")));
+
+        // wait for the server to close the idle connection
+        std::thread::sleep(Duration::from_millis(200));
+
+        let response = connections.send(&address, &request).unwrap();
+        assert_eq!(response.body, Some(String::from("1")));
+
+        drop(connections);
+        server.join().unwrap();
+    }
+
+    /// Starts a server which answers the first request on its first connection, then reads (and
+    /// counts) the second request, but closes that connection without answering it. Every request
+    /// on later connections is answered.
+    fn start_flaky_server(connections: usize) -> (Address, Arc<AtomicUsize>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::new(IpAddr::from([127, 0, 0, 1]), listener.local_addr().unwrap().port());
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+
+        let handle = std::thread::spawn(move || {
+            for (index, stream) in listener.incoming().take(connections).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                while let Ok(Some(_)) = Message::read_next(&mut reader) {
+                    let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    if index == 0 && count == 2 {
+                        break;
+                    }
+                    Message::respond_ok().with_body(count.to_string()).write(&mut stream).unwrap();
+                }
+            }
+        });
+
+        (address, received, handle)
+    }
+
+    #[test]
+    fn test_retry_idempotent_request() {
+        let (address, received, server) = start_flaky_server(2);
+        let connections = Connections::default();
+        let request = Message::request_get("/datum");
+
+        assert_eq!(connections.send(&address, &request).unwrap().body, Some(String::from("1")));
+
+        // the second request is lost with the first connection, so it is sent again
+        assert_eq!(connections.send(&address, &request).unwrap().body, Some(String::from("3")));
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+
+        drop(connections);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_no_retry_non_idempotent_request() {
+        let (address, received, server) = start_flaky_server(1);
+        let connections = Connections::default();
+        let request = Message::request_post("/command").with_body("Hello, World!");
+
+        assert_eq!(connections.send(&address, &request).unwrap().body, Some(String::from("1")));
+
+        // the peer may have acted on the second request before the connection was closed
+        assert!(connections.send(&address, &request).is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+
+        drop(connections);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_discard_closed_idle_connection() {
+        let (address, server) = start_server(2, Duration::from_millis(50));
+        let connections = Connections::default();
+        let request = Message::request_post("/command").with_body("Hello, World!");
+
+        assert_eq!(connections.send(&address, &request).unwrap().body, Some(String::from("1")));
+
+        // wait for the server to close the idle connection, which is then not used for a POST
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(connections.send(&address, &request).unwrap().body, Some(String::from("1")));
+
+        drop(connections);
+        server.join().unwrap();
+    }
+}
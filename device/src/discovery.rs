@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::error::Error;
+use crate::handle::Shutdown;
+use crate::id::Id;
+use crate::json::Value;
+use crate::model::Model;
+use crate::name::Name;
+
+/// An `Event` describes a `Device` joining or leaving the network.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A `Device` has been found, and can be reached at the address in its `ServiceInfo`.
+    Resolved(ServiceInfo),
+    /// The `Device` with this full name (e.g. `id.model._sensor._tcp.local.`) has left the network.
+    Removed(String),
+}
+
+/// The `Event`s seen by [`Discovery::browse`], in the order in which they happened.
+///
+/// The iterator ends once browsing is stopped, or the `Discovery` is shut down.
+pub type Browse = Box<dyn Iterator<Item = Event> + Send>;
+
+/// A `Discovery` advertises a `Device` to its peers, and finds those peers for it.
+///
+/// Every `Device` owns its own `Discovery`, which it gets by [`connect`](Backend::connect)ing to a
+/// [`Backend`] when it is started.
+///
+/// **Design Decision**: `Device`s are still described by `ServiceInfo`s, whichever `Backend` is used,
+/// so that the `Address`, `Id`, `Model`, and `Name` of a `Device` are extracted in the same way, no
+/// matter how it was found.
+pub trait Discovery: Send + Sync {
+    /// Advertises a `Device`, so that it can be found by its peers.
+    fn register(&self, info: ServiceInfo) -> Result<(), Error>;
+
+    /// Starts looking for `Device`s of the specified `service_type` (e.g. `_sensor._tcp.local.`).
+    fn browse(&self, service_type: &str) -> Result<Browse, Error>;
+
+    /// Stops looking for `Device`s of the specified `service_type`, ending the `Browse` for it.
+    fn stop_browse(&self, service_type: &str) -> Result<(), Error>;
+
+    /// Withdraws every `Device` this `Discovery` has registered, and ends all of its `Browse`s.
+    fn shutdown(&self) -> Result<(), Error>;
+}
+
+/// A `Backend` selects how `Device`s find each other. It is chosen when a `Device` is started.
+#[derive(Clone)]
+pub enum Backend {
+    /// `Device`s advertise themselves and find each other via multicast DNS.
+    Mdns,
+    /// `Device`s are found in a fixed list, for networks where multicast is unavailable.
+    Static(StaticRegistry),
+    /// `Device`s find each other within this process only, which keeps test clusters isolated.
+    Memory(MemoryRegistry),
+}
+
+impl Backend {
+    /// Selects a `Backend` using the `DISCOVERY` environment variable.
+    ///
+    /// - unset, or `mdns`: [`Backend::Mdns`]
+    /// - `static`: [`Backend::Static`], with `Device`s listed (as JSON) in the `DISCOVERY_DEVICES`
+    ///   environment variable, or in the file at the path given by `DISCOVERY_FILE`
+    pub fn from_env() -> Result<Backend, Error> {
+        let backend = std::env::var("DISCOVERY").unwrap_or(String::from("mdns"));
+
+        match backend.as_str() {
+            "mdns" => Ok(Backend::Mdns),
+            "static" => match (std::env::var("DISCOVERY_DEVICES"), std::env::var("DISCOVERY_FILE")) {
+                (Ok(json), _) => StaticRegistry::parse(json).map(Backend::Static),
+                (_, Ok(path)) => StaticRegistry::from_file(path).map(Backend::Static),
+                _ => Err(Error::Discovery(String::from("DISCOVERY=static requires DISCOVERY_DEVICES or DISCOVERY_FILE"))),
+            },
+            other => Err(Error::Discovery(format!("unknown DISCOVERY backend '{}', expected 'mdns' or 'static'", other))),
+        }
+    }
+
+    /// Creates a new `Discovery` for a single `Device`, which is shut down along with that `Device`.
+    pub fn connect(&self, shutdown: &Shutdown) -> Result<Arc<dyn Discovery>, Error> {
+        let discovery: Arc<dyn Discovery> = match self {
+            Backend::Mdns => Arc::new(Mdns::new()?),
+            Backend::Static(registry) => Arc::new(registry.clone()),
+            Backend::Memory(registry) => Arc::new(registry.connect()),
+        };
+
+        let stopping = Arc::clone(&discovery);
+        shutdown.on_shutdown(move || {
+            if let Err(e) = stopping.shutdown() {
+                warn!("[Discovery] cannot shut down: {}", e);
+            }
+        });
+
+        Ok(discovery)
+    }
+}
+
+/// The version of the HTTP API which `Device`s speak to one another, advertised as the `protocol` property.
+///
+/// This should be incremented whenever a change is made which older `Device`s cannot understand.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Describes a `Device` so that it can be advertised and found by a [`Discovery`].
+///
+/// Each `Device` is named `id.model` within the `group._tcp.local.` domain, and carries its `id`,
+/// `name`, `model`, and [`protocol`](PROTOCOL_VERSION) version as properties, along with any
+/// other `properties` which describe what it can do (e.g. its `endpoints`).
+pub fn service_info(group: &str, id: &Id, name: &Name, model: Model, ip: IpAddr, port: u16, properties: HashMap<String, String>) -> Result<ServiceInfo, Error> {
+    let host = ip.to_string();
+    let instance = format!("{}.{}", id, model);
+    let domain = format!("{}._tcp.local.", group);
+
+    let mut properties = properties;
+    properties.insert("protocol".to_string(), PROTOCOL_VERSION.to_string());
+    properties.insert("id".to_string(), id.to_string());
+    properties.insert("name".to_string(), name.to_string());
+    properties.insert("model".to_string(), model.to_string());
+
+    let info = ServiceInfo::new(domain.as_str(), instance.as_str(), host.as_str(), ip, port, properties)?;
+    Ok(info)
+}
+
+/// Finds `Device`s via multicast DNS, using a `ServiceDaemon` of its own.
+pub struct Mdns {
+    daemon: ServiceDaemon,
+    registered: Mutex<Vec<String>>,
+}
+
+impl Mdns {
+    pub fn new() -> Result<Mdns, Error> {
+        Ok(Mdns {
+            daemon: ServiceDaemon::new()?,
+            registered: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl Discovery for Mdns {
+    fn register(&self, info: ServiceInfo) -> Result<(), Error> {
+        let fullname = info.get_fullname().to_string();
+        self.daemon.register(info)?;
+        self.registered.lock().unwrap().push(fullname);
+        Ok(())
+    }
+
+    fn browse(&self, service_type: &str) -> Result<Browse, Error> {
+        let receiver = self.daemon.browse(service_type)?;
+        let found = receiver.into_iter().filter_map(|event| match event {
+            ServiceEvent::ServiceResolved(info) => Some(Event::Resolved(info)),
+            ServiceEvent::ServiceRemoved(_, fullname) => Some(Event::Removed(fullname)),
+            _ => None,
+        });
+        Ok(Box::new(found))
+    }
+
+    fn stop_browse(&self, service_type: &str) -> Result<(), Error> {
+        self.daemon.stop_browse(service_type)?;
+        Ok(())
+    }
+
+    /// Unregistering tells other `Device`s that this one is gone, then stopping the daemon ends every `Browse`.
+    fn shutdown(&self) -> Result<(), Error> {
+        for fullname in self.registered.lock().unwrap().drain(..) {
+            match self.daemon.unregister(fullname.as_str()) {
+                Ok(status) => {
+                    let _ = status.recv_timeout(Duration::from_secs(1));
+                }
+                Err(e) => warn!("[Mdns] cannot unregister {}: {}", fullname, e),
+            }
+        }
+
+        self.daemon.shutdown()?;
+        Ok(())
+    }
+}
+
+/// A `StaticRegistry` is a fixed list of `Device`s, read from JSON like
+///
+/// ```json
+/// [{"group":"_sensor","id":"thermo-5000","name":"My Sensor","model":"thermo5000","ip":"10.0.0.2","port":8787}]
+/// ```
+///
+/// Registering with a `StaticRegistry` has no effect, so every `Device` which should be found must be listed.
+#[derive(Clone, Debug, Default)]
+pub struct StaticRegistry {
+    devices: Vec<ServiceInfo>,
+}
+
+impl StaticRegistry {
+    /// Parses a `StaticRegistry` from a JSON array of `Device`s.
+    pub fn parse<S: AsRef<str>>(json: S) -> Result<StaticRegistry, Error> {
+        let value = Value::parse(json)?;
+        let entries = value.as_array().ok_or(Error::Parse(String::from("static registry must be a JSON array")))?;
+
+        let devices = entries.iter().map(Self::parse_device).collect::<Result<Vec<ServiceInfo>, Error>>()?;
+        Ok(StaticRegistry { devices })
+    }
+
+    /// Reads a `StaticRegistry` from the JSON file at `path`.
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<StaticRegistry, Error> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|e| Error::Io(format!("cannot read {}: {}", path.display(), e)))?;
+        Self::parse(json)
+    }
+
+    fn parse_device(entry: &Value) -> Result<ServiceInfo, Error> {
+        let field = |key: &str| {
+            entry
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or(Error::Parse(format!("device is missing \"{}\"", key)))
+        };
+
+        let ip: IpAddr = field("ip")?
+            .parse()
+            .map_err(|_| Error::Parse(format!("invalid ip '{}'", field("ip").unwrap_or_default())))?;
+        let port = match entry.get("port").and_then(Value::as_f64) {
+            Some(port) if port.fract() == 0.0 && (1.0..=65535.0).contains(&port) => port as u16,
+            _ => return Err(Error::Parse(String::from("device is missing a valid \"port\""))),
+        };
+
+        // a listed Device may also describe what it can do, just like a Device which registers itself
+        let properties = ["endpoints", "kind", "unit", "commands"]
+            .into_iter()
+            .filter_map(|key| entry.get(key).and_then(Value::as_str).map(|value| (key.to_string(), value.to_string())))
+            .collect();
+
+        let model = Model::parse(field("model")?)?;
+        service_info(field("group")?, &Id::new(field("id")?), &Name::new(field("name")?), model, ip, port, properties)
+    }
+}
+
+impl Discovery for StaticRegistry {
+    fn register(&self, info: ServiceInfo) -> Result<(), Error> {
+        if !self.devices.iter().any(|device| device.get_fullname() == info.get_fullname()) {
+            warn!("[StaticRegistry] {} is not listed, so it cannot be found by other Devices", info.get_fullname());
+        }
+        Ok(())
+    }
+
+    fn browse(&self, service_type: &str) -> Result<Browse, Error> {
+        let found: Vec<ServiceInfo> = self.devices.iter().filter(|device| device.get_type() == service_type).cloned().collect();
+        Ok(Box::new(found.into_iter().map(Event::Resolved)))
+    }
+
+    fn stop_browse(&self, _service_type: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// every registered `Device`, and the session which registered it, keyed by its full name
+    devices: HashMap<String, (usize, ServiceInfo)>,
+    /// every open `Browse`, as (session, service type, sender)
+    browsers: Vec<(usize, String, Sender<Event>)>,
+    /// sessions which have been shut down, and can no longer register or browse
+    closed: HashSet<usize>,
+    sessions: usize,
+}
+
+/// A `MemoryRegistry` lets `Device`s in the same process find each other, without using the network.
+///
+/// Clones of a `MemoryRegistry` share the same `Device`s, but separate `MemoryRegistry::new()`s are
+/// completely isolated from one another, so many clusters of `Device`s can run side-by-side in tests.
+#[derive(Clone, Default)]
+pub struct MemoryRegistry {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> MemoryRegistry {
+        MemoryRegistry::default()
+    }
+
+    /// Creates a new `Discovery` session for a single `Device`.
+    pub fn connect(&self) -> MemoryDiscovery {
+        let mut registry = self.registry.lock().unwrap();
+        registry.sessions += 1;
+
+        MemoryDiscovery {
+            registry: Arc::clone(&self.registry),
+            session: registry.sessions,
+        }
+    }
+}
+
+/// A single `Device`'s view of a [`MemoryRegistry`].
+///
+/// Once it has been shut down, registering with a `MemoryDiscovery` has no effect, and browsing
+/// with it finds nothing.
+pub struct MemoryDiscovery {
+    registry: Arc<Mutex<Registry>>,
+    session: usize,
+}
+
+impl Discovery for MemoryDiscovery {
+    fn register(&self, info: ServiceInfo) -> Result<(), Error> {
+        let mut registry = self.registry.lock().unwrap();
+        if registry.closed.contains(&self.session) {
+            return Ok(());
+        }
+
+        // Browses which have been dropped are forgotten
+        registry
+            .browsers
+            .retain(|(_, service_type, sender)| service_type != info.get_type() || sender.send(Event::Resolved(info.clone())).is_ok());
+
+        info!("[MemoryRegistry] registered {}", info.get_fullname());
+        registry.devices.insert(info.get_fullname().to_string(), (self.session, info));
+        Ok(())
+    }
+
+    fn browse(&self, service_type: &str) -> Result<Browse, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let mut registry = self.registry.lock().unwrap();
+
+        // the sender is dropped, so the Browse ends immediately
+        if registry.closed.contains(&self.session) {
+            return Ok(Box::new(receiver.into_iter()));
+        }
+
+        for (_, info) in registry.devices.values().filter(|(_, info)| info.get_type() == service_type) {
+            let _ = sender.send(Event::Resolved(info.clone()));
+        }
+
+        registry.browsers.push((self.session, service_type.to_string(), sender));
+        Ok(Box::new(receiver.into_iter()))
+    }
+
+    fn stop_browse(&self, service_type: &str) -> Result<(), Error> {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .browsers
+            .retain(|(session, browsing, _)| *session != self.session || browsing != service_type);
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        let mut registry = self.registry.lock().unwrap();
+        registry.closed.insert(self.session);
+        registry.browsers.retain(|(session, _, _)| *session != self.session);
+
+        let withdrawn: Vec<ServiceInfo> = registry
+            .devices
+            .values()
+            .filter(|(session, _)| *session == self.session)
+            .map(|(_, info)| info.clone())
+            .collect();
+
+        // every other Device which is browsing for a withdrawn Device is told that it has gone
+        for info in withdrawn {
+            registry.devices.remove(info.get_fullname());
+            for (_, service_type, sender) in registry.browsers.iter() {
+                if service_type == info.get_type() {
+                    let _ = sender.send(Event::Removed(info.get_fullname().to_string()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod device_discovery_tests {
+    use super::*;
+
+    fn info(group: &str, id: &str, port: u16) -> ServiceInfo {
+        service_info(
+            group,
+            &Id::new(id),
+            &Name::new(id),
+            Model::Thermo5000,
+            IpAddr::from([127, 0, 0, 1]),
+            port,
+            HashMap::new(),
+        )
+        .unwrap()
+    }
+
+    fn resolved(found: Browse) -> Vec<ServiceInfo> {
+        found
+            .map(|event| match event {
+                Event::Resolved(info) => info,
+                Event::Removed(fullname) => panic!("unexpected removal of {}", fullname),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_service_info() {
+        let info = info("_sensor", "my_id", 8787);
+
+        assert_eq!(info.get_fullname(), "my_id.thermo5000._sensor._tcp.local.");
+        assert_eq!(info.get_type(), "_sensor._tcp.local.");
+        assert_eq!(info.get_port(), 8787);
+        assert_eq!(info.get_property_val_str("id"), Some("my_id"));
+        assert_eq!(info.get_property_val_str("model"), Some("thermo5000"));
+        assert_eq!(info.get_property_val_str("protocol"), Some("1"));
+    }
+
+    #[test]
+    fn test_static_registry() {
+        let json = r#"[
+            {"group":"_sensor","id":"s1","name":"Sensor 1","model":"thermo5000","ip":"10.0.0.2","port":8787,"kind":"float","unit":"°C"},
+            {"group":"_actuator","id":"s1","name":"Actuator 1","model":"thermo5000","ip":"10.0.0.3","port":9898}
+        ]"#;
+
+        let registry = StaticRegistry::parse(json).unwrap();
+        let found: Vec<ServiceInfo> = resolved(registry.browse("_sensor._tcp.local.").unwrap());
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get_property_val_str("name"), Some("Sensor 1"));
+        assert_eq!(found[0].get_port(), 8787);
+        assert!(found[0].get_addresses().contains(&IpAddr::from([10, 0, 0, 2])));
+        assert_eq!(found[0].get_property_val_str("unit"), Some("°C"));
+        assert_eq!(found[0].get_property_val_str("endpoints"), None);
+    }
+
+    #[test]
+    fn test_static_registry_failure() {
+        let missing_port = r#"[{"group":"_sensor","id":"s1","name":"Sensor 1","model":"thermo5000","ip":"10.0.0.2"}]"#;
+        assert_eq!(
+            StaticRegistry::parse(missing_port).unwrap_err(),
+            Error::Parse(String::from("device is missing a valid \"port\""))
+        );
+
+        let bad_ip = r#"[{"group":"_sensor","id":"s1","name":"Sensor 1","model":"thermo5000","ip":"nope","port":1}]"#;
+        assert_eq!(StaticRegistry::parse(bad_ip).unwrap_err(), Error::Parse(String::from("invalid ip 'nope'")));
+
+        assert!(StaticRegistry::parse("{}").is_err());
+    }
+
+    #[test]
+    fn test_memory_registry() {
+        let registry = MemoryRegistry::new();
+        let sensor = registry.connect();
+        let controller = registry.connect();
+
+        // Devices registered before and after browsing are both found
+        sensor.register(info("_sensor", "first", 1)).unwrap();
+        let mut found = controller.browse("_sensor._tcp.local.").unwrap();
+        sensor.register(info("_sensor", "second", 2)).unwrap();
+        sensor.register(info("_actuator", "third", 3)).unwrap();
+
+        let mut port = || match found.next() {
+            Some(Event::Resolved(info)) => Some(info.get_port()),
+            _ => None,
+        };
+        assert_eq!(port(), Some(1));
+        assert_eq!(port(), Some(2));
+
+        // shutting down a session withdraws its Devices, and tells everyone else who is browsing for them
+        sensor.shutdown().unwrap();
+        let mut removed: Vec<String> = (0..2)
+            .filter_map(|_| match found.next() {
+                Some(Event::Removed(fullname)) => Some(fullname),
+                _ => None,
+            })
+            .collect();
+        removed.sort();
+        assert_eq!(removed, vec!["first.thermo5000._sensor._tcp.local.", "second.thermo5000._sensor._tcp.local."]);
+
+        // shutting down a session ends its Browses
+        controller.shutdown().unwrap();
+        assert!(found.next().is_none());
+
+        let latecomer = registry.connect();
+        let found = latecomer.browse("_sensor._tcp.local.").unwrap();
+        latecomer.shutdown().unwrap();
+        assert_eq!(found.count(), 0);
+
+        // a session which has been shut down can no longer register, or browse
+        latecomer.register(info("_sensor", "fourth", 4)).unwrap();
+        assert_eq!(latecomer.browse("_sensor._tcp.local.").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_memory_registry_isolation() {
+        let one = MemoryRegistry::new().connect();
+        let two = MemoryRegistry::new().connect();
+
+        one.register(info("_sensor", "first", 1)).unwrap();
+
+        let found = two.browse("_sensor._tcp.local.").unwrap();
+        two.stop_browse("_sensor._tcp.local.").unwrap();
+
+        assert_eq!(found.count(), 0);
+    }
+}
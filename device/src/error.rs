@@ -0,0 +1,89 @@
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+/// An `Error` describes anything which can go wrong while a `Device` is communicating with its peers.
+///
+/// **Design Decision**: variants carry a human-readable `String` rather than wrapping the underlying
+/// error types (`std::io::Error`, `mdns_sd::Error`, etc.). This allows `Error` to derive `PartialEq`
+/// and `Clone`, which makes it much easier to test and to pass between threads.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Error {
+    /// Reading from or writing to a socket failed.
+    Io(String),
+    /// Some value (a `Model`, a `Datum`, a header, etc.) could not be parsed.
+    Parse(String),
+    /// A peer sent a `Message` which does not conform to HTTP/1.1.
+    Protocol(String),
+    /// A `Device` could not be registered with, or found via, mDNS.
+    Discovery(String),
+    /// A socket operation did not complete in time.
+    Timeout(String),
+    /// A peer sent a `Message` with a body larger than we will accept.
+    TooLarge(String),
+}
+
+/// Allows `Error`s to be converted to `String`s with `to_string()`.
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(msg) => write!(f, "I/O error: {}", msg),
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Discovery(msg) => write!(f, "discovery error: {}", msg),
+            Error::Timeout(msg) => write!(f, "timeout: {}", msg),
+            Error::TooLarge(msg) => write!(f, "too large: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Allows `?` to be used on `std::io::Result`s in functions which return `Result<_, device::Error>`.
+///
+/// **Design Decision**: sockets with a read or write timeout report an expired timeout as either
+/// `TimedOut` or `WouldBlock`, depending on the platform, so both are mapped to `Error::Timeout`.
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout(error.to_string()),
+            _ => Error::Io(error.to_string()),
+        }
+    }
+}
+
+/// Allows `?` to be used on `mdns_sd::Result`s in functions which return `Result<_, device::Error>`.
+impl From<mdns_sd::Error> for Error {
+    fn from(error: mdns_sd::Error) -> Self {
+        Error::Discovery(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod device_error_tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Error::Io("a".into()).to_string(), "I/O error: a");
+        assert_eq!(Error::Parse("b".into()).to_string(), "parse error: b");
+        assert_eq!(Error::Protocol("c".into()).to_string(), "protocol error: c");
+        assert_eq!(Error::Discovery("d".into()).to_string(), "discovery error: d");
+        assert_eq!(Error::Timeout("e".into()).to_string(), "timeout: e");
+        assert_eq!(Error::TooLarge("f".into()).to_string(), "too large: f");
+    }
+
+    #[test]
+    fn test_from_io_error() {
+        let error = std::io::Error::new(ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(Error::from(error), Error::Io("refused".into()));
+    }
+
+    #[test]
+    fn test_from_io_error_timeout() {
+        let timed_out = std::io::Error::new(ErrorKind::TimedOut, "too slow");
+        assert_eq!(Error::from(timed_out), Error::Timeout("too slow".into()));
+
+        let would_block = std::io::Error::new(ErrorKind::WouldBlock, "too slow");
+        assert_eq!(Error::from(would_block), Error::Timeout("too slow".into()));
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::address::Address;
+use crate::error::Error;
+use crate::name::Name;
+
+/// A hook which is run once, when a `Device` is asked to shut down.
+type Hook = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Inner {
+    requested: Mutex<bool>,
+    condvar: Condvar,
+    hooks: Mutex<Vec<Hook>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    address: Mutex<Option<Result<Address, Error>>>,
+    bound: Condvar,
+}
+
+/// A `Shutdown` is shared between a [`DeviceHandle`] and all of the threads of the `Device` it controls.
+///
+/// Long-running threads should either be [`spawn`](Self::spawn)ed through the `Shutdown`, and exit
+/// soon after it is [`request`](Self::request)ed, or should register a [hook](Self::on_shutdown)
+/// which unblocks them.
+///
+/// **Design Decision**: blocking calls (like `TcpListener::accept` or `mdns_sd::Receiver::recv`)
+/// cannot be interrupted by a flag alone, so each part of a `Device` registers a hook which knows
+/// how to unblock its own threads (e.g. by connecting to the listener, or stopping the mDNS daemon).
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Returns `true` if this `Device` has been asked to shut down.
+    pub fn is_requested(&self) -> bool {
+        *self.inner.requested.lock().unwrap()
+    }
+
+    /// Sleeps for `duration`, or until shutdown is requested, whichever happens first.
+    ///
+    /// Returns `true` if the calling thread should keep running, which makes this convenient to use
+    /// as the condition of a polling loop.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let requested = self.inner.requested.lock().unwrap();
+        let (requested, _) = self.inner.condvar.wait_timeout_while(requested, duration, |requested| !*requested).unwrap();
+        !*requested
+    }
+
+    /// Registers a `hook` which is run when shutdown is requested, or immediately, if it already has been.
+    ///
+    /// Hooks are run in the order in which they were registered.
+    pub fn on_shutdown(&self, hook: impl FnOnce() + Send + 'static) {
+        // the `requested` lock is held so that `request` cannot run the hooks while this one is being added
+        let requested = self.inner.requested.lock().unwrap();
+        if *requested {
+            drop(requested);
+            hook();
+        } else {
+            self.inner.hooks.lock().unwrap().push(Box::new(hook));
+        }
+    }
+
+    /// Reports the `Address` which the `Device` is listening on (or the reason it could not bind to
+    /// one), so that it can be read from the [`DeviceHandle`].
+    pub fn set_address(&self, address: Result<Address, Error>) {
+        *self.inner.address.lock().unwrap() = Some(address);
+        self.inner.bound.notify_all();
+    }
+
+    /// Spawns a thread which will be joined when the `Device` is shut down.
+    pub fn spawn(&self, f: impl FnOnce() + Send + 'static) {
+        self.inner.threads.lock().unwrap().push(std::thread::spawn(f));
+    }
+
+    /// Asks the `Device` to shut down, waking any sleeping threads and running all registered hooks.
+    ///
+    /// Calling this method more than once has no further effect.
+    pub fn request(&self) {
+        let hooks: Vec<Hook> = {
+            let mut requested = self.inner.requested.lock().unwrap();
+            if *requested {
+                return;
+            }
+            *requested = true;
+            self.inner.hooks.lock().unwrap().drain(..).collect()
+        };
+
+        self.inner.condvar.notify_all();
+        hooks.into_iter().for_each(|hook| hook());
+    }
+
+    /// Joins every thread which was spawned through this `Shutdown`, including any spawned while joining.
+    fn join(&self) {
+        loop {
+            let threads: Vec<JoinHandle<()>> = self.inner.threads.lock().unwrap().drain(..).collect();
+            if threads.is_empty() {
+                return;
+            }
+            threads.into_iter().for_each(|thread| {
+                if thread.join().is_err() {
+                    warn!("[Shutdown] a Device thread panicked");
+                }
+            });
+        }
+    }
+}
+
+/// A `DeviceHandle` is returned when a `Device` is started, and is used to stop it again.
+pub struct DeviceHandle {
+    name: Name,
+    shutdown: Shutdown,
+    thread: JoinHandle<()>,
+}
+
+impl DeviceHandle {
+    /// Runs `f` on a new thread, passing it the `Shutdown` which the returned `DeviceHandle` will request.
+    pub fn spawn(name: Name, f: impl FnOnce(Shutdown) + Send + 'static) -> DeviceHandle {
+        let shutdown = Shutdown::new();
+        let token = shutdown.clone();
+        let thread = std::thread::spawn(move || f(token));
+        DeviceHandle { name, shutdown, thread }
+    }
+
+    /// Returns the name of the `Device` which this `DeviceHandle` controls.
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// Returns the `Address` which the `Device` is listening on, waiting until it has bound to one.
+    ///
+    /// When a `Device` is started on port `0`, the operating system chooses a free port, and this is
+    /// the only way to find out which one.
+    ///
+    /// Returns an `Error` if the `Device` could not bind to its port, or stopped before it tried to.
+    pub fn address(&self) -> Result<Address, Error> {
+        let mut address = self.shutdown.inner.address.lock().unwrap();
+        loop {
+            if let Some(address) = address.as_ref() {
+                return address.clone();
+            }
+
+            // the Device's main thread may fail (e.g. if the mDNS daemon cannot start) before it binds
+            if self.thread.is_finished() {
+                return Err(Error::Io(format!("\"{}\" stopped before binding to a port", self.name)));
+            }
+
+            (address, _) = self.shutdown.inner.bound.wait_timeout(address, Duration::from_millis(50)).unwrap();
+        }
+    }
+
+    /// Returns `true` if the `Device`'s main thread has stopped (e.g. because it could not bind to its port).
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Stops the `Device`, and waits for all of its threads to finish.
+    ///
+    /// Polling loops are stopped, the `Device` is unregistered from mDNS, its `TcpListener` is closed,
+    /// and any open connections are shut down.
+    pub fn shutdown(self) {
+        info!("[DeviceHandle] shutting down \"{}\"", self.name);
+
+        self.shutdown.request();
+
+        if self.thread.join().is_err() {
+            warn!("[DeviceHandle] \"{}\" panicked", self.name);
+        }
+
+        self.shutdown.join();
+    }
+}
+
+/// Blocks the calling thread until the process receives `SIGINT` (Ctrl-C) or `SIGTERM`.
+///
+/// This should be called at most once per process, usually at the end of `main`, before
+/// [`shutdown`](DeviceHandle::shutdown)ting any running `Device`s.
+pub fn wait_for_termination() -> Result<(), Error> {
+    let (sender, receiver) = mpsc::channel();
+
+    ctrlc::set_handler(move || {
+        let _ = sender.send(());
+    })
+    .map_err(|e| Error::Io(format!("cannot handle termination signals: {}", e)))?;
+
+    let _ = receiver.recv();
+    Ok(())
+}
+
+#[cfg(test)]
+mod device_handle_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_sleep() {
+        let shutdown = Shutdown::new();
+        assert!(shutdown.sleep(Duration::from_millis(1)));
+
+        let sleeper = shutdown.clone();
+        let thread = std::thread::spawn(move || sleeper.sleep(Duration::from_secs(60)));
+
+        let start = Instant::now();
+        shutdown.request();
+
+        assert!(!thread.join().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(shutdown.is_requested());
+    }
+
+    #[test]
+    fn test_on_shutdown() {
+        let shutdown = Shutdown::new();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&count);
+        shutdown.on_shutdown(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        shutdown.request();
+        shutdown.request();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // hooks registered after shutdown is requested are run immediately
+        let counter = Arc::clone(&count);
+        shutdown.on_shutdown(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_device_handle_shutdown() {
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&iterations);
+
+        let handle = DeviceHandle::spawn(Name::new("my_device"), move |shutdown| {
+            let polling = shutdown.clone();
+            shutdown.spawn(move || {
+                while polling.sleep(Duration::from_millis(5)) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        });
+
+        assert_eq!(handle.name(), &Name::new("my_device"));
+
+        // this Device never binds to a port
+        assert!(handle.address().is_err());
+
+        std::thread::sleep(Duration::from_millis(50));
+        handle.shutdown();
+
+        // the polling thread has been joined, so it can no longer be counting
+        let count = iterations.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(iterations.load(Ordering::SeqCst), count);
+    }
+}
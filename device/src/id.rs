@@ -1,4 +1,6 @@
+use std::convert::Infallible;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 /// `Id` is a unique, immutable identifier associated with a `Device`.
 #[derive(PartialEq, Debug, Clone, Eq, Hash)]
@@ -17,6 +19,15 @@ impl Id {
     }
 }
 
+/// Allows `Id`s to be extracted from `Router` path parameters with `params.parse::<Id>("id")`.
+impl FromStr for Id {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Id::new(s))
+    }
+}
+
 #[cfg(test)]
 mod device_id_tests {
     use super::*;
@@ -34,4 +45,10 @@ mod device_id_tests {
         let actual = id.to_string();
         assert_eq!(actual, "id");
     }
+
+    #[test]
+    fn test_from_str() {
+        let actual = "id".parse::<Id>();
+        assert_eq!(actual, Ok(Id::new("id")));
+    }
 }
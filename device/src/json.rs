@@ -0,0 +1,319 @@
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::error::Error;
+
+/// Escapes the provided `string` so that it can be embedded between the quotes of a JSON string.
+///
+/// **Design Decision**: `Message` bodies are built with `format!` rather than with a JSON library,
+/// so any user-provided text (error messages, names, etc.) must be escaped before it is embedded.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc8259#section-7
+pub fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The deepest that arrays and objects may be nested within a `Value` which is parsed.
+///
+/// **Design Decision**: `Value`s are parsed recursively, so without a limit, a small body like
+/// `[[[[...` could overflow the stack, which aborts the whole process rather than failing one request.
+pub const MAX_DEPTH: usize = 128;
+
+/// A `Value` is a parsed JSON document, used to read the (small) JSON bodies which clients send to `Device`s.
+///
+/// **Design Decision**: object members are kept in the order in which they were parsed, rather than
+/// in a `HashMap`, so that a `Value` can be serialized back into (nearly) the same text it was
+/// parsed from. Some `Device`s (e.g. the Thermo5000 actuator) expect their fields in a fixed order.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Attempts to parse a `Value` from the provided string or string slice.
+    pub fn parse<S: AsRef<str>>(s: S) -> Result<Value, Error> {
+        let mut chars = s.as_ref().chars().peekable();
+        let value = Self::parse_value(&mut chars, 0)?;
+
+        Self::skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(Error::Parse(format!("unexpected '{}' after JSON value", c))),
+        }
+    }
+
+    /// Returns the member of this object with the given `key`, if this is an object and the member exists.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Returns this `Value` as a string slice, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Returns this `Value` as an `f64`, if it is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns this `Value` as a `bool`, if it is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns this `Value` as a slice of `Value`s, if it is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values.as_slice()),
+            _ => None,
+        }
+    }
+
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), Error> {
+        Self::skip_whitespace(chars);
+        match chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(Error::Parse(format!("expected '{}' but found '{}'", expected, c))),
+            None => Err(Error::Parse(format!("expected '{}' but found end of input", expected))),
+        }
+    }
+
+    /// Parses a `Value` which is nested within `depth` arrays and / or objects.
+    fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Value, Error> {
+        Self::skip_whitespace(chars);
+        match chars.peek() {
+            Some('{' | '[') if depth >= MAX_DEPTH => Err(Error::Parse("JSON is nested too deeply".into())),
+            Some('{') => Self::parse_object(chars, depth + 1),
+            Some('[') => Self::parse_array(chars, depth + 1),
+            Some('"') => Self::parse_string(chars).map(Value::String),
+            Some('t') => Self::parse_literal(chars, "true", Value::Bool(true)),
+            Some('f') => Self::parse_literal(chars, "false", Value::Bool(false)),
+            Some('n') => Self::parse_literal(chars, "null", Value::Null),
+            Some(c) if *c == '-' || c.is_ascii_digit() => Self::parse_number(chars),
+            Some(c) => Err(Error::Parse(format!("unexpected '{}' at start of JSON value", c))),
+            None => Err(Error::Parse("unexpected end of input".into())),
+        }
+    }
+
+    fn parse_literal(chars: &mut Peekable<Chars>, literal: &str, value: Value) -> Result<Value, Error> {
+        for expected in literal.chars() {
+            if chars.next() != Some(expected) {
+                return Err(Error::Parse(format!("invalid literal, expected '{}'", literal)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_number(chars: &mut Peekable<Chars>) -> Result<Value, Error> {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+            number.push(c);
+        }
+
+        number
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| Error::Parse(format!("invalid number '{}'", number)))
+    }
+
+    fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+        Self::expect(chars, '"')?;
+        let mut string = String::new();
+
+        loop {
+            match chars.next() {
+                None => return Err(Error::Parse("unterminated string".into())),
+                Some('"') => return Ok(string),
+                Some('\\') => match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+                        let code = u32::from_str_radix(hex.as_str(), 16).map_err(|_| Error::Parse(format!("invalid unicode escape '\\u{}'", hex)))?;
+                        // surrogate pairs are not supported, and are replaced with U+FFFD
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => return Err(Error::Parse(format!("invalid escape '\\{}'", c))),
+                    None => return Err(Error::Parse("unterminated string".into())),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn parse_array(chars: &mut Peekable<Chars>, depth: usize) -> Result<Value, Error> {
+        Self::expect(chars, '[')?;
+        let mut values = Vec::new();
+
+        Self::skip_whitespace(chars);
+        if chars.next_if_eq(&']').is_some() {
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(Self::parse_value(chars, depth)?);
+            Self::skip_whitespace(chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(Error::Parse("expected ',' or ']' in array".into())),
+            }
+        }
+    }
+
+    fn parse_object(chars: &mut Peekable<Chars>, depth: usize) -> Result<Value, Error> {
+        Self::expect(chars, '{')?;
+        let mut members = Vec::new();
+
+        Self::skip_whitespace(chars);
+        if chars.next_if_eq(&'}').is_some() {
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            Self::skip_whitespace(chars);
+            let key = Self::parse_string(chars)?;
+            Self::expect(chars, ':')?;
+            let value = Self::parse_value(chars, depth)?;
+            members.push((key, value));
+
+            Self::skip_whitespace(chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err(Error::Parse("expected ',' or '}' in object".into())),
+            }
+        }
+    }
+}
+
+/// Allows `Value`s to be converted to (compact) JSON `String`s with `to_string()`.
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "\"{}\"", escape(s)),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", values.join(","))
+            }
+            Value::Object(members) => {
+                let members: Vec<String> = members.iter().map(|(k, v)| format!("\"{}\":{}", escape(k), v)).collect();
+                write!(f, "{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod device_json_tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain °C"), "plain °C");
+        assert_eq!(escape(r#"could not parse "Some(\"x\")""#), r#"could not parse \"Some(\\\"x\\\")\""#);
+        assert_eq!(escape("line\r\nbreak\ttab\u{0}"), "line\\r\\nbreak\\ttab\\u0000");
+    }
+
+    #[test]
+    fn test_parse() {
+        let actual = Value::parse(
+            r#" {"type": "command", "id":"my_id", "command":{"name":"HeatBy","value":"25"}, "seconds": 3600, "ok": [true, false, null, -1.5e2]} "#,
+        )
+        .unwrap();
+
+        assert_eq!(actual.get("type").and_then(Value::as_str), Some("command"));
+        assert_eq!(actual.get("seconds").and_then(Value::as_f64), Some(3600.0));
+        assert_eq!(
+            actual.get("ok").and_then(Value::as_array),
+            Some([Value::Bool(true), Value::Bool(false), Value::Null, Value::Number(-150.0)].as_slice())
+        );
+        assert_eq!(actual.get("missing"), None);
+
+        // objects are serialized in their original order
+        assert_eq!(actual.get("command").unwrap().to_string(), r#"{"name":"HeatBy","value":"25"}"#);
+    }
+
+    #[test]
+    fn test_parse_string_escapes() {
+        let actual = Value::parse(r#""a\"b\\c\n°C""#).unwrap();
+        assert_eq!(actual, Value::String(String::from("a\"b\\c\n°C")));
+        assert_eq!(actual.to_string(), r#""a\"b\\c\n°C""#);
+    }
+
+    #[test]
+    fn test_parse_empty_containers() {
+        assert_eq!(Value::parse("[]"), Ok(Value::Array(vec![])));
+        assert_eq!(Value::parse("{ }"), Ok(Value::Object(vec![])));
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(Value::parse(""), Err(Error::Parse("unexpected end of input".into())));
+        assert_eq!(Value::parse("{\"a\":1"), Err(Error::Parse("expected ',' or '}' in object".into())));
+        assert_eq!(Value::parse("[1 2]"), Err(Error::Parse("expected ',' or ']' in array".into())));
+        assert_eq!(Value::parse("\"abc"), Err(Error::Parse("unterminated string".into())));
+        assert_eq!(Value::parse("tru"), Err(Error::Parse("invalid literal, expected 'true'".into())));
+        assert_eq!(Value::parse("1 2"), Err(Error::Parse("unexpected '2' after JSON value".into())));
+        assert_eq!(Value::parse("--1"), Err(Error::Parse("invalid number '--1'".into())));
+    }
+
+    #[test]
+    fn test_parse_too_deep() {
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Value::parse(nested).is_ok());
+
+        let too_deep = format!("{}{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+        assert_eq!(Value::parse(too_deep), Err(Error::Parse("JSON is nested too deeply".into())));
+
+        // without a limit, this would overflow the stack
+        assert_eq!(Value::parse("[".repeat(200_000)), Err(Error::Parse("JSON is nested too deeply".into())));
+        assert_eq!(Value::parse(r#"{"a":"#.repeat(200_000)), Err(Error::Parse("JSON is nested too deeply".into())));
+    }
+}
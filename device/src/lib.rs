@@ -1,28 +1,71 @@
 use std::collections::HashMap;
 use std::io::Write;
-use std::net::{IpAddr, TcpListener, TcpStream};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
+use std::time::Duration;
 
-use log::{error, info};
-use mdns_sd::{ServiceDaemon, ServiceInfo};
+use log::{error, info, warn};
+use mdns_sd::ServiceInfo;
+
+use datum::kind::Kind;
+use datum::unit::Unit;
 
 use crate::address::Address;
+use crate::connection::OpenConnections;
+use crate::discovery::{Discovery, Event};
+use crate::error::Error;
+use crate::handle::Shutdown;
 use crate::id::Id;
 use crate::message::Message;
 use crate::model::Model;
 use crate::name::Name;
+use crate::pool::{PoolConfig, WorkerPool};
 
 pub mod address;
+pub mod broadcast;
+pub mod chunked;
+pub mod client;
+pub mod connection;
+pub mod discovery;
+pub mod error;
+pub mod handle;
 pub mod id;
+pub mod json;
 pub mod message;
 pub mod model;
 pub mod name;
+pub mod pool;
+pub mod router;
+pub mod sse;
+pub mod websocket;
+
+/// After this many consecutive failed requests to a `Device` found by [`Device::discover_once`], it
+/// is forgotten, and discovery starts over.
+pub const REDISCOVER_AFTER: u32 = 3;
+
+/// How long to wait before browsing again, once a `Browse` has ended.
+const REBROWSE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long [`Device::discover_once`] waits for a `Device` to be found, before it starts browsing over again.
+const REBROWSE_AFTER: Duration = Duration::from_secs(5);
 
 /// A `Handler` describes how a `Device` should handle incoming HTTP requests.
-pub type Handler = Box<dyn Fn(&mut TcpStream)>;
+///
+/// It receives the `TcpStream` to respond on and the `Message` which was read from that stream.
+///
+/// **Design Decision**: the `Message` is read by [`Device::respond`] rather than by the `Handler`,
+/// because a single persistent connection may carry many `Message`s, which must all be read from
+/// the same buffer.
+///
+/// **Design Decision**: a `Handler` returns a `Result` rather than panicking so that a single
+/// misbehaving connection can be logged and dropped without killing the `Device`.
+///
+/// **Design Decision**: a `Handler` must be `Send + Sync` so that a single `Handler` can be shared by
+/// all of the workers in the `WorkerPool` which handles a `Device`'s incoming connections.
+pub type Handler = Box<dyn Fn(&mut TcpStream, Message) -> Result<(), Error> + Send + Sync>;
 
-/// A `Device` exists on the network and is discoverable via mDNS.
+/// A `Device` exists on the network and is discoverable via a [`Discovery`] backend (mDNS, by default).
 ///
 /// **Design Decision**: `Device` must implement `Sized` so that we can call `Self::new` in the
 /// `start` methods of `Actuator`, `Controller`, `Environment`, and `Sensor`. We need a `new` method
@@ -39,72 +82,197 @@ pub trait Device: Sized {
     fn get_model() -> Model;
 
     /// Returns the helper which defines how to handle HTTP requests.
-    fn get_handler(&self) -> Handler;
+    ///
+    /// `address` is the address this `Device` is actually bound to, which is only known once it has
+    /// been bound (for example, when it was asked to listen on port `0`).
+    fn get_handler(&self, address: Address) -> Handler;
+
+    /// Returns the size of the `WorkerPool` used to handle incoming HTTP requests concurrently.
+    ///
+    /// Override this method to allow more (or fewer) concurrent requests for a particular `Device`.
+    fn get_pool_config(&self) -> PoolConfig {
+        PoolConfig::default()
+    }
+
+    /// Returns how long an idle, kept-alive connection is held open, waiting for another request.
+    ///
+    /// Override this method to return `Duration::ZERO` to close every connection after a single request.
+    fn get_keep_alive_timeout(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    /// Returns the endpoints (e.g. `GET /datum`) which this `Device` responds to, which are advertised
+    /// via discovery so that its peers can check that they are compatible with it before using it.
+    ///
+    /// Override this method to advertise the endpoints of a particular kind of `Device`.
+    fn get_endpoints(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns any other properties (e.g. a `Sensor`'s `kind` and `unit`) which are advertised via discovery.
+    ///
+    /// Override this method to describe what a particular kind of `Device` can do.
+    fn get_properties(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
 
     /// Provides a standard way to deal with failures in `get_handler()`.
     ///
+    /// Responds with the given HTTP `status` and a JSON body (see [`Message::respond_error`])
+    /// containing the human-readable `msg`, the machine-readable `code`, and this `Device`'s name.
+    ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handler_failure(self_name: Name, tcp_stream: &mut impl Write, msg: &str) {
+    fn handler_failure(self_name: Name, tcp_stream: &mut impl Write, status: u16, code: &str, msg: &str) -> Result<(), Error> {
         error!("[{}] {}", self_name, msg);
-        let response = Message::respond_bad_request().with_body(msg);
+        let response = Message::respond_error(status, code, msg, &self_name);
         response.write(tcp_stream)
     }
 
-    /// Returns the `ServiceInfo` for this `Device`, which is used to register it via mDNS.
+    /// Returns the `ServiceInfo` for this `Device`, which is used to register it for discovery.
     ///
     /// **Design Decision**: this logic has been extracted from [`register`](Self::register) to make
-    /// it easier to test (no `mdns: ServiceDaemon` is required).
-    fn get_service_info(&self, ip: IpAddr, port: u16, group: &str) -> ServiceInfo {
-        let host = ip.to_string();
-        let label = self.get_name().to_string();
-        let name = format!("{}.{}", self.get_id(), Self::get_model());
-        let domain = format!("{}._tcp.local.", group);
-
-        info!("[Device::register] registering new Device \"{}\" via mDNS at {}.{}", label, name, domain);
-
-        let mut properties = HashMap::new();
-        properties.insert("id".to_string(), self.get_id().to_string());
-        properties.insert("name".to_string(), self.get_name().to_string());
-        properties.insert("model".to_string(), Self::get_model().to_string());
-
-        ServiceInfo::new(domain.as_str(), name.as_str(), host.as_str(), ip, port, properties).unwrap()
+    /// it easier to test (no `Discovery` is required).
+    fn get_service_info(&self, ip: IpAddr, port: u16, group: &str) -> Result<ServiceInfo, Error> {
+        let mut properties = self.get_properties();
+        properties.insert("endpoints".to_string(), self.get_endpoints().join(","));
+
+        let info = discovery::service_info(group, self.get_id(), self.get_name(), Self::get_model(), ip, port, properties)?;
+        info!("[Device::register] registering new Device \"{}\" as {}", self.get_name(), info.get_fullname());
+        Ok(info)
     }
 
-    /// Registers this `Device` with mDNS in the specified group.
-    fn register(&self, service_info: ServiceInfo, mdns: ServiceDaemon) {
-        mdns.register(service_info).unwrap()
+    /// Registers this `Device` with `discovery`, so that other `Device`s can find it.
+    ///
+    /// The `Device` is withdrawn again when its `Discovery` is shut down.
+    fn register(&self, service_info: ServiceInfo, discovery: &dyn Discovery) -> Result<(), Error> {
+        discovery.register(service_info)
     }
 
     /// Creates a `TcpListener` and binds it to the specified `ip` and `port`.
-    fn bind(&self, address: Address) -> TcpListener {
+    ///
+    /// Use port `0` to bind to any free port, then ask the `TcpListener` for its `local_addr()`.
+    fn bind(&self, address: Address) -> Result<TcpListener, Error> {
         let address = address.to_string();
         let name = &self.get_name();
 
         info!("[Device::bind] binding new TCP listener to \"{}\" at {}", name, address);
 
-        TcpListener::bind(address).unwrap()
+        TcpListener::bind(address.as_str()).map_err(|e| Error::Io(format!("cannot bind to {}: {}", address, e)))
     }
 
-    /// `register`s and `bind`s this `Device`, then spawns a new thread where it will continually
-    /// listen for incoming `TcpStream`s and handle them appropriately.
-    fn respond(&self, ip: IpAddr, port: u16, group: &str, mdns: ServiceDaemon) {
-        let service_info = self.get_service_info(ip, port, group);
+    /// `bind`s and `register`s this `Device`, then continually listens for incoming `TcpStream`s
+    /// and dispatches them to a `WorkerPool`, where they are handled concurrently.
+    ///
+    /// Connections are kept alive between requests, unless the client asks for them to be closed,
+    /// leaves them idle for longer than [`get_keep_alive_timeout`](Self::get_keep_alive_timeout),
+    /// or other connections are waiting for a free worker.
+    ///
+    /// When every worker is busy and the pool's queue is full, new connections are immediately
+    /// rejected with a `503 Service Unavailable` response.
+    ///
+    /// If `port` is `0`, the operating system chooses a free port, which is advertised via `discovery`
+    /// and reported through `shutdown` to the `DeviceHandle`.
+    ///
+    /// Returns once `shutdown` is requested, after every open connection has been closed, and every
+    /// worker has finished.
+    ///
+    /// Returns an `Error` only if this `Device` could not be registered or bound. Failures on
+    /// individual connections are logged and the connection is dropped.
+    fn respond(&self, ip: IpAddr, port: u16, group: &str, discovery: &dyn Discovery, shutdown: &Shutdown) -> Result<(), Error> {
+        // the listener is bound first, so that the port which is advertised is the one which was actually bound
+        let listener = match self.bind(Address::new(ip, port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                shutdown.set_address(Err(e.clone()));
+                return Err(e);
+            }
+        };
+
+        let address = Address::new(ip, listener.local_addr()?.port());
+        shutdown.set_address(Ok(address));
+
+        let service_info = self.get_service_info(ip, address.port(), group)?;
+        self.register(service_info, discovery)?;
+
+        // accept() cannot be interrupted, so the listener is woken up by connecting to it
+        let mut wake = listener.local_addr()?;
+        if wake.ip().is_unspecified() {
+            wake = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), wake.port());
+        }
+        shutdown.on_shutdown(move || {
+            let _ = TcpStream::connect_timeout(&wake, Duration::from_secs(1));
+        });
+
+        // Anything which depends on self must be cloned outside of the |stream| lambda.
+        // We cannot refer to `self` inside of this lambda.
+        let self_name = self.get_name().clone();
+        let handler = self.get_handler(address);
+        let idle_timeout = self.get_keep_alive_timeout();
+
+        // the number of connections which have been accepted, but not yet picked up by a worker
+        let waiting = Arc::new(AtomicUsize::new(0));
+        let queued = Arc::clone(&waiting);
+
+        // connections are closed on shutdown, so that workers blocked on them can finish
+        let open = Arc::new(OpenConnections::default());
+        let tracked = Arc::clone(&open);
+        shutdown.on_shutdown(move || tracked.close_all());
+
+        let stopping = shutdown.clone();
+
+        let pool = WorkerPool::new(self.get_pool_config(), move |stream: TcpStream| {
+            queued.fetch_sub(1, Ordering::SeqCst);
+
+            let key = match open.insert(&stream) {
+                Ok(key) => key,
+                Err(e) => {
+                    warn!("[Device::respond] \"{}\" failed to track connection: {}", self_name, e);
+                    return;
+                }
+            };
+
+            // a connection which was queued when shutdown was requested is dropped without being served
+            if !stopping.is_requested() {
+                if let Err(e) = connection::serve(&self_name, stream, &handler, idle_timeout, &queued) {
+                    warn!("[Device::respond] \"{}\" failed to handle request: {}", self_name, e);
+                }
+            }
 
-        self.register(service_info, mdns);
-        let listener = self.bind(Address::new(ip, port));
+            open.remove(key);
+        });
 
         for stream in listener.incoming() {
-            let mut stream = stream.unwrap();
-            (*self.get_handler())(&mut stream);
+            if shutdown.is_requested() {
+                break;
+            }
+
+            match stream {
+                Ok(stream) => {
+                    waiting.fetch_add(1, Ordering::SeqCst);
+                    if let Err(mut stream) = pool.try_submit(stream) {
+                        waiting.fetch_sub(1, Ordering::SeqCst);
+                        warn!("[Device::respond] \"{}\" is saturated, rejecting connection", self.get_name());
+                        let response = Message::respond_service_unavailable().with_body("too many concurrent requests, try again later");
+                        if let Err(e) = response.write(&mut stream) {
+                            warn!("[Device::respond] \"{}\" failed to reject connection: {}", self.get_name(), e);
+                        }
+                    }
+                }
+                Err(e) => warn!("[Device::respond] \"{}\" failed to accept connection: {}", self.get_name(), e),
+            }
         }
+
+        info!("[Device::respond] \"{}\" stopped accepting connections", self.get_name());
+
+        // dropping the pool joins all of its workers
+        drop(pool);
+        Ok(())
     }
 
-    /// Extracts the `Address` of a `Device` from its `ServiceInfo` found via mDNS.
-    fn extract_address(info: &ServiceInfo) -> Address {
-        let ip = *info.get_addresses().iter().next().unwrap();
-        let port = info.get_port();
-        Address::new(ip, port)
+    /// Extracts the `Address` of a `Device` from its discovered `ServiceInfo`.
+    fn extract_address(info: &ServiceInfo) -> Result<Address, Error> {
+        Address::try_from(info)
     }
 
     /// Extracts the [`Id`](Id) of a `Device` from its `ServiceInfo`.
@@ -118,7 +286,7 @@ pub trait Device: Sized {
     /// Extracts the [`Model`](Model) of a `Device` from its `ServiceInfo`.
     ///
     /// The `model` property is set when a device is [`register`ed](Self::register) with mDNS.
-    fn extract_model(info: &ServiceInfo) -> Option<Result<Model, String>> {
+    fn extract_model(info: &ServiceInfo) -> Option<Result<Model, Error>> {
         let model = info.get_property("model").map(|p| p.to_string());
         model.map(|m| Model::parse(m.trim_start_matches("model=")))
     }
@@ -131,53 +299,156 @@ pub trait Device: Sized {
         name.map(|i| Name::new(i.trim_start_matches("name=")))
     }
 
+    /// Extracts the [`protocol`](discovery::PROTOCOL_VERSION) version spoken by a `Device` from its `ServiceInfo`.
+    ///
+    /// The `protocol` property is set when a device is [`register`ed](Self::register) with mDNS.
+    fn extract_protocol_version(info: &ServiceInfo) -> Option<Result<u32, Error>> {
+        let version = info.get_property_val_str("protocol");
+        version.map(|v| v.parse().map_err(|_| Error::Parse(format!("cannot parse '{}' as a protocol version", v))))
+    }
+
+    /// Extracts the endpoints (e.g. `GET /datum`) which a `Device` responds to from its `ServiceInfo`.
+    ///
+    /// Returns an empty `Vec` if the `Device` did not advertise any endpoints.
+    fn extract_endpoints(info: &ServiceInfo) -> Vec<String> {
+        Self::extract_list(info, "endpoints")
+    }
+
+    /// Extracts the [`Kind`](Kind) of the `Datum`s produced by a `Sensor` from its `ServiceInfo`.
+    ///
+    /// Only `Sensor`s advertise a `kind` property.
+    fn extract_kind(info: &ServiceInfo) -> Option<Result<Kind, Error>> {
+        let kind = info.get_property_val_str("kind");
+        kind.map(|k| Kind::parse(k).map_err(Error::Parse))
+    }
+
+    /// Extracts the [`Unit`](Unit) of the `Datum`s produced by a `Sensor` from its `ServiceInfo`.
+    ///
+    /// Only `Sensor`s advertise a `unit` property.
+    fn extract_unit(info: &ServiceInfo) -> Option<Result<Unit, Error>> {
+        let unit = info.get_property_val_str("unit");
+        unit.map(|u| Unit::parse(u).map_err(Error::Parse))
+    }
+
+    /// Extracts the names of the `Command`s which an `Actuator` accepts from its `ServiceInfo`.
+    ///
+    /// Only `Actuator`s advertise a `commands` property. Returns an empty `Vec` for any other `Device`.
+    fn extract_commands(info: &ServiceInfo) -> Vec<String> {
+        Self::extract_list(info, "commands")
+    }
+
+    /// Extracts a comma-separated list from the property with the given `key`.
+    ///
+    /// **Design Decision**: lists are advertised as single comma-separated properties, rather than as
+    /// one property per item, because mDNS TXT records cannot hold more than one value per key.
+    fn extract_list(info: &ServiceInfo, key: &str) -> Vec<String> {
+        match info.get_property_val_str(key) {
+            None => Vec::new(),
+            Some(list) => list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect(),
+        }
+    }
+
     /// Creates a new thread to discover one or more `Device`s on the network in the specified `group`.
+    ///
+    /// Every discovery `Event` is passed to `update`. Whenever browsing ends (because it was stopped,
+    /// or because a static list of `Device`s was exhausted), it starts over again.
+    ///
+    /// The thread is joined when the `Device` shuts down. It ends once `discovery` is shut down.
     fn discover<T: Sync + Send + 'static>(
         &self,
         group: &str,
         container: &Arc<Mutex<T>>,
-        mdns: ServiceDaemon,
-        save: fn(ServiceInfo, &String, &Arc<Mutex<T>>),
-        unique: bool,
-    ) -> JoinHandle<()> {
+        discovery: &Arc<dyn Discovery>,
+        update: fn(Event, &String, &Arc<Mutex<T>>),
+        shutdown: &Shutdown,
+    ) {
         let group = String::from(group);
         let mutex = Arc::clone(container);
+        let discovery = Arc::clone(discovery);
+        let stopping = shutdown.clone();
 
         // Anything which depends on self must be cloned outside of the || lambda.
         // We cannot refer to `self` inside of this lambda.
         let self_name = self.get_name().to_string();
 
-        std::thread::spawn(move || {
+        shutdown.spawn(move || {
             let service_type = format!("{}._tcp.local.", group);
             let service_type = service_type.as_str();
-            let receiver = mdns.browse(service_type).unwrap();
 
-            while let Ok(event) = receiver.recv() {
-                if let mdns_sd::ServiceEvent::ServiceResolved(info) = event {
-                    save(info, &self_name, &mutex);
-                    if unique {
-                        break;
-                    }
+            while !stopping.is_requested() {
+                match discovery.browse(service_type) {
+                    Ok(found) => found.for_each(|event| update(event, &self_name, &mutex)),
+                    Err(e) => error!("[Device::discover] \"{}\" cannot browse for {}: {}", self_name, service_type, e),
                 }
-            }
 
-            mdns.stop_browse(service_type).unwrap();
+                if !stopping.sleep(REBROWSE_INTERVAL) {
+                    break;
+                }
+            }
         })
     }
 
-    /// Creates a new thread to discover a single `Device` on the network in the specified `group`.
+    /// Creates new threads to discover a single `Device` on the network in the specified `group`.
     ///
-    /// Once that single `Device` is discovered, the thread is completed.
-    fn discover_once(&self, group: &str, devices: &Arc<Mutex<Option<ServiceInfo>>>, mdns: ServiceDaemon) -> JoinHandle<()> {
-        self.discover(group, devices, mdns, Self::save_unique_device, true)
+    /// Browsing continues once that `Device` is found, so that any change to its address is picked up.
+    /// If it leaves the network, or is forgotten after repeated failures (see [`record_request`](Self::record_request)),
+    /// discovery starts over, and the next `Device` found in the `group` takes its place.
+    fn discover_once(&self, group: &str, devices: &Arc<Mutex<Option<ServiceInfo>>>, discovery: &Arc<dyn Discovery>, shutdown: &Shutdown) {
+        let update = |event, self_name: &String, device: &Arc<Mutex<Option<ServiceInfo>>>| match event {
+            Event::Resolved(info) => Self::save_unique_device(info, self_name, device),
+            Event::Removed(fullname) => Self::remove_unique_device(fullname.as_str(), self_name, device),
+        };
+
+        self.discover(group, devices, discovery, update, shutdown);
+
+        // Anything which depends on self must be cloned outside of the || lambda.
+        // We cannot refer to `self` inside of this lambda.
+        let self_name = self.get_name().clone();
+        let service_type = format!("{}._tcp.local.", group);
+        let device = Arc::clone(devices);
+        let discovery = Arc::clone(discovery);
+        let watching = shutdown.clone();
+
+        // a forgotten Device is still known to `discovery`, which must be asked to look again, and
+        // while no Device has been found, it is asked again every so often
+        shutdown.spawn(move || {
+            let interval = Duration::from_millis(250);
+            let mut known = false;
+            let mut waited = Duration::ZERO;
+
+            while watching.sleep(interval) {
+                if device.lock().unwrap().is_some() {
+                    known = true;
+                    waited = Duration::ZERO;
+                    continue;
+                }
+
+                waited += interval;
+                if known || waited >= REBROWSE_AFTER {
+                    info!("[Device::discover_once] \"{}\" is looking for a new {}", self_name, service_type);
+                    if let Err(e) = discovery.stop_browse(service_type.as_str()) {
+                        warn!("[Device::discover_once] \"{}\" cannot stop browsing for {}: {}", self_name, service_type, e);
+                    }
+                    known = false;
+                    waited = Duration::ZERO;
+                }
+            }
+        });
     }
 
     /// Creates a new thread to continually discover `Device`s on the network in the specified group.
-    fn discover_continually(&self, group: &str, devices: &Arc<Mutex<HashMap<Id, ServiceInfo>>>, mdns: ServiceDaemon) -> JoinHandle<()> {
-        self.discover(group, devices, mdns, Self::save_device, false)
+    ///
+    /// `Device`s are added to `devices` when they are resolved, and removed again when they leave the network.
+    fn discover_continually(&self, group: &str, devices: &Arc<Mutex<HashMap<Id, ServiceInfo>>>, discovery: &Arc<dyn Discovery>, shutdown: &Shutdown) {
+        let update = |event, self_name: &String, devices: &Arc<Mutex<HashMap<Id, ServiceInfo>>>| match event {
+            Event::Resolved(info) => Self::save_device(info, self_name, devices),
+            Event::Removed(fullname) => Self::remove_device(fullname.as_str(), self_name, devices),
+        };
+
+        self.discover(group, devices, discovery, update, shutdown)
     }
 
-    /// Saves the `ServiceInfo` of a `Device` found via mDNS into the `map`.
+    /// Saves the discovered `ServiceInfo` of a `Device` into the `map`.
     ///
     /// **Design Decision**: this logic has been extracted from
     /// [`discover_continually`](Self::discover_continually) to make it easier to test.
@@ -186,16 +457,40 @@ pub trait Device: Sized {
         let devices_lock = map.lock();
         let mut devices_guard = devices_lock.unwrap();
 
-        info!(
-            "[Device::discover_continually] \"{}\" discovered \"{}\"",
-            self_name,
-            info.get_property("name").map(|p| p.val_str()).unwrap_or("<unknown>")
-        );
+        // a Device may be resolved again (e.g. when browsing starts over), which is only worth logging if it has moved
+        let known = id.as_ref().and_then(|i| devices_guard.get(i)).map(Self::extract_address);
+        if known != Some(Self::extract_address(&info)) {
+            info!(
+                "[Device::discover_continually] \"{}\" discovered \"{}\"",
+                self_name,
+                info.get_property("name").map(|p| p.val_str()).unwrap_or("<unknown>")
+            );
+        }
 
         id.map(|i| devices_guard.insert(i, info));
     }
 
-    /// Saves the `ServiceInfo` of a `Device` found via mDNS into the `container`.
+    /// Removes the `Device` with the given mDNS `fullname` from the `map`, once it has left the network.
+    ///
+    /// **Design Decision**: this logic has been extracted from
+    /// [`discover_continually`](Self::discover_continually) to make it easier to test.
+    fn remove_device(fullname: &str, self_name: &String, map: &Arc<Mutex<HashMap<Id, ServiceInfo>>>) {
+        let mut devices = map.lock().unwrap();
+
+        // mDNS names are case-insensitive
+        devices.retain(|_, info| {
+            let removed = info.get_fullname().eq_ignore_ascii_case(fullname);
+            if removed {
+                info!("[Device::discover_continually] \"{}\" lost \"{}\"", self_name, fullname);
+            }
+            !removed
+        });
+    }
+
+    /// Saves the discovered `ServiceInfo` of a `Device` into the `container`.
+    ///
+    /// If a different `Device` has already been found, `info` is ignored. If the same `Device` is
+    /// found again, it replaces the old `info`, which picks up any change to its address.
     ///
     /// **Design Decision**: this logic has been extracted from
     /// [`discover_once`](Self::discover_once) to make it easier to test.
@@ -203,14 +498,57 @@ pub trait Device: Sized {
         let devices_lock = container.lock();
         let mut device = devices_lock.unwrap();
 
-        info!(
-            "[Device::discover_once] \"{}\" discovered \"{}\"",
-            self_name,
-            info.get_property("name").map(|p| p.val_str()).unwrap_or("<unknown>")
-        );
+        let known = match device.as_ref() {
+            Some(known) if !known.get_fullname().eq_ignore_ascii_case(info.get_fullname()) => return,
+            Some(known) => Some(Self::extract_address(known)),
+            None => None,
+        };
+
+        if known != Some(Self::extract_address(&info)) {
+            info!(
+                "[Device::discover_once] \"{}\" discovered \"{}\"",
+                self_name,
+                info.get_property("name").map(|p| p.val_str()).unwrap_or("<unknown>")
+            );
+        }
 
         let _ = device.insert(info);
     }
+
+    /// Forgets the `Device` in the `container` if it has the given mDNS `fullname`, once it has left the network.
+    ///
+    /// **Design Decision**: this logic has been extracted from
+    /// [`discover_once`](Self::discover_once) to make it easier to test.
+    fn remove_unique_device(fullname: &str, self_name: &String, container: &Arc<Mutex<Option<ServiceInfo>>>) {
+        let mut device = container.lock().unwrap();
+
+        if device.as_ref().is_some_and(|info| info.get_fullname().eq_ignore_ascii_case(fullname)) {
+            info!("[Device::discover_once] \"{}\" lost \"{}\"", self_name, fullname);
+            *device = None;
+        }
+    }
+
+    /// Records whether the latest request to the `Device` found by [`discover_once`](Self::discover_once)
+    /// succeeded, where `failures` counts the consecutive requests which have failed.
+    ///
+    /// After [`REDISCOVER_AFTER`] consecutive failures, the `Device` is forgotten, and discovery starts over.
+    ///
+    /// **Design Decision**: this takes the (already-locked) `device` rather than its `Mutex`, because
+    /// callers hold that lock while they make the request.
+    fn record_request(device: &mut Option<ServiceInfo>, failures: &AtomicU32, succeeded: bool) {
+        if succeeded {
+            failures.store(0, Ordering::SeqCst);
+        } else if failures.fetch_add(1, Ordering::SeqCst) + 1 >= REDISCOVER_AFTER {
+            failures.store(0, Ordering::SeqCst);
+            if let Some(info) = device.take() {
+                warn!(
+                    "[Device::record_request] forgetting {} after {} failed requests",
+                    info.get_fullname(),
+                    REDISCOVER_AFTER
+                );
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -244,8 +582,12 @@ mod device_tests {
             Model::Unsupported
         }
 
-        fn get_handler(&self) -> Handler {
-            Box::new(|_| ())
+        fn get_handler(&self, _address: Address) -> Handler {
+            Box::new(|_, _| Ok(()))
+        }
+
+        fn get_endpoints(&self) -> Vec<String> {
+            vec![String::from("GET /hello"), String::from("POST /hello")]
         }
     }
 
@@ -255,12 +597,47 @@ mod device_tests {
         let mut buffer = Vec::new();
         let msg = "this is the message";
 
-        TestDevice::handler_failure(self_name, &mut buffer, msg);
+        TestDevice::handler_failure(self_name, &mut buffer, 409, "my_error", msg).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
-        let expected = Message::respond_bad_request().with_body(msg);
 
-        assert_eq!(actual, expected.to_string());
+        let expected = [
+            "HTTP/1.1 409 Conflict",
+            "Content-Length: 70",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"this is the message","code":"my_error","device":"self_name"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_respond_shutdown() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        let handle = handle::DeviceHandle::spawn(Name::new("myName"), move |shutdown| {
+            let device = TestDevice::new("myName", "myId");
+            let discovery = discovery::Backend::Memory(discovery::MemoryRegistry::new()).connect(&shutdown).unwrap();
+            device.respond(ip, 0, "_test", discovery.as_ref(), &shutdown).unwrap();
+        });
+
+        // the operating system chooses the port, which is reported through the DeviceHandle
+        let address = handle.address().unwrap();
+        let port = address.port();
+        assert_ne!(port, 0);
+
+        // hold a kept-alive connection open
+        let mut connection = TcpStream::connect(SocketAddr::from(address)).unwrap();
+        Message::request_get("/").write(&mut connection).unwrap();
+
+        handle.shutdown();
+
+        // the open connection has been closed (or reset), and the port can be bound again
+        let mut reader = std::io::BufReader::new(connection);
+        assert!(matches!(Message::read_next(&mut reader), Ok(None) | Err(Error::Io(_))));
+        assert!(TcpListener::bind((ip, port)).is_ok());
     }
 
     // ServiceInfo doesn't implement PartialEq, so we have to compare field-by-field...
@@ -294,16 +671,83 @@ mod device_tests {
         let port = 10101;
         let group = "myGroup";
 
-        let actual = device.get_service_info(ip, port, group);
+        let actual = device.get_service_info(ip, port, group).unwrap();
 
         let mut properties: HashMap<String, String> = HashMap::new();
         properties.insert("name".into(), name.into());
         properties.insert("id".into(), id.into());
         properties.insert("model".into(), "unsupported".into());
+        properties.insert("protocol".into(), "1".into());
+        properties.insert("endpoints".into(), "GET /hello,POST /hello".into());
 
         let expected = ServiceInfo::new("myGroup._tcp.local.", "myId.unsupported", "123.234.123.234", ip, port, properties).unwrap();
 
-        compare_service_info(&actual, &expected)
+        compare_service_info(&actual, &expected);
+        assert_eq!(actual.get_property("protocol"), expected.get_property("protocol"));
+        assert_eq!(actual.get_property("endpoints"), expected.get_property("endpoints"));
+    }
+
+    #[test]
+    fn test_respond_bind_failure() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let taken = TcpListener::bind((ip, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let handle = handle::DeviceHandle::spawn(Name::new("myName"), move |shutdown| {
+            let device = TestDevice::new("myName", "myId");
+            let discovery = discovery::Backend::Memory(discovery::MemoryRegistry::new()).connect(&shutdown).unwrap();
+
+            // this Device never finds its peer, but its discovery thread must still stop at shutdown
+            device.discover_once("_peer", &Arc::new(Mutex::new(None)), &discovery, &shutdown);
+            assert!(device.respond(ip, port, "_test", discovery.as_ref(), &shutdown).is_err());
+        });
+
+        assert!(matches!(handle.address(), Err(Error::Io(_))));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn test_discover_continually() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let registry = discovery::MemoryRegistry::new();
+        let devices: Arc<Mutex<HashMap<Id, ServiceInfo>>> = Arc::new(Mutex::new(HashMap::new()));
+
+        let backend = discovery::Backend::Memory(registry.clone());
+        let found = Arc::clone(&devices);
+        let observer = handle::DeviceHandle::spawn(Name::new("observer"), move |shutdown| {
+            let device = TestDevice::new("observer", "observerId");
+            let discovery = backend.connect(&shutdown).unwrap();
+            device.discover_continually("_test", &found, &discovery, &shutdown);
+        });
+
+        let backend = discovery::Backend::Memory(registry);
+        let observed = handle::DeviceHandle::spawn(Name::new("observed"), move |shutdown| {
+            let device = TestDevice::new("observed", "observedId");
+            let discovery = backend.connect(&shutdown).unwrap();
+            device.respond(ip, 0, "_test", discovery.as_ref(), &shutdown).unwrap();
+        });
+
+        // discovery happens on another thread, so we wait (briefly) for it to catch up
+        let found = |present: bool| {
+            let start = std::time::Instant::now();
+            while devices.lock().unwrap().contains_key(&Id::new("observedId")) != present && start.elapsed() < Duration::from_secs(5) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        // the port which was found is the port which was actually bound
+        let address = observed.address().unwrap();
+        found(true);
+
+        let info = devices.lock().unwrap().get(&Id::new("observedId")).cloned().unwrap();
+        assert_eq!(TestDevice::extract_address(&info), Ok(address));
+
+        // once the observed Device leaves the network, it is forgotten
+        observed.shutdown();
+        found(false);
+        assert!(!devices.lock().unwrap().contains_key(&Id::new("observedId")));
+
+        observer.shutdown();
     }
 
     #[test]
@@ -316,12 +760,22 @@ mod device_tests {
         let port = 10101;
         let group = "myGroup";
 
-        let info = device.get_service_info(ip, port, group);
+        let info = device.get_service_info(ip, port, group).unwrap();
 
         let actual = TestDevice::extract_address(&info);
         let expected = Address::new(ip, port);
 
-        assert_eq!(actual, expected);
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn test_extract_address_failure() {
+        let info = ServiceInfo::new("myGroup._tcp.local.", "myId.unsupported", "host", "", 10101, None).unwrap();
+
+        let actual = TestDevice::extract_address(&info);
+        let expected = Error::Discovery("no addresses advertised for myId.unsupported.myGroup._tcp.local.".into());
+
+        assert_eq!(actual, Err(expected));
     }
 
     fn create_service_info() -> ServiceInfo {
@@ -333,7 +787,7 @@ mod device_tests {
         let port = 10101;
         let group = "myGroup";
 
-        device.get_service_info(ip, port, group)
+        device.get_service_info(ip, port, group).unwrap()
     }
 
     #[test]
@@ -360,6 +814,55 @@ mod device_tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_extract_protocol_version() {
+        let info = create_service_info();
+        let actual = TestDevice::extract_protocol_version(&info);
+        assert_eq!(actual, Some(Ok(discovery::PROTOCOL_VERSION)));
+
+        let mut properties = HashMap::new();
+        properties.insert(String::from("protocol"), String::from("two"));
+        let info = ServiceInfo::new("myGroup._tcp.local.", "myId", "localhost", "127.0.0.1", 10101, properties).unwrap();
+        let actual = TestDevice::extract_protocol_version(&info);
+        assert_eq!(actual, Some(Err(Error::Parse(String::from("cannot parse 'two' as a protocol version")))));
+
+        let info = ServiceInfo::new("myGroup._tcp.local.", "myId", "localhost", "127.0.0.1", 10101, HashMap::new()).unwrap();
+        assert_eq!(TestDevice::extract_protocol_version(&info), None);
+    }
+
+    #[test]
+    fn test_extract_endpoints() {
+        let info = create_service_info();
+        let actual = TestDevice::extract_endpoints(&info);
+        let expected = vec![String::from("GET /hello"), String::from("POST /hello")];
+        assert_eq!(actual, expected);
+
+        // a Device which does not advertise any endpoints
+        let info = ServiceInfo::new("myGroup._tcp.local.", "myId", "localhost", "127.0.0.1", 10101, HashMap::new()).unwrap();
+        assert!(TestDevice::extract_endpoints(&info).is_empty());
+    }
+
+    #[test]
+    fn test_extract_kind_unit_and_commands() {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("kind"), String::from("float"));
+        properties.insert(String::from("unit"), String::from("°C"));
+        properties.insert(String::from("commands"), String::from("CoolBy,HeatBy"));
+
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let info = discovery::service_info("myGroup", &Id::new("myId"), &Name::new("myName"), Model::Thermo5000, ip, 10101, properties).unwrap();
+
+        assert_eq!(TestDevice::extract_kind(&info), Some(Ok(Kind::Float)));
+        assert_eq!(TestDevice::extract_unit(&info), Some(Ok(Unit::DegreesC)));
+        assert_eq!(TestDevice::extract_commands(&info), vec![String::from("CoolBy"), String::from("HeatBy")]);
+
+        // a Device which is not a Sensor or an Actuator advertises none of these
+        let info = create_service_info();
+        assert_eq!(TestDevice::extract_kind(&info), None);
+        assert_eq!(TestDevice::extract_unit(&info), None);
+        assert!(TestDevice::extract_commands(&info).is_empty());
+    }
+
     #[test]
     fn test_save_device() {
         let info = create_service_info();
@@ -376,6 +879,126 @@ mod device_tests {
         compare_service_info(actual, expected)
     }
 
+    #[test]
+    fn test_remove_device() {
+        let info = create_service_info();
+        let self_name = String::from("mySelfName");
+        let container = Arc::new(Mutex::new(HashMap::new()));
+
+        TestDevice::save_device(info.clone(), &self_name, &container);
+
+        // some other Device has left the network
+        TestDevice::remove_device("otherId.unsupported.myGroup._tcp.local.", &self_name, &container);
+        assert!(container.lock().unwrap().contains_key(&Id::new("myId")));
+
+        TestDevice::remove_device(info.get_fullname(), &self_name, &container);
+        assert!(container.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_unique_device_moved() {
+        let self_name = String::from("mySelfName");
+        let device = TestDevice::new("myName", "myId");
+        let container = Arc::new(Mutex::new(None));
+
+        let original = device.get_service_info(IpAddr::from([10, 0, 0, 1]), 10101, "myGroup").unwrap();
+        let moved = device.get_service_info(IpAddr::from([10, 0, 0, 2]), 20202, "myGroup").unwrap();
+        let other = TestDevice::new("otherName", "otherId")
+            .get_service_info(IpAddr::from([10, 0, 0, 3]), 30303, "myGroup")
+            .unwrap();
+
+        TestDevice::save_unique_device(original, &self_name, &container);
+
+        // some other Device in the same group does not replace the one which was already found...
+        TestDevice::save_unique_device(other, &self_name, &container);
+        let actual = TestDevice::extract_address(container.lock().unwrap().as_ref().unwrap());
+        assert_eq!(actual, Ok(Address::new(IpAddr::from([10, 0, 0, 1]), 10101)));
+
+        // ...but the same Device, at a new address, does
+        TestDevice::save_unique_device(moved.clone(), &self_name, &container);
+        let actual = TestDevice::extract_address(container.lock().unwrap().as_ref().unwrap());
+        assert_eq!(actual, Ok(Address::new(IpAddr::from([10, 0, 0, 2]), 20202)));
+
+        TestDevice::remove_unique_device("otherId.unsupported.myGroup._tcp.local.", &self_name, &container);
+        assert!(container.lock().unwrap().is_some());
+
+        TestDevice::remove_unique_device(moved.get_fullname(), &self_name, &container);
+        assert!(container.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_record_request() {
+        let mut device = Some(create_service_info());
+        let failures = AtomicU32::new(0);
+
+        // a single success resets the count of consecutive failures
+        (1..REDISCOVER_AFTER).for_each(|_| TestDevice::record_request(&mut device, &failures, false));
+        TestDevice::record_request(&mut device, &failures, true);
+        (1..REDISCOVER_AFTER).for_each(|_| TestDevice::record_request(&mut device, &failures, false));
+        assert!(device.is_some());
+
+        TestDevice::record_request(&mut device, &failures, false);
+        assert!(device.is_none());
+    }
+
+    #[test]
+    fn test_discover_once_rediscovers() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let registry = discovery::MemoryRegistry::new();
+        let environment: Arc<Mutex<Option<ServiceInfo>>> = Arc::new(Mutex::new(None));
+
+        let backend = discovery::Backend::Memory(registry.clone());
+        let found = Arc::clone(&environment);
+        let observer = handle::DeviceHandle::spawn(Name::new("observer"), move |shutdown| {
+            let device = TestDevice::new("observer", "observerId");
+            let discovery = backend.connect(&shutdown).unwrap();
+            device.discover_once("_test", &found, &discovery, &shutdown);
+        });
+
+        let start = |registry: &discovery::MemoryRegistry| {
+            let backend = discovery::Backend::Memory(registry.clone());
+            handle::DeviceHandle::spawn(Name::new("observed"), move |shutdown| {
+                let device = TestDevice::new("observed", "observedId");
+                let discovery = backend.connect(&shutdown).unwrap();
+                device.respond(ip, 0, "_test", discovery.as_ref(), &shutdown).unwrap();
+            })
+        };
+
+        // discovery happens on other threads, so we wait (briefly) for it to catch up
+        let address = || {
+            let start = std::time::Instant::now();
+            loop {
+                let address = environment.lock().unwrap().as_ref().map(TestDevice::extract_address);
+                if address.is_some() || start.elapsed() > Duration::from_secs(5) {
+                    return address;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let observed = start(&registry);
+        assert_eq!(address(), Some(observed.address()));
+
+        // a forgotten Device which is still on the network is found again
+        std::thread::sleep(Duration::from_millis(500));
+        *environment.lock().unwrap() = None;
+        assert_eq!(address(), Some(observed.address()));
+
+        // a Device which restarts at a new address is found there
+        observed.shutdown();
+        let restarted = start(&registry);
+        let restarted_address = restarted.address();
+
+        let start = std::time::Instant::now();
+        while address() != Some(restarted_address.clone()) && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(address(), Some(restarted_address));
+
+        restarted.shutdown();
+        observer.shutdown();
+    }
+
     #[test]
     fn test_save_unique_device() {
         let info = create_service_info();
@@ -1,8 +1,28 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
 use std::net::TcpStream;
 
+use crate::chunked::{read_chunked, ChunkedWriter};
+use crate::error::Error;
+use crate::json;
+use crate::name::Name;
+use crate::websocket;
+
+/// The largest body (in bytes) which will be read from a `Message`.
+///
+/// **Design Decision**: the buffer for a body is allocated before the body is read, so its size
+/// cannot be left to the `Content-Length` (or chunk sizes) sent by a peer. A failed allocation
+/// aborts the whole process, which no `catch_unwind` can recover from.
+pub const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+/// The largest head (in bytes) which will be read from a `Message`: its start line and all of its
+/// header lines, including any blank lines sent before the start line.
+///
+/// **Design Decision**: a line is buffered until its line ending is received, so without a limit
+/// a peer could send a single endless header line (or endlessly many headers) to exhaust memory.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
 /// `Device`s communicate by sending and receiving `Message`s.
 ///
 /// In this codebase, `Message`s are HTTP requests.
@@ -35,10 +55,13 @@ impl Display for Message {
         let headers = headers.collect::<Vec<String>>().join("\r\n");
 
         // headers are always followed by a blank line, i.e. \r\n\r\n
-        let headers = format!("{}\r\n", headers);
+        write!(f, "{}\r\n{}\r\n\r\n", self.start_line.trim(), headers)?;
 
-        let body = &self.body.as_ref().map(|b| format!("\r\n{}\r\n", b)).unwrap_or(String::from(""));
-        write!(f, "{}\r\n{}{}\r\n", self.start_line.trim(), headers, body)
+        // ...and the body is exactly Content-Length bytes, so nothing may follow it
+        match &self.body {
+            Some(body) => write!(f, "{}", body),
+            None => Ok(()),
+        }
     }
 }
 
@@ -67,6 +90,126 @@ impl Message {
         self.headers.get(key)
     }
 
+    /// Returns the status code of this `Message`, if it is a response (e.g. `404` in `HTTP/1.1 404 Not Found`).
+    pub fn status(&self) -> Option<u16> {
+        let mut pieces = self.start_line.split_whitespace();
+        match (pieces.next(), pieces.next()) {
+            (Some(version), Some(code)) if version.starts_with("HTTP/") => code.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the sender of this `Message` is willing to keep its connection open afterward.
+    ///
+    /// HTTP/1.1 connections are persistent unless either side sends `Connection: close`. HTTP/1.0
+    /// connections are closed after every `Message` unless the sender asks for `Connection: keep-alive`.
+    ///
+    /// See: https://www.rfc-editor.org/rfc/rfc9112#name-persistence
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(|c| c.to_ascii_lowercase()).unwrap_or_default();
+        let options: Vec<&str> = connection.split(',').map(|option| option.trim()).collect();
+
+        if options.contains(&"close") {
+            false
+        } else if self.start_line.split_whitespace().any(|piece| piece == "HTTP/1.0") {
+            options.contains(&"keep-alive")
+        } else {
+            true
+        }
+    }
+
+    /// Returns `true` if sending this `Message` (a request) more than once has the same effect as sending it once.
+    ///
+    /// See: https://www.rfc-editor.org/rfc/rfc9110#name-idempotent-methods
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self.method(), Some("GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"))
+    }
+
+    /// Returns `true` if this `Message` is a request to upgrade its connection to a WebSocket.
+    ///
+    /// See: https://www.rfc-editor.org/rfc/rfc6455#section-4.2.1
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let upgrade = self.header("Upgrade").map(|u| u.eq_ignore_ascii_case("websocket")).unwrap_or_default();
+        let connection = self.header("Connection").map(|c| c.to_ascii_lowercase()).unwrap_or_default();
+        let connection = connection.split(',').any(|option| option.trim() == "upgrade");
+
+        self.method() == Some("GET") && upgrade && connection && self.header("Sec-WebSocket-Key").is_some()
+    }
+
+    /// Returns the method of this `Message`, if it is a request (e.g. `GET` in `GET /data HTTP/1.1`).
+    pub fn method(&self) -> Option<&str> {
+        self.request_line().map(|(method, _)| method)
+    }
+
+    /// Returns the path of this `Message`, if it is a request (e.g. `/data` in `GET /data?limit=5 HTTP/1.1`).
+    pub fn path(&self) -> Option<&str> {
+        self.request_line()
+            .map(|(_, target)| target.split_once('?').map(|(path, _)| path).unwrap_or(target))
+    }
+
+    /// Returns the query parameters of this `Message` (e.g. `limit = "5"` in `GET /data?limit=5 HTTP/1.1`).
+    ///
+    /// Keys and values are percent-decoded. Parameters without a value (e.g. `?verbose`) map to an
+    /// empty string. If a key appears more than once, the last value wins.
+    ///
+    /// **Design Decision**: `+` is _not_ decoded as a space. That convention belongs to HTML forms
+    /// (`application/x-www-form-urlencoded`), not to URLs, and decoding it would mangle the `+00:00`
+    /// offsets of RFC 3339 timestamps passed as query parameters.
+    pub fn query(&self) -> HashMap<String, String> {
+        let query = self.request_line().and_then(|(_, target)| target.split_once('?')).map(|(_, query)| query);
+
+        query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (Self::percent_decode(key), Self::percent_decode(value))
+            })
+            .collect()
+    }
+
+    /// Decodes `%XX`-escaped bytes in a URL component. Malformed escapes are left as-is.
+    fn percent_decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut index = 0;
+
+        while index < bytes.len() {
+            let escaped = match (bytes[index], bytes.get(index + 1..index + 3)) {
+                (b'%', Some(&[high, low])) => match ((high as char).to_digit(16), (low as char).to_digit(16)) {
+                    (Some(high), Some(low)) => Some((high * 16 + low) as u8),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            match escaped {
+                Some(byte) => {
+                    decoded.push(byte);
+                    index += 3;
+                }
+                None => {
+                    decoded.push(bytes[index]);
+                    index += 1;
+                }
+            }
+        }
+
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+
+    /// Splits the `start_line` of a request into its method and its request target.
+    ///
+    /// Responses (whose `start_line`s begin with `HTTP/`) have neither.
+    fn request_line(&self) -> Option<(&str, &str)> {
+        let mut pieces = self.start_line.split_whitespace();
+        match (pieces.next(), pieces.next(), pieces.next()) {
+            (Some(method), Some(target), Some(version)) if !method.starts_with("HTTP/") && version.starts_with("HTTP/") => Some((method, target)),
+            _ => None,
+        }
+    }
+
     /// Creates an arbitrary HTTP/1.1 request.
     ///
     /// **Design Decision**: this method is purposefully not `pub`. Users should instead use the
@@ -88,19 +231,92 @@ impl Message {
 
     /// Creates an HTTP/1.1 response from its status `code`.
     ///
-    /// **Design Decision**: this method is purposefully not `pub`. Users should instead use the
-    /// `pub` `respond_x` methods to construct HTTP responses of the required types.
-    fn respond(code: u16) -> Message {
-        let text = match code {
+    /// Prefer the `respond_x` methods for common responses. This method can be used for any other
+    /// status code. Status codes registered in RFC 9110 are given their standard reason phrase;
+    /// any other code in the range `100..=599` is sent without one.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `code` is not a valid (three-digit, `1xx`-`5xx`) HTTP status code.
+    pub fn respond(code: u16) -> Message {
+        if !(100..=599).contains(&code) {
+            panic!("unexpected response code: {}", code)
+        }
+
+        let start_line = format!("HTTP/1.1 {} {}", code, Self::reason(code));
+        Message::new(start_line.trim_end().into(), HashMap::new(), None)
+    }
+
+    /// Creates a response with the given HTTP `status` and a standard JSON error body.
+    ///
+    /// The body has the form `{"error":"<msg>","code":"<code>","device":"<device>"}`, where the
+    /// `"error"` field holds the human-readable `msg`, and the `"code"` field holds the short,
+    /// machine-readable `code` (e.g. `unknown_sensor`) which allows clients to distinguish between
+    /// failures which share a status.
+    pub fn respond_error(status: u16, code: &str, msg: &str, device: &Name) -> Message {
+        let body = format!(
+            r#"{{"error":"{}","code":"{}","device":"{}"}}"#,
+            json::escape(msg),
+            json::escape(code),
+            json::escape(device.to_string().as_str())
+        );
+
+        Self::respond(status).with_body(body)
+    }
+
+    /// Returns the reason phrase for the given status `code`, or an empty string for unregistered codes.
+    ///
+    /// See: https://www.rfc-editor.org/rfc/rfc9110#name-status-codes
+    fn reason(code: u16) -> &'static str {
+        match code {
+            100 => "Continue",
+            101 => "Switching Protocols",
             200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
             400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
             404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
             501 => "Not Implemented",
-            _ => panic!("unexpected response code: {}", code),
-        };
-
-        let start_line = format!("HTTP/1.1 {} {}", code, text);
-        Message::new(start_line, HashMap::new(), None)
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
     }
 
     /// Creates a simple `200 OK` response to acknowledge the successful handling of some request.
@@ -108,6 +324,46 @@ impl Message {
         Self::respond(200)
     }
 
+    /// Creates a `201 Created` response to indicate that a new resource was created.
+    pub fn respond_created() -> Message {
+        Self::respond(201)
+    }
+
+    /// Creates a `202 Accepted` response to indicate that a request will be handled, but has not been handled yet.
+    pub fn respond_accepted() -> Message {
+        Self::respond(202)
+    }
+
+    /// Creates a `204 No Content` response to indicate that a request succeeded, but there is nothing to send back.
+    pub fn respond_no_content() -> Message {
+        Self::respond(204)
+    }
+
+    /// Creates a `409 Conflict` response to indicate that a request conflicts with the current state of a resource.
+    pub fn respond_conflict() -> Message {
+        Self::respond(409)
+    }
+
+    /// Creates a `413 Content Too Large` response to indicate that the body of a request is larger than we will accept.
+    pub fn respond_content_too_large() -> Message {
+        Self::respond(413)
+    }
+
+    /// Creates a `429 Too Many Requests` response to indicate that a client should slow down.
+    pub fn respond_too_many_requests() -> Message {
+        Self::respond(429)
+    }
+
+    /// Creates a `500 Internal Server Error` response to indicate that something went wrong which is not the client's fault.
+    pub fn respond_internal_server_error() -> Message {
+        Self::respond(500)
+    }
+
+    /// Creates a `502 Bad Gateway` response to indicate that a request could not be forwarded to another `Device`.
+    pub fn respond_bad_gateway() -> Message {
+        Self::respond(502)
+    }
+
     /// Creates a `501 Not Implemented` response to indicate that we've not yet implemented some endpoint.
     pub fn respond_not_implemented() -> Message {
         Self::respond(501)
@@ -123,6 +379,41 @@ impl Message {
         Self::respond(404)
     }
 
+    /// Creates a `405 Method Not Allowed` response to indicate that the requested resource exists, but does not support the requested method.
+    pub fn respond_method_not_allowed() -> Message {
+        Self::respond(405)
+    }
+
+    /// Creates the `101 Switching Protocols` response which completes the WebSocket handshake begun by `request`.
+    ///
+    /// Once this response has been written, the connection no longer carries HTTP `Message`s, but
+    /// WebSocket [`Frame`](crate::websocket::Frame)s.
+    pub fn respond_websocket_upgrade(request: &Message) -> Result<Message, Error> {
+        if !request.is_websocket_upgrade() {
+            return Err(Error::Protocol("not a WebSocket upgrade request".into()));
+        }
+
+        // only version 13 (RFC 6455) of the protocol is supported
+        //     see: https://www.rfc-editor.org/rfc/rfc6455#section-4.4
+        if request.header("Sec-WebSocket-Version").map(|v| v.as_str()) != Some("13") {
+            return Err(Error::Protocol("unsupported Sec-WebSocket-Version, expected 13".into()));
+        }
+
+        let key = request.header("Sec-WebSocket-Key").map(|k| k.as_str()).unwrap_or_default();
+
+        let mut headers = HashMap::new();
+        headers.insert("Upgrade", String::from("websocket"));
+        headers.insert("Connection", String::from("Upgrade"));
+        headers.insert("Sec-WebSocket-Accept", websocket::accept_key(key));
+
+        Ok(Self::respond(101).with_headers(headers))
+    }
+
+    /// Creates a `503 Service Unavailable` response to indicate that the `Device` is too busy to handle the request right now.
+    pub fn respond_service_unavailable() -> Message {
+        Self::respond(503)
+    }
+
     /// Appends the given `headers` to this `Message`.
     pub fn with_headers(mut self, headers: HashMap<impl Into<String>, impl Into<String>>) -> Message {
         headers.into_iter().for_each(|(key, value)| {
@@ -143,12 +434,43 @@ impl Message {
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    pub fn write(&self, tcp_stream: &mut impl Write) {
-        tcp_stream.write_all(self.to_string().as_bytes()).unwrap();
+    pub fn write(&self, tcp_stream: &mut impl Write) -> Result<(), Error> {
+        tcp_stream.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes the start line and headers of this `Message` into the provided `tcp_stream`, with
+    /// `Transfer-Encoding: chunked`, then returns a `ChunkedWriter` which streams the body.
+    ///
+    /// If this `Message` already has a body, it is sent as the first chunk. The caller must call
+    /// [`ChunkedWriter::finish`] once the whole body has been written.
+    ///
+    /// **Design Decision**: this allows large bodies (e.g. the `Controller`'s data exports) to be
+    /// serialized directly into the `tcp_stream`, rather than being built up as a `String` first.
+    pub fn write_chunked<W: Write>(&self, tcp_stream: W) -> Result<ChunkedWriter<W>, Error> {
+        let mut headers = self.headers.clone();
+        headers.remove("Content-Length");
+        headers.insert("Transfer-Encoding".into(), "chunked".into());
+
+        let head = Message::new(self.start_line.clone(), headers, None);
+
+        let mut tcp_stream = tcp_stream;
+        head.write(&mut tcp_stream)?;
+
+        let mut writer = ChunkedWriter::new(tcp_stream);
+        if let Some(body) = &self.body {
+            writer.write_all(body.as_bytes())?;
+        }
+
+        Ok(writer)
     }
 
     /// Attempts to read a `Message` from the provided `tcp_stream`.
-    pub fn read(mut tcp_stream: &mut TcpStream) -> Result<Message, String> {
+    ///
+    /// This method should only be used for connections which carry a single `Message` in each
+    /// direction. Use [`read_next`](Self::read_next) with a long-lived `BufRead` to read several
+    /// `Message`s from the same persistent connection.
+    pub fn read(mut tcp_stream: &mut TcpStream) -> Result<Message, Error> {
         Message::read_from_buffer(BufReader::new(&mut tcp_stream))
     }
 
@@ -157,15 +479,50 @@ impl Message {
     /// **Design Decision**: similar to [`write`](Self::write), `tcp_stream` is of type `impl BufRead`
     /// rather than `TcpStream` because this is easier to test. [`read`](Self::read) is provided
     /// as well, for user convenience.
-    fn read_from_buffer(mut tcp_stream: impl BufRead) -> Result<Message, String> {
+    fn read_from_buffer(mut tcp_stream: impl BufRead) -> Result<Message, Error> {
+        Message::read_next(&mut tcp_stream)?.ok_or(Error::Protocol("connection closed before a start line was received".into()))
+    }
+
+    /// Attempts to read the next `Message` from a persistent connection.
+    ///
+    /// Returns `None` if the peer closed the connection cleanly, before sending another `Message`.
+    ///
+    /// **Design Decision**: the `BufRead` must outlive any single `Message`, because it may have
+    /// buffered the beginning of the _next_ `Message` while reading this one. Any blank lines before
+    /// the start line (which some clients send after a body) are skipped, as
+    /// [RFC 9112](https://www.rfc-editor.org/rfc/rfc9112#section-2.2) recommends.
+    ///
+    /// Heads larger than [`MAX_HEAD_SIZE`] in total are rejected with an `Error::TooLarge`.
+    pub fn read_next(tcp_stream: &mut impl BufRead) -> Result<Option<Message>, Error> {
+        let mut head = tcp_stream.take(MAX_HEAD_SIZE as u64);
+
         let mut message = String::new();
-        tcp_stream.read_line(&mut message).map_err(|_| String::from("cannot read message"))?;
+        while message.trim().is_empty() {
+            message.clear();
+            if head.read_line(&mut message)? == 0 {
+                return match head.limit() {
+                    0 => Err(Self::head_too_large()),
+                    _ => Ok(None),
+                };
+            }
+        }
+
+        if !message.ends_with('\n') && head.limit() == 0 {
+            return Err(Self::head_too_large());
+        }
 
         let mut headers: HashMap<String, String> = HashMap::new();
 
         loop {
             let mut line = String::new();
-            match tcp_stream.read_line(&mut line) {
+            let read = head.read_line(&mut line);
+
+            // a line cut off by the limit (rather than by its line ending) was too long to be read in full
+            if !line.ends_with('\n') && head.limit() == 0 {
+                return Err(Self::head_too_large());
+            }
+
+            match read {
                 Ok(size) if size > 2 => {
                     // a blank line (CRLF only) separates HTTP headers and body
                     match line.split_once(": ") {
@@ -178,19 +535,52 @@ impl Message {
             };
         }
 
+        let tcp_stream = head.into_inner();
         let mut body: Option<String> = None;
 
-        // we write the Content-Length header, so we can assume it's correctly formatted
-        if let Some(length) = headers.get("Content-Length") {
-            let length = length.parse::<usize>().unwrap();
+        let chunked = headers
+            .get("Transfer-Encoding")
+            .map(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or_default();
+
+        // a chunked body is decoded in full, so the Message looks like it was sent with a Content-Length
+        //     see: https://www.rfc-editor.org/rfc/rfc9112#name-decoding-chunked
+        if chunked {
+            let buffer = read_chunked(tcp_stream)?;
+            let string = String::from_utf8(buffer).map_err(|_| Error::Parse("body is not valid UTF-8".into()))?;
+
+            headers.remove("Transfer-Encoding");
+            headers.insert("Content-Length".into(), string.len().to_string());
+            body = Some(string);
+        }
+        // the Content-Length header may have been written by anyone, so we cannot assume it's correctly formatted
+        else if let Some(length) = headers.get("Content-Length") {
+            let length = length
+                .parse::<usize>()
+                .map_err(|_| Error::Protocol(format!("invalid Content-Length: '{}'", length)))?;
+
+            if length > MAX_BODY_SIZE {
+                return Err(Error::TooLarge(format!("Content-Length {} exceeds the maximum of {}", length, MAX_BODY_SIZE)));
+            }
+
             let mut buffer = vec![0; length];
-            tcp_stream.read_exact(&mut buffer).unwrap();
-            body = Some(std::str::from_utf8(buffer.as_slice()).unwrap().into());
+            tcp_stream.read_exact(&mut buffer).map_err(|error| match error.kind() {
+                ErrorKind::UnexpectedEof => Error::Protocol(format!("body is shorter than Content-Length: {}", length)),
+                _ => Error::from(error),
+            })?;
+
+            let string = String::from_utf8(buffer).map_err(|_| Error::Parse("body is not valid UTF-8".into()))?;
+            body = Some(string);
         }
 
         let message = Message::new(String::from(message.trim()), headers, body);
 
-        Ok(message)
+        Ok(Some(message))
+    }
+
+    /// The `Error` returned when the head of a `Message` is larger than [`MAX_HEAD_SIZE`].
+    fn head_too_large() -> Error {
+        Error::TooLarge(format!("head exceeds the maximum of {}", MAX_HEAD_SIZE))
     }
 }
 
@@ -267,7 +657,7 @@ mod device_message_tests {
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -291,7 +681,7 @@ mod device_message_tests {
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -334,18 +724,129 @@ mod device_message_tests {
         assert_eq!(actual, format!("{}\r\n\r\n", expected))
     }
 
+    #[test]
+    fn test_respond_method_not_allowed() {
+        let message = Message::respond_method_not_allowed();
+        let actual = message.to_string();
+
+        let expected = ["HTTP/1.1 405 Method Not Allowed", "Content-Type: text/json; charset=utf-8"].join("\r\n");
+
+        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+    }
+
+    #[test]
+    fn test_method_and_path() {
+        let message = Message::request_post("/datum/my_id");
+        assert_eq!(message.method(), Some("POST"));
+        assert_eq!(message.path(), Some("/datum/my_id"));
+    }
+
+    #[test]
+    fn test_path_and_query() {
+        let message = Message::request_get("/data?limit=5&since=2024-01-05T12%3A39%3A36%2B00%3A00&verbose&name=a%20b");
+        assert_eq!(message.path(), Some("/data"));
+
+        let mut expected = HashMap::new();
+        expected.insert(String::from("limit"), String::from("5"));
+        expected.insert(String::from("since"), String::from("2024-01-05T12:39:36+00:00"));
+        expected.insert(String::from("verbose"), String::from(""));
+        expected.insert(String::from("name"), String::from("a b"));
+
+        assert_eq!(message.query(), expected);
+    }
+
+    #[test]
+    fn test_query_empty() {
+        assert!(Message::request_get("/data").query().is_empty());
+        assert!(Message::request_get("/data?").query().is_empty());
+        assert!(Message::respond_ok().query().is_empty());
+    }
+
+    #[test]
+    fn test_query_malformed_escape() {
+        let message = Message::request_get("/data?since=2024-01-05T12:39:36+00:00&bad=100%&worse=%zz");
+        let query = message.query();
+
+        assert_eq!(query.get("since"), Some(&String::from("2024-01-05T12:39:36+00:00")));
+        assert_eq!(query.get("bad"), Some(&String::from("100%")));
+        assert_eq!(query.get("worse"), Some(&String::from("%zz")));
+    }
+
+    #[test]
+    fn test_method_and_path_of_response() {
+        let message = Message::respond_ok();
+        assert_eq!(message.method(), None);
+        assert_eq!(message.path(), None);
+    }
+
+    #[test]
+    fn test_respond_service_unavailable() {
+        let message = Message::respond_service_unavailable();
+        let actual = message.to_string();
+
+        let expected = ["HTTP/1.1 503 Service Unavailable", "Content-Type: text/json; charset=utf-8"].join("\r\n");
+
+        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+    }
+
     #[test]
     #[should_panic]
     fn test_respond_unknown_code() {
         Message::respond(999);
     }
 
+    #[test]
+    fn test_respond_any_code() {
+        assert_eq!(Message::respond_created().start_line, "HTTP/1.1 201 Created");
+        assert_eq!(Message::respond_accepted().start_line, "HTTP/1.1 202 Accepted");
+        assert_eq!(Message::respond_no_content().start_line, "HTTP/1.1 204 No Content");
+        assert_eq!(Message::respond_conflict().start_line, "HTTP/1.1 409 Conflict");
+        assert_eq!(Message::respond_content_too_large().start_line, "HTTP/1.1 413 Content Too Large");
+        assert_eq!(Message::respond_too_many_requests().start_line, "HTTP/1.1 429 Too Many Requests");
+        assert_eq!(Message::respond_internal_server_error().start_line, "HTTP/1.1 500 Internal Server Error");
+        assert_eq!(Message::respond_bad_gateway().start_line, "HTTP/1.1 502 Bad Gateway");
+
+        // unregistered codes have no reason phrase
+        assert_eq!(Message::respond(299).to_string(), Message::respond_ok().to_string().replace("200 OK", "299"));
+    }
+
+    #[test]
+    fn test_is_idempotent() {
+        assert!(Message::request_get("/datum").is_idempotent());
+        assert!(!Message::request_post("/command").is_idempotent());
+        assert!(!Message::respond_ok().is_idempotent());
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(Message::respond_ok().status(), Some(200));
+        assert_eq!(Message::respond(299).status(), Some(299));
+        assert_eq!(Message::request_get("/").status(), None);
+    }
+
+    #[test]
+    fn test_respond_error() {
+        let message = Message::respond_error(404, "unknown_sensor", r#"unknown Sensor ID "abc""#, &Name::new("myName"));
+        let actual = message.to_string();
+
+        let expected = [
+            "HTTP/1.1 404 Not Found",
+            "Content-Length: 79",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"unknown Sensor ID \"abc\"","code":"unknown_sensor","device":"myName"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
     #[test]
     fn test_write() {
         let message = Message::respond_ok();
 
         let mut tcp_stream = Vec::new();
-        message.write(&mut tcp_stream);
+        message.write(&mut tcp_stream).unwrap();
         let actual = String::from_utf8(tcp_stream).unwrap();
 
         let expected = ["HTTP/1.1 200 OK", "Content-Type: text/json; charset=utf-8"].join("\r\n");
@@ -399,4 +900,209 @@ mod device_message_tests {
 
         assert_eq!(actual, expected)
     }
+
+    #[test]
+    fn test_read_empty() {
+        let actual = Message::read_from_buffer("".as_bytes());
+        let expected = Error::Protocol("connection closed before a start line was received".into());
+
+        assert_eq!(actual, Err(expected))
+    }
+
+    #[test]
+    fn test_read_with_truncated_body() {
+        let serialized = ["GET / HTTP/1.1", "Content-Length: 42", "", "Hello, World!"].join("\r\n");
+
+        let actual = Message::read_from_buffer(serialized.as_bytes());
+        let expected = Error::Protocol("body is shorter than Content-Length: 42".into());
+
+        assert_eq!(actual, Err(expected))
+    }
+
+    #[test]
+    fn test_read_with_invalid_content_length() {
+        let serialized = ["GET / HTTP/1.1", "Content-Length: forty-two", "", "Hello, World!"].join("\r\n");
+
+        let actual = Message::read_from_buffer(serialized.as_bytes());
+        let expected = Error::Protocol("invalid Content-Length: 'forty-two'".into());
+
+        assert_eq!(actual, Err(expected))
+    }
+
+    #[test]
+    fn test_read_with_invalid_utf8_body() {
+        let mut serialized = ["GET / HTTP/1.1", "Content-Length: 2", "", ""].join("\r\n").into_bytes();
+        serialized.extend_from_slice(&[0xC3, 0x28]);
+
+        let actual = Message::read_from_buffer(serialized.as_slice());
+        let expected = Error::Parse("body is not valid UTF-8".into());
+
+        assert_eq!(actual, Err(expected))
+    }
+
+    #[test]
+    fn test_read_next() {
+        let first = Message::request_post("/command").with_body("Hello, World!");
+        let second = Message::request_get("/datum");
+
+        // Messages are serialized back-to-back on a persistent connection
+        let serialized = format!("{}{}", first, second);
+        let mut reader = serialized.as_bytes();
+
+        assert_eq!(Message::read_next(&mut reader), Ok(Some(first)));
+        assert_eq!(Message::read_next(&mut reader), Ok(Some(second)));
+        assert_eq!(Message::read_next(&mut reader), Ok(None));
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let mut close = HashMap::new();
+        close.insert("Connection", "Close");
+
+        let mut keep_alive = HashMap::new();
+        keep_alive.insert("Connection", "Upgrade, keep-alive");
+
+        assert!(Message::request_get("/").keep_alive());
+        assert!(Message::respond_ok().keep_alive());
+        assert!(!Message::request_get("/").with_headers(close.clone()).keep_alive());
+        assert!(!Message::respond_ok().with_headers(close).keep_alive());
+
+        let http_1_0 = Message::read_from_buffer("GET / HTTP/1.0\r\n\r\n".as_bytes()).unwrap();
+        assert!(!http_1_0.keep_alive());
+        assert!(http_1_0.with_headers(keep_alive).keep_alive());
+    }
+
+    fn websocket_upgrade_request() -> Message {
+        let mut headers = HashMap::new();
+        headers.insert("Upgrade", "websocket");
+        headers.insert("Connection", "keep-alive, Upgrade");
+        headers.insert("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+        headers.insert("Sec-WebSocket-Version", "13");
+
+        Message::request_get("/ws").with_headers(headers)
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        assert!(websocket_upgrade_request().is_websocket_upgrade());
+        assert!(!Message::request_get("/ws").is_websocket_upgrade());
+
+        let mut headers = HashMap::new();
+        headers.insert("Connection", "keep-alive");
+        assert!(!websocket_upgrade_request().with_headers(headers).is_websocket_upgrade());
+    }
+
+    #[test]
+    fn test_respond_websocket_upgrade() {
+        let actual = Message::respond_websocket_upgrade(&websocket_upgrade_request()).unwrap();
+
+        let expected = [
+            "HTTP/1.1 101 Switching Protocols",
+            "Connection: Upgrade",
+            "Content-Type: text/json; charset=utf-8",
+            "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            "Upgrade: websocket",
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual.to_string(), format!("{}\r\n\r\n", expected));
+    }
+
+    #[test]
+    fn test_respond_websocket_upgrade_failure() {
+        let actual = Message::respond_websocket_upgrade(&Message::request_get("/ws"));
+        assert_eq!(actual, Err(Error::Protocol("not a WebSocket upgrade request".into())));
+
+        let mut headers = HashMap::new();
+        headers.insert("Sec-WebSocket-Version", "8");
+
+        let actual = Message::respond_websocket_upgrade(&websocket_upgrade_request().with_headers(headers));
+        assert_eq!(actual, Err(Error::Protocol("unsupported Sec-WebSocket-Version, expected 13".into())));
+    }
+
+    #[test]
+    fn test_write_chunked() {
+        let message = Message::respond_ok().with_body("Hello, ");
+
+        let mut writer = message.write_chunked(Vec::new()).unwrap();
+        writer.write_all(b"World!").unwrap();
+        let actual = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        let expected = [
+            "HTTP/1.1 200 OK",
+            "Content-Type: text/json; charset=utf-8",
+            "Transfer-Encoding: chunked",
+            "",
+            "d",
+            "Hello, World!",
+            "0",
+            "",
+            "",
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let mut writer = Message::respond_ok().write_chunked(Vec::new()).unwrap();
+        writer.write_all(b"Hello, World!").unwrap();
+        let serialized = writer.finish().unwrap();
+
+        // a chunked body is indistinguishable from one sent with a Content-Length
+        let actual = Message::read_from_buffer(serialized.as_slice()).unwrap();
+        let expected = Message::respond_ok().with_body("Hello, World!");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_read_too_large() {
+        let serialized = "POST /command HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\nabc";
+
+        let actual = Message::read_from_buffer(serialized.as_bytes());
+        let expected = format!("Content-Length 99999999999999 exceeds the maximum of {}", MAX_BODY_SIZE);
+
+        assert_eq!(actual, Err(Error::TooLarge(expected)))
+    }
+
+    #[test]
+    fn test_read_head_too_large() {
+        let expected = Err(Error::TooLarge(format!("head exceeds the maximum of {}", MAX_HEAD_SIZE)));
+
+        // a single endless line...
+        let serialized = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        assert_eq!(Message::read_from_buffer(serialized.as_bytes()), expected);
+
+        // ...endlessly many headers...
+        let serialized = format!("GET / HTTP/1.1\r\n{}\r\n", "foo: bar\r\n".repeat(MAX_HEAD_SIZE / 10));
+        assert_eq!(Message::read_from_buffer(serialized.as_bytes()), expected);
+
+        // ...and endlessly many blank lines are all rejected
+        let serialized = "\r\n".repeat(MAX_HEAD_SIZE);
+        assert_eq!(Message::read_from_buffer(serialized.as_bytes()), expected);
+
+        // but a head just within the limit is read in full
+        let line = "foo: bar\r\n";
+        let start = "GET / HTTP/1.1\r\n";
+        let count = (MAX_HEAD_SIZE - start.len() - 2) / line.len();
+        let serialized = format!("{}{}\r\n", start, line.repeat(count));
+        assert!(Message::read_from_buffer(serialized.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_read_next_chunked() {
+        let mut writer = Message::respond_ok().write_chunked(Vec::new()).unwrap();
+        writer.write_all(b"first").unwrap();
+        let mut serialized = writer.finish().unwrap();
+
+        Message::respond_ok().with_body("second").write(&mut serialized).unwrap();
+
+        let mut reader = serialized.as_slice();
+
+        assert_eq!(Message::read_next(&mut reader).unwrap().unwrap().body, Some(String::from("first")));
+        assert_eq!(Message::read_next(&mut reader).unwrap().unwrap().body, Some(String::from("second")));
+        assert_eq!(Message::read_next(&mut reader), Ok(None));
+    }
 }
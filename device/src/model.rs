@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter};
 
+use crate::error::Error;
+
 /// `Model` gives a unique identifier for each _kind_ of `Device`.
 ///
 /// There can be multiple `Device`s of the same `Model` on the network. Each _individual_ `Device` has a unique [`Id`](crate::Id) and a user-defined [`Name`](crate::Name).
@@ -38,14 +40,14 @@ impl Display for Model {
 
 impl Model {
     /// Attempts to parse a `Model` from the provided string or string slice.
-    pub fn parse<S: Into<String>>(s: S) -> Result<Model, String> {
+    pub fn parse<S: Into<String>>(s: S) -> Result<Model, Error> {
         let string = s.into();
         match string.as_str() {
             "controller" => Ok(Model::Controller),
             "environment" => Ok(Model::Environment),
             "unsupported" => Ok(Model::Unsupported),
             "thermo5000" => Ok(Model::Thermo5000),
-            _ => Err(format!("unknown Model '{}'", string)),
+            _ => Err(Error::Parse(format!("unknown Model '{}'", string))),
         }
     }
 }
@@ -90,7 +92,7 @@ mod device_model_tests {
     fn test_parse_failure() {
         let serialized = "blorp";
         let actual = Model::parse(serialized);
-        let msg = String::from("unknown Model 'blorp'");
-        assert_eq!(Err(msg), actual)
+        let error = Error::Parse(String::from("unknown Model 'blorp'"));
+        assert_eq!(Err(error), actual)
     }
 }
@@ -0,0 +1,150 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use log::error;
+
+/// `PoolConfig` describes the size of the `WorkerPool` which a `Device` uses to handle incoming requests.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PoolConfig {
+    /// the number of threads which handle requests concurrently
+    pub workers: usize,
+    /// the number of requests which can wait for a free worker before new requests are rejected
+    pub capacity: usize,
+}
+
+impl PoolConfig {
+    pub fn new(workers: usize, capacity: usize) -> PoolConfig {
+        PoolConfig { workers, capacity }
+    }
+}
+
+/// By default, a `Device` handles up to 4 requests at a time, with up to 16 more waiting.
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig::new(4, 16)
+    }
+}
+
+/// A `WorkerPool` processes items of type `T` on a fixed number of threads.
+///
+/// **Design Decision**: the queue between the caller and the workers is bounded. When it is full,
+/// [`try_submit`](Self::try_submit) hands the item straight back to the caller, rather than
+/// blocking, so that the caller can decide how to reject it (e.g. with a `503 Service Unavailable`).
+pub struct WorkerPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Spawns `config.workers` threads, each of which calls `work` on every item it receives.
+    pub fn new(config: PoolConfig, work: impl Fn(T) + Send + Sync + 'static) -> WorkerPool<T> {
+        let (sender, receiver) = mpsc::sync_channel(config.capacity);
+        let receiver: Arc<Mutex<Receiver<T>>> = Arc::new(Mutex::new(receiver));
+        let work = Arc::new(work);
+
+        let workers = (0..config.workers.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let work = Arc::clone(&work);
+
+                std::thread::spawn(move || loop {
+                    // the lock must be released before the item is processed, so other workers can receive
+                    let item = receiver.lock().unwrap().recv();
+                    match item {
+                        // a panic while processing one item must not take this worker down with it
+                        Ok(item) => {
+                            if std::panic::catch_unwind(AssertUnwindSafe(|| work(item))).is_err() {
+                                error!("[WorkerPool] worker panicked while processing an item");
+                            }
+                        }
+                        Err(_) => break, // the pool has been dropped
+                    }
+                })
+            })
+            .collect();
+
+        WorkerPool { sender: Some(sender), workers }
+    }
+
+    /// Attempts to queue the `item` for processing, returning it if the queue is full.
+    pub fn try_submit(&self, item: T) -> Result<(), T> {
+        match self.sender.as_ref().map(|sender| sender.try_send(item)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(TrySendError::Full(item))) | Some(Err(TrySendError::Disconnected(item))) => Err(item),
+            None => unreachable!("the sender is only taken when the pool is dropped"),
+        }
+    }
+}
+
+/// Dropping a `WorkerPool` lets every worker finish its queued items, then joins all worker threads.
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.workers.drain(..).for_each(|worker| {
+            let _ = worker.join();
+        });
+    }
+}
+
+#[cfg(test)]
+mod device_pool_tests {
+    use std::sync::mpsc::Sender;
+
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let actual = PoolConfig::default();
+        let expected = PoolConfig::new(4, 16);
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_try_submit() {
+        let (sender, receiver) = mpsc::channel();
+
+        let pool = WorkerPool::new(PoolConfig::new(2, 4), move |item: i32| sender.send(item * 2).unwrap());
+
+        for item in 1..=4 {
+            assert_eq!(pool.try_submit(item), Ok(()));
+        }
+
+        drop(pool); // joins all workers
+
+        let mut actual: Vec<i32> = receiver.iter().collect();
+        actual.sort();
+
+        assert_eq!(actual, vec![2, 4, 6, 8])
+    }
+
+    #[test]
+    fn test_try_submit_saturated() {
+        // each item tells the test when it has started, then waits until the test tells it to finish
+        type Item = (Sender<()>, Receiver<()>);
+
+        let pool = WorkerPool::new(PoolConfig::new(1, 1), |(started, finish): Item| {
+            started.send(()).unwrap();
+            finish.recv().unwrap();
+        });
+
+        let (started_sender, started) = mpsc::channel();
+
+        // the first item occupies the only worker...
+        let (finish_first, receiver) = mpsc::channel();
+        assert!(pool.try_submit((started_sender.clone(), receiver)).is_ok());
+        started.recv().unwrap();
+
+        // ...the second item fills the queue...
+        let (finish_second, receiver) = mpsc::channel();
+        assert!(pool.try_submit((started_sender.clone(), receiver)).is_ok());
+
+        // ...so the third item is rejected
+        let (_, receiver) = mpsc::channel();
+        assert!(pool.try_submit((started_sender, receiver)).is_err());
+
+        finish_first.send(()).unwrap();
+        finish_second.send(()).unwrap();
+    }
+}
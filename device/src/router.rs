@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpStream;
+use std::str::FromStr;
+
+use log::{error, warn};
+
+use crate::error::Error;
+use crate::json;
+use crate::message::Message;
+use crate::name::Name;
+use crate::Handler;
+
+/// A `RouteHandler` describes how a single route of a `Router` handles a request.
+///
+/// It receives the `TcpStream` to respond on, the `Message` which was read from that stream, and any
+/// `Params` extracted from the request path.
+pub type RouteHandler = Box<dyn Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync>;
+
+/// `Params` are the named segments extracted from a request path by a `Router`.
+///
+/// For example, the pattern `/datum/:id` matched against the path `/datum/abc` produces `id = "abc"`.
+#[derive(PartialEq, Debug, Default)]
+pub struct Params(HashMap<String, String>);
+
+impl Params {
+    /// Returns the raw value of the named parameter, if it was extracted.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| value.as_str())
+    }
+
+    /// Attempts to parse the named parameter as a `T`.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, Error> {
+        let value = self.get(name).ok_or(Error::Parse(format!("missing path parameter '{}'", name)))?;
+        value
+            .parse()
+            .map_err(|_| Error::Parse(format!("cannot parse path parameter '{}' from '{}'", name, value)))
+    }
+}
+
+/// A single segment of a route pattern, e.g. `datum` or `:id` in `/datum/:id`.
+#[derive(PartialEq, Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// A `Route` ties an HTTP method and a path pattern to a `RouteHandler`.
+struct Route {
+    method: String,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: RouteHandler,
+}
+
+impl Route {
+    /// Attempts to match the provided `path` against this `Route`'s pattern, extracting any `Params`.
+    fn matches(&self, path: &[&str]) -> Option<Params> {
+        if path.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+
+        for (segment, piece) in self.segments.iter().zip(path) {
+            match segment {
+                Segment::Literal(literal) if literal == piece => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), piece.to_string());
+                }
+            }
+        }
+
+        Some(Params(params))
+    }
+}
+
+/// The result of looking up a request in a `Router`.
+#[derive(PartialEq, Debug)]
+enum Resolution {
+    /// the route at the given index matched, producing these `Params`
+    Found(usize, Params),
+    /// the path matched at least one route, but none of them accept this method
+    MethodNotAllowed(Vec<String>),
+    /// the request is for the generated `GET /routes` listing
+    Routes,
+    /// no route matched the path
+    NotFound,
+}
+
+/// A `Router` dispatches incoming requests to the `RouteHandler` registered for their method and path.
+///
+/// Requests for paths which are not registered receive a `404 Not Found` response. Requests for
+/// registered paths with an unregistered method receive a `405 Method Not Allowed` response. Every
+/// `Router` also responds to `GET /routes` with a JSON listing of all of its registered routes.
+///
+/// **Design Decision**: routes are matched in the order in which they were registered, so more
+/// specific patterns (e.g. `/datum/latest`) should be registered before less specific ones (e.g.
+/// `/datum/:id`).
+pub struct Router {
+    name: Name,
+    routes: Vec<Route>,
+}
+
+impl Router {
+    /// Creates an empty `Router` for the `Device` with the provided `name` (used for logging).
+    pub fn new(name: Name) -> Router {
+        Router { name, routes: Vec::new() }
+    }
+
+    /// Registers a `handler` for requests with the given `method` whose path matches the `pattern`.
+    ///
+    /// Path segments of the `pattern` which begin with `:` (e.g. `:id`) match any single path
+    /// segment and are made available to the `handler` as `Params`.
+    pub fn route(
+        mut self,
+        method: &str,
+        pattern: &str,
+        handler: impl Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Router {
+        let segments = Self::split(pattern)
+            .into_iter()
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method: method.to_uppercase(),
+            pattern: pattern.to_string(),
+            segments,
+            handler: Box::new(handler),
+        });
+
+        self
+    }
+
+    /// Registers a `handler` for `GET` requests whose path matches the `pattern`.
+    pub fn get(self, pattern: &str, handler: impl Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync + 'static) -> Router {
+        self.route("GET", pattern, handler)
+    }
+
+    /// Registers a `handler` for `POST` requests whose path matches the `pattern`.
+    pub fn post(self, pattern: &str, handler: impl Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync + 'static) -> Router {
+        self.route("POST", pattern, handler)
+    }
+
+    /// Registers a `handler` for `PUT` requests whose path matches the `pattern`.
+    pub fn put(self, pattern: &str, handler: impl Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync + 'static) -> Router {
+        self.route("PUT", pattern, handler)
+    }
+
+    /// Registers a `handler` for `DELETE` requests whose path matches the `pattern`.
+    pub fn delete(self, pattern: &str, handler: impl Fn(&mut TcpStream, Message, &Params) -> Result<(), Error> + Send + Sync + 'static) -> Router {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// Splits a path or pattern into its non-empty segments.
+    fn split(path: &str) -> Vec<&str> {
+        path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+
+    /// Finds the route which should handle a request with the given `method` and `path`.
+    ///
+    /// Methods are matched case-insensitively, in the same way that they are registered.
+    fn resolve(&self, method: &str, path: &str) -> Resolution {
+        let method = method.to_uppercase();
+        let pieces = Self::split(path);
+        let mut allowed = Vec::new();
+
+        for (index, route) in self.routes.iter().enumerate() {
+            if let Some(params) = route.matches(&pieces) {
+                if route.method == method {
+                    return Resolution::Found(index, params);
+                } else if !allowed.contains(&route.method) {
+                    allowed.push(route.method.clone());
+                }
+            }
+        }
+
+        if !allowed.is_empty() {
+            Resolution::MethodNotAllowed(allowed)
+        } else if method == "GET" && pieces == ["routes"] {
+            Resolution::Routes
+        } else {
+            Resolution::NotFound
+        }
+    }
+
+    /// Dispatches an already-read `message` to the appropriate route.
+    pub fn dispatch(&self, tcp_stream: &mut TcpStream, message: Message) -> Result<(), Error> {
+        let (method, path) = match (message.method(), message.path()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => {
+                let msg = format!("cannot parse request: {}", message.start_line);
+                error!("[{}] {}", self.name, msg);
+                return Message::respond_error(400, "invalid_request", msg.as_str(), &self.name).write(tcp_stream);
+            }
+        };
+
+        match self.resolve(method.as_str(), path.as_str()) {
+            Resolution::Found(index, params) => (self.routes[index].handler)(tcp_stream, message, &params),
+            Resolution::Routes => self.handle_get_routes(tcp_stream),
+            Resolution::MethodNotAllowed(allowed) => self.handle_method_not_allowed(tcp_stream, &method, &path, allowed),
+            Resolution::NotFound => self.handle_not_found(tcp_stream, &method, &path),
+        }
+    }
+
+    /// Describes how `GET /routes` requests are handled by every `Router`.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    fn handle_get_routes(&self, tcp_stream: &mut impl Write) -> Result<(), Error> {
+        // list all of the routes this Device responds to
+        //     ex: curl 10.12.50.26:5454/routes
+
+        let routes: Vec<String> = self
+            .routes()
+            .iter()
+            .map(|(method, pattern)| format!(r#"{{"method":"{}","path":"{}"}}"#, json::escape(method), json::escape(pattern)))
+            .collect();
+        let body = format!("[{}]", routes.join(","));
+
+        Message::respond_ok().with_body(body).write(tcp_stream)
+    }
+
+    /// Responds with `405 Method Not Allowed`, listing the `allowed` methods in the `Allow` header.
+    fn handle_method_not_allowed(&self, tcp_stream: &mut impl Write, method: &str, path: &str, allowed: Vec<String>) -> Result<(), Error> {
+        let msg = format!("method {} is not allowed for {}", method, path);
+        warn!("[{}] {}", self.name, msg);
+
+        let mut headers = HashMap::new();
+        headers.insert("Allow", allowed.join(", "));
+
+        Message::respond_error(405, "method_not_allowed", msg.as_str(), &self.name)
+            .with_headers(headers)
+            .write(tcp_stream)
+    }
+
+    /// Responds with `404 Not Found`.
+    fn handle_not_found(&self, tcp_stream: &mut impl Write, method: &str, path: &str) -> Result<(), Error> {
+        let msg = format!("no route for {} {}", method, path);
+        warn!("[{}] {}", self.name, msg);
+
+        Message::respond_error(404, "not_found", msg.as_str(), &self.name).write(tcp_stream)
+    }
+
+    /// Returns the method and pattern of every registered route, including the generated `GET /routes`.
+    pub fn routes(&self) -> Vec<(String, String)> {
+        let mut routes: Vec<(String, String)> = self.routes.iter().map(|route| (route.method.clone(), route.pattern.clone())).collect();
+        routes.push(("GET".into(), "/routes".into()));
+        routes
+    }
+
+    /// Converts this `Router` into a `Handler`, which can be returned from `Device::get_handler`.
+    pub fn into_handler(self) -> Handler {
+        Box::new(move |stream, message| self.dispatch(stream, message))
+    }
+}
+
+#[cfg(test)]
+mod device_router_tests {
+    use super::*;
+
+    fn create_router() -> Router {
+        Router::new(Name::new("myName"))
+            .get("/data", |_, _, _| Ok(()))
+            .get("/datum/:id", |_, _, _| Ok(()))
+            .post("/datum/:id", |_, _, _| Ok(()))
+            .post("/command", |_, _, _| Ok(()))
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn test_resolve_literal() {
+        let router = create_router();
+        assert_eq!(router.resolve("GET", "/data"), Resolution::Found(0, Params::default()));
+        assert_eq!(router.resolve("GET", "/data/"), Resolution::Found(0, Params::default()));
+    }
+
+    #[test]
+    fn test_resolve_param() {
+        let router = create_router();
+        assert_eq!(router.resolve("GET", "/datum/abc"), Resolution::Found(1, params(&[("id", "abc")])));
+        assert_eq!(router.resolve("POST", "/datum/abc"), Resolution::Found(2, params(&[("id", "abc")])));
+    }
+
+    #[test]
+    fn test_resolve_lowercase_method() {
+        let router = create_router();
+        assert_eq!(router.resolve("get", "/datum/abc"), Resolution::Found(1, params(&[("id", "abc")])));
+        assert_eq!(router.resolve("Post", "/datum/abc"), Resolution::Found(2, params(&[("id", "abc")])));
+        assert_eq!(router.resolve("get", "/routes"), Resolution::Routes);
+    }
+
+    #[test]
+    fn test_resolve_method_not_allowed() {
+        let router = create_router();
+        assert_eq!(router.resolve("GET", "/command"), Resolution::MethodNotAllowed(vec!["POST".into()]));
+        assert_eq!(
+            router.resolve("DELETE", "/datum/abc"),
+            Resolution::MethodNotAllowed(vec!["GET".into(), "POST".into()])
+        );
+    }
+
+    #[test]
+    fn test_resolve_put_and_delete() {
+        let router = create_router().put("/datum/:id", |_, _, _| Ok(())).delete("/datum/:id", |_, _, _| Ok(()));
+        assert_eq!(router.resolve("PUT", "/datum/abc"), Resolution::Found(4, params(&[("id", "abc")])));
+        assert_eq!(router.resolve("DELETE", "/datum/abc"), Resolution::Found(5, params(&[("id", "abc")])));
+        assert_eq!(
+            router.resolve("PATCH", "/datum/abc"),
+            Resolution::MethodNotAllowed(vec!["GET".into(), "POST".into(), "PUT".into(), "DELETE".into()])
+        );
+    }
+
+    #[test]
+    fn test_resolve_not_found() {
+        let router = create_router();
+        assert_eq!(router.resolve("GET", "/"), Resolution::NotFound);
+        assert_eq!(router.resolve("GET", "/datum"), Resolution::NotFound);
+        assert_eq!(router.resolve("GET", "/datum/abc/def"), Resolution::NotFound);
+    }
+
+    #[test]
+    fn test_resolve_routes() {
+        let router = create_router();
+        assert_eq!(router.resolve("GET", "/routes"), Resolution::Routes);
+        assert_eq!(router.resolve("POST", "/routes"), Resolution::NotFound);
+    }
+
+    #[test]
+    fn test_params_parse() {
+        let params = params(&[("id", "abc"), ("limit", "42")]);
+
+        assert_eq!(params.get("id"), Some("abc"));
+        assert_eq!(params.parse::<u32>("limit"), Ok(42));
+        assert_eq!(
+            params.parse::<u32>("id"),
+            Err(Error::Parse("cannot parse path parameter 'id' from 'abc'".into()))
+        );
+        assert_eq!(params.parse::<u32>("nope"), Err(Error::Parse("missing path parameter 'nope'".into())));
+    }
+
+    #[test]
+    fn test_handle_get_routes() {
+        let router = create_router();
+        let mut buffer = Vec::new();
+
+        router.handle_get_routes(&mut buffer).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = [
+            r#"{"method":"GET","path":"/data"}"#,
+            r#"{"method":"GET","path":"/datum/:id"}"#,
+            r#"{"method":"POST","path":"/datum/:id"}"#,
+            r#"{"method":"POST","path":"/command"}"#,
+            r#"{"method":"GET","path":"/routes"}"#,
+        ]
+        .join(",");
+        let json = format!("[{}]", json);
+
+        let expected = Message::respond_ok().with_body(json);
+
+        assert_eq!(actual, expected.to_string())
+    }
+
+    #[test]
+    fn test_handle_get_routes_escapes() {
+        let router = Router::new(Name::new("myName")).get(r#"/say/"hi"\there"#, |_, _, _| Ok(()));
+        let mut buffer = Vec::new();
+
+        router.handle_get_routes(&mut buffer).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = [r#"{"method":"GET","path":"/say/\"hi\"\\there"}"#, r#"{"method":"GET","path":"/routes"}"#].join(",");
+        let expected = Message::respond_ok().with_body(format!("[{}]", json));
+
+        assert_eq!(actual, expected.to_string())
+    }
+
+    #[test]
+    fn test_handle_method_not_allowed() {
+        let router = create_router();
+        let mut buffer = Vec::new();
+
+        router
+            .handle_method_not_allowed(&mut buffer, "DELETE", "/datum/abc", vec!["GET".into(), "POST".into()])
+            .unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let expected = [
+            "HTTP/1.1 405 Method Not Allowed",
+            "Allow: GET, POST",
+            "Content-Length: 101",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"method DELETE is not allowed for /datum/abc","code":"method_not_allowed","device":"myName"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_handle_not_found() {
+        let router = create_router();
+        let mut buffer = Vec::new();
+
+        router.handle_not_found(&mut buffer, "GET", "/nope").unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let expected = [
+            "HTTP/1.1 404 Not Found",
+            "Content-Length: 71",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"no route for GET /nope","code":"not_found","device":"myName"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::error::Error;
+use crate::message::Message;
+
+/// An `EventStream` writes [Server-Sent Events](https://html.spec.whatwg.org/multipage/server-sent-events.html)
+/// to a client which has made a long-lived `GET` request (e.g. via the browser's `EventSource` API).
+///
+/// **Design Decision**: the response has neither a `Content-Length` nor `Transfer-Encoding: chunked`,
+/// so the body ends only when the connection is closed. An `EventStream` should be the last thing
+/// written to a connection.
+pub struct EventStream<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> EventStream<W> {
+    /// Writes the start line and headers of a `text/event-stream` response, then returns an
+    /// `EventStream` which can be used to send events to the client.
+    pub fn open(mut inner: W) -> Result<EventStream<W>, Error> {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type", "text/event-stream; charset=utf-8");
+        headers.insert("Cache-Control", "no-cache");
+        headers.insert("Connection", "close");
+
+        Message::respond_ok().with_headers(headers).write(&mut inner)?;
+        inner.flush()?;
+
+        Ok(EventStream { inner })
+    }
+
+    /// Sends a single event of the given type (`event`) to the client.
+    ///
+    /// Multi-line `data` is split across several `data:` fields, which the client joins back together.
+    pub fn send(&mut self, event: &str, data: &str) -> Result<(), Error> {
+        let mut serialized = format!("event: {}\n", event);
+        data.lines().for_each(|line| serialized.push_str(format!("data: {}\n", line).as_str()));
+        serialized.push('\n');
+
+        self.inner.write_all(serialized.as_bytes())?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// Sends a comment, which is ignored by the client. This keeps idle connections open through
+    /// proxies, and lets the server notice clients which have gone away.
+    pub fn heartbeat(&mut self) -> Result<(), Error> {
+        self.inner.write_all(b": heartbeat\n\n")?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod device_sse_tests {
+    use super::*;
+
+    #[test]
+    fn test_event_stream() {
+        let mut buffer = Vec::new();
+
+        let mut stream = EventStream::open(&mut buffer).unwrap();
+        stream.send("datum", r#"{"value":"1.0"}"#).unwrap();
+        stream.heartbeat().unwrap();
+        stream.send("note", "first line\nsecond line").unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let expected = [
+            "HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Type: text/event-stream; charset=utf-8\r\n\r\n",
+            "event: datum\ndata: {\"value\":\"1.0\"}\n\n",
+            ": heartbeat\n\n",
+            "event: note\ndata: first line\ndata: second line\n\n",
+        ]
+        .concat();
+
+        assert_eq!(actual, expected)
+    }
+}
@@ -0,0 +1,403 @@
+use std::io::{ErrorKind, Read, Write};
+
+use base64::Engine;
+
+use crate::error::Error;
+
+/// The GUID which every WebSocket server appends to the client's `Sec-WebSocket-Key`.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message (after reassembling fragments) which a [`Reader`] will accept by default.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Computes the `Sec-WebSocket-Accept` header which a server sends in response to a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = sha1_smol::Sha1::from(format!("{}{}", key.trim(), GUID)).digest().bytes();
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// A `Frame` is a single, complete WebSocket message.
+///
+/// Fragmented messages are reassembled by the [`Reader`] before they are returned, so clients of
+/// this module never see continuation frames.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc6455#section-5
+#[derive(PartialEq, Debug, Clone)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// a request to close the connection, with an optional status code
+    Close(Option<u16>),
+}
+
+impl Frame {
+    fn opcode(&self) -> u8 {
+        match self {
+            Frame::Text(_) => 0x1,
+            Frame::Binary(_) => 0x2,
+            Frame::Close(_) => 0x8,
+            Frame::Ping(_) => 0x9,
+            Frame::Pong(_) => 0xA,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Frame::Text(text) => text.as_bytes().to_vec(),
+            Frame::Binary(bytes) | Frame::Ping(bytes) | Frame::Pong(bytes) => bytes.clone(),
+            Frame::Close(code) => code.map(|c| c.to_be_bytes().to_vec()).unwrap_or_default(),
+        }
+    }
+
+    /// Serializes this `Frame` as a single (unfragmented) WebSocket frame, masked with `mask` if provided.
+    fn encode(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut payload = self.payload();
+        let mut encoded = vec![0x80 | self.opcode()];
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0x00 };
+
+        match payload.len() {
+            length if length < 126 => encoded.push(mask_bit | length as u8),
+            length if length <= u16::MAX as usize => {
+                encoded.push(mask_bit | 126);
+                encoded.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                encoded.push(mask_bit | 127);
+                encoded.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        if let Some(mask) = mask {
+            encoded.extend_from_slice(&mask);
+            apply_mask(&mut payload, mask);
+        }
+
+        encoded.extend_from_slice(&payload);
+        encoded
+    }
+
+    /// Writes this `Frame` into the provided `tcp_stream`.
+    ///
+    /// **Design Decision**: frames sent by a server must not be masked, and every `Device` which
+    /// speaks WebSocket is a server, so this method never masks its output.
+    ///
+    /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
+    /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
+    pub fn write(&self, tcp_stream: &mut impl Write) -> Result<(), Error> {
+        tcp_stream.write_all(self.encode(None).as_slice())?;
+        tcp_stream.flush()?;
+        Ok(())
+    }
+}
+
+/// XORs the `payload` with the 4-byte `mask`, which both masks and unmasks it.
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    payload.iter_mut().enumerate().for_each(|(index, byte)| *byte ^= mask[index % 4]);
+}
+
+/// A `Reader` reads `Frame`s sent by a WebSocket client.
+///
+/// **Design Decision**: a `Reader` holds the fragments of a partially-received message between
+/// calls to [`read_next`](Self::read_next), because control frames (`Ping`, `Pong`, `Close`) may arrive in the
+/// middle of a fragmented message, and are returned as soon as they arrive.
+pub struct Reader<R: Read> {
+    inner: R,
+    max_message_size: usize,
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Creates a `Reader` which accepts messages of up to [`DEFAULT_MAX_MESSAGE_SIZE`] bytes.
+    pub fn new(inner: R) -> Reader<R> {
+        Self::with_max_message_size(inner, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Creates a `Reader` which accepts messages of up to `max_message_size` bytes.
+    pub fn with_max_message_size(inner: R, max_message_size: usize) -> Reader<R> {
+        Reader {
+            inner,
+            max_message_size,
+            partial: None,
+        }
+    }
+
+    /// Reads the next complete `Frame` from the client.
+    ///
+    /// Returns `None` if the client closed the connection cleanly, between frames.
+    pub fn read_next(&mut self) -> Result<Option<Frame>, Error> {
+        loop {
+            let (fin, opcode, payload) = match self.read_frame()? {
+                None => return Ok(None),
+                Some(frame) => frame,
+            };
+
+            match opcode {
+                // continuation of a fragmented message
+                0x0 => {
+                    let (opcode, mut buffer) = self.partial.take().ok_or(Error::Protocol("unexpected continuation frame".into()))?;
+                    buffer.extend_from_slice(&payload);
+                    if fin {
+                        return Self::data_frame(opcode, buffer).map(Some);
+                    }
+                    self.partial = Some((opcode, buffer));
+                }
+                0x1 | 0x2 if self.partial.is_some() => return Err(Error::Protocol("expected continuation frame".into())),
+                0x1 | 0x2 if fin => return Self::data_frame(opcode, payload).map(Some),
+                0x1 | 0x2 => self.partial = Some((opcode, payload)),
+                0x8..=0xA if !fin => return Err(Error::Protocol("control frames must not be fragmented".into())),
+                0x8 => {
+                    let code = match payload.as_slice() {
+                        [high, low, ..] => Some(u16::from_be_bytes([*high, *low])),
+                        _ => None,
+                    };
+                    return Ok(Some(Frame::Close(code)));
+                }
+                0x9 => return Ok(Some(Frame::Ping(payload))),
+                0xA => return Ok(Some(Frame::Pong(payload))),
+                other => return Err(Error::Protocol(format!("unknown opcode: {:#x}", other))),
+            }
+        }
+    }
+
+    fn data_frame(opcode: u8, payload: Vec<u8>) -> Result<Frame, Error> {
+        match opcode {
+            0x1 => String::from_utf8(payload)
+                .map(Frame::Text)
+                .map_err(|_| Error::Parse("text frame is not valid UTF-8".into())),
+            _ => Ok(Frame::Binary(payload)),
+        }
+    }
+
+    /// Reads a single (possibly fragmented) frame, returning its FIN bit, opcode, and unmasked payload.
+    fn read_frame(&mut self) -> Result<Option<(bool, u8, Vec<u8>)>, Error> {
+        let mut header = [0u8; 2];
+
+        // distinguish a connection which was closed between frames from one which was closed mid-frame
+        match self.inner.read(&mut header[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => self.inner.read_exact(&mut header[1..]).map_err(Self::truncated)?,
+            Err(e) => return Err(Error::from(e)),
+        }
+
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+
+        if header[0] & 0x70 != 0 {
+            return Err(Error::Protocol("reserved bits set, but no extensions were negotiated".into()));
+        }
+
+        // every frame sent by a client must be masked
+        //     see: https://www.rfc-editor.org/rfc/rfc6455#section-5.1
+        if !masked {
+            return Err(Error::Protocol("client frames must be masked".into()));
+        }
+
+        let length = match header[1] & 0x7F {
+            126 => {
+                let mut bytes = [0u8; 2];
+                self.inner.read_exact(&mut bytes).map_err(Self::truncated)?;
+                u16::from_be_bytes(bytes) as u64
+            }
+            127 => {
+                let mut bytes = [0u8; 8];
+                self.inner.read_exact(&mut bytes).map_err(Self::truncated)?;
+
+                // the most significant bit of a 64-bit length must be 0
+                //     see: https://www.rfc-editor.org/rfc/rfc6455#section-5.2
+                match u64::from_be_bytes(bytes) {
+                    length if length >> 63 != 0 => return Err(Error::Protocol("payload length has its most significant bit set".into())),
+                    length => length,
+                }
+            }
+            length => length as u64,
+        };
+
+        // control frames can be interleaved with the fragments of a message, but are never larger than 125 bytes
+        //     see: https://www.rfc-editor.org/rfc/rfc6455#section-5.5
+        let control = opcode & 0x8 != 0;
+        if control && length > 125 {
+            return Err(Error::Protocol("control frames must not be larger than 125 bytes".into()));
+        }
+
+        // only a continuation frame adds to the fragments which are already buffered
+        let buffered = match &self.partial {
+            Some((_, buffer)) if !control => buffer.len() as u64,
+            _ => 0,
+        };
+        if length.saturating_add(buffered) > self.max_message_size as u64 {
+            return Err(Error::Protocol(format!("message is larger than {} bytes", self.max_message_size)));
+        }
+
+        let mut mask = [0u8; 4];
+        self.inner.read_exact(&mut mask).map_err(Self::truncated)?;
+
+        let mut payload = vec![0u8; length as usize];
+        self.inner.read_exact(&mut payload).map_err(Self::truncated)?;
+        apply_mask(&mut payload, mask);
+
+        Ok(Some((fin, opcode, payload)))
+    }
+
+    fn truncated(error: std::io::Error) -> Error {
+        match error.kind() {
+            ErrorKind::UnexpectedEof => Error::Protocol("connection closed in the middle of a frame".into()),
+            _ => Error::from(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod device_websocket_tests {
+    use super::*;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    #[test]
+    fn test_accept_key() {
+        // the example from https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_write() {
+        let mut buffer = Vec::new();
+        Frame::Text(String::from("Hello")).write(&mut buffer).unwrap();
+
+        // the example from https://www.rfc-editor.org/rfc/rfc6455#section-5.7
+        assert_eq!(buffer, vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+    }
+
+    #[test]
+    fn test_write_extended_lengths() {
+        let mut buffer = Vec::new();
+        Frame::Binary(vec![0; 256]).write(&mut buffer).unwrap();
+        assert_eq!(buffer[..4], [0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(buffer.len(), 4 + 256);
+
+        let mut buffer = Vec::new();
+        Frame::Binary(vec![0; 65536]).write(&mut buffer).unwrap();
+        assert_eq!(buffer[..10], [0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(buffer.len(), 10 + 65536);
+    }
+
+    #[test]
+    fn test_read() {
+        // the example from https://www.rfc-editor.org/rfc/rfc6455#section-5.7
+        let bytes = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let mut reader = Reader::new(bytes.as_slice());
+
+        assert_eq!(reader.read_next(), Ok(Some(Frame::Text(String::from("Hello")))));
+        assert_eq!(reader.read_next(), Ok(None));
+    }
+
+    #[test]
+    fn test_read_round_trip() {
+        let frames = [
+            Frame::Text(String::from("°C")),
+            Frame::Binary(vec![7; 300]),
+            Frame::Ping(vec![1, 2]),
+            Frame::Pong(vec![]),
+            Frame::Close(Some(1000)),
+            Frame::Close(None),
+        ];
+
+        let bytes: Vec<u8> = frames.iter().flat_map(|frame| frame.encode(Some(MASK))).collect();
+        let mut reader = Reader::new(bytes.as_slice());
+
+        for frame in frames {
+            assert_eq!(reader.read_next(), Ok(Some(frame)));
+        }
+    }
+
+    #[test]
+    fn test_read_fragmented() {
+        // "Hel" + (Ping) + "lo", split across two data frames
+        let mut bytes = vec![0x01, 0x83];
+        bytes.extend_from_slice(&MASK);
+        bytes.extend(b"Hel".iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+
+        bytes.extend(Frame::Ping(vec![]).encode(Some(MASK)));
+
+        bytes.extend_from_slice(&[0x80, 0x82]);
+        bytes.extend_from_slice(&MASK);
+        bytes.extend(b"lo".iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+
+        let mut reader = Reader::new(bytes.as_slice());
+
+        assert_eq!(reader.read_next(), Ok(Some(Frame::Ping(vec![]))));
+        assert_eq!(reader.read_next(), Ok(Some(Frame::Text(String::from("Hello")))));
+    }
+
+    #[test]
+    fn test_read_unmasked() {
+        let mut bytes = Vec::new();
+        Frame::Text(String::from("Hello")).write(&mut bytes).unwrap();
+
+        let mut reader = Reader::new(bytes.as_slice());
+        assert_eq!(reader.read_next(), Err(Error::Protocol("client frames must be masked".into())));
+    }
+
+    #[test]
+    fn test_read_too_large() {
+        let bytes = Frame::Binary(vec![0; 11]).encode(Some(MASK));
+
+        let mut reader = Reader::with_max_message_size(bytes.as_slice(), 10);
+        assert_eq!(reader.read_next(), Err(Error::Protocol("message is larger than 10 bytes".into())));
+    }
+
+    #[test]
+    fn test_read_too_large_after_fragment() {
+        let mut bytes = vec![0x01, 0x83];
+        bytes.extend_from_slice(&MASK);
+        bytes.extend(b"Hel".iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+
+        // a continuation frame which claims the largest valid 64-bit length must not overflow
+        bytes.extend_from_slice(&[0x80, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        bytes.extend_from_slice(&MASK);
+
+        let mut reader = Reader::new(bytes.as_slice());
+        assert_eq!(
+            reader.read_next(),
+            Err(Error::Protocol(format!("message is larger than {} bytes", DEFAULT_MAX_MESSAGE_SIZE)))
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_lengths() {
+        let bytes = [0x82, 0xFF, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        let mut reader = Reader::new(bytes.as_slice());
+        assert_eq!(
+            reader.read_next(),
+            Err(Error::Protocol("payload length has its most significant bit set".into()))
+        );
+
+        let bytes = Frame::Ping(vec![0; 126]).encode(Some(MASK));
+        let mut reader = Reader::new(bytes.as_slice());
+        assert_eq!(
+            reader.read_next(),
+            Err(Error::Protocol("control frames must not be larger than 125 bytes".into()))
+        );
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let bytes = Frame::Text(String::from("Hello")).encode(Some(MASK));
+
+        let mut reader = Reader::new(&bytes[..8]);
+        assert_eq!(reader.read_next(), Err(Error::Protocol("connection closed in the middle of a frame".into())));
+    }
+
+    #[test]
+    fn test_read_unexpected_continuation() {
+        let bytes = [0x80, 0x80, 0, 0, 0, 0];
+
+        let mut reader = Reader::new(bytes.as_slice());
+        assert_eq!(reader.read_next(), Err(Error::Protocol("unexpected continuation frame".into())));
+    }
+}
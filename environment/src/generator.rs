@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
 use rand::random;
 
+use actuator_temperature::command::Command;
 use datum::unit::Unit;
 use datum::Datum;
 
@@ -65,6 +66,16 @@ impl DatumGenerator {
 
         Datum::new(value, self.unit, now)
     }
+
+    /// Changes the `coefficients` of this `DatumGenerator` in response to a Thermo5000 `Command`.
+    ///
+    /// Every degree of `HeatBy` or `CoolBy` moves the generated values by one hundredth of a degree.
+    pub fn apply(&mut self, command: &Command) {
+        match command {
+            Command::CoolBy(delta) => self.coefficients.constant -= delta * 0.01,
+            Command::HeatBy(delta) => self.coefficients.constant += delta * 0.01,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -124,4 +135,16 @@ mod generator_tests {
         // a value generated earlier is greater than a value generated later
         assert!(earlier.get_as_float() > later.get_as_float());
     }
+
+    #[test]
+    fn test_apply() {
+        let coefficients = Coefficients::new(20.0, 0.0, 0.0, 0.0, 0.0);
+        let mut generator = DatumGenerator::new(coefficients, 0.0, Unit::DegreesC);
+
+        generator.apply(&Command::HeatBy(100.0));
+        assert_eq!(generator.generate().get_as_float(), Some(21.0));
+
+        generator.apply(&Command::CoolBy(50.0));
+        assert_eq!(generator.generate().get_as_float(), Some(20.5));
+    }
 }
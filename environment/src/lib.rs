@@ -2,24 +2,27 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
 
-use log::debug;
-use mdns_sd::ServiceDaemon;
+use log::{debug, error};
 
 use actuator_temperature::command::Command;
 use datum::kind::Kind;
 use datum::unit::Unit;
 use datum::Datum;
+use device::address::Address;
+use device::discovery::Backend;
+use device::error::Error;
+use device::handle::DeviceHandle;
 use device::id::Id;
 use device::message::Message;
 use device::model::Model;
 use device::name::Name;
+use device::router::Router;
 use device::{Device, Handler};
 
 use crate::generator::{Coefficients, DatumGenerator};
 
-mod generator;
+pub mod generator;
 
 /// `Environment` is a test-only example environment which produces `Datum`s detected by `Sensor`s.
 ///
@@ -43,26 +46,28 @@ impl Device for Environment {
         Model::Environment
     }
 
-    fn get_handler(&self) -> Handler {
+    fn get_handler(&self, _address: Address) -> Handler {
         // Anything which depends on self must be cloned outside of the |stream| lambda.
         // We cannot refer to `self` inside of this lambda.
-        let self_name = self.name.clone();
-        let self_generators = Arc::clone(&self.generators);
-
-        Box::new(move |stream| {
-            if let Ok(message) = Message::read(stream) {
-                if message.start_line.starts_with("GET /datum/") {
-                    Self::handle_get_datum(stream, message, &self_name, &self_generators)
-                } else if message.start_line == "POST /command HTTP/1.1" {
-                    Self::handle_post_command(stream, message, &self_name, &self_generators)
-                } else {
-                    let msg = format!("cannot parse request: {}", message.start_line);
-                    Self::handler_failure(self_name.clone(), stream, msg.as_str())
-                }
-            } else {
-                Self::handler_failure(self_name.clone(), stream, "unable to read Message from stream")
-            }
-        })
+        let datum_name = self.name.clone();
+        let datum_generators = Arc::clone(&self.generators);
+
+        let command_name = self.name.clone();
+        let command_generators = Arc::clone(&self.generators);
+
+        Router::new(self.name.clone())
+            .get("/datum/:id", move |stream, message, params| {
+                let id = params.parse::<Id>("id")?;
+                Self::handle_get_datum(stream, message, id, &datum_name, &datum_generators)
+            })
+            .post("/command", move |stream, message, _| {
+                Self::handle_post_command(stream, message, &command_name, &command_generators)
+            })
+            .into_handler()
+    }
+
+    fn get_endpoints(&self) -> Vec<String> {
+        vec![String::from("GET /datum/:id"), String::from("POST /command")]
     }
 }
 
@@ -75,11 +80,17 @@ impl Environment {
         }
     }
 
-    /// Describes how `GET /datum/` requests are handled by the `Environment`.
+    /// Describes how `GET /datum/:id` requests are handled by the `Environment`.
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_datum(tcp_stream: &mut impl Write, message: Message, self_name: &Name, generators: &Arc<Mutex<HashMap<Id, DatumGenerator>>>) {
+    fn handle_get_datum(
+        tcp_stream: &mut impl Write,
+        message: Message,
+        id: Id,
+        self_name: &Name,
+        generators: &Arc<Mutex<HashMap<Id, DatumGenerator>>>,
+    ) -> Result<(), Error> {
         // Ask the Environment for the latest Datum for a Sensor by its ID.
         //
         // There are two possibilities here:
@@ -88,12 +99,9 @@ impl Environment {
         //
         // In case (1), all we need is the ID. In case (2), we also need to know the kind of data to generate.
 
-        let id = message.start_line.trim_start_matches("GET /datum/").trim_end_matches(" HTTP/1.1");
-        let id = Id::new(id);
-
         let mut generators = generators.lock().unwrap();
 
-        fn success(stream: &mut impl Write, datum: Datum) {
+        fn success(stream: &mut impl Write, datum: Datum) -> Result<(), Error> {
             let datum = datum.to_string();
             debug!("[Environment] generated Datum to send back to sensor: {}", datum);
             let response = Message::respond_ok().with_body(datum);
@@ -109,11 +117,9 @@ impl Environment {
                         (Ok(kind), Ok(unit)) => {
                             // we need to return the type (bool, f32, i32) of data the Sensor expects
                             let generator = match kind {
-                                Kind::Bool => {
-                                    unimplemented!()
-                                }
-                                Kind::Int => {
-                                    unimplemented!()
+                                Kind::Bool | Kind::Int => {
+                                    let msg = format!("cannot yet generate data of kind '{}'", kind);
+                                    return Self::handler_failure(self_name.clone(), tcp_stream, 501, "unsupported_kind", msg.as_str());
                                 }
                                 Kind::Float => {
                                     let coefficients = Coefficients::new(0.0, 0.0, 5.0, 10000.0, 0.0);
@@ -122,17 +128,15 @@ impl Environment {
                                 }
                             };
 
-                            // register this Datum generator to this Id
+                            // generate a random value, then register this Datum generator to this Id
+                            let datum = generator.generate();
                             generators.insert(id.clone(), generator);
 
-                            // generate a random value
-                            let datum = generators.get_mut(&id).unwrap().generate();
-
-                            success(tcp_stream, datum);
+                            success(tcp_stream, datum)
                         }
                         _ => {
                             let msg = "could not parse required headers";
-                            Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                            Self::handler_failure(self_name.clone(), tcp_stream, 400, "invalid_header", msg)
                         }
                     },
                     _ => {
@@ -140,7 +144,7 @@ impl Environment {
                             "unknown Sensor ID '{}'. To register a new sensor, you must include 'kind' and 'unit' headers in your request",
                             id
                         );
-                        Self::handler_failure(self_name.clone(), tcp_stream, msg.as_str())
+                        Self::handler_failure(self_name.clone(), tcp_stream, 404, "unknown_sensor", msg.as_str())
                     }
                 }
             }
@@ -156,8 +160,13 @@ impl Environment {
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_post_command(tcp_stream: &mut impl Write, message: Message, self_name: &Name, generators: &Arc<Mutex<HashMap<Id, DatumGenerator>>>) {
-        fn success(stream: &mut impl Write) {
+    fn handle_post_command(
+        tcp_stream: &mut impl Write,
+        message: Message,
+        self_name: &Name,
+        generators: &Arc<Mutex<HashMap<Id, DatumGenerator>>>,
+    ) -> Result<(), Error> {
+        fn success(stream: &mut impl Write) -> Result<(), Error> {
             debug!("[Environment] updated generator for Sensor");
             let response = Message::respond_ok();
             response.write(stream)
@@ -170,15 +179,15 @@ impl Environment {
                 (id, Ok(model)) => match model {
                     Model::Controller => {
                         let msg = "does not accept Commands directly from the Controller";
-                        Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                        Self::handler_failure(self_name.clone(), tcp_stream, 403, "forbidden_sender", msg)
                     }
                     Model::Environment => {
                         let msg = "does not accept Commands from itself";
-                        Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                        Self::handler_failure(self_name.clone(), tcp_stream, 403, "forbidden_sender", msg)
                     }
                     Model::Unsupported => {
                         let msg = "unsupported device";
-                        Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                        Self::handler_failure(self_name.clone(), tcp_stream, 400, "unsupported_model", msg)
                     }
                     Model::Thermo5000 => match message.body.as_ref().map(Command::parse) {
                         Some(Ok(command)) => {
@@ -186,51 +195,53 @@ impl Environment {
 
                             let mut generators = generators.lock().unwrap();
 
-                            match generators.contains_key(&id) {
-                                false => {
+                            match generators.get_mut(&id) {
+                                None => {
                                     let msg = format!("cannot update generator for unknown id: {}", id);
-                                    Self::handler_failure(self_name.clone(), tcp_stream, msg.as_str())
+                                    Self::handler_failure(self_name.clone(), tcp_stream, 404, "unknown_sensor", msg.as_str())
                                 }
-                                true => {
-                                    let generator = generators.get_mut(&id).unwrap();
-                                    match command {
-                                        Command::CoolBy(delta) => {
-                                            generator.coefficients.constant -= delta * 0.01;
-                                        }
-                                        Command::HeatBy(delta) => {
-                                            generator.coefficients.constant += delta * 0.01;
-                                        }
-                                    }
-
+                                Some(generator) => {
+                                    generator.apply(&command);
                                     success(tcp_stream)
                                 }
                             }
                         }
                         _ => {
                             let msg = format!("could not parse \"{:?}\" as Thermo5000 Command", message.body);
-                            Self::handler_failure(self_name.clone(), tcp_stream, msg.as_str())
+                            Self::handler_failure(self_name.clone(), tcp_stream, 400, "invalid_command", msg.as_str())
                         }
                     },
                 },
                 _ => {
                     let msg = "could not parse required headers";
-                    Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                    Self::handler_failure(self_name.clone(), tcp_stream, 400, "invalid_header", msg)
                 }
             },
             _ => {
                 let msg = "missing required headers. 'id' and 'model' headers are required to update a generator.";
-                Self::handler_failure(self_name.clone(), tcp_stream, msg)
+                Self::handler_failure(self_name.clone(), tcp_stream, 400, "missing_header", msg)
             }
         }
     }
 
-    pub fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String) -> JoinHandle<()> {
-        std::thread::spawn(move || {
+    /// Starts the `Environment` on a new thread, returning a `DeviceHandle` which can be used to stop it.
+    ///
+    /// The `Environment` finds (and is found by) its peers using the `discovery` `Backend`.
+    pub fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String, discovery: Backend) -> DeviceHandle {
+        DeviceHandle::spawn(name.clone(), move |shutdown| {
             let device = Self::new(id, name);
 
-            let mdns = ServiceDaemon::new().unwrap();
+            let discovery = match discovery.connect(&shutdown) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    error!("[Environment] cannot start discovery: {}", e);
+                    return;
+                }
+            };
 
-            device.respond(ip, port, group.as_str(), mdns)
+            if let Err(e) = device.respond(ip, port, group.as_str(), discovery.as_ref(), &shutdown) {
+                error!("[Environment] stopped responding to requests: {}", e)
+            }
         })
     }
 }
@@ -279,7 +290,7 @@ mod environment_tests {
 
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
@@ -291,11 +302,10 @@ mod environment_tests {
         assert!(actual.contains("\r\nContent-Type: text/json; charset=utf-8\r\n\r\n{\"value\":\"")); // and then a value
         assert!(actual.contains("\",\"unit\":\"")); // and then a unit
         assert!(actual.contains("\",\"timestamp\":\"")); // and then a timestamp
-        assert!(actual.ends_with("\"}\r\n\r\n"));
+        assert!(actual.ends_with("\"}"));
     }
 
     #[test]
-    #[should_panic]
     fn test_handle_get_datum_new_generator_int_unimplemented() {
         let mut buffer = Vec::new();
 
@@ -310,11 +320,23 @@ mod environment_tests {
 
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let expected = [
+            "HTTP/1.1 501 Not Implemented",
+            "Content-Length: 105",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"cannot yet generate data of kind 'int'","code":"unsupported_kind","device":"name is arbitrary"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
     }
 
     #[test]
-    #[should_panic]
     fn test_handle_get_datum_new_generator_bool_unimplemented() {
         let mut buffer = Vec::new();
 
@@ -329,7 +351,20 @@ mod environment_tests {
 
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let expected = [
+            "HTTP/1.1 501 Not Implemented",
+            "Content-Length: 106",
+            "Content-Type: text/json; charset=utf-8",
+            "",
+            r#"{"error":"cannot yet generate data of kind 'bool'","code":"unsupported_kind","device":"name is arbitrary"}"#,
+        ]
+        .join("\r\n");
+
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -347,20 +382,20 @@ mod environment_tests {
 
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
             "HTTP/1.1 400 Bad Request",
-            "Content-Length: 32",
+            "Content-Length: 97",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "could not parse required headers",
+            r#"{"error":"could not parse required headers","code":"invalid_header","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -378,20 +413,20 @@ mod environment_tests {
 
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
-            "HTTP/1.1 400 Bad Request",
-            "Content-Length: 128",
+            "HTTP/1.1 404 Not Found",
+            "Content-Length: 176",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "unknown Sensor ID 'HTTP/1.1 404 Not Found'. To register a new sensor, you must include 'kind' and 'unit' headers in your request",
+            r#"{"error":"unknown Sensor ID 'my_id'. To register a new sensor, you must include 'kind' and 'unit' headers in your request","code":"unknown_sensor","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -408,7 +443,7 @@ mod environment_tests {
         generators.insert(Id::new("my_id"), generator);
         let generators = Arc::new(Mutex::new(generators));
 
-        Environment::handle_get_datum(&mut buffer, message, &name, &generators);
+        Environment::handle_get_datum(&mut buffer, message, Id::new("my_id"), &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
@@ -420,7 +455,7 @@ mod environment_tests {
         assert!(actual.contains("\r\nContent-Type: text/json; charset=utf-8\r\n\r\n{\"value\":\"")); // and then a value
         assert!(actual.contains("\",\"unit\":\"")); // and then a unit
         assert!(actual.contains("\",\"timestamp\":\"")); // and then a timestamp
-        assert!(actual.ends_with("\"}\r\n\r\n"));
+        assert!(actual.ends_with("\"}"));
     }
 
     #[test]
@@ -444,7 +479,7 @@ mod environment_tests {
         generators.insert(Id::new("my_id"), generator);
         let generators = Arc::new(Mutex::new(generators));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
@@ -474,7 +509,7 @@ mod environment_tests {
         generators.insert(Id::new("my_id"), generator);
         let generators = Arc::new(Mutex::new(generators));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
@@ -504,20 +539,20 @@ mod environment_tests {
         generators.insert(Id::new("known_id"), generator);
         let generators = Arc::new(Mutex::new(generators));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
-            "HTTP/1.1 400 Bad Request",
-            "Content-Length: 50",
+            "HTTP/1.1 404 Not Found",
+            "Content-Length: 115",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "cannot update generator for unknown id: unknown_id",
+            r#"{"error":"cannot update generator for unknown id: unknown_id","code":"unknown_sensor","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected));
+        assert_eq!(actual, expected);
     }
 
     #[test]
@@ -535,20 +570,20 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
             "HTTP/1.1 400 Bad Request",
-            "Content-Length: 86",
+            "Content-Length: 156",
             "Content-Type: text/json; charset=utf-8",
             "",
-            r#"could not parse "Some("this is not a valid thermo5000 command")" as Thermo5000 Command"#,
+            r#"{"error":"could not parse \"Some(\"this is not a valid thermo5000 command\")\" as Thermo5000 Command","code":"invalid_command","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -564,20 +599,20 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
-            "HTTP/1.1 400 Bad Request",
-            "Content-Length: 53",
+            "HTTP/1.1 403 Forbidden",
+            "Content-Length: 120",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "does not accept Commands directly from the Controller",
+            r#"{"error":"does not accept Commands directly from the Controller","code":"forbidden_sender","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -593,20 +628,20 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
-            "HTTP/1.1 400 Bad Request",
-            "Content-Length: 36",
+            "HTTP/1.1 403 Forbidden",
+            "Content-Length: 103",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "does not accept Commands from itself",
+            r#"{"error":"does not accept Commands from itself","code":"forbidden_sender","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -622,20 +657,20 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
             "HTTP/1.1 400 Bad Request",
-            "Content-Length: 18",
+            "Content-Length: 86",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "unsupported device",
+            r#"{"error":"unsupported device","code":"unsupported_model","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -651,20 +686,20 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
             "HTTP/1.1 400 Bad Request",
-            "Content-Length: 32",
+            "Content-Length: 97",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "could not parse required headers",
+            r#"{"error":"could not parse required headers","code":"invalid_header","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -680,19 +715,19 @@ mod environment_tests {
         let name = Name::new("name is arbitrary");
         let generators = Arc::new(Mutex::new(HashMap::new()));
 
-        Environment::handle_post_command(&mut buffer, message, &name, &generators);
+        Environment::handle_post_command(&mut buffer, message, &name, &generators).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let expected = [
             "HTTP/1.1 400 Bad Request",
-            "Content-Length: 86",
+            "Content-Length: 151",
             "Content-Type: text/json; charset=utf-8",
             "",
-            "missing required headers. 'id' and 'model' headers are required to update a generator.",
+            r#"{"error":"missing required headers. 'id' and 'model' headers are required to update a generator.","code":"missing_header","device":"name is arbitrary"}"#,
         ]
         .join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 }
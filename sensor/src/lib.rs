@@ -1,19 +1,26 @@
 use std::collections::{HashMap, VecDeque};
 use std::io::Write;
-use std::net::{IpAddr, TcpStream};
+use std::net::IpAddr;
+use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
 use std::time::Duration;
 
-use log::{debug, warn};
-use mdns_sd::{ServiceDaemon, ServiceInfo};
+use log::{debug, error, warn};
+use mdns_sd::ServiceInfo;
 
+use datum::filter::Filter;
 use datum::kind::Kind;
 use datum::unit::Unit;
 use datum::Datum;
+use device::address::Address;
+use device::client::Client;
+use device::discovery::Backend;
+use device::error::Error;
+use device::handle::DeviceHandle;
 use device::id::Id;
 use device::message::Message;
 use device::name::Name;
+use device::router::Router;
 use device::{Device, Handler};
 
 /// A Sensor collects data from the Environment.
@@ -30,40 +37,52 @@ pub trait Sensor: Device {
 
     fn get_data(&self) -> &Arc<Mutex<VecDeque<Datum>>>;
 
-    /// By default, a `Sensor` responds to any request with the latest `Datum`.
-    fn get_handler(&self) -> Handler {
-        let self_name = self.get_name().clone();
+    /// A `Sensor` advertises the endpoints served by its default [`get_handler`](Sensor::get_handler).
+    fn get_endpoints(&self) -> Vec<String> {
+        vec![String::from("GET /data"), String::from("GET /datum")]
+    }
 
-        // Anything which depends on self must be cloned outside of the |stream| lambda.
-        // We cannot refer to `self` inside of this lambda.
-        let self_data = Arc::clone(self.get_data());
-
-        Box::new(move |stream| {
-            if let Ok(message) = Message::read(stream) {
-                if message.start_line == "GET /data HTTP/1.1" {
-                    Self::handle_get_data(stream, &self_data)
-                } else if message.start_line == "GET /datum HTTP/1.1" {
-                    Self::handle_get_datum(stream, &self_data)
-                } else {
-                    let msg = format!("cannot parse request: {}", message.start_line);
-                    Self::handler_failure(self_name.clone(), stream, msg.as_str())
-                }
-            } else {
-                Self::handler_failure(self_name.clone(), stream, "unable to read Message from stream")
-            }
-        })
+    /// A `Sensor` advertises the `Kind` and `Unit` of the `Datum`s it produces, so that the
+    /// `Controller` can check that it knows how to assess them before it polls this `Sensor`.
+    fn get_properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("kind"), Self::get_datum_value_type().to_string());
+        properties.insert(String::from("unit"), Self::get_datum_unit().to_string());
+        properties
+    }
+
+    /// By default, a `Sensor` responds to `GET /data` with its buffered `Datum`s (optionally filtered
+    /// by the `since`, `until`, `limit`, and `offset` query parameters), and to `GET /datum` with the
+    /// latest `Datum`.
+    fn get_handler(&self, _address: Address) -> Handler {
+        // Anything which depends on self must be cloned outside of the |stream| lambdas.
+        // We cannot refer to `self` inside of these lambdas.
+        let self_name = self.get_name().clone();
+        let data = Arc::clone(self.get_data());
+        let datum = Arc::clone(self.get_data());
+
+        Router::new(self.get_name().clone())
+            .get("/data", move |stream, message, _| match Filter::parse(&message.query()) {
+                Ok(filter) => Self::handle_get_data(stream, &data, &filter),
+                Err(msg) => Self::handler_failure(self_name.clone(), stream, 400, "invalid_query", msg.as_str()),
+            })
+            .get("/datum", move |stream, _, _| Self::handle_get_datum(stream, &datum))
+            .into_handler()
     }
 
     /// Describes how `GET /data` requests are handled by `Sensor`s.
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_data(tcp_stream: &mut impl Write, data: &Arc<Mutex<VecDeque<Datum>>>) {
-        // get all of the data in this Sensor's buffer
+    fn handle_get_data(tcp_stream: &mut impl Write, data: &Arc<Mutex<VecDeque<Datum>>>, filter: &Filter) -> Result<(), Error> {
+        // get all of the data in this Sensor's buffer, newest first
         //     ex: curl 10.12.50.26:5454/data
+        //
+        // or only some of it
+        //     ex: curl '10.12.50.26:5454/data?since=2024-01-05T12:39:36Z&limit=5&offset=10'
 
         let data = data.lock().unwrap();
-        let data: Vec<String> = data.iter().map(|d| d.to_string()).collect();
+        let data: Vec<String> = filter.apply(data.iter()).iter().map(|d| d.to_string()).collect();
         let data = data.join(",");
         let data = format!("[{}]", data);
 
@@ -75,7 +94,7 @@ pub trait Sensor: Device {
     ///
     /// **Design Decision**: `tcp_stream` is of type `impl Write` rather than `TcpStream` because
     /// this is easier to test. We do not use any `TcpStream`-specific APIs in this method.
-    fn handle_get_datum(tcp_stream: &mut impl Write, data: &Arc<Mutex<VecDeque<Datum>>>) {
+    fn handle_get_datum(tcp_stream: &mut impl Write, data: &Arc<Mutex<VecDeque<Datum>>>) -> Result<(), Error> {
         // get the latest Datum from this Sensor's buffer
         //     ex: curl 10.12.50.26:5454/datum
 
@@ -87,17 +106,26 @@ pub trait Sensor: Device {
         response.write(tcp_stream)
     }
 
-    fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String) -> JoinHandle<()> {
-        std::thread::spawn(move || {
+    /// Starts this `Sensor` on a new thread, returning a `DeviceHandle` which can be used to stop it.
+    ///
+    /// The `Sensor` finds (and is found by) its peers using the `discovery` `Backend`.
+    fn start(ip: IpAddr, port: u16, id: Id, name: Name, group: String, discovery: Backend) -> DeviceHandle {
+        DeviceHandle::spawn(name.clone(), move |shutdown| {
             // --------------------------------------------------------------------------------
             // create Device and discover required Message targets
             // --------------------------------------------------------------------------------
             let device = Self::new(id, name);
 
-            let mdns = ServiceDaemon::new().unwrap();
+            let discovery = match discovery.connect(&shutdown) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    error!("[Sensor] {} cannot start discovery: {}", device.get_name(), e);
+                    return;
+                }
+            };
 
-            device.discover_once("_controller", device.get_controller(), mdns.clone());
-            device.discover_once("_environment", device.get_environment(), mdns.clone());
+            device.discover_once("_controller", device.get_controller(), &discovery, &shutdown);
+            device.discover_once("_environment", device.get_environment(), &discovery, &shutdown);
 
             // --------------------------------------------------------------------------------
             // ping the Environment at regular intervals to get latest data
@@ -115,8 +143,9 @@ pub trait Sensor: Device {
 
             let data = Arc::clone(device.get_data());
             let environment = Arc::clone(device.get_environment());
+            let polling = shutdown.clone();
 
-            std::thread::spawn(move || {
+            shutdown.spawn(move || {
                 let url = format!("/datum/{}", device_id);
 
                 let mut headers: HashMap<&str, String> = HashMap::new();
@@ -125,36 +154,44 @@ pub trait Sensor: Device {
 
                 let query = Message::request_get(url.as_str()).with_headers(headers);
 
+                // the same Connection to the Environment is reused for every query
+                let client = Client::new();
+
+                // after repeated failures, the Environment is forgotten, and discovered again
+                let failures = AtomicU32::new(0);
+
                 loop {
                     {
-                        let environment = environment.lock().unwrap();
+                        let mut environment = environment.lock().unwrap();
 
                         match environment.as_ref().map(Self::extract_address) {
                             None => {
                                 warn!("[Sensor] {} could not find environment", device_name);
                             }
-                            Some(address) => {
-                                let mut stream = TcpStream::connect(address.to_string()).unwrap();
-
-                                debug!("[Sensor] {} is querying environment for a Datum", device_name);
-                                query.write(&mut stream);
-                                let message = Message::read(&mut stream).unwrap();
-                                let datum = Datum::parse(message.body.unwrap()).unwrap();
-
-                                debug!("[Sensor] {} received a Datum from environment: {}", device_name, datum);
-
-                                // enforce buffer length, then push, then process
-                                // .lock() must go in an inner scope so it is _unlocked_ while are thread::sleep()-ing, below
-                                let mut data = data.lock().unwrap();
-                                if data.len() == buffer_size {
-                                    data.pop_back();
+                            Some(address) => match address.and_then(|address| Self::query_environment(&client, &address, &query)) {
+                                Err(e) => {
+                                    warn!("[Sensor] {} could not get a Datum from environment: {}", device_name, e);
+                                    Self::record_request(&mut environment, &failures, false);
                                 }
-                                data.push_front(datum.clone());
-                            }
+                                Ok(datum) => {
+                                    debug!("[Sensor] {} received a Datum from environment: {}", device_name, datum);
+                                    Self::record_request(&mut environment, &failures, true);
+
+                                    // enforce buffer length, then push, then process
+                                    // .lock() must go in an inner scope so it is _unlocked_ while are thread::sleep()-ing, below
+                                    let mut data = data.lock().unwrap();
+                                    if data.len() == buffer_size {
+                                        data.pop_back();
+                                    }
+                                    data.push_front(datum.clone());
+                                }
+                            },
                         }
                     }
 
-                    std::thread::sleep(sleep_duration);
+                    if !polling.sleep(sleep_duration) {
+                        break;
+                    }
                 }
             });
 
@@ -162,9 +199,22 @@ pub trait Sensor: Device {
             // respond to incoming requests
             // --------------------------------------------------------------------------------
 
-            device.respond(ip, port, group.as_str(), mdns)
+            if let Err(e) = device.respond(ip, port, group.as_str(), discovery.as_ref(), &shutdown) {
+                error!("[Sensor] {} stopped responding to requests: {}", device.get_name(), e)
+            }
         })
     }
+
+    /// Sends the `query` to the `Environment` at the specified `address` and parses its response as a `Datum`.
+    fn query_environment(client: &Client, address: &Address, query: &Message) -> Result<Datum, Error> {
+        debug!("[Sensor] querying environment @ {} for a Datum", address);
+        let message = client.send(address, query)?;
+
+        match message.body {
+            None => Err(Error::Protocol(format!("environment responded without a body: {}", message.start_line))),
+            Some(body) => Datum::parse(body).map_err(Error::Parse),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -227,8 +277,8 @@ mod sensor_tests {
             Model::Unsupported
         }
 
-        fn get_handler(&self) -> Handler {
-            Box::new(|_| ())
+        fn get_handler(&self, _address: Address) -> Handler {
+            Box::new(|_, _| Ok(()))
         }
     }
 
@@ -246,23 +296,19 @@ mod sensor_tests {
 
         let mut buffer = Vec::new();
 
-        TestSensor::handle_get_data(&mut buffer, &data);
+        TestSensor::handle_get_data(&mut buffer, &data, &Filter::default()).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let json = [datum3, datum2, datum1].map(|e| e.to_string()).join(",");
         let json = format!("[{}]", json);
 
-        let expected = [
-            "HTTP/1.1 200 OK",
-            "Content-Length: 229",
-            "Content-Type: text/json; charset=utf-8",
-            "",
-            json.as_str(),
-        ]
-        .join("\r\n");
+        // timestamps are serialized with as much precision as the platform clock provides
+        let length = format!("Content-Length: {}", json.len());
+
+        let expected = ["HTTP/1.1 200 OK", length.as_str(), "Content-Type: text/json; charset=utf-8", "", json.as_str()].join("\r\n");
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected)
     }
 
     #[test]
@@ -279,22 +325,53 @@ mod sensor_tests {
 
         let mut buffer = Vec::new();
 
-        TestSensor::handle_get_datum(&mut buffer, &data);
+        TestSensor::handle_get_datum(&mut buffer, &data).unwrap();
 
         let actual = String::from_utf8(buffer).unwrap();
 
         let json = datum3.to_string();
         let json = format!("[{}]", json);
 
-        let expected = [
-            "HTTP/1.1 200 OK",
-            "Content-Length: 77",
-            "Content-Type: text/json; charset=utf-8",
-            "",
-            json.as_str(),
-        ]
-        .join("\r\n");
+        // timestamps are serialized with as much precision as the platform clock provides
+        let length = format!("Content-Length: {}", json.len());
+
+        let expected = ["HTTP/1.1 200 OK", length.as_str(), "Content-Type: text/json; charset=utf-8", "", json.as_str()].join("\r\n");
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_handle_get_data_filtered() {
+        let datum = |value: &str, second: &str| {
+            let serialized = format!(r#"{{"value":"{}","unit":"°C","timestamp":"2024-01-05T12:00:{}+00:00"}}"#, value, second);
+            Datum::parse(serialized).unwrap()
+        };
+
+        let mut data = VecDeque::new();
+        let datum1 = datum("1.0", "01");
+        let datum2 = datum("2.0", "02");
+        let datum3 = datum("3.0", "03");
+        data.push_front(datum1.clone());
+        data.push_front(datum2.clone());
+        data.push_front(datum3.clone());
+
+        let data = Arc::new(Mutex::new(data));
+
+        let mut buffer = Vec::new();
+
+        let filter = Filter {
+            since: Some(datum1.timestamp),
+            limit: Some(1),
+            ..Filter::default()
+        };
+
+        TestSensor::handle_get_data(&mut buffer, &data, &filter).unwrap();
+
+        let actual = String::from_utf8(buffer).unwrap();
+
+        let json = format!("[{}]", datum3);
+        let expected = Message::respond_ok().with_body(json);
 
-        assert_eq!(actual, format!("{}\r\n\r\n", expected))
+        assert_eq!(actual, expected.to_string())
     }
 }
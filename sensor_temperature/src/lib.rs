@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 
 use mdns_sd::ServiceInfo;
@@ -6,6 +6,7 @@ use mdns_sd::ServiceInfo;
 use datum::kind::Kind;
 use datum::unit::Unit;
 use datum::Datum;
+use device::address::Address;
 use device::id::Id;
 use device::model::Model;
 use device::name::Name;
@@ -34,8 +35,16 @@ impl Device for TemperatureSensor {
         Model::Thermo5000
     }
 
-    fn get_handler(&self) -> Handler {
-        Sensor::get_handler(self)
+    fn get_handler(&self, address: Address) -> Handler {
+        Sensor::get_handler(self, address)
+    }
+
+    fn get_endpoints(&self) -> Vec<String> {
+        Sensor::get_endpoints(self)
+    }
+
+    fn get_properties(&self) -> HashMap<String, String> {
+        Sensor::get_properties(self)
     }
 }
 
@@ -73,7 +82,6 @@ impl Sensor for TemperatureSensor {
 
 #[cfg(test)]
 mod sensor_temperature_tests {
-    use std::collections::HashMap;
     use std::net::IpAddr;
 
     use super::*;
@@ -183,6 +191,17 @@ mod sensor_temperature_tests {
         assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn test_get_service_info() {
+        let sensor = TemperatureSensor::new(Id::new("myId"), Name::new("myName"));
+        let info = sensor.get_service_info(IpAddr::from([1, 2, 3, 4]), 42, "_sensor").unwrap();
+
+        assert_eq!(TemperatureSensor::extract_kind(&info), Some(Ok(Kind::Float)));
+        assert_eq!(TemperatureSensor::extract_unit(&info), Some(Ok(Unit::DegreesC)));
+        assert_eq!(TemperatureSensor::extract_endpoints(&info), vec!["GET /data", "GET /datum"]);
+        assert!(TemperatureSensor::extract_protocol_version(&info).is_some());
+    }
+
     #[test]
     fn test_get_data() {
         let sensor = TemperatureSensor::new(Id::new("myId"), Name::new("myName"));
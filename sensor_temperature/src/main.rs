@@ -1,5 +1,5 @@
-use std::time::Duration;
-
+use device::discovery::Backend;
+use device::handle::wait_for_termination;
 use device::id::Id;
 use device::name::Name;
 use sensor::Sensor;
@@ -15,7 +15,13 @@ fn main() {
     let ip = local_ip_address::local_ip().unwrap();
     let group = String::from("_sensor");
 
-    TemperatureSensor::start(ip, port, id, name, group);
+    // Devices find each other via mDNS, unless DISCOVERY=static selects a fixed list of Devices instead
+    let discovery = Backend::from_env().unwrap();
+
+    let handle = TemperatureSensor::start(ip, port, id, name, group, discovery);
     println!("TemperatureSensor is running...");
-    std::thread::sleep(Duration::MAX)
+
+    // stop cleanly on Ctrl-C, or when the container runtime sends SIGTERM
+    wait_for_termination().unwrap();
+    handle.shutdown();
 }
@@ -1,22 +1,35 @@
+use std::net::TcpListener;
 use std::time::Duration;
 
 use uuid::Uuid;
 
 use actuator::Actuator;
 use actuator_temperature::TemperatureActuator;
+use controller::config::Config;
 use controller::Controller;
+use device::client::Client;
+use device::discovery::{Backend, MemoryRegistry};
 use device::id::Id;
+use device::json::Value;
+use device::message::Message;
 use device::name::Name;
 use environment::Environment;
 use sensor::Sensor;
 use sensor_temperature::TemperatureSensor;
 
 #[test]
-// this basic integration test just checks that nothing panics when running the demo for 5 seconds
+// this basic integration test just checks that nothing panics when running the demo for 5 seconds,
+// that the Controller collects data from the Sensor, and that every Device shuts down cleanly afterward
 fn test_demo() {
     // in the local demo, all devices have the same ip (localhost)
     let ip = local_ip_address::local_ip().unwrap();
 
+    // every Device is started on port 0, so the OS picks a free port, and test runs cannot collide
+    let port = 0;
+
+    // Devices find each other in-process, so this cluster cannot see (or be seen by) any other
+    let discovery = Backend::Memory(MemoryRegistry::new());
+
     // --------------------------------------------------------------------------------
     // spin up a sensor-actuator pair
     // --------------------------------------------------------------------------------
@@ -25,34 +38,69 @@ fn test_demo() {
     let id = Id::new(Uuid::new_v4());
 
     // here is the Sensor
-    let sensor_port = 8787;
-    TemperatureSensor::start(ip, sensor_port, id.clone(), Name::new("My Thermo-5000 Sensor"), "_sensor".into());
+    let sensor = TemperatureSensor::start(ip, port, id.clone(), Name::new("My Thermo-5000 Sensor"), "_sensor".into(), discovery.clone());
 
     // here is the Actuator
-    TemperatureActuator::start(ip, 9898, id.clone(), Name::new("My Thermo-5000 Actuator"), "_actuator".into());
+    let actuator = TemperatureActuator::start(
+        ip,
+        port,
+        id.clone(),
+        Name::new("My Thermo-5000 Actuator"),
+        "_actuator".into(),
+        discovery.clone(),
+    );
 
     // --------------------------------------------------------------------------------
     // spin up the controller and the environment
     // --------------------------------------------------------------------------------
 
-    let container_mode = false;
-    Controller::start(
-        ip,
-        6565,
-        Id::new("controller"),
-        Name::new("Controller"),
-        String::from("_controller"),
-        container_mode,
-    );
+    let config = Config::new(discovery.clone()).with_container_mode(false);
+    let controller = Controller::start(ip, port, Id::new("controller"), Name::new("Controller"), String::from("_controller"), config);
 
-    let environment_port = 5454;
-    Environment::start(
+    let environment = Environment::start(
         ip,
-        environment_port,
+        port,
         Id::new("environment"),
         Name::new("Environment"),
         String::from("_environment"),
+        discovery,
+    );
+
+    let handles = [controller, sensor, actuator, environment];
+    let addresses: Vec<_> = handles.iter().map(|handle| handle.address().unwrap()).collect();
+
+    std::thread::sleep(Duration::from_secs(5));
+
+    // --------------------------------------------------------------------------------
+    // the Controller can only have data if it found the Sensor on the port it advertised
+    // --------------------------------------------------------------------------------
+
+    let response = Client::new().send(&addresses[0], &Message::request_get("/data")).unwrap();
+    let body = Value::parse(response.body.unwrap_or_default()).unwrap();
+
+    let sensors = body.as_array().unwrap();
+    let sensor_data = sensors
+        .iter()
+        .find(|sensor| sensor.get("id").and_then(Value::as_str) == Some(id.to_string().as_str()));
+    assert!(
+        sensor_data
+            .and_then(|sensor| sensor.get("data"))
+            .and_then(Value::as_array)
+            .is_some_and(|data| !data.is_empty()),
+        "the Controller has no data for Sensor {}",
+        id
     );
 
-    std::thread::sleep(Duration::from_secs(5))
+    // --------------------------------------------------------------------------------
+    // shut everything down, then check that every port has been released
+    // --------------------------------------------------------------------------------
+
+    for handle in handles {
+        assert!(!handle.is_finished(), "{} stopped before it was shut down", handle.name());
+        handle.shutdown();
+    }
+
+    for address in addresses {
+        assert!(TcpListener::bind((address.ip(), address.port())).is_ok(), "{} is still in use", address);
+    }
 }